@@ -1,5 +1,11 @@
+use std::{
+    collections::HashMap,
+    os::fd::{AsFd, OwnedFd},
+};
+
 use futures_sink::Sink;
 use pin_project_lite::pin_project;
+use stagecraft::Handle;
 use tokio::{net::UnixStream, sync::mpsc};
 use tokio_stream::{Stream, StreamExt};
 use tokio_util::sync::CancellationToken;
@@ -8,9 +14,174 @@ use tracing::error;
 use waynest::{Message, ObjectId, ProtocolError, Socket};
 use waynest_server::{Client as _, Store};
 
-use crate::{VerdiError, protocol::wayland::display::Display};
+use crate::{
+    VerdiError,
+    actors::{
+        compositor::{Compositor, CompositorMessage, SelectionKind},
+        renderer::{OutputId, Preferred, Presented, Renderer},
+    },
+    protocol::{
+        data_control::device::DataControlDevice,
+        idle_notify::notification::{ExtIdleNotificationV1, IdleNotification},
+        layer_shell::surface::{LayerSurface, ZwlrLayerSurfaceV1},
+        output_power::power::{Mode as PowerMode, OutputPower, ZwlrOutputPowerV1},
+        presentation_time::feedback::PresentationFeedback,
+        primary_selection::device::PrimarySelectionDevice,
+        session_lock::{lock::SessionLock, surface::LockSurface},
+        wayland::{
+            buffer::{Buffer, WlBuffer},
+            callback::{Callback, WlCallback},
+            data_device::{DataDevice, OfferedDrag, OfferedSelection, WlDataDevice},
+            data_device_manager::{DND_ACTIONS_VERSION, DndAction},
+            data_offer::DataOffer,
+            data_source::{DataSource, WlDataSource},
+            display::{Display, WlDisplay},
+            selection,
+            surface::Surface,
+        },
+        wlr_data_control::device::WlrDataControlDevice,
+    },
+};
 
-pub enum ClientMessage {}
+pub enum ClientMessage {
+    /// A refresh cycle of the given surfaces is over, along with their
+    /// `wl_surface.frame` callbacks, `time` in milliseconds
+    FrameDone {
+        callbacks: Vec<(ObjectId, Vec<ObjectId>)>,
+        time: u32,
+    },
+    /// The target time of a commit held back by a `wp_commit_timer_v1` has
+    /// come
+    CommitTimerExpired {
+        surface: ObjectId,
+    },
+    /// The acquire point of a commit waiting for the client to finish
+    /// rendering was signalled
+    AcquireSignalled {
+        surface: ObjectId,
+    },
+    /// The renderer stopped reading from the given `wl_buffer`s
+    ReleaseBuffers {
+        buffers: Vec<ObjectId>,
+    },
+    /// The updates of the given `wp_presentation_feedback`s were shown, or
+    /// discarded if `None`
+    PresentationFeedback {
+        feedback: Vec<(ObjectId, Option<Presented>)>,
+    },
+    /// The given surfaces moved to an output of another scale or transform
+    Preferred {
+        surfaces: Vec<(ObjectId, Preferred)>,
+    },
+    /// The client got keyboard focus, along with the current selections
+    KeyboardEnter {
+        selections: Vec<(SelectionKind, OfferedSelection)>,
+    },
+    KeyboardLeave,
+    /// A selection changed while the client has keyboard focus
+    Selection {
+        kind: SelectionKind,
+        selection: Option<OfferedSelection>,
+    },
+    /// A selection changed, as seen by data control devices
+    ControlSelection {
+        kind: SelectionKind,
+        selection: Option<OfferedSelection>,
+    },
+    /// Another client wants the data of the given source, written into `fd`
+    SendData {
+        source: ObjectId,
+        mime_type: String,
+        fd: OwnedFd,
+    },
+    /// The given source no longer backs a selection or a drag
+    SourceCancelled {
+        source: ObjectId,
+    },
+    /// A drag entered the given surface, at surface local coordinates
+    DragEnter {
+        surface: ObjectId,
+        x: f64,
+        y: f64,
+        offer: Option<OfferedDrag>,
+    },
+    DragMotion {
+        x: f64,
+        y: f64,
+        time: u32,
+    },
+    DragLeave,
+    DragDrop,
+    /// The action a drop on the client would perform changed
+    DragAction {
+        action: DndAction,
+    },
+    /// The target of a drag from the given `wl_data_source` accepts the
+    /// given mime type, or none
+    DragTarget {
+        source: ObjectId,
+        mime_type: Option<String>,
+    },
+    /// The action a drop of the given `wl_data_source` would perform
+    /// changed
+    DragSourceAction {
+        source: ObjectId,
+        action: DndAction,
+    },
+    /// The given `wl_data_source` was dropped, the target may still be
+    /// transferring
+    DropPerformed {
+        source: ObjectId,
+    },
+    /// The target of a drop of the given `wl_data_source` is done with it
+    DragFinished {
+        source: ObjectId,
+    },
+    /// The given `zwlr_layer_surface_v1` was arranged at a new size
+    ConfigureLayerSurface {
+        layer_surface: ObjectId,
+        width: u32,
+        height: u32,
+    },
+    /// The output of the given `zwlr_layer_surface_v1` went away
+    LayerSurfaceClosed {
+        layer_surface: ObjectId,
+    },
+    /// There was no activity for the timeout of the given
+    /// `ext_idle_notification_v1`
+    Idled {
+        notification: ObjectId,
+    },
+    /// There was input since the given `ext_idle_notification_v1` idled
+    IdleResumed {
+        notification: ObjectId,
+    },
+    /// The session is locked for the given `ext_session_lock_v1`
+    SessionLocked {
+        lock: ObjectId,
+    },
+    /// The given `ext_session_lock_v1` won't lock the session
+    LockFinished {
+        lock: ObjectId,
+    },
+    /// The given `ext_session_lock_surface_v1` has to cover an output of
+    /// the given size
+    ConfigureLockSurface {
+        lock_surface: ObjectId,
+        width: u32,
+        height: u32,
+    },
+    /// The outputs were turned on or off, for the given
+    /// `zwlr_output_power_v1`
+    OutputPowerMode {
+        power: ObjectId,
+        on: bool,
+    },
+    /// The given `zwlr_output_power_v1` can't control the outputs
+    OutputPowerFailed {
+        power: ObjectId,
+    },
+}
 
 #[derive(Clone)]
 #[allow(unused)]
@@ -26,9 +197,22 @@ pin_project! {
         store: Store<Client, VerdiError>,
         next_object_id: ObjectId,
         next_event_serial: u32,
+        focus_serial: Option<u32>,
+        selections: HashMap<SelectionKind, OfferedSelection>,
+        data_devices: Vec<ObjectId>,
+        primary_selection_devices: Vec<ObjectId>,
+        /// Selections as data control devices see them, regardless of focus
+        control_selections: HashMap<SelectionKind, OfferedSelection>,
+        data_control_devices: Vec<ObjectId>,
+        /// Bound `wl_output`s and the outputs they stand for
+        outputs: Vec<(ObjectId, OutputId)>,
+        privileged: bool,
+        drag_offers: Vec<ObjectId>,
         receiver: Option<mpsc::Receiver<ClientMessage>>,
         sender: mpsc::Sender<ClientMessage>,
         client_id: u32,
+        compositor_handle: Handle<Compositor>,
+        renderer_handle: Handle<Renderer>,
         shutdown_token: CancellationToken,
     }
 }
@@ -106,6 +290,9 @@ impl Client {
     pub fn new(
         stream: UnixStream,
         client_id: u32,
+        privileged: bool,
+        compositor_handle: Handle<Compositor>,
+        renderer_handle: Handle<Renderer>,
         shutdown_token: CancellationToken,
     ) -> Result<Self, VerdiError> {
         let (sender, receiver) = mpsc::channel(128);
@@ -115,9 +302,20 @@ impl Client {
             store: Store::new(),
             next_object_id: unsafe { ObjectId::from_raw(0xff000000) },
             next_event_serial: 0,
+            focus_serial: None,
+            selections: HashMap::new(),
+            data_devices: Vec::new(),
+            primary_selection_devices: Vec::new(),
+            control_selections: HashMap::new(),
+            data_control_devices: Vec::new(),
+            outputs: Vec::new(),
+            privileged,
+            drag_offers: Vec::new(),
             receiver: Some(receiver),
             sender,
             client_id,
+            compositor_handle,
+            renderer_handle,
             shutdown_token,
         };
 
@@ -132,6 +330,40 @@ impl Client {
         ClientHandle::new(self.sender.clone(), self.client_id)
     }
 
+    pub fn id(&self) -> u32 {
+        self.client_id
+    }
+
+    /// Whether the client may use privileged protocols
+    pub fn is_privileged(&self) -> bool {
+        self.privileged
+    }
+
+    pub fn compositor(&self) -> &Handle<Compositor> {
+        &self.compositor_handle
+    }
+
+    pub fn renderer(&self) -> &Handle<Renderer> {
+        &self.renderer_handle
+    }
+
+    /// Removes an object from the store and lets the client reuse its id
+    pub async fn destroy_object(&mut self, id: ObjectId) -> Result<(), VerdiError> {
+        self.remove(id);
+
+        Display::default()
+            .delete_id(self, ObjectId::DISPLAY, id.as_raw())
+            .await
+    }
+
+    /// Allocates an id for an object created by the compositor
+    pub fn next_object_id(&mut self) -> ObjectId {
+        let id = self.next_object_id;
+        self.next_object_id = unsafe { ObjectId::from_raw(id.as_raw() + 1) };
+
+        id
+    }
+
     pub fn next_event_serial(&mut self) -> u32 {
         let prev = self.next_event_serial;
         self.next_event_serial = self.next_event_serial.wrapping_add(1);
@@ -139,6 +371,89 @@ impl Client {
         prev
     }
 
+    /// Whether `serial` was sent while the client has had keyboard focus
+    pub fn is_focus_serial(&self, serial: u32) -> bool {
+        self.focus_serial.is_some_and(|focus| {
+            serial.wrapping_sub(focus) < self.next_event_serial.wrapping_sub(focus)
+        })
+    }
+
+    /// A selection offered to the client, only while it has keyboard focus
+    pub fn selection(&self, kind: SelectionKind) -> Option<&OfferedSelection> {
+        self.selections.get(&kind)
+    }
+
+    pub fn add_data_device(&mut self, id: ObjectId) {
+        self.data_devices.push(id);
+    }
+
+    pub fn remove_data_device(&mut self, id: ObjectId) {
+        self.data_devices.retain(|device| *device != id);
+    }
+
+    pub fn add_primary_selection_device(&mut self, id: ObjectId) {
+        self.primary_selection_devices.push(id);
+    }
+
+    pub fn remove_primary_selection_device(&mut self, id: ObjectId) {
+        self.primary_selection_devices
+            .retain(|device| *device != id);
+    }
+
+    /// A selection as seen by data control devices
+    pub fn control_selection(&self, kind: SelectionKind) -> Option<&OfferedSelection> {
+        self.control_selections.get(&kind)
+    }
+
+    pub fn add_data_control_device(&mut self, id: ObjectId) {
+        self.data_control_devices.push(id);
+    }
+
+    pub fn remove_data_control_device(&mut self, id: ObjectId) {
+        self.data_control_devices.retain(|device| *device != id);
+    }
+
+    pub fn add_output(&mut self, id: ObjectId, output: OutputId) {
+        self.outputs.push((id, output));
+    }
+
+    pub fn remove_output(&mut self, id: ObjectId) {
+        self.outputs.retain(|(object, _)| *object != id);
+    }
+
+    /// The `wl_output`s the client bound for an output
+    pub fn output_objects(&self, output: OutputId) -> Vec<ObjectId> {
+        self.outputs
+            .iter()
+            .filter(|(_, bound)| *bound == output)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Offers the current selection on every device of its kind
+    async fn offer_selection(&mut self, kind: SelectionKind) -> Result<(), VerdiError> {
+        let selection = self.selections.get(&kind).cloned();
+
+        match kind {
+            SelectionKind::Clipboard => {
+                for id in self.data_devices.clone() {
+                    if let Some(device) = self.get::<DataDevice>(id) {
+                        device.send_selection(self, id, selection.as_ref()).await?;
+                    }
+                }
+            }
+            SelectionKind::Primary => {
+                for id in self.primary_selection_devices.clone() {
+                    if let Some(device) = self.get::<PrimarySelectionDevice>(id) {
+                        device.send_selection(self, id, selection.as_ref()).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn run(mut self) {
         let mut receiver = self.receiver.take().expect("Internal error");
         let shutdown_token = self.shutdown_token.clone();
@@ -159,6 +474,10 @@ impl Client {
                             };
                             if let Err(err) = result {
                                 error!("Error while handling message for client {}: {err}", self.client_id);
+
+                                if self.post_error(err).await {
+                                    break;
+                                }
                             }
                         },
                         Ok(None) => {
@@ -172,14 +491,292 @@ impl Client {
                     }
                 }
                 Some(msg) = receiver.recv() => {
-                    self.handle_message(msg);
+                    if let Err(err) = self.handle_message(msg).await {
+                        error!("Error while handling compositor message for client {}: {err}", self.client_id);
+
+                        if self.post_error(err).await {
+                            break;
+                        }
+                    }
                 }
             }
         }
+
+        let _ = self
+            .compositor_handle
+            .cast(CompositorMessage::ClientDisconnected {
+                client_id: self.client_id,
+            })
+            .await;
     }
 
-    fn handle_message(&mut self, msg: ClientMessage) {
-        match msg {}
+    /// Sends protocol errors to the client, returning whether it has to be
+    /// disconnected
+    async fn post_error(&mut self, err: VerdiError) -> bool {
+        let VerdiError::Client {
+            object_id,
+            code,
+            message,
+        } = err
+        else {
+            return false;
+        };
+
+        let _ = Display::default()
+            .error(self, ObjectId::DISPLAY, object_id, code, message)
+            .await;
+
+        true
+    }
+
+    async fn handle_message(&mut self, msg: ClientMessage) -> Result<(), VerdiError> {
+        match msg {
+            ClientMessage::FrameDone { callbacks, time } => {
+                for (surface, callbacks) in callbacks {
+                    for id in callbacks {
+                        // The surface may have been destroyed in the meantime
+                        let Some(callback) = self.get::<Callback>(id) else {
+                            continue;
+                        };
+
+                        callback.done(self, id, time).await?;
+                        self.destroy_object(id).await?;
+                    }
+
+                    if let Some(object) = self.get::<Surface>(surface) {
+                        object.clear_fifo_barrier(self, surface).await?;
+                    }
+                }
+            }
+            ClientMessage::CommitTimerExpired { surface }
+            | ClientMessage::AcquireSignalled { surface } => {
+                if let Some(object) = self.get::<Surface>(surface) {
+                    object.apply_queued(self, surface).await?;
+                }
+            }
+            ClientMessage::ReleaseBuffers { buffers } => {
+                for id in buffers {
+                    // Destroyed buffers don't get released
+                    let Some(buffer) = self.get::<Buffer>(id) else {
+                        continue;
+                    };
+
+                    buffer.release(self, id).await?;
+                }
+            }
+            ClientMessage::PresentationFeedback { feedback } => {
+                for (id, presented) in feedback {
+                    let Some(object) = self.get::<PresentationFeedback>(id) else {
+                        continue;
+                    };
+
+                    match presented {
+                        Some(presented) => object.send_presented(self, id, presented).await?,
+                        None => object.send_discarded(self, id).await?,
+                    }
+
+                    self.destroy_object(id).await?;
+                }
+            }
+            ClientMessage::Preferred { surfaces } => {
+                for (id, preferred) in surfaces {
+                    if let Some(surface) = self.get::<Surface>(id) {
+                        surface.set_preferred(self, id, preferred).await?;
+                    }
+                }
+            }
+            ClientMessage::KeyboardEnter { selections } => {
+                self.focus_serial = Some(self.next_event_serial());
+                self.selections = selections.into_iter().collect();
+
+                for kind in [SelectionKind::Clipboard, SelectionKind::Primary] {
+                    self.offer_selection(kind).await?;
+                }
+            }
+            ClientMessage::KeyboardLeave => {
+                self.focus_serial = None;
+                self.selections.clear();
+            }
+            ClientMessage::Selection { kind, selection } => {
+                // Focus may have moved on since the compositor sent this
+                if self.focus_serial.is_some() {
+                    match selection {
+                        Some(selection) => self.selections.insert(kind, selection),
+                        None => self.selections.remove(&kind),
+                    };
+
+                    self.offer_selection(kind).await?;
+                }
+            }
+            ClientMessage::ControlSelection { kind, selection } => {
+                match selection {
+                    Some(selection) => self.control_selections.insert(kind, selection),
+                    None => self.control_selections.remove(&kind),
+                };
+
+                let selection = self.control_selections.get(&kind).cloned();
+
+                for id in self.data_control_devices.clone() {
+                    if let Some(device) = self.get::<DataControlDevice>(id) {
+                        device
+                            .send_selection(self, id, kind, selection.as_ref())
+                            .await?;
+                    } else if let Some(device) = self.get::<WlrDataControlDevice>(id) {
+                        device
+                            .send_selection(self, id, kind, selection.as_ref())
+                            .await?;
+                    }
+                }
+            }
+            ClientMessage::SendData {
+                source,
+                mime_type,
+                fd,
+            } => {
+                selection::send_data(self, source, mime_type, fd.as_fd()).await?;
+            }
+            ClientMessage::SourceCancelled { source } => {
+                selection::cancel_source(self, source).await?;
+            }
+            ClientMessage::DragEnter {
+                surface,
+                x,
+                y,
+                offer,
+            } => {
+                let serial = self.next_event_serial();
+                self.drag_offers.clear();
+
+                for id in self.data_devices.clone() {
+                    let Some(device) = self.get::<DataDevice>(id) else {
+                        continue;
+                    };
+
+                    if let Some(offer) = device
+                        .send_drag_enter(self, id, serial, surface, (x, y), offer.as_ref())
+                        .await?
+                    {
+                        self.drag_offers.push(offer);
+                    }
+                }
+            }
+            ClientMessage::DragMotion { x, y, time } => {
+                for id in self.data_devices.clone() {
+                    if let Some(device) = self.get::<DataDevice>(id) {
+                        device.send_drag_motion(self, id, time, (x, y)).await?;
+                    }
+                }
+            }
+            ClientMessage::DragLeave => {
+                self.drag_offers.clear();
+
+                for id in self.data_devices.clone() {
+                    if let Some(device) = self.get::<DataDevice>(id) {
+                        device.leave(self, id).await?;
+                    }
+                }
+            }
+            ClientMessage::DragDrop => {
+                for id in self.data_devices.clone() {
+                    if let Some(device) = self.get::<DataDevice>(id) {
+                        device.send_drop(self, id).await?;
+                    }
+                }
+            }
+            ClientMessage::DragAction { action } => {
+                for id in self.drag_offers.clone() {
+                    if let Some(offer) = self.get::<DataOffer>(id) {
+                        offer.send_action(self, id, action).await?;
+                    }
+                }
+            }
+            ClientMessage::DragTarget { source, mime_type } => {
+                if let Some(object) = self.get::<DataSource>(source) {
+                    object.target(self, source, mime_type).await?;
+                }
+            }
+            ClientMessage::DragSourceAction { source, action } => {
+                if let Some(object) = self.get::<DataSource>(source)
+                    && object.version() >= DND_ACTIONS_VERSION
+                {
+                    object.action(self, source, action).await?;
+                }
+            }
+            ClientMessage::DropPerformed { source } => {
+                if let Some(object) = self.get::<DataSource>(source)
+                    && object.version() >= DND_ACTIONS_VERSION
+                {
+                    object.dnd_drop_performed(self, source).await?;
+                }
+            }
+            ClientMessage::DragFinished { source } => {
+                if let Some(object) = self.get::<DataSource>(source)
+                    && object.version() >= DND_ACTIONS_VERSION
+                {
+                    object.dnd_finished(self, source).await?;
+                }
+            }
+            ClientMessage::ConfigureLayerSurface {
+                layer_surface,
+                width,
+                height,
+            } => {
+                if let Some(object) = self.get::<LayerSurface>(layer_surface) {
+                    object
+                        .send_configure(self, layer_surface, width, height)
+                        .await?;
+                }
+            }
+            ClientMessage::LayerSurfaceClosed { layer_surface } => {
+                if let Some(object) = self.get::<LayerSurface>(layer_surface) {
+                    object.closed(self, layer_surface).await?;
+                }
+            }
+            ClientMessage::Idled { notification } => {
+                if let Some(object) = self.get::<IdleNotification>(notification) {
+                    object.idled(self, notification).await?;
+                }
+            }
+            ClientMessage::IdleResumed { notification } => {
+                if let Some(object) = self.get::<IdleNotification>(notification) {
+                    object.resumed(self, notification).await?;
+                }
+            }
+            ClientMessage::SessionLocked { lock } => {
+                if let Some(object) = self.get::<SessionLock>(lock) {
+                    object.send_locked(self, lock).await?;
+                }
+            }
+            ClientMessage::LockFinished { lock } => {
+                if let Some(object) = self.get::<SessionLock>(lock) {
+                    object.send_finished(self, lock).await?;
+                }
+            }
+            ClientMessage::ConfigureLockSurface {
+                lock_surface,
+                width,
+                height,
+            } => {
+                if let Some(object) = self.get::<LockSurface>(lock_surface) {
+                    object
+                        .send_configure(self, lock_surface, width, height)
+                        .await?;
+                }
+            }
+            ClientMessage::OutputPowerMode { power, on } => {
+                if let Some(object) = self.get::<OutputPower>(power) {
+                    let mode = if on { PowerMode::On } else { PowerMode::Off };
+                    object.mode(self, power, mode).await?;
+                }
+            }
+            ClientMessage::OutputPowerFailed { power } => {
+                if let Some(object) = self.get::<OutputPower>(power) {
+                    object.failed(self, power).await?;
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -187,4 +784,220 @@ impl ClientHandle {
     pub fn new(sender: mpsc::Sender<ClientMessage>, client_id: u32) -> Self {
         Self { sender, client_id }
     }
+
+    pub async fn frame_done(&self, callbacks: Vec<(ObjectId, Vec<ObjectId>)>, time: u32) {
+        let _ = self
+            .sender
+            .send(ClientMessage::FrameDone { callbacks, time })
+            .await;
+    }
+
+    pub async fn commit_timer_expired(&self, surface: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::CommitTimerExpired { surface })
+            .await;
+    }
+
+    pub async fn acquire_signalled(&self, surface: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::AcquireSignalled { surface })
+            .await;
+    }
+
+    pub async fn release_buffers(&self, buffers: Vec<ObjectId>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::ReleaseBuffers { buffers })
+            .await;
+    }
+
+    pub async fn presentation_feedback(&self, feedback: Vec<(ObjectId, Option<Presented>)>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::PresentationFeedback { feedback })
+            .await;
+    }
+
+    pub async fn preferred(&self, surfaces: Vec<(ObjectId, Preferred)>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::Preferred { surfaces })
+            .await;
+    }
+
+    pub async fn keyboard_enter(&self, selections: Vec<(SelectionKind, OfferedSelection)>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::KeyboardEnter { selections })
+            .await;
+    }
+
+    pub async fn keyboard_leave(&self) {
+        let _ = self.sender.send(ClientMessage::KeyboardLeave).await;
+    }
+
+    pub async fn selection(&self, kind: SelectionKind, selection: Option<OfferedSelection>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::Selection { kind, selection })
+            .await;
+    }
+
+    pub async fn control_selection(
+        &self,
+        kind: SelectionKind,
+        selection: Option<OfferedSelection>,
+    ) {
+        let _ = self
+            .sender
+            .send(ClientMessage::ControlSelection { kind, selection })
+            .await;
+    }
+
+    pub async fn send_data(&self, source: ObjectId, mime_type: String, fd: OwnedFd) {
+        let _ = self
+            .sender
+            .send(ClientMessage::SendData {
+                source,
+                mime_type,
+                fd,
+            })
+            .await;
+    }
+
+    pub async fn source_cancelled(&self, source: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::SourceCancelled { source })
+            .await;
+    }
+
+    pub async fn drag_enter(&self, surface: ObjectId, x: f64, y: f64, offer: Option<OfferedDrag>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DragEnter {
+                surface,
+                x,
+                y,
+                offer,
+            })
+            .await;
+    }
+
+    pub async fn drag_motion(&self, x: f64, y: f64, time: u32) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DragMotion { x, y, time })
+            .await;
+    }
+
+    pub async fn drag_leave(&self) {
+        let _ = self.sender.send(ClientMessage::DragLeave).await;
+    }
+
+    pub async fn drag_drop(&self) {
+        let _ = self.sender.send(ClientMessage::DragDrop).await;
+    }
+
+    pub async fn drag_action(&self, action: DndAction) {
+        let _ = self.sender.send(ClientMessage::DragAction { action }).await;
+    }
+
+    pub async fn drag_target(&self, source: ObjectId, mime_type: Option<String>) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DragTarget { source, mime_type })
+            .await;
+    }
+
+    pub async fn drag_source_action(&self, source: ObjectId, action: DndAction) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DragSourceAction { source, action })
+            .await;
+    }
+
+    pub async fn drop_performed(&self, source: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DropPerformed { source })
+            .await;
+    }
+
+    pub async fn drag_finished(&self, source: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::DragFinished { source })
+            .await;
+    }
+
+    pub async fn configure_layer_surface(&self, layer_surface: ObjectId, width: u32, height: u32) {
+        let _ = self
+            .sender
+            .send(ClientMessage::ConfigureLayerSurface {
+                layer_surface,
+                width,
+                height,
+            })
+            .await;
+    }
+
+    pub async fn layer_surface_closed(&self, layer_surface: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::LayerSurfaceClosed { layer_surface })
+            .await;
+    }
+
+    pub async fn idled(&self, notification: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::Idled { notification })
+            .await;
+    }
+
+    pub async fn idle_resumed(&self, notification: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::IdleResumed { notification })
+            .await;
+    }
+
+    pub async fn session_locked(&self, lock: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::SessionLocked { lock })
+            .await;
+    }
+
+    pub async fn lock_finished(&self, lock: ObjectId) {
+        let _ = self.sender.send(ClientMessage::LockFinished { lock }).await;
+    }
+
+    pub async fn configure_lock_surface(&self, lock_surface: ObjectId, width: u32, height: u32) {
+        let _ = self
+            .sender
+            .send(ClientMessage::ConfigureLockSurface {
+                lock_surface,
+                width,
+                height,
+            })
+            .await;
+    }
+
+    pub async fn output_power_mode(&self, power: ObjectId, on: bool) {
+        let _ = self
+            .sender
+            .send(ClientMessage::OutputPowerMode { power, on })
+            .await;
+    }
+
+    pub async fn output_power_failed(&self, power: ObjectId) {
+        let _ = self
+            .sender
+            .send(ClientMessage::OutputPowerFailed { power })
+            .await;
+    }
 }
@@ -1,32 +1,239 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    os::fd::OwnedFd,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use colpetto::event::KeyState;
+use colpetto::event::{ButtonState, KeyState};
 use input_linux_sys::KEY_ESC;
 use saddle::Seat;
 use stagecraft::{Actor, Context, Handle, HasMailbox};
-use tokio::{net::UnixStream, sync::RwLock};
-use tracing::{debug, info};
+use tokio::{
+    net::UnixStream,
+    sync::RwLock,
+    time::{Instant, sleep_until},
+};
+use tracing::{debug, info, warn};
+use waynest::ObjectId;
 use waynest_server::Listener;
 
 use crate::{
-    Client,
+    Client, OutputConfig,
     actors::{
         client::ClientHandle,
         client_listener::{ClientListener, ClientListenerInit},
         input_manager::{InputManager, InputManagerExt, InputManagerInit},
-        renderer::{Renderer, RendererExt},
+        renderer::{
+            Layer, OutputId, OutputInfo, Preferred, Presented, Renderer, RendererExt, damage::Rect,
+        },
         session::{Session, SessionExt, SessionRef},
     },
     keymap::{KeyMap, ModifierState},
+    protocol::{
+        layer_shell::surface::LayerState,
+        wayland::{
+            data_device::{OfferedDrag, OfferedSelection},
+            data_device_manager::DndAction,
+            surface::SurfaceId,
+        },
+    },
 };
 
+/// Offset between the origins of consecutively mapped windows
+const CASCADE_STEP: i32 = 32;
+
 #[derive(Debug)]
 #[stagecraft::message(Compositor)]
 pub enum CompositorMessage {
-    NewClient { stream: UnixStream },
+    NewClient {
+        stream: UnixStream,
+    },
+    ClientDisconnected {
+        client_id: u32,
+    },
     Input(InputEvent),
     SessionLost,
     SessionResumed,
+    OutputsChanged {
+        outputs: Vec<OutputInfo>,
+    },
+    MapToplevel {
+        surface: SurfaceId,
+    },
+    UnmapSurface {
+        surface: SurfaceId,
+    },
+    /// A layer surface committed new state, `None` if unchanged, or got
+    /// mapped
+    CommitLayerSurface {
+        surface: SurfaceId,
+        layer_surface: ObjectId,
+        /// Output the client asked for, `None` for the one the pointer is on
+        output: Option<OutputId>,
+        state: Option<LayerState>,
+        mapped: bool,
+    },
+    /// A layer surface was unmapped or destroyed, its next commit starting
+    /// over
+    RemoveLayerSurface {
+        surface: SurfaceId,
+    },
+    /// A client asked to lock the session
+    Lock {
+        client_id: u32,
+        lock: ObjectId,
+    },
+    /// The client holding the session lock unlocked it
+    Unlock {
+        client_id: u32,
+        lock: ObjectId,
+    },
+    /// A lock was destroyed without unlocking, before it was answered
+    LockDestroyed {
+        client_id: u32,
+        lock: ObjectId,
+    },
+    NewLockSurface {
+        lock: ObjectId,
+        surface: SurfaceId,
+        lock_surface: ObjectId,
+        output: OutputId,
+    },
+    MapLockSurface {
+        surface: SurfaceId,
+    },
+    RemoveLockSurface {
+        surface: SurfaceId,
+    },
+    /// A client wants to know when there was no activity for `timeout`
+    /// milliseconds, `inhibitable` if idle inhibitors hold it back
+    NewIdleNotification {
+        client_id: u32,
+        notification: ObjectId,
+        timeout: u32,
+        inhibitable: bool,
+    },
+    IdleNotificationDestroyed {
+        client_id: u32,
+        notification: ObjectId,
+    },
+    /// Idle is held back while the given surface is visible
+    NewIdleInhibitor {
+        surface: SurfaceId,
+        inhibitor: ObjectId,
+    },
+    IdleInhibitorDestroyed {
+        client_id: u32,
+        inhibitor: ObjectId,
+    },
+    /// An idle notification may be due
+    IdleCheck,
+    /// The given `zwlr_output_power_v1` wants to know the power mode of
+    /// `output`
+    NewOutputPower {
+        client_id: u32,
+        power: ObjectId,
+        output: OutputId,
+    },
+    /// Turns the output of the given `zwlr_output_power_v1` on or off
+    SetOutputPower {
+        client_id: u32,
+        power: ObjectId,
+        on: bool,
+    },
+    OutputPowerDestroyed {
+        client_id: u32,
+        power: ObjectId,
+    },
+    /// Every output that's on flipped a frame drawn since locking
+    LockShown,
+    /// A refresh cycle of the given surfaces is over, with the
+    /// `wl_surface.frame` callbacks to fire
+    FrameDone {
+        callbacks: Vec<(SurfaceId, Vec<ObjectId>)>,
+        time: u32,
+    },
+    /// Dmabufs the renderer is done with, per surface that showed them
+    ReleaseBuffers {
+        buffers: Vec<(SurfaceId, ObjectId)>,
+    },
+    /// `wp_presentation_feedback` objects whose update was shown, or
+    /// discarded if `None`
+    PresentationFeedback {
+        feedback: Vec<(SurfaceId, ObjectId, Option<Presented>)>,
+    },
+    /// Surfaces that moved to an output of another scale or transform
+    Preferred {
+        surfaces: Vec<(SurfaceId, Preferred)>,
+    },
+    /// A client set a selection from a source with the given mime types, or
+    /// cleared it
+    SetSelection {
+        client_id: u32,
+        kind: SelectionKind,
+        source: Option<(ObjectId, Vec<String>)>,
+    },
+    /// A privileged client set a selection through data control, which
+    /// doesn't need focus
+    ControlSelection {
+        client_id: u32,
+        kind: SelectionKind,
+        source: Option<(ObjectId, Vec<String>)>,
+    },
+    /// A client wants the data of the selection or drag it was offered
+    /// written into `fd`
+    ReceiveData {
+        offer: u64,
+        mime_type: String,
+        fd: OwnedFd,
+    },
+    /// A `wl_data_source` or `zwp_primary_selection_source_v1` was destroyed
+    DataSourceDestroyed {
+        client_id: u32,
+        source: ObjectId,
+    },
+    /// The focused client started a drag, `source` being `None` for drags
+    /// within the client
+    StartDrag {
+        client_id: u32,
+        source: Option<DragSource>,
+        icon: Option<SurfaceId>,
+    },
+    /// A drag icon committed a new offset
+    MoveDragIcon {
+        surface: SurfaceId,
+        dx: i32,
+        dy: i32,
+    },
+    /// The target of a drag accepts the given mime type, or none
+    DragAccept {
+        offer: u64,
+        mime_type: Option<String>,
+    },
+    /// The target of a drag supports the given actions
+    DragActions {
+        offer: u64,
+        actions: DndAction,
+        preferred: DndAction,
+    },
+    /// The target of a drop is done transferring
+    DragFinished {
+        offer: u64,
+    },
+    DragOfferDestroyed {
+        offer: u64,
+    },
+}
+
+/// The `wl_data_source` a drag was started with
+#[derive(Debug)]
+pub struct DragSource {
+    pub source: ObjectId,
+    pub mime_types: Vec<String>,
+    pub actions: DndAction,
 }
 
 #[derive(Debug)]
@@ -40,6 +247,8 @@ pub struct InputEvent {
 #[non_exhaustive]
 pub enum EventType {
     Keyboard(KeyboardEvent),
+    Pointer(PointerEvent),
+    Touch(TouchEvent),
     Unknown,
 }
 
@@ -53,6 +262,36 @@ pub enum KeyboardEvent {
     },
 }
 
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PointerEvent {
+    Motion {
+        dx: f64,
+        dy: f64,
+        time: u64,
+    },
+    /// Position on a tablet or similar device, as fractions of its size
+    MotionAbsolute {
+        x: f64,
+        y: f64,
+        time: u64,
+    },
+    Button {
+        button: u32,
+        state: colpetto::event::ButtonState,
+        time: u64,
+    },
+}
+
+/// Touch points move the pointer, positions being fractions of the size of
+/// the touch screen
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TouchEvent {
+    Down { x: f64, y: f64, time: u64 },
+    Motion { x: f64, y: f64, time: u64 },
+}
+
 impl From<&colpetto::Event> for EventType {
     fn from(value: &colpetto::Event) -> Self {
         match value {
@@ -63,6 +302,42 @@ impl From<&colpetto::Event> for EventType {
                     time: event.time_usec(),
                 })
             }
+            colpetto::Event::Pointer(colpetto::event::PointerEvent::Motion(event)) => {
+                EventType::Pointer(PointerEvent::Motion {
+                    dx: event.dx(),
+                    dy: event.dy(),
+                    time: event.time_usec(),
+                })
+            }
+            // Transforming to a size of 1 gives fractions of the device size
+            colpetto::Event::Pointer(colpetto::event::PointerEvent::MotionAbsolute(event)) => {
+                EventType::Pointer(PointerEvent::MotionAbsolute {
+                    x: event.absolute_x_transformed(1),
+                    y: event.absolute_y_transformed(1),
+                    time: event.time_usec(),
+                })
+            }
+            colpetto::Event::Touch(colpetto::event::TouchEvent::Down(event)) => {
+                EventType::Touch(TouchEvent::Down {
+                    x: event.x_transformed(1),
+                    y: event.y_transformed(1),
+                    time: event.time_usec(),
+                })
+            }
+            colpetto::Event::Touch(colpetto::event::TouchEvent::Motion(event)) => {
+                EventType::Touch(TouchEvent::Motion {
+                    x: event.x_transformed(1),
+                    y: event.y_transformed(1),
+                    time: event.time_usec(),
+                })
+            }
+            colpetto::Event::Pointer(colpetto::event::PointerEvent::Button(event)) => {
+                EventType::Pointer(PointerEvent::Button {
+                    button: event.button(),
+                    state: event.button_state(),
+                    time: event.time_usec(),
+                })
+            }
             _ => EventType::Unknown,
         }
     }
@@ -70,17 +345,164 @@ impl From<&colpetto::Event> for EventType {
 
 pub struct CompositorInit {
     pub socket_path: Option<PathBuf>,
+    pub outputs: HashMap<String, OutputConfig>,
+    pub privileged_clients: Vec<PathBuf>,
+}
+
+/// Selections are independent of each other, each with its own owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionKind {
+    /// Set through `wl_data_device`
+    Clipboard,
+    /// Set through `zwp_primary_selection_device_v1`, pasted with a middle
+    /// click
+    Primary,
+}
+
+/// A selection, as set from a source of its owner
+struct Selection {
+    client_id: u32,
+    source: ObjectId,
+    offered: OfferedSelection,
+}
+
+/// A `zwlr_layer_surface_v1` arranged on an output
+struct LayerSurface {
+    surface: SurfaceId,
+    layer_surface: ObjectId,
+    output: OutputId,
+    state: LayerState,
+    mapped: bool,
+    /// Size it was last configured with
+    size: Option<(i32, i32)>,
+}
+
+/// The `ext_session_lock_v1` holding the session lock
+struct SessionLock {
+    client_id: u32,
+    lock: ObjectId,
+}
+
+/// An `ext_session_lock_surface_v1` covering an output
+struct LockSurface {
+    surface: SurfaceId,
+    lock_surface: ObjectId,
+    output: OutputId,
+    mapped: bool,
+    /// Size it was last configured with
+    size: Option<(i32, i32)>,
+}
+
+/// An `ext_idle_notification_v1`
+struct IdleNotification {
+    client_id: u32,
+    notification: ObjectId,
+    timeout: Duration,
+    /// Unset for input idle notifications, which ignore idle inhibitors
+    inhibitable: bool,
+    created: Instant,
+    idle: bool,
+}
+
+impl IdleNotification {
+    /// When it becomes idle, given the last input and the last time an idle
+    /// inhibitor was seen visible
+    fn deadline(&self, activity: Instant, inhibited: Instant) -> Instant {
+        let since = if self.inhibitable {
+            activity.max(inhibited)
+        } else {
+            activity
+        };
+
+        since.max(self.created) + self.timeout
+    }
+}
+
+/// A `zwp_idle_inhibitor_v1`
+struct IdleInhibitor {
+    surface: SurfaceId,
+    inhibitor: ObjectId,
+}
+
+/// A `zwlr_output_power_v1`
+struct OutputPower {
+    client_id: u32,
+    power: ObjectId,
+    output: OutputId,
+}
+
+/// A drag following the pointer until all buttons are released
+struct Drag {
+    client_id: u32,
+    /// `None` for drags within the client that started it
+    source: Option<DragSource>,
+    icon: Option<SurfaceId>,
+    /// Position of the icon relative to the pointer
+    icon_offset: (i32, i32),
+    /// Surface under the pointer
+    target: Option<SurfaceId>,
+    /// Id of the offers the target got, new for every surface entered
+    offer: u64,
+    /// Whether the target accepts one of the mime types
+    accepted: bool,
+    /// Actions the target supports and the one it prefers
+    target_actions: (DndAction, DndAction),
+    /// What a drop would do, negotiated between source and target
+    action: DndAction,
+    /// Dropped on the target, which may still be transferring
+    dropped: bool,
 }
 
 pub struct Compositor {
     next_client_id: u32,
     clients: HashMap<u32, ClientHandle>,
+    /// Executables allowed to use privileged protocols
+    privileged_clients: Vec<PathBuf>,
+    /// Connected clients running one of them
+    privileged: HashSet<u32>,
     key_map: KeyMap,
     modifier_state: Arc<RwLock<ModifierState>>,
     has_control: bool,
     session_ref: SessionRef,
     input_manager_handle: Handle<InputManager>,
     renderer_handle: Handle<Renderer>,
+    outputs: Vec<OutputInfo>,
+    pointer: (f64, f64),
+    mapped_windows: u32,
+    /// Mapped toplevels, the most recently mapped last
+    toplevels: Vec<SurfaceId>,
+    /// Layer surfaces in the order they were created
+    layer_surfaces: Vec<LayerSurface>,
+    /// The part of each output not reserved by layer surfaces
+    usable_areas: HashMap<OutputId, Rect>,
+    /// Only lock surfaces are shown and focused, until the client holding
+    /// the lock unlocks
+    locked: bool,
+    /// `None` while locked means the locker died, the session stays locked
+    /// until another one takes over
+    session_lock: Option<SessionLock>,
+    /// Whether the outputs show nothing but the lock screen yet, the locker
+    /// only hears about the lock from then on
+    lock_shown: bool,
+    lock_surfaces: Vec<LockSurface>,
+    /// Last input from any device
+    last_activity: Instant,
+    /// Last time an idle inhibitor was seen visible
+    last_inhibited: Instant,
+    idle_notifications: Vec<IdleNotification>,
+    idle_inhibitors: Vec<IdleInhibitor>,
+    /// When the pending idle check fires, if any
+    idle_check: Option<Instant>,
+    /// Outputs an idle daemon turned off
+    outputs_off: Vec<OutputId>,
+    output_powers: Vec<OutputPower>,
+    /// The toplevel, layer or lock surface with keyboard focus
+    focus: Option<SurfaceId>,
+    selections: HashMap<SelectionKind, Selection>,
+    /// Pointer buttons held down
+    buttons: Vec<u32>,
+    drag: Option<Drag>,
+    next_offer_id: u64,
 }
 
 impl Compositor {
@@ -89,6 +511,733 @@ impl Compositor {
         self.next_client_id = self.next_client_id.wrapping_add(1);
         prev
     }
+
+    /// Moves the pointer by the given delta, keeping it inside the outputs
+    async fn move_pointer(&mut self, dx: f64, dy: f64) {
+        self.warp_pointer(self.pointer.0 + dx, self.pointer.1 + dy)
+            .await;
+    }
+
+    /// Maps a position on an absolute device, as fractions of its size, to
+    /// the layout
+    ///
+    /// Devices cover the panel of the first output. The panel shows the
+    /// layout turned by the output transform, which gets undone here.
+    fn absolute_position(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let output = self.outputs.first()?;
+        let panel = (output.mode.0 as f64, output.mode.1 as f64);
+
+        let (x, y) = output
+            .transform
+            .invert()
+            .point((x * panel.0, y * panel.1), panel);
+
+        Some((
+            output.geometry.x as f64 + x / output.scale,
+            output.geometry.y as f64 + y / output.scale,
+        ))
+    }
+
+    /// Puts the pointer at the given layout position, keeping it inside the
+    /// outputs
+    async fn warp_pointer(&mut self, mut x: f64, mut y: f64) {
+        let bounds = self.outputs.iter().fold(Rect::default(), |bounds, output| {
+            bounds.bounding(&output.geometry)
+        });
+
+        if bounds.is_empty() {
+            return;
+        }
+
+        x = x.clamp(bounds.x as f64, (bounds.right() - 1) as f64);
+        y = y.clamp(bounds.y as f64, (bounds.bottom() - 1) as f64);
+
+        self.pointer = (x, y);
+
+        let _ = self.renderer_handle.move_cursor(x, y).await;
+    }
+
+    /// Whether the client runs one of the executables allowed privileged
+    /// protocols
+    fn is_privileged(&self, stream: &UnixStream) -> bool {
+        if self.privileged_clients.is_empty() {
+            return false;
+        }
+
+        let Some(pid) = stream.peer_cred().ok().and_then(|cred| cred.pid()) else {
+            return false;
+        };
+
+        std::fs::read_link(format!("/proc/{pid}/exe"))
+            .is_ok_and(|exe| self.privileged_clients.contains(&exe))
+    }
+
+    /// Moves keyboard focus, telling the clients involved about it
+    async fn set_focus(&mut self, focus: Option<SurfaceId>) {
+        let previous = std::mem::replace(&mut self.focus, focus);
+        let (previous, focus) = (
+            previous.map(|surface| surface.client_id),
+            focus.map(|surface| surface.client_id),
+        );
+
+        // Focus is per client for now, as only data devices make use of it
+        if previous == focus {
+            return;
+        }
+
+        if let Some(client) = previous.and_then(|id| self.clients.get(&id)) {
+            client.keyboard_leave().await;
+        }
+
+        if let Some(client) = focus.and_then(|id| self.clients.get(&id)) {
+            client.keyboard_enter(self.offered_selections()).await;
+        }
+    }
+
+    /// Focuses the layer surface grabbing the keyboard, or else the most
+    /// recently mapped toplevel
+    ///
+    /// While locked, only a lock surface can be focused.
+    async fn refocus(&mut self) {
+        let focus = if self.locked {
+            self.lock_focus()
+        } else {
+            self.keyboard_grab().or(self.toplevels.last().copied())
+        };
+
+        self.set_focus(focus).await;
+    }
+
+    /// The lock surface on the output the pointer is on, or else any other
+    fn lock_focus(&self) -> Option<SurfaceId> {
+        let output = self.pointer_output().map(|output| output.id);
+        let mut mapped = self
+            .lock_surfaces
+            .iter()
+            .filter(|lock_surface| lock_surface.mapped);
+
+        mapped
+            .clone()
+            .find(|lock_surface| Some(lock_surface.output) == output)
+            .or_else(|| mapped.next())
+            .map(|lock_surface| lock_surface.surface)
+    }
+
+    /// Moves focus along with changes to layer surfaces, leaving it where it
+    /// is as long as it's still wanted there
+    async fn update_focus(&mut self) {
+        let keep = !self.locked
+            && self.keyboard_grab().is_none()
+            && self.focus.is_some_and(|focus| {
+                self.toplevels.contains(&focus)
+                    || self.layer_surfaces.iter().any(|layer_surface| {
+                        layer_surface.surface == focus
+                            && layer_surface.mapped
+                            && layer_surface.state.wants_keyboard()
+                    })
+            });
+
+        if !keep {
+            self.refocus().await;
+        }
+    }
+
+    /// The topmost mapped layer surface with exclusive keyboard
+    /// interactivity, which keeps focus to itself
+    fn keyboard_grab(&self) -> Option<SurfaceId> {
+        self.layer_surfaces
+            .iter()
+            .filter(|layer_surface| layer_surface.mapped && layer_surface.state.grabs_keyboard())
+            .max_by_key(|layer_surface| layer_surface.state.layer)
+            .map(|layer_surface| layer_surface.surface)
+    }
+
+    /// Clicking a toplevel, or a layer surface that wants keyboard input,
+    /// focuses it
+    async fn focus_at_pointer(&mut self) {
+        if !self.locked && self.keyboard_grab().is_some() {
+            return;
+        }
+
+        let Ok(Some(surface)) = self
+            .renderer_handle
+            .placed_at(self.pointer.0, self.pointer.1)
+            .await
+        else {
+            return;
+        };
+
+        let focusable = if self.locked {
+            self.lock_surfaces
+                .iter()
+                .any(|lock_surface| lock_surface.surface == surface)
+        } else {
+            self.toplevels.contains(&surface)
+                || self.layer_surfaces.iter().any(|layer_surface| {
+                    layer_surface.surface == surface && layer_surface.state.wants_keyboard()
+                })
+        };
+
+        if focusable {
+            self.set_focus(Some(surface)).await;
+        }
+    }
+
+    /// Sizes every lock surface to its output, dropping those whose output
+    /// went away
+    async fn arrange_lock_surfaces(&mut self) {
+        let (kept, gone): (Vec<_>, Vec<_>) = std::mem::take(&mut self.lock_surfaces)
+            .into_iter()
+            .partition(|lock_surface| {
+                self.outputs
+                    .iter()
+                    .any(|output| output.id == lock_surface.output)
+            });
+        self.lock_surfaces = kept;
+
+        for lock_surface in gone {
+            let _ = self
+                .renderer_handle
+                .unmap_surface(lock_surface.surface)
+                .await;
+        }
+
+        for lock_surface in &mut self.lock_surfaces {
+            let Some(output) = self
+                .outputs
+                .iter()
+                .find(|output| output.id == lock_surface.output)
+            else {
+                continue;
+            };
+
+            let geometry = output.geometry;
+            let size = (geometry.width, geometry.height);
+
+            if lock_surface.size != Some(size) {
+                lock_surface.size = Some(size);
+
+                if let Some(client) = self.clients.get(&lock_surface.surface.client_id) {
+                    client
+                        .configure_lock_surface(
+                            lock_surface.lock_surface,
+                            size.0 as u32,
+                            size.1 as u32,
+                        )
+                        .await;
+                }
+            }
+
+            if lock_surface.mapped {
+                let _ = self
+                    .renderer_handle
+                    .place_surface(lock_surface.surface, geometry.x, geometry.y, Layer::Lock)
+                    .await;
+            }
+        }
+    }
+
+    /// Ends the session lock, hiding the lock surfaces
+    async fn unlock(&mut self) {
+        info!("Unlocking the session");
+
+        self.locked = false;
+        self.session_lock = None;
+
+        for lock_surface in std::mem::take(&mut self.lock_surfaces) {
+            let _ = self
+                .renderer_handle
+                .unmap_surface(lock_surface.surface)
+                .await;
+        }
+
+        let _ = self.renderer_handle.set_locked(false).await;
+        self.refocus().await;
+    }
+
+    /// The output the pointer is on
+    fn pointer_output(&self) -> Option<&OutputInfo> {
+        let (x, y) = (self.pointer.0.floor() as i32, self.pointer.1.floor() as i32);
+
+        self.outputs
+            .iter()
+            .find(|output| output.geometry.contains_point(x, y))
+            .or(self.outputs.first())
+    }
+
+    /// Lays out the layer surfaces of every output, configuring those whose
+    /// size changed
+    ///
+    /// Surfaces reserving an exclusive zone go first, from the top layer
+    /// down, the others are arranged within what they left.
+    async fn arrange_layers(&mut self) {
+        self.usable_areas.clear();
+
+        for output in &self.outputs {
+            let mut usable = output.geometry;
+
+            let mut order: Vec<usize> = (0..self.layer_surfaces.len())
+                .filter(|index| self.layer_surfaces[*index].output == output.id)
+                .collect();
+            order.sort_by_key(|index| {
+                let state = &self.layer_surfaces[*index].state;
+                (state.exclusive().is_none(), Reverse(state.layer))
+            });
+
+            for index in order {
+                let layer_surface = &mut self.layer_surfaces[index];
+
+                // Surfaces with an exclusive zone of -1 ignore the others
+                let bounds = if layer_surface.state.exclusive_zone < 0 {
+                    output.geometry
+                } else {
+                    usable
+                };
+
+                let geometry = layer_surface.state.geometry(bounds);
+                layer_surface.state.reserve(&mut usable);
+
+                let size = (geometry.width, geometry.height);
+
+                if layer_surface.size != Some(size) {
+                    layer_surface.size = Some(size);
+
+                    if let Some(client) = self.clients.get(&layer_surface.surface.client_id) {
+                        client
+                            .configure_layer_surface(
+                                layer_surface.layer_surface,
+                                size.0 as u32,
+                                size.1 as u32,
+                            )
+                            .await;
+                    }
+                }
+
+                if layer_surface.mapped {
+                    let _ = self
+                        .renderer_handle
+                        .place_surface(
+                            layer_surface.surface,
+                            geometry.x,
+                            geometry.y,
+                            layer_surface.state.layer,
+                        )
+                        .await;
+                }
+            }
+
+            self.usable_areas.insert(output.id, usable);
+        }
+    }
+
+    fn offered_selections(&self) -> Vec<(SelectionKind, OfferedSelection)> {
+        self.selections
+            .iter()
+            .map(|(kind, selection)| (*kind, selection.offered.clone()))
+            .collect()
+    }
+
+    /// Moves the drag icon along with the pointer, and the drag over to the
+    /// surface under it
+    async fn update_drag(&mut self, time: u32) {
+        let Some(drag) = self.drag.as_ref().filter(|drag| !drag.dropped) else {
+            return;
+        };
+
+        let (x, y) = self.pointer;
+        let (icon, current, own_client) = (drag.icon, drag.target, drag.client_id);
+        let has_source = drag.source.is_some();
+
+        self.place_drag_icon().await;
+
+        // Drags without a source stay within their client
+        let target = self
+            .renderer_handle
+            .surface_at(x, y, icon)
+            .await
+            .ok()
+            .flatten()
+            .filter(|(surface, ..)| has_source || surface.client_id == own_client);
+
+        match target {
+            Some((surface, x, y)) if current == Some(surface) => {
+                if let Some(client) = self.clients.get(&surface.client_id) {
+                    client.drag_motion(x, y, time).await;
+                }
+            }
+            _ => {
+                self.leave_drag_target().await;
+
+                if let Some((surface, x, y)) = target {
+                    self.enter_drag_target(surface, x, y).await;
+                }
+            }
+        }
+    }
+
+    async fn place_drag_icon(&mut self) {
+        let Some(drag) = self.drag.as_ref().filter(|drag| !drag.dropped) else {
+            return;
+        };
+
+        if let Some(icon) = drag.icon {
+            let (x, y) = (self.pointer.0.floor() as i32, self.pointer.1.floor() as i32);
+
+            let _ = self
+                .renderer_handle
+                .place_surface(
+                    icon,
+                    x + drag.icon_offset.0,
+                    y + drag.icon_offset.1,
+                    Layer::Overlay,
+                )
+                .await;
+        }
+    }
+
+    async fn enter_drag_target(&mut self, surface: SurfaceId, x: f64, y: f64) {
+        self.next_offer_id = self.next_offer_id.wrapping_add(1);
+
+        let Some(drag) = &mut self.drag else {
+            return;
+        };
+
+        drag.target = Some(surface);
+        drag.offer = self.next_offer_id;
+
+        let offer = drag.source.as_ref().map(|source| OfferedDrag {
+            id: drag.offer,
+            mime_types: source.mime_types.clone(),
+            actions: source.actions,
+        });
+
+        if let Some(client) = self.clients.get(&surface.client_id) {
+            client.drag_enter(surface.object_id, x, y, offer).await;
+        }
+    }
+
+    /// Takes the drag away from the surface it's over, the source losing
+    /// whatever the target accepted
+    async fn leave_drag_target(&mut self) {
+        let Some(drag) = &mut self.drag else {
+            return;
+        };
+
+        let Some(target) = drag.target.take() else {
+            return;
+        };
+
+        if let Some(client) = self.clients.get(&target.client_id) {
+            client.drag_leave().await;
+        }
+
+        if let Some(source) = &drag.source
+            && let Some(client) = self.clients.get(&drag.client_id)
+        {
+            if drag.accepted {
+                client.drag_target(source.source, None).await;
+            }
+
+            if !drag.action.is_empty() {
+                client
+                    .drag_source_action(source.source, DndAction::empty())
+                    .await;
+            }
+        }
+
+        drag.accepted = false;
+        drag.target_actions = (DndAction::empty(), DndAction::empty());
+        drag.action = DndAction::empty();
+    }
+
+    /// Picks what a drop would do from the actions both sides support,
+    /// telling them when it changes
+    async fn negotiate_drag_action(&mut self) {
+        let Some(drag) = &mut self.drag else {
+            return;
+        };
+
+        let Some(source) = &drag.source else {
+            return;
+        };
+
+        let (actions, preferred) = drag.target_actions;
+        let common = source.actions & actions;
+
+        let action = if !preferred.is_empty() && common.contains(preferred) {
+            preferred
+        } else {
+            [DndAction::Copy, DndAction::Move, DndAction::Ask]
+                .into_iter()
+                .find(|action| common.contains(*action))
+                .unwrap_or(DndAction::empty())
+        };
+
+        if action == drag.action {
+            return;
+        }
+
+        drag.action = action;
+
+        if let Some(client) = self.clients.get(&drag.client_id) {
+            client.drag_source_action(source.source, action).await;
+        }
+
+        if let Some(client) = drag
+            .target
+            .and_then(|target| self.clients.get(&target.client_id))
+        {
+            client.drag_action(action).await;
+        }
+    }
+
+    /// Drops on the target once the buttons are released, cancelling the
+    /// drag if it doesn't take anything
+    async fn drop_drag(&mut self) {
+        let Some(drag) = self.drag.as_mut().filter(|drag| !drag.dropped) else {
+            return;
+        };
+
+        let target = drag
+            .target
+            .filter(|_| drag.source.is_none() || (drag.accepted && !drag.action.is_empty()));
+
+        let Some(target) = target else {
+            self.end_drag(true).await;
+            return;
+        };
+
+        drag.dropped = true;
+
+        let (client_id, source) = (
+            drag.client_id,
+            drag.source.as_ref().map(|source| source.source),
+        );
+
+        if let Some(icon) = drag.icon {
+            let _ = self.renderer_handle.unmap_surface(icon).await;
+        }
+
+        if let Some(client) = self.clients.get(&target.client_id) {
+            client.drag_drop().await;
+        }
+
+        match source {
+            Some(source) => {
+                if let Some(client) = self.clients.get(&client_id) {
+                    client.drop_performed(source).await;
+                }
+            }
+            // Nothing to transfer within a client
+            None => self.drag = None,
+        }
+    }
+
+    /// Ends the drag, cancelling its source unless it's gone already
+    async fn end_drag(&mut self, cancel: bool) {
+        // Dropped drags already left their target
+        if self.drag.as_ref().is_some_and(|drag| !drag.dropped) {
+            self.leave_drag_target().await;
+        }
+
+        let Some(drag) = self.drag.take() else {
+            return;
+        };
+
+        if !drag.dropped
+            && let Some(icon) = drag.icon
+        {
+            let _ = self.renderer_handle.unmap_surface(icon).await;
+        }
+
+        if cancel
+            && let Some(source) = drag.source
+            && let Some(client) = self.clients.get(&drag.client_id)
+        {
+            client.source_cancelled(source.source).await;
+        }
+    }
+
+    /// Wakes the compositor up once the next idle notification is due,
+    /// unless an earlier check is pending already
+    fn schedule_idle_check(&mut self, ctx: &mut Context<Self>) {
+        let Some(deadline) = self
+            .idle_notifications
+            .iter()
+            .filter(|notification| !notification.idle)
+            .map(|notification| notification.deadline(self.last_activity, self.last_inhibited))
+            .min()
+        else {
+            return;
+        };
+
+        if self.idle_check.is_some_and(|check| check <= deadline) {
+            return;
+        }
+
+        self.idle_check = Some(deadline);
+        let handle = ctx.handle();
+
+        ctx.track(async move {
+            sleep_until(deadline).await;
+            let _ = handle.cast(CompositorMessage::IdleCheck).await;
+        });
+    }
+
+    /// Input resets the idle timers, telling idle clients about it
+    async fn idle_activity(&mut self, ctx: &mut Context<Self>) {
+        self.last_activity = Instant::now();
+
+        let mut resumed = false;
+
+        for notification in &mut self.idle_notifications {
+            if !std::mem::take(&mut notification.idle) {
+                continue;
+            }
+
+            resumed = true;
+
+            if let Some(client) = self.clients.get(&notification.client_id) {
+                client.idle_resumed(notification.notification).await;
+            }
+        }
+
+        if resumed {
+            self.schedule_idle_check(ctx);
+        }
+    }
+
+    /// Sends `idled` to the notifications whose timeout passed
+    ///
+    /// A visible idle inhibitor counts as activity for the notifications it
+    /// holds back, which get checked again a timeout later.
+    async fn check_idle(&mut self, ctx: &mut Context<Self>) {
+        self.idle_check = None;
+        let now = Instant::now();
+
+        if !self.idle_inhibitors.is_empty() {
+            let surfaces = self
+                .idle_inhibitors
+                .iter()
+                .map(|inhibitor| inhibitor.surface)
+                .collect();
+
+            if let Ok(true) = self.renderer_handle.any_visible(surfaces).await {
+                self.last_inhibited = now;
+            }
+        }
+
+        for notification in &mut self.idle_notifications {
+            if notification.idle
+                || notification.deadline(self.last_activity, self.last_inhibited) > now
+            {
+                continue;
+            }
+
+            notification.idle = true;
+
+            if let Some(client) = self.clients.get(&notification.client_id) {
+                client.idled(notification.notification).await;
+            }
+        }
+
+        self.schedule_idle_check(ctx);
+    }
+
+    /// Tells the `zwlr_output_power_v1`s of `output` it was turned on or off
+    async fn set_output_on(&mut self, output: OutputId, on: bool) {
+        if self.outputs_off.contains(&output) != on {
+            return;
+        }
+
+        if on {
+            self.outputs_off.retain(|off| *off != output);
+        } else {
+            self.outputs_off.push(output);
+        }
+
+        for output_power in &self.output_powers {
+            if output_power.output != output {
+                continue;
+            }
+
+            if let Some(client) = self.clients.get(&output_power.client_id) {
+                client.output_power_mode(output_power.power, on).await;
+            }
+        }
+    }
+
+    /// Replaces a selection, cancelling the source it was set from
+    async fn replace_selection(&mut self, kind: SelectionKind, selection: Option<Selection>) {
+        let previous = match selection {
+            Some(selection) => self.selections.insert(kind, selection),
+            None => self.selections.remove(&kind),
+        };
+
+        let current = self.selections.get(&kind);
+
+        if let Some(previous) = previous {
+            let reused = current.is_some_and(|selection| {
+                selection.client_id == previous.client_id && selection.source == previous.source
+            });
+
+            if !reused && let Some(client) = self.clients.get(&previous.client_id) {
+                client.source_cancelled(previous.source).await;
+            }
+        }
+
+        let offered = current.map(|selection| selection.offered.clone());
+
+        if let Some(client) = self
+            .focus
+            .and_then(|focus| self.clients.get(&focus.client_id))
+        {
+            client.selection(kind, offered.clone()).await;
+        }
+
+        for client in self
+            .privileged
+            .iter()
+            .filter_map(|client_id| self.clients.get(client_id))
+        {
+            client.control_selection(kind, offered.clone()).await;
+        }
+    }
+
+    /// Sets a selection from the given source, or clears it
+    async fn set_selection(
+        &mut self,
+        client_id: u32,
+        kind: SelectionKind,
+        source: Option<(ObjectId, Vec<String>)>,
+    ) {
+        let selection = source.map(|(source, mime_types)| {
+            self.next_offer_id = self.next_offer_id.wrapping_add(1);
+
+            Selection {
+                client_id,
+                source,
+                offered: OfferedSelection {
+                    id: self.next_offer_id,
+                    mime_types,
+                },
+            }
+        });
+
+        self.replace_selection(kind, selection).await;
+    }
+
+    /// The selections set from the given source
+    fn selections_of(&self, client_id: u32, source: Option<ObjectId>) -> Vec<SelectionKind> {
+        self.selections
+            .iter()
+            .filter(|(_, selection)| {
+                selection.client_id == client_id
+                    && source.is_none_or(|source| selection.source == source)
+            })
+            .map(|(kind, _)| *kind)
+            .collect()
+    }
 }
 
 impl HasMailbox for Compositor {
@@ -121,7 +1270,11 @@ impl Actor for Compositor {
             session_ref: session_ref.clone(),
         });
 
-        let renderer_handle = ctx.spawn::<Renderer>(Renderer::new(session_ref.clone()));
+        let renderer_handle = ctx.spawn::<Renderer>(Renderer::new(
+            session_ref.clone(),
+            ctx.handle(),
+            init.outputs,
+        ));
 
         let listener = if let Some(ref path) = init.socket_path {
             Listener::new_with_path(path).expect("Failed to start client listener")
@@ -137,12 +1290,36 @@ impl Actor for Compositor {
         Self {
             next_client_id: 1,
             clients: HashMap::new(),
+            privileged_clients: init.privileged_clients,
+            privileged: HashSet::new(),
             key_map: KeyMap::new(),
             modifier_state: Arc::new(RwLock::new(ModifierState::new())),
             has_control: false,
             session_ref,
             input_manager_handle,
             renderer_handle,
+            outputs: Vec::new(),
+            pointer: (0.0, 0.0),
+            mapped_windows: 0,
+            toplevels: Vec::new(),
+            layer_surfaces: Vec::new(),
+            usable_areas: HashMap::new(),
+            locked: false,
+            session_lock: None,
+            lock_shown: false,
+            lock_surfaces: Vec::new(),
+            last_activity: Instant::now(),
+            last_inhibited: Instant::now(),
+            idle_notifications: Vec::new(),
+            idle_inhibitors: Vec::new(),
+            idle_check: None,
+            outputs_off: Vec::new(),
+            output_powers: Vec::new(),
+            focus: None,
+            selections: HashMap::new(),
+            buttons: Vec::new(),
+            drag: None,
+            next_offer_id: 0,
         }
     }
 
@@ -154,52 +1331,167 @@ impl Actor for Compositor {
         match msg {
             CompositorMessage::NewClient { stream } => {
                 let client_id = self.next_client_id();
+                let privileged = self.is_privileged(&stream);
 
                 let token = ctx.child_token();
-                match Client::new(stream, client_id, token) {
+                match Client::new(
+                    stream,
+                    client_id,
+                    privileged,
+                    ctx.handle(),
+                    self.renderer_handle.clone(),
+                    token,
+                ) {
                     Ok(client) => {
-                        self.clients.insert(client_id, client.handle());
+                        let handle = client.handle();
                         ctx.track(client.run());
+
+                        // Data control sees the selections regardless of focus
+                        if privileged {
+                            info!("Client {client_id} is privileged");
+
+                            for (kind, selection) in self.offered_selections() {
+                                handle.control_selection(kind, Some(selection)).await;
+                            }
+
+                            self.privileged.insert(client_id);
+                        }
+
+                        self.clients.insert(client_id, handle);
                     }
                     Err(e) => {
                         tracing::error!("Failed to create client {client_id}: {e}");
                     }
                 }
             }
-            #[allow(clippy::single_match)]
-            CompositorMessage::Input(event) => match event.event_type {
-                EventType::Keyboard(KeyboardEvent::Key { key, state, .. }) => {
-                    let (should_check_vt_switch, is_ctrl_alt_pressed) = {
-                        let mut ms = self.modifier_state.write().await;
-                        ms.update(key, state);
-                        (state == KeyState::Pressed, ms.is_ctrl_alt_pressed())
-                    };
+            CompositorMessage::ClientDisconnected { client_id } => {
+                self.clients.remove(&client_id);
+                self.privileged.remove(&client_id);
+                let _ = self.renderer_handle.remove_client(client_id).await;
+
+                self.toplevels
+                    .retain(|surface| surface.client_id != client_id);
+                self.layer_surfaces
+                    .retain(|layer_surface| layer_surface.surface.client_id != client_id);
+                self.arrange_layers().await;
+
+                // The session stays locked without its locker, showing
+                // nothing until another one takes over
+                if self
+                    .session_lock
+                    .as_ref()
+                    .is_some_and(|lock| lock.client_id == client_id)
+                {
+                    warn!("Client {client_id} holding the session lock is gone");
+                    self.session_lock = None;
+                }
+                self.lock_surfaces
+                    .retain(|lock_surface| lock_surface.surface.client_id != client_id);
+
+                self.idle_notifications
+                    .retain(|notification| notification.client_id != client_id);
+                self.idle_inhibitors
+                    .retain(|inhibitor| inhibitor.surface.client_id != client_id);
+                self.output_powers
+                    .retain(|output_power| output_power.client_id != client_id);
 
-                    if should_check_vt_switch {
-                        if key as i32 == KEY_ESC {
-                            ctx.shutdown();
+                self.refocus().await;
+
+                for kind in self.selections_of(client_id, None) {
+                    self.replace_selection(kind, None).await;
+                }
+
+                if let Some(drag) = &self.drag {
+                    if drag.client_id == client_id {
+                        self.end_drag(false).await;
+                    } else if drag
+                        .target
+                        .is_some_and(|target| target.client_id == client_id)
+                    {
+                        if drag.dropped {
+                            self.end_drag(true).await;
+                        } else {
+                            self.leave_drag_target().await;
                         }
+                    }
+                }
+            }
+            CompositorMessage::Input(event) => {
+                self.idle_activity(ctx).await;
+
+                match event.event_type {
+                    EventType::Keyboard(KeyboardEvent::Key { key, state, .. }) => {
+                        let (should_check_vt_switch, is_ctrl_alt_pressed) = {
+                            let mut ms = self.modifier_state.write().await;
+                            ms.update(key, state);
+                            (state == KeyState::Pressed, ms.is_ctrl_alt_pressed())
+                        };
+
+                        if should_check_vt_switch {
+                            if key as i32 == KEY_ESC {
+                                ctx.shutdown();
+                            }
+
+                            if is_ctrl_alt_pressed && let Some(vt) = self.key_map.get_vt(key) {
+                                if self.has_control {
+                                    info!("Ctrl+Alt+F{vt} pressed, attempting a VT switch to {vt}");
 
-                        if is_ctrl_alt_pressed && let Some(vt) = self.key_map.get_vt(key) {
-                            if self.has_control {
-                                info!("Ctrl+Alt+F{vt} pressed, attempting a VT switch to {vt}");
-
-                                if let Ok(current_vt) = self.session_ref.current_vt().await
-                                    && vt != current_vt
-                                {
-                                    info!(
-                                        "Deactivating session - destroying rendering context completely"
-                                    );
-                                    let _ = self.session_ref.switch_vt(vt).await;
+                                    if let Ok(current_vt) = self.session_ref.current_vt().await
+                                        && vt != current_vt
+                                    {
+                                        info!(
+                                            "Deactivating session - destroying rendering context completely"
+                                        );
+                                        let _ = self.session_ref.switch_vt(vt).await;
+                                    }
+                                } else {
+                                    debug!("Not switching VT - session inactive");
                                 }
-                            } else {
-                                debug!("Not switching VT - session inactive");
                             }
                         }
                     }
+                    EventType::Pointer(PointerEvent::Motion { dx, dy, time }) => {
+                        self.move_pointer(dx, dy).await;
+                        self.update_drag((time / 1000) as u32).await;
+                    }
+                    EventType::Pointer(PointerEvent::MotionAbsolute { x, y, time })
+                    | EventType::Touch(TouchEvent::Motion { x, y, time }) => {
+                        if let Some((x, y)) = self.absolute_position(x, y) {
+                            self.warp_pointer(x, y).await;
+                            self.update_drag((time / 1000) as u32).await;
+                        }
+                    }
+                    // Touching focuses like a click, there's no wl_touch yet
+                    EventType::Touch(TouchEvent::Down { x, y, .. }) => {
+                        if let Some((x, y)) = self.absolute_position(x, y) {
+                            self.warp_pointer(x, y).await;
+
+                            if self.drag.is_none() {
+                                self.focus_at_pointer().await;
+                            }
+                        }
+                    }
+                    EventType::Pointer(PointerEvent::Button { button, state, .. }) => match state {
+                        ButtonState::Pressed => {
+                            if !self.buttons.contains(&button) {
+                                self.buttons.push(button);
+                            }
+
+                            if self.drag.is_none() {
+                                self.focus_at_pointer().await;
+                            }
+                        }
+                        ButtonState::Released => {
+                            self.buttons.retain(|held| *held != button);
+
+                            if self.buttons.is_empty() {
+                                self.drop_drag().await;
+                            }
+                        }
+                    },
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             CompositorMessage::SessionLost => {
                 let _ = self.input_manager_handle.suspend().await;
                 let _ = self.renderer_handle.suspend().await;
@@ -212,6 +1504,626 @@ impl Actor for Compositor {
                 let _ = self.input_manager_handle.resume().await;
                 let _ = self.renderer_handle.resume().await;
             }
+            CompositorMessage::OutputsChanged { outputs } => {
+                for output in &outputs {
+                    info!("Output {} at {:?}", output.name, output.geometry);
+                }
+
+                self.outputs = outputs;
+                self.move_pointer(0.0, 0.0).await;
+
+                // Power objects of outputs that went away are of no use
+                // anymore
+                let (kept, failed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.output_powers)
+                    .into_iter()
+                    .partition(|output_power| {
+                        self.outputs
+                            .iter()
+                            .any(|output| output.id == output_power.output)
+                    });
+                self.output_powers = kept;
+
+                for output_power in failed {
+                    if let Some(client) = self.clients.get(&output_power.client_id) {
+                        client.output_power_failed(output_power.power).await;
+                    }
+                }
+
+                // Freshly set up outputs are on, even if they were turned off
+                // before a VT switch
+                for output in self.outputs_off.clone() {
+                    self.set_output_on(output, true).await;
+                }
+
+                // Layer surfaces don't move between outputs, they're closed
+                // along with theirs
+                let (kept, closed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.layer_surfaces)
+                    .into_iter()
+                    .partition(|layer_surface| {
+                        self.outputs
+                            .iter()
+                            .any(|output| output.id == layer_surface.output)
+                    });
+                self.layer_surfaces = kept;
+
+                for layer_surface in closed {
+                    let _ = self
+                        .renderer_handle
+                        .unmap_surface(layer_surface.surface)
+                        .await;
+
+                    if let Some(client) = self.clients.get(&layer_surface.surface.client_id) {
+                        client
+                            .layer_surface_closed(layer_surface.layer_surface)
+                            .await;
+                    }
+                }
+
+                self.arrange_layers().await;
+                self.arrange_lock_surfaces().await;
+                self.update_focus().await;
+            }
+            CompositorMessage::MapToplevel { surface } => {
+                // Cascade new windows from the top left corner of the first
+                // output, next to the panels on it
+                let origin = self
+                    .outputs
+                    .first()
+                    .map(|output| {
+                        let area = self
+                            .usable_areas
+                            .get(&output.id)
+                            .unwrap_or(&output.geometry);
+                        (area.x, area.y)
+                    })
+                    .unwrap_or_default();
+                let offset = (self.mapped_windows % 16) as i32 * CASCADE_STEP;
+                self.mapped_windows = self.mapped_windows.wrapping_add(1);
+
+                let _ = self
+                    .renderer_handle
+                    .place_surface(surface, origin.0 + offset, origin.1 + offset, Layer::Normal)
+                    .await;
+
+                self.toplevels.push(surface);
+                self.refocus().await;
+            }
+            CompositorMessage::UnmapSurface { surface } => {
+                let _ = self.renderer_handle.unmap_surface(surface).await;
+
+                self.toplevels.retain(|toplevel| *toplevel != surface);
+                if self.focus == Some(surface) {
+                    self.refocus().await;
+                }
+            }
+            CompositorMessage::CommitLayerSurface {
+                surface,
+                layer_surface,
+                output,
+                state,
+                mapped,
+            } => {
+                let index = match self
+                    .layer_surfaces
+                    .iter()
+                    .position(|existing| existing.surface == surface)
+                {
+                    Some(index) => index,
+                    None => {
+                        // Only the initial commit puts a surface on an output
+                        let Some(state) = state else {
+                            return;
+                        };
+
+                        // Outputs unplugged meanwhile close the surface right
+                        // away
+                        let output = match output {
+                            Some(output) => self
+                                .outputs
+                                .iter()
+                                .find(|info| info.id == output)
+                                .map(|info| info.id),
+                            None => self.pointer_output().map(|output| output.id),
+                        };
+
+                        let Some(output) = output else {
+                            if let Some(client) = self.clients.get(&surface.client_id) {
+                                client.layer_surface_closed(layer_surface).await;
+                            }
+
+                            return;
+                        };
+
+                        self.layer_surfaces.push(LayerSurface {
+                            surface,
+                            layer_surface,
+                            output,
+                            state,
+                            mapped,
+                            size: None,
+                        });
+                        self.layer_surfaces.len() - 1
+                    }
+                };
+
+                let entry = &mut self.layer_surfaces[index];
+                entry.mapped = mapped;
+
+                if let Some(state) = state {
+                    entry.state = state;
+                }
+
+                self.arrange_layers().await;
+                self.update_focus().await;
+            }
+            CompositorMessage::RemoveLayerSurface { surface } => {
+                let Some(index) = self
+                    .layer_surfaces
+                    .iter()
+                    .position(|layer_surface| layer_surface.surface == surface)
+                else {
+                    return;
+                };
+
+                self.layer_surfaces.remove(index);
+                let _ = self.renderer_handle.unmap_surface(surface).await;
+
+                self.arrange_layers().await;
+                self.update_focus().await;
+            }
+            CompositorMessage::Lock { client_id, lock } => {
+                let Some(client) = self.clients.get(&client_id).cloned() else {
+                    return;
+                };
+
+                // Only one client holds the lock at a time
+                if self.session_lock.is_some() {
+                    client.lock_finished(lock).await;
+                    return;
+                }
+
+                self.session_lock = Some(SessionLock { client_id, lock });
+
+                // A locker taking over from one that died finds the session
+                // locked already
+                if !self.locked {
+                    info!("Locking the session for client {client_id}");
+
+                    self.locked = true;
+                    self.lock_shown = false;
+                    let _ = self.renderer_handle.set_locked(true).await;
+                    self.end_drag(true).await;
+                }
+
+                // Otherwise answered once the renderer flipped the locked
+                // frames
+                if self.lock_shown {
+                    client.session_locked(lock).await;
+                }
+
+                self.refocus().await;
+            }
+            CompositorMessage::LockShown => {
+                // Unlocked before the frames made it
+                if !self.locked {
+                    return;
+                }
+
+                self.lock_shown = true;
+
+                if let Some(held) = &self.session_lock
+                    && let Some(client) = self.clients.get(&held.client_id)
+                {
+                    client.session_locked(held.lock).await;
+                }
+            }
+            CompositorMessage::Unlock { client_id, lock } => {
+                if self
+                    .session_lock
+                    .as_ref()
+                    .is_some_and(|held| held.client_id == client_id && held.lock == lock)
+                {
+                    self.unlock().await;
+                }
+            }
+            CompositorMessage::LockDestroyed { client_id, lock } => {
+                if self
+                    .session_lock
+                    .as_ref()
+                    .is_some_and(|held| held.client_id == client_id && held.lock == lock)
+                {
+                    warn!("Client {client_id} gave up on the session lock");
+                    self.session_lock = None;
+
+                    for lock_surface in std::mem::take(&mut self.lock_surfaces) {
+                        let _ = self
+                            .renderer_handle
+                            .unmap_surface(lock_surface.surface)
+                            .await;
+                    }
+
+                    self.refocus().await;
+                }
+            }
+            CompositorMessage::NewLockSurface {
+                lock,
+                surface,
+                lock_surface,
+                output,
+            } => {
+                if !self
+                    .session_lock
+                    .as_ref()
+                    .is_some_and(|held| held.client_id == surface.client_id && held.lock == lock)
+                {
+                    return;
+                }
+
+                // Surfaces for outputs gone meanwhile get dropped right away
+                self.lock_surfaces.push(LockSurface {
+                    surface,
+                    lock_surface,
+                    output,
+                    mapped: false,
+                    size: None,
+                });
+
+                self.arrange_lock_surfaces().await;
+            }
+            CompositorMessage::MapLockSurface { surface } => {
+                let Some(lock_surface) = self
+                    .lock_surfaces
+                    .iter_mut()
+                    .find(|lock_surface| lock_surface.surface == surface)
+                else {
+                    return;
+                };
+
+                lock_surface.mapped = true;
+
+                self.arrange_lock_surfaces().await;
+                self.refocus().await;
+            }
+            CompositorMessage::RemoveLockSurface { surface } => {
+                let Some(index) = self
+                    .lock_surfaces
+                    .iter()
+                    .position(|lock_surface| lock_surface.surface == surface)
+                else {
+                    return;
+                };
+
+                self.lock_surfaces.remove(index);
+                let _ = self.renderer_handle.unmap_surface(surface).await;
+
+                self.refocus().await;
+            }
+            CompositorMessage::NewIdleNotification {
+                client_id,
+                notification,
+                timeout,
+                inhibitable,
+            } => {
+                self.idle_notifications.push(IdleNotification {
+                    client_id,
+                    notification,
+                    timeout: Duration::from_millis(timeout.into()),
+                    inhibitable,
+                    created: Instant::now(),
+                    idle: false,
+                });
+
+                self.schedule_idle_check(ctx);
+            }
+            CompositorMessage::IdleNotificationDestroyed {
+                client_id,
+                notification,
+            } => {
+                self.idle_notifications.retain(|existing| {
+                    existing.client_id != client_id || existing.notification != notification
+                });
+            }
+            CompositorMessage::NewIdleInhibitor { surface, inhibitor } => {
+                self.idle_inhibitors
+                    .push(IdleInhibitor { surface, inhibitor });
+            }
+            CompositorMessage::IdleInhibitorDestroyed {
+                client_id,
+                inhibitor,
+            } => {
+                self.idle_inhibitors.retain(|existing| {
+                    existing.surface.client_id != client_id || existing.inhibitor != inhibitor
+                });
+            }
+            CompositorMessage::IdleCheck => self.check_idle(ctx).await,
+            CompositorMessage::NewOutputPower {
+                client_id,
+                power,
+                output,
+            } => {
+                let Some(client) = self.clients.get(&client_id) else {
+                    return;
+                };
+
+                if !self.outputs.iter().any(|info| info.id == output) {
+                    client.output_power_failed(power).await;
+                    return;
+                }
+
+                client
+                    .output_power_mode(power, !self.outputs_off.contains(&output))
+                    .await;
+
+                self.output_powers.push(OutputPower {
+                    client_id,
+                    power,
+                    output,
+                });
+            }
+            CompositorMessage::SetOutputPower {
+                client_id,
+                power,
+                on,
+            } => {
+                let Some(output) = self
+                    .output_powers
+                    .iter()
+                    .find(|existing| existing.client_id == client_id && existing.power == power)
+                    .map(|existing| existing.output)
+                else {
+                    return;
+                };
+
+                if let Ok(true) = self.renderer_handle.set_power(output, on).await {
+                    self.set_output_on(output, on).await;
+                    return;
+                }
+
+                warn!(
+                    "Failed to turn output {output} {} for client {client_id}",
+                    if on { "on" } else { "off" }
+                );
+
+                // A failed power object is of no use anymore
+                self.output_powers
+                    .retain(|existing| existing.client_id != client_id || existing.power != power);
+
+                if let Some(client) = self.clients.get(&client_id) {
+                    client.output_power_failed(power).await;
+                }
+            }
+            CompositorMessage::OutputPowerDestroyed { client_id, power } => {
+                self.output_powers
+                    .retain(|existing| existing.client_id != client_id || existing.power != power);
+            }
+            CompositorMessage::FrameDone { callbacks, time } => {
+                let mut per_client: HashMap<u32, Vec<(ObjectId, Vec<ObjectId>)>> = HashMap::new();
+
+                for (surface, ids) in callbacks {
+                    per_client
+                        .entry(surface.client_id)
+                        .or_default()
+                        .push((surface.object_id, ids));
+                }
+
+                for (client_id, callbacks) in per_client {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.frame_done(callbacks, time).await;
+                    }
+                }
+            }
+            CompositorMessage::ReleaseBuffers { buffers } => {
+                let mut per_client: HashMap<u32, Vec<ObjectId>> = HashMap::new();
+
+                for (surface, buffer) in buffers {
+                    per_client
+                        .entry(surface.client_id)
+                        .or_default()
+                        .push(buffer);
+                }
+
+                for (client_id, buffers) in per_client {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.release_buffers(buffers).await;
+                    }
+                }
+            }
+            CompositorMessage::PresentationFeedback { feedback } => {
+                let mut per_client: HashMap<u32, Vec<(ObjectId, Option<Presented>)>> =
+                    HashMap::new();
+
+                for (surface, id, presented) in feedback {
+                    per_client
+                        .entry(surface.client_id)
+                        .or_default()
+                        .push((id, presented));
+                }
+
+                for (client_id, feedback) in per_client {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.presentation_feedback(feedback).await;
+                    }
+                }
+            }
+            CompositorMessage::Preferred { surfaces } => {
+                let mut per_client: HashMap<u32, Vec<(ObjectId, Preferred)>> = HashMap::new();
+
+                for (surface, preferred) in surfaces {
+                    per_client
+                        .entry(surface.client_id)
+                        .or_default()
+                        .push((surface.object_id, preferred));
+                }
+
+                for (client_id, surfaces) in per_client {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.preferred(surfaces).await;
+                    }
+                }
+            }
+            CompositorMessage::SetSelection {
+                client_id,
+                kind,
+                source,
+            } => {
+                // The serial may have been valid while focus was already moving
+                if self.focus.map(|focus| focus.client_id) != Some(client_id) {
+                    debug!("Ignoring selection of unfocused client {client_id}");
+                    return;
+                }
+
+                self.set_selection(client_id, kind, source).await;
+            }
+            CompositorMessage::ControlSelection {
+                client_id,
+                kind,
+                source,
+            } => {
+                // Data control globals can only be bound by privileged clients
+                if !self.privileged.contains(&client_id) {
+                    debug!("Ignoring selection of unprivileged client {client_id}");
+                    return;
+                }
+
+                self.set_selection(client_id, kind, source).await;
+            }
+            CompositorMessage::ReceiveData {
+                offer,
+                mime_type,
+                fd,
+            } => {
+                let selection = self
+                    .selections
+                    .values()
+                    .find(|selection| selection.offered.id == offer)
+                    .map(|selection| (selection.client_id, selection.source));
+
+                let owner = selection.or_else(|| {
+                    self.drag
+                        .as_ref()
+                        .filter(|drag| drag.offer == offer)
+                        .and_then(|drag| {
+                            drag.source
+                                .as_ref()
+                                .map(|source| (drag.client_id, source.source))
+                        })
+                });
+
+                // Stale offers get nothing, dropping the fd closes the pipe
+                if let Some((client_id, source)) = owner
+                    && let Some(client) = self.clients.get(&client_id)
+                {
+                    client.send_data(source, mime_type, fd).await;
+                }
+            }
+            CompositorMessage::DataSourceDestroyed { client_id, source } => {
+                for kind in self.selections_of(client_id, Some(source)) {
+                    // Nothing to cancel, the source is gone already
+                    self.selections.remove(&kind);
+                    self.replace_selection(kind, None).await;
+                }
+
+                if self.drag.as_ref().is_some_and(|drag| {
+                    drag.client_id == client_id
+                        && drag.source.as_ref().map(|source| source.source) == Some(source)
+                }) {
+                    self.end_drag(false).await;
+                }
+            }
+            CompositorMessage::StartDrag {
+                client_id,
+                source,
+                icon,
+            } => {
+                // Without a grab to tie them to, drags need a button held by
+                // the focused client
+                if self.locked
+                    || self.buttons.is_empty()
+                    || self.focus.map(|focus| focus.client_id) != Some(client_id)
+                    || self.drag.as_ref().is_some_and(|drag| !drag.dropped)
+                {
+                    debug!("Cancelling drag of client {client_id}");
+
+                    if let Some(source) = source
+                        && let Some(client) = self.clients.get(&client_id)
+                    {
+                        client.source_cancelled(source.source).await;
+                    }
+
+                    return;
+                }
+
+                // Gives up on the transfer of an earlier drop
+                self.end_drag(true).await;
+
+                self.drag = Some(Drag {
+                    client_id,
+                    source,
+                    icon,
+                    icon_offset: (0, 0),
+                    target: None,
+                    offer: 0,
+                    accepted: false,
+                    target_actions: (DndAction::empty(), DndAction::empty()),
+                    action: DndAction::empty(),
+                    dropped: false,
+                });
+
+                self.update_drag(0).await;
+            }
+            CompositorMessage::MoveDragIcon { surface, dx, dy } => {
+                if let Some(drag) = &mut self.drag
+                    && drag.icon == Some(surface)
+                {
+                    drag.icon_offset = (drag.icon_offset.0 + dx, drag.icon_offset.1 + dy);
+                    self.place_drag_icon().await;
+                }
+            }
+            CompositorMessage::DragAccept { offer, mime_type } => {
+                let Some(drag) = self.drag.as_mut().filter(|drag| drag.offer == offer) else {
+                    return;
+                };
+
+                drag.accepted = mime_type.is_some();
+
+                if let Some(source) = &drag.source
+                    && let Some(client) = self.clients.get(&drag.client_id)
+                {
+                    client.drag_target(source.source, mime_type).await;
+                }
+            }
+            CompositorMessage::DragActions {
+                offer,
+                actions,
+                preferred,
+            } => {
+                if let Some(drag) = self.drag.as_mut().filter(|drag| drag.offer == offer) {
+                    drag.target_actions = (actions, preferred);
+                    self.negotiate_drag_action().await;
+                }
+            }
+            CompositorMessage::DragFinished { offer } => {
+                if self
+                    .drag
+                    .as_ref()
+                    .is_some_and(|drag| drag.offer == offer && drag.dropped)
+                    && let Some(drag) = self.drag.take()
+                    && let Some(source) = drag.source
+                    && let Some(client) = self.clients.get(&drag.client_id)
+                {
+                    client.drag_finished(source.source).await;
+                }
+            }
+            CompositorMessage::DragOfferDestroyed { offer } => {
+                // The target gave up on the drop without finishing
+                if self
+                    .drag
+                    .as_ref()
+                    .is_some_and(|drag| drag.offer == offer && drag.dropped)
+                {
+                    self.end_drag(true).await;
+                }
+            }
         }
     }
 }
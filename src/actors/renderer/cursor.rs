@@ -0,0 +1,64 @@
+/// Built-in arrow used until clients provide their own cursor images
+const ARROW: [&str; 19] = [
+    "X...........",
+    "XX..........",
+    "XoX.........",
+    "XooX........",
+    "XoooX.......",
+    "XooooX......",
+    "XoooooX.....",
+    "XooooooX....",
+    "XoooooooX...",
+    "XooooooooX..",
+    "XoooooooooX.",
+    "XooooooXXXXX",
+    "XoooXooX....",
+    "XooXXooX....",
+    "XoX..XooX...",
+    "XX...XooX...",
+    "X.....XooX..",
+    "......XooX..",
+    ".......XX...",
+];
+
+#[derive(Debug, Clone)]
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot_x: i32,
+    pub hotspot_y: i32,
+    /// Premultiplied ARGB8888 pixels, tightly packed
+    pub pixels: Vec<u8>,
+}
+
+impl CursorImage {
+    pub fn default_arrow() -> Self {
+        let width = ARROW[0].len() as u32;
+        let height = ARROW.len() as u32;
+
+        let pixels = ARROW
+            .iter()
+            .flat_map(|row| row.bytes())
+            .flat_map(|pixel| {
+                match pixel {
+                    b'X' => 0xff000000u32,
+                    b'o' => 0xffffffff,
+                    _ => 0x00000000,
+                }
+                .to_le_bytes()
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            hotspot_x: 0,
+            hotspot_y: 0,
+            pixels,
+        }
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.width * 4
+    }
+}
@@ -0,0 +1,176 @@
+//! Hardware cursor planes
+//!
+//! The cursor image lives in a dumb buffer shown on the cursor plane of the
+//! CRTC, so moving the pointer only takes an atomic commit instead of
+//! repainting the output.
+
+use std::{ptr::null_mut, sync::Arc};
+
+use anyhow::{Result, ensure};
+use diretto::Device as DrmDevice;
+use rustix::mm::{MapFlags, ProtFlags, mmap, munmap};
+use tracing::warn;
+
+use super::{
+    cursor::CursorImage,
+    drm::{self, AtomicRequest, DumbBuffer, FramebufferPlane, PlaneProps},
+};
+
+/// Format of cursor images, premultiplied ARGB8888
+const ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+
+pub struct CursorPlane {
+    device: Arc<DrmDevice>,
+    plane_id: u32,
+    crtc_id: u32,
+    props: PlaneProps,
+    buffer: DumbBuffer,
+    fb_id: u32,
+    /// Size of the buffer, as required by the driver
+    size: (u32, u32),
+    /// Top left corner in output local coordinates, `None` while hidden
+    position: Option<(i32, i32)>,
+    /// What the plane currently shows
+    committed: Option<(i32, i32)>,
+}
+
+impl CursorPlane {
+    /// Uploads the image for the plane
+    ///
+    /// Returns `None` if the image doesn't fit in the cursor size the driver
+    /// supports, it then has to be composited.
+    pub fn new(
+        device: &Arc<DrmDevice>,
+        plane_id: u32,
+        crtc_id: u32,
+        props: PlaneProps,
+        image: &CursorImage,
+    ) -> Result<Option<Self>> {
+        let (width, height) = drm::cursor_size(&**device);
+
+        if image.width > width || image.height > height {
+            return Ok(None);
+        }
+
+        let buffer = drm::create_dumb_buffer(&**device, width, height)?;
+
+        let fb_id = Self::upload(device, buffer, image).and_then(|()| {
+            let plane = FramebufferPlane {
+                handle: buffer.handle,
+                pitch: buffer.pitch,
+                offset: 0,
+            };
+
+            drm::add_framebuffer(&**device, width, height, ARGB8888, None, &[plane])
+        });
+
+        let fb_id = match fb_id {
+            Ok(fb_id) => fb_id,
+            Err(e) => {
+                let _ = drm::destroy_dumb_buffer(&**device, buffer.handle);
+                return Err(e);
+            }
+        };
+
+        Ok(Some(Self {
+            device: device.clone(),
+            plane_id,
+            crtc_id,
+            props,
+            buffer,
+            fb_id,
+            size: (width, height),
+            position: None,
+            committed: None,
+        }))
+    }
+
+    /// Copies the image to the top left corner of the buffer, leaving the
+    /// rest transparent
+    fn upload(device: &DrmDevice, buffer: DumbBuffer, image: &CursorImage) -> Result<()> {
+        let offset = drm::map_dumb_buffer(device, buffer.handle)?;
+        let size = buffer.size as usize;
+        let pitch = buffer.pitch as usize;
+        let stride = image.stride() as usize;
+
+        ensure!(pitch >= stride, "Cursor buffer pitch is too small");
+
+        let mem = unsafe {
+            mmap(
+                null_mut(),
+                size,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::SHARED,
+                device,
+                offset,
+            )?
+        };
+
+        let pixels = unsafe { std::slice::from_raw_parts_mut(mem.cast::<u8>(), size) };
+        pixels.fill(0);
+
+        for (row, src) in image.pixels.chunks_exact(stride).enumerate() {
+            pixels[row * pitch..row * pitch + stride].copy_from_slice(src);
+        }
+
+        unsafe { munmap(mem, size)? };
+
+        Ok(())
+    }
+
+    /// Places the cursor, `position` being its top left corner in output
+    /// local coordinates or `None` if it's not on the output
+    pub fn set_position(&mut self, position: Option<(i32, i32)>) {
+        self.position = position;
+    }
+
+    /// Whether the plane doesn't show the latest position yet
+    pub fn is_dirty(&self) -> bool {
+        self.position != self.committed
+    }
+
+    /// Adds the plane state to a commit
+    pub fn add_to(&self, request: &mut AtomicRequest) {
+        let Some((x, y)) = self.position else {
+            request.set(self.plane_id, self.props.fb_id, 0).set(
+                self.plane_id,
+                self.props.crtc_id,
+                0,
+            );
+            return;
+        };
+
+        let (width, height) = (self.size.0 as u64, self.size.1 as u64);
+
+        request
+            .set(self.plane_id, self.props.fb_id, self.fb_id as u64)
+            .set(self.plane_id, self.props.crtc_id, self.crtc_id as u64)
+            .set(self.plane_id, self.props.src_x, 0)
+            .set(self.plane_id, self.props.src_y, 0)
+            .set(self.plane_id, self.props.src_w, width << 16)
+            .set(self.plane_id, self.props.src_h, height << 16)
+            // CRTC coordinates are signed, the cursor can hang off the edges
+            .set(self.plane_id, self.props.crtc_x, x as i64 as u64)
+            .set(self.plane_id, self.props.crtc_y, y as i64 as u64)
+            .set(self.plane_id, self.props.crtc_w, width)
+            .set(self.plane_id, self.props.crtc_h, height);
+    }
+
+    /// Records that a commit including the plane state went through
+    pub fn committed(&mut self) {
+        self.committed = self.position;
+    }
+}
+
+impl Drop for CursorPlane {
+    fn drop(&mut self) {
+        // Removing the framebuffer also takes it off the plane
+        if let Err(e) = drm::remove_framebuffer(&*self.device, self.fb_id) {
+            warn!("Failed to remove cursor framebuffer: {e}");
+        }
+
+        if let Err(e) = drm::destroy_dumb_buffer(&*self.device, self.buffer.handle) {
+            warn!("Failed to destroy cursor buffer: {e}");
+        }
+    }
+}
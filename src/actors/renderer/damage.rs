@@ -0,0 +1,483 @@
+use std::collections::VecDeque;
+
+use crate::Transform;
+
+/// Once a region grows past this many rectangles it gets collapsed into its
+/// bounding box, trading a bit of overdraw for fewer scissored draws
+const MAX_RECTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x.saturating_add(self.width)
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y.saturating_add(self.height)
+    }
+
+    pub fn translate(&self, dx: i32, dy: i32) -> Self {
+        Self::new(
+            self.x.saturating_add(dx),
+            self.y.saturating_add(dy),
+            self.width,
+            self.height,
+        )
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.right() && y < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        let rect = Rect::new(x, y, right - x, bottom - y);
+        (!rect.is_empty()).then_some(rect)
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Scales the edges by `factor`, rounding them to the nearest pixel so
+    /// adjacent rectangles stay adjacent
+    pub fn scale(&self, factor: f64) -> Self {
+        let edge = |value: i32| (value as f64 * factor).round() as i32;
+        let (x, y) = (edge(self.x), edge(self.y));
+
+        Self::new(x, y, edge(self.right()) - x, edge(self.bottom()) - y)
+    }
+
+    /// Smallest rectangle covering `self` scaled by `factor`
+    pub fn scale_out(&self, factor: f64) -> Self {
+        let x = (self.x as f64 * factor).floor() as i32;
+        let y = (self.y as f64 * factor).floor() as i32;
+        let right = (self.right() as f64 * factor).ceil() as i32;
+        let bottom = (self.bottom() as f64 * factor).ceil() as i32;
+
+        Self::new(x, y, right - x, bottom - y)
+    }
+
+    /// Where the rectangle ends up once something of the given size
+    /// containing it gets transformed
+    pub fn transform(&self, transform: Transform, size: (i32, i32)) -> Self {
+        let mut rect = *self;
+        let (mut width, mut height) = size;
+
+        if transform.is_flipped() {
+            rect.x = width - rect.right();
+        }
+
+        for _ in 0..transform.quarter_turns() {
+            // Counter-clockwise, the right edge becomes the top one
+            rect = Self::new(rect.y, width - rect.right(), rect.height, rect.width);
+            (width, height) = (height, width);
+        }
+
+        rect
+    }
+
+    /// Smallest rectangle containing both `self` and `other`
+    pub fn bounding(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+
+        if other.is_empty() {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// A set of rectangles that need repainting
+///
+/// Rectangles may overlap, the only guarantee is that no rectangle is fully
+/// contained in another one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Region {
+    rects: Vec<Rect>,
+}
+
+impl Region {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_rect(rect: Rect) -> Self {
+        let mut region = Self::new();
+        region.add(rect);
+        region
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() || self.rects.iter().any(|existing| existing.contains(&rect)) {
+            return;
+        }
+
+        self.rects.retain(|existing| !rect.contains(existing));
+        self.rects.push(rect);
+
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self.bounds();
+            self.rects.clear();
+            self.rects.push(bounds);
+        }
+    }
+
+    pub fn extend(&mut self, other: &Region) {
+        for rect in &other.rects {
+            self.add(*rect);
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        self.rects
+            .iter()
+            .fold(Rect::default(), |bounds, rect| bounds.bounding(rect))
+    }
+
+    pub fn translate(&self, dx: i32, dy: i32) -> Region {
+        Region {
+            rects: self
+                .rects
+                .iter()
+                .map(|rect| rect.translate(dx, dy))
+                .collect(),
+        }
+    }
+
+    /// Scales every rectangle by `factor`, rounding outwards
+    pub fn scale_out(&self, factor: f64) -> Region {
+        self.rects
+            .iter()
+            .map(|rect| rect.scale_out(factor))
+            .collect()
+    }
+
+    /// Transforms every rectangle along with something of the given size
+    /// containing them
+    pub fn transform(&self, transform: Transform, size: (i32, i32)) -> Region {
+        self.rects
+            .iter()
+            .map(|rect| rect.transform(transform, size))
+            .collect()
+    }
+
+    /// Restricts the region to the area covered by `bounds`
+    pub fn clip(&self, bounds: Rect) -> Region {
+        let mut region = Region::new();
+
+        for rect in &self.rects {
+            if let Some(clipped) = rect.intersection(&bounds) {
+                region.add(clipped);
+            }
+        }
+
+        region
+    }
+}
+
+impl FromIterator<Rect> for Region {
+    fn from_iter<T: IntoIterator<Item = Rect>>(iter: T) -> Self {
+        let mut region = Region::new();
+
+        for rect in iter {
+            region.add(rect);
+        }
+
+        region
+    }
+}
+
+/// An exact area built by adding and subtracting rectangles, as with
+/// `wl_region`
+///
+/// Nothing gets merged, so unlike [`Region`] it never grows past what was
+/// asked for. Meant for the input and opaque regions of surfaces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Area {
+    /// Each rectangle and whether it was added, in the order they came
+    ops: Vec<(Rect, bool)>,
+}
+
+impl Area {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if !rect.is_empty() {
+            self.ops.push((rect, true));
+        }
+    }
+
+    pub fn subtract(&mut self, rect: Rect) {
+        if !rect.is_empty() {
+            self.ops.push((rect, false));
+        }
+    }
+
+    /// The last rectangle covering the point decides whether it's inside
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        self.ops
+            .iter()
+            .rev()
+            .find(|(rect, _)| rect.contains_point(x, y))
+            .is_some_and(|(_, added)| *added)
+    }
+}
+
+/// History of the damage of previously rendered frames
+///
+/// A swapchain image that was last drawn `age` frames ago is missing every
+/// change made since then, so it has to be repainted with the union of the
+/// damage of those frames on top of the current one.
+#[derive(Debug)]
+pub struct DamageRing {
+    frames: VecDeque<Region>,
+    capacity: usize,
+}
+
+impl DamageRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records the damage of a frame that has just been rendered
+    pub fn push(&mut self, damage: Region) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_back();
+        }
+
+        self.frames.push_front(damage);
+    }
+
+    /// Forgets every previous frame, forcing the next ones to be fully redrawn
+    pub fn reset(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Damage accumulated since a buffer of the given age was last presented,
+    /// not including the damage of the frame about to be rendered
+    ///
+    /// Returns `None` when the buffer contents are unknown (age 0) or older
+    /// than the tracked history, in which case the whole output has to be
+    /// repainted.
+    pub fn damage_for_age(&self, age: usize) -> Option<Region> {
+        if age == 0 || age - 1 > self.frames.len() {
+            return None;
+        }
+
+        let mut region = Region::new();
+
+        for frame in self.frames.iter().take(age - 1) {
+            region.extend(frame);
+        }
+
+        Some(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_drops_contained_rects() {
+        let mut region = Region::new();
+        region.add(Rect::new(10, 10, 10, 10));
+        region.add(Rect::new(12, 12, 2, 2));
+
+        assert_eq!(region.rects(), &[Rect::new(10, 10, 10, 10)]);
+
+        region.add(Rect::new(0, 0, 50, 50));
+
+        assert_eq!(region.rects(), &[Rect::new(0, 0, 50, 50)]);
+    }
+
+    #[test]
+    fn add_ignores_empty_rects() {
+        let mut region = Region::new();
+        region.add(Rect::new(0, 0, 0, 10));
+        region.add(Rect::new(0, 0, 10, -1));
+
+        assert!(region.is_empty());
+    }
+
+    #[test]
+    fn add_collapses_into_bounds_past_max_rects() {
+        let mut region = Region::new();
+
+        for i in 0..=MAX_RECTS as i32 {
+            region.add(Rect::new(i * 10, 0, 5, 5));
+        }
+
+        assert_eq!(
+            region.rects(),
+            &[Rect::new(0, 0, MAX_RECTS as i32 * 10 + 5, 5)]
+        );
+    }
+
+    #[test]
+    fn extend_merges_regions() {
+        let mut region = Region::from_rect(Rect::new(0, 0, 10, 10));
+        region.extend(&Region::from_iter([
+            Rect::new(2, 2, 4, 4),
+            Rect::new(20, 20, 10, 10),
+        ]));
+
+        assert_eq!(
+            region.rects(),
+            &[Rect::new(0, 0, 10, 10), Rect::new(20, 20, 10, 10)]
+        );
+        assert_eq!(region.bounds(), Rect::new(0, 0, 30, 30));
+    }
+
+    #[test]
+    fn clip_keeps_the_overlap() {
+        let region = Region::from_iter([Rect::new(-5, -5, 10, 10), Rect::new(100, 100, 10, 10)]);
+
+        assert_eq!(
+            region.clip(Rect::new(0, 0, 50, 50)).rects(),
+            &[Rect::new(0, 0, 5, 5)]
+        );
+    }
+
+    #[test]
+    fn clip_outside_is_empty() {
+        let region = Region::from_rect(Rect::new(60, 60, 10, 10));
+
+        assert!(region.clip(Rect::new(0, 0, 50, 50)).is_empty());
+    }
+
+    #[test]
+    fn clip_touching_edges_is_empty() {
+        let region = Region::from_rect(Rect::new(50, 0, 10, 10));
+
+        assert!(region.clip(Rect::new(0, 0, 50, 50)).is_empty());
+    }
+
+    #[test]
+    fn age_zero_is_unknown() {
+        let ring = DamageRing::new(4);
+
+        assert_eq!(ring.damage_for_age(0), None);
+    }
+
+    #[test]
+    fn age_one_needs_nothing_extra() {
+        let mut ring = DamageRing::new(4);
+        ring.push(Region::from_rect(Rect::new(0, 0, 10, 10)));
+
+        assert_eq!(ring.damage_for_age(1), Some(Region::new()));
+    }
+
+    #[test]
+    fn older_buffers_get_the_damage_since() {
+        let mut ring = DamageRing::new(4);
+        ring.push(Region::from_rect(Rect::new(0, 0, 10, 10)));
+        ring.push(Region::from_rect(Rect::new(20, 0, 10, 10)));
+        ring.push(Region::from_rect(Rect::new(40, 0, 10, 10)));
+
+        assert_eq!(
+            ring.damage_for_age(2),
+            Some(Region::from_rect(Rect::new(40, 0, 10, 10)))
+        );
+        assert_eq!(
+            ring.damage_for_age(3),
+            Some(Region::from_iter([
+                Rect::new(40, 0, 10, 10),
+                Rect::new(20, 0, 10, 10),
+            ]))
+        );
+    }
+
+    #[test]
+    fn ages_past_the_history_are_unknown() {
+        let mut ring = DamageRing::new(2);
+        ring.push(Region::from_rect(Rect::new(0, 0, 10, 10)));
+        ring.push(Region::from_rect(Rect::new(20, 0, 10, 10)));
+        ring.push(Region::from_rect(Rect::new(40, 0, 10, 10)));
+
+        assert!(ring.damage_for_age(3).is_some());
+        assert_eq!(ring.damage_for_age(4), None);
+    }
+
+    #[test]
+    fn reset_forgets_the_history() {
+        let mut ring = DamageRing::new(4);
+        ring.push(Region::from_rect(Rect::new(0, 0, 10, 10)));
+        ring.reset();
+
+        assert_eq!(ring.damage_for_age(2), None);
+    }
+
+    #[test]
+    fn area_follows_the_last_covering_rect() {
+        let mut area = Area::new();
+        area.add(Rect::new(0, 0, 100, 100));
+        area.subtract(Rect::new(40, 40, 20, 20));
+        area.add(Rect::new(45, 45, 5, 5));
+
+        assert!(area.contains_point(0, 0));
+        assert!(area.contains_point(99, 99));
+        assert!(!area.contains_point(100, 50));
+        assert!(!area.contains_point(40, 40));
+        assert!(area.contains_point(45, 45));
+        assert!(!area.contains_point(50, 50));
+        assert!(!Area::new().contains_point(0, 0));
+    }
+}
@@ -0,0 +1,486 @@
+//! Zero-copy import of client dmabufs through the Vulkan backend
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd, IntoRawFd, OwnedFd},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use ash::{ext, khr, vk};
+use waynest::ObjectId;
+use wgpu::{TextureFormat, hal::api::Vulkan};
+
+use super::{
+    output::OutputId,
+    pipeline::{FLAG_OPAQUE, FLAG_SWAP_RB},
+    syncobj::SyncPoint,
+};
+
+/// Device extensions needed to import dmabufs
+pub const DEVICE_EXTENSIONS: [&std::ffi::CStr; 3] = [
+    khr::external_memory_fd::NAME,
+    ext::external_memory_dma_buf::NAME,
+    ext::image_drm_format_modifier::NAME,
+];
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// How a DRM format maps onto Vulkan and wgpu
+struct FormatMapping {
+    fourcc: u32,
+    vk: vk::Format,
+    wgpu: TextureFormat,
+    flags: u32,
+}
+
+/// Single plane formats we can sample from, formats without a matching
+/// Vulkan format reuse the one with red and blue swapped
+const FORMATS: [FormatMapping; 10] = [
+    FormatMapping {
+        fourcc: fourcc(b"AR24"),
+        vk: vk::Format::B8G8R8A8_UNORM,
+        wgpu: TextureFormat::Bgra8Unorm,
+        flags: 0,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"XR24"),
+        vk: vk::Format::B8G8R8A8_UNORM,
+        wgpu: TextureFormat::Bgra8Unorm,
+        flags: FLAG_OPAQUE,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"AB24"),
+        vk: vk::Format::R8G8B8A8_UNORM,
+        wgpu: TextureFormat::Rgba8Unorm,
+        flags: 0,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"XB24"),
+        vk: vk::Format::R8G8B8A8_UNORM,
+        wgpu: TextureFormat::Rgba8Unorm,
+        flags: FLAG_OPAQUE,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"AB30"),
+        vk: vk::Format::A2B10G10R10_UNORM_PACK32,
+        wgpu: TextureFormat::Rgb10a2Unorm,
+        flags: 0,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"XB30"),
+        vk: vk::Format::A2B10G10R10_UNORM_PACK32,
+        wgpu: TextureFormat::Rgb10a2Unorm,
+        flags: FLAG_OPAQUE,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"AR30"),
+        vk: vk::Format::A2B10G10R10_UNORM_PACK32,
+        wgpu: TextureFormat::Rgb10a2Unorm,
+        flags: FLAG_SWAP_RB,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"XR30"),
+        vk: vk::Format::A2B10G10R10_UNORM_PACK32,
+        wgpu: TextureFormat::Rgb10a2Unorm,
+        flags: FLAG_SWAP_RB | FLAG_OPAQUE,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"AB4H"),
+        vk: vk::Format::R16G16B16A16_SFLOAT,
+        wgpu: TextureFormat::Rgba16Float,
+        flags: 0,
+    },
+    FormatMapping {
+        fourcc: fourcc(b"XB4H"),
+        vk: vk::Format::R16G16B16A16_SFLOAT,
+        wgpu: TextureFormat::Rgba16Float,
+        flags: FLAG_OPAQUE,
+    },
+];
+
+fn format_mapping(fourcc: u32) -> Option<&'static FormatMapping> {
+    FORMATS.iter().find(|mapping| mapping.fourcc == fourcc)
+}
+
+/// Whether the format has no alpha channel, covering whatever is below
+pub fn is_opaque(fourcc: u32) -> bool {
+    format_mapping(fourcc).is_some_and(|mapping| mapping.flags & FLAG_OPAQUE != 0)
+}
+
+#[derive(Debug)]
+pub struct DmabufPlane {
+    pub fd: OwnedFd,
+    pub offset: u32,
+    pub stride: u32,
+}
+
+/// A client buffer living in GPU memory
+#[derive(Debug)]
+pub struct Dmabuf {
+    pub width: u32,
+    pub height: u32,
+    /// DRM fourcc code
+    pub format: u32,
+    pub modifier: u64,
+    pub planes: Vec<DmabufPlane>,
+}
+
+/// Surface contents backed by a dmabuf, shared with the `wl_buffer`
+#[derive(Debug, Clone)]
+pub struct DmabufContent {
+    /// The `wl_buffer` to release once the renderer stops sampling it
+    pub buffer: ObjectId,
+    pub dmabuf: Arc<Dmabuf>,
+    /// Explicit sync points to signal along with the buffer release
+    pub release: Vec<SyncPoint>,
+}
+
+/// A format and modifier pair the renderer or a plane can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DmabufFormat {
+    pub format: u32,
+    pub modifier: u64,
+}
+
+/// What clients get told about dmabuf support
+///
+/// Every pair is importable with a single plane, which is all the importer
+/// handles.
+#[derive(Debug, Clone)]
+pub struct DmabufFeedback {
+    /// `dev_t` of the DRM device the renderer runs on
+    pub main_device: u64,
+    pub formats: Vec<DmabufFormat>,
+    /// Pairs among `formats` the overlay plane of each output can scan out
+    ///
+    /// The primary plane is left out, it's flipped by the Vulkan WSI and
+    /// never shows client buffers.
+    pub scanout: HashMap<OutputId, Vec<DmabufFormat>>,
+    /// Largest importable size of every pair
+    max_extents: HashMap<DmabufFormat, (u32, u32)>,
+}
+
+impl DmabufFeedback {
+    /// Largest size a dmabuf of an advertised pair can have, `None` if the
+    /// pair isn't advertised
+    pub fn max_extent(&self, format: DmabufFormat) -> Option<(u32, u32)> {
+        self.max_extents.get(&format).copied()
+    }
+}
+
+pub struct DmabufImporter {
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    memory_fd: khr::external_memory_fd::Device,
+    main_device: u64,
+    /// Largest importable size of every supported pair
+    formats: HashMap<DmabufFormat, (u32, u32)>,
+}
+
+impl DmabufImporter {
+    /// Returns `None` if the device was created without the extensions
+    /// needed for dmabuf import
+    pub fn new(device: &wgpu::Device, main_device: u64) -> Result<Option<Self>> {
+        let hal_device = unsafe { device.as_hal::<Vulkan>() }.context("Not a Vulkan device")?;
+
+        if !DEVICE_EXTENSIONS
+            .iter()
+            .all(|name| hal_device.enabled_device_extensions().contains(name))
+        {
+            return Ok(None);
+        }
+
+        let instance = hal_device.shared_instance().raw_instance().clone();
+        let raw_device = hal_device.raw_device().clone();
+        let memory_fd = khr::external_memory_fd::Device::new(&instance, &raw_device);
+
+        let mut importer = Self {
+            instance,
+            physical_device: hal_device.raw_physical_device(),
+            device: raw_device,
+            memory_fd,
+            main_device,
+            formats: HashMap::new(),
+        };
+
+        for mapping in &FORMATS {
+            for modifier in importer.modifiers(mapping.vk) {
+                if let Some(extent) = importer.max_extent(mapping.vk, modifier) {
+                    importer.formats.insert(
+                        DmabufFormat {
+                            format: mapping.fourcc,
+                            modifier,
+                        },
+                        extent,
+                    );
+                }
+            }
+        }
+
+        Ok(Some(importer))
+    }
+
+    pub fn feedback(&self) -> DmabufFeedback {
+        let mut formats: Vec<_> = self.formats.keys().copied().collect();
+        formats.sort_by_key(|format| (format.format, format.modifier));
+
+        DmabufFeedback {
+            main_device: self.main_device,
+            formats,
+            scanout: HashMap::new(),
+            max_extents: self.formats.clone(),
+        }
+    }
+
+    /// Single plane modifiers the format can be sampled with
+    fn modifiers(&self, format: vk::Format) -> Vec<u64> {
+        let count = {
+            let mut list = vk::DrmFormatModifierPropertiesListEXT::default();
+            let mut properties = vk::FormatProperties2::default().push_next(&mut list);
+
+            unsafe {
+                self.instance.get_physical_device_format_properties2(
+                    self.physical_device,
+                    format,
+                    &mut properties,
+                )
+            };
+
+            list.drm_format_modifier_count as usize
+        };
+
+        let mut modifiers = vec![vk::DrmFormatModifierPropertiesEXT::default(); count];
+
+        {
+            let mut list = vk::DrmFormatModifierPropertiesListEXT::default()
+                .drm_format_modifier_properties(&mut modifiers);
+            let mut properties = vk::FormatProperties2::default().push_next(&mut list);
+
+            unsafe {
+                self.instance.get_physical_device_format_properties2(
+                    self.physical_device,
+                    format,
+                    &mut properties,
+                )
+            };
+        }
+
+        modifiers
+            .into_iter()
+            .filter(|properties| {
+                properties.drm_format_modifier_plane_count == 1
+                    && properties
+                        .drm_format_modifier_tiling_features
+                        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+            })
+            .map(|properties| properties.drm_format_modifier)
+            .collect()
+    }
+
+    /// Largest size a dmabuf of the given format can be imported with, if
+    /// at all
+    fn max_extent(&self, format: vk::Format, modifier: u64) -> Option<(u32, u32)> {
+        let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::default()
+            .drm_format_modifier(modifier)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::default()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+        let info = vk::PhysicalDeviceImageFormatInfo2::default()
+            .format(format)
+            .ty(vk::ImageType::TYPE_2D)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(vk::ImageUsageFlags::SAMPLED)
+            .push_next(&mut modifier_info)
+            .push_next(&mut external_info);
+
+        let mut external_properties = vk::ExternalImageFormatProperties::default();
+
+        let extent = {
+            let mut properties =
+                vk::ImageFormatProperties2::default().push_next(&mut external_properties);
+
+            unsafe {
+                self.instance.get_physical_device_image_format_properties2(
+                    self.physical_device,
+                    &info,
+                    &mut properties,
+                )
+            }
+            .ok()?;
+
+            properties.image_format_properties.max_extent
+        };
+
+        external_properties
+            .external_memory_properties
+            .external_memory_features
+            .contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+            .then_some((extent.width, extent.height))
+    }
+
+    /// Wraps a dmabuf into a texture, returning it along with the quad flags
+    /// needed to sample it
+    pub fn import(&self, device: &wgpu::Device, dmabuf: &Dmabuf) -> Result<(wgpu::Texture, u32)> {
+        let mapping = format_mapping(dmabuf.format)
+            .with_context(|| format!("Unsupported dmabuf format {:#x}", dmabuf.format))?;
+
+        let Some(&(max_width, max_height)) = self.formats.get(&DmabufFormat {
+            format: dmabuf.format,
+            modifier: dmabuf.modifier,
+        }) else {
+            bail!(
+                "Unsupported dmabuf modifier {:#x} for format {:#x}",
+                dmabuf.modifier,
+                dmabuf.format
+            );
+        };
+
+        ensure!(
+            dmabuf.width <= max_width && dmabuf.height <= max_height,
+            "Dmabuf of {}x{} is too large",
+            dmabuf.width,
+            dmabuf.height
+        );
+
+        let [plane] = dmabuf.planes.as_slice() else {
+            bail!("Multi-planar dmabufs aren't supported");
+        };
+
+        let layouts = [vk::SubresourceLayout {
+            offset: plane.offset as u64,
+            size: 0,
+            row_pitch: plane.stride as u64,
+            array_pitch: 0,
+            depth_pitch: 0,
+        }];
+
+        let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::default()
+            .drm_format_modifier(dmabuf.modifier)
+            .plane_layouts(&layouts);
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::default()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+        let info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(mapping.vk)
+            .extent(vk::Extent3D {
+                width: dmabuf.width,
+                height: dmabuf.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+            .usage(vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut external_info)
+            .push_next(&mut modifier_info);
+
+        let image = unsafe { self.device.create_image(&info, None)? };
+
+        let memory = match self.bind_memory(image, plane) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.device.destroy_image(image, None) };
+                return Err(e);
+            }
+        };
+
+        let size = wgpu::Extent3d {
+            width: dmabuf.width,
+            height: dmabuf.height,
+            depth_or_array_layers: 1,
+        };
+
+        let raw_device = self.device.clone();
+        let hal_texture = unsafe {
+            device
+                .as_hal::<Vulkan>()
+                .context("Not a Vulkan device")?
+                .texture_from_raw(
+                    image,
+                    &wgpu::hal::TextureDescriptor {
+                        label: Some("client dmabuf"),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: mapping.wgpu,
+                        usage: wgpu::TextureUses::RESOURCE,
+                        memory_flags: wgpu::hal::MemoryFlags::empty(),
+                        view_formats: Vec::new(),
+                    },
+                    Some(Box::new(move || {
+                        raw_device.destroy_image(image, None);
+                        raw_device.free_memory(memory, None);
+                    })),
+                    wgpu::hal::vulkan::TextureMemory::External,
+                )
+        };
+
+        let texture = unsafe {
+            device.create_texture_from_hal::<Vulkan>(
+                hal_texture,
+                &wgpu::TextureDescriptor {
+                    label: Some("client dmabuf"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: mapping.wgpu,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+            )
+        };
+
+        Ok((texture, mapping.flags))
+    }
+
+    /// Imports the memory behind the plane and binds it to the image
+    fn bind_memory(&self, image: vk::Image, plane: &DmabufPlane) -> Result<vk::DeviceMemory> {
+        let mut fd_properties = vk::MemoryFdPropertiesKHR::default();
+        unsafe {
+            self.memory_fd.get_memory_fd_properties(
+                vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+                plane.fd.as_raw_fd(),
+                &mut fd_properties,
+            )?
+        };
+
+        let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let memory_types = requirements.memory_type_bits & fd_properties.memory_type_bits;
+
+        ensure!(memory_types != 0, "No memory type can hold the dmabuf");
+
+        // Vulkan takes ownership of the fd on success, the client keeps its own
+        let fd = plane.fd.try_clone()?;
+
+        let mut import_info = vk::ImportMemoryFdInfoKHR::default()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .fd(fd.as_raw_fd());
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
+
+        let info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_types.trailing_zeros())
+            .push_next(&mut import_info)
+            .push_next(&mut dedicated_info);
+
+        let memory = unsafe { self.device.allocate_memory(&info, None)? };
+        let _ = fd.into_raw_fd();
+
+        if let Err(e) = unsafe { self.device.bind_image_memory(image, memory, 0) } {
+            unsafe { self.device.free_memory(memory, None) };
+            return Err(e.into());
+        }
+
+        Ok(memory)
+    }
+}
@@ -0,0 +1,955 @@
+//! Thin wrappers around the DRM ioctls diretto doesn't expose yet
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{CStr, CString},
+    io,
+};
+
+use anyhow::{Result, ensure};
+use diretto::Device as DrmDevice;
+use rustix::{
+    fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    fs::{Mode, OFlags},
+    ioctl::{Opcode, Setter, Updater, ioctl, opcode},
+};
+
+/// Sequence is relative to the current one
+const DRM_CRTC_SEQUENCE_RELATIVE: u32 = 0x1;
+/// Fire on the following vblank if the requested one was already missed
+const DRM_CRTC_SEQUENCE_NEXT_ON_MISS: u32 = 0x2;
+
+const DRM_EVENT_CRTC_SEQUENCE: u32 = 0x03;
+
+const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
+/// The framebuffer comes with explicit modifiers
+const DRM_MODE_FB_MODIFIERS: u32 = 1 << 1;
+
+const DRM_CAP_CURSOR_WIDTH: u64 = 0x8;
+const DRM_CAP_CURSOR_HEIGHT: u64 = 0x9;
+const DRM_CAP_SYNCOBJ_TIMELINE: u64 = 0x14;
+const DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP: u64 = 0x15;
+
+/// Cursor size drivers have to support when they don't report one
+const DEFAULT_CURSOR_SIZE: u32 = 64;
+
+const DRM_MODE_ATOMIC_TEST_ONLY: u32 = 0x0100;
+const DRM_MODE_ATOMIC_NONBLOCK: u32 = 0x0200;
+const DRM_MODE_ATOMIC_ALLOW_MODESET: u32 = 0x0400;
+/// Flip right away instead of waiting for the vblank, tearing
+const DRM_MODE_PAGE_FLIP_ASYNC: u32 = 0x02;
+
+/// `sync_file` status once every fence signalled
+const SYNC_FILE_SIGNALED: i32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeGetEncoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeGetPlane {
+    plane_id: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    possible_crtcs: u32,
+    gamma_size: u32,
+    count_format_types: u32,
+    format_type_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmCrtcGetSequence {
+    crtc_id: u32,
+    active: u32,
+    sequence: u64,
+    sequence_ns: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmCrtcQueueSequence {
+    crtc_id: u32,
+    flags: u32,
+    sequence: u64,
+    user_data: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeGetBlob {
+    blob_id: u32,
+    length: u32,
+    data: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmGetCap {
+    capability: u64,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeCreateDumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeDestroyDumb {
+    handle: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmPrimeHandle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmGemClose {
+    handle: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeFbCmd2 {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    flags: u32,
+    handles: [u32; 4],
+    pitches: [u32; 4],
+    offsets: [u32; 4],
+    modifier: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmSyncobjHandle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmSyncobjDestroy {
+    handle: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmSyncobjTimelineArray {
+    handles: u64,
+    points: u64,
+    count_handles: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmSyncobjEventfd {
+    handle: u32,
+    flags: u32,
+    point: u64,
+    fd: i32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct DrmModeAtomic {
+    flags: u32,
+    count_objs: u32,
+    objs_ptr: u64,
+    count_props_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    reserved: u64,
+    user_data: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct SyncFenceInfo {
+    obj_name: [u8; 32],
+    driver_name: [u8; 32],
+    status: i32,
+    flags: u32,
+    timestamp_ns: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+#[allow(dead_code)]
+struct SyncFileInfo {
+    name: [u8; 32],
+    status: i32,
+    flags: u32,
+    num_fences: u32,
+    pad: u32,
+    sync_fence_info: u64,
+}
+
+const SYNC_IOC_FILE_INFO: Opcode = opcode::read_write::<SyncFileInfo>(b'>', 4);
+
+const DRM_IOCTL_GET_CAP: Opcode = opcode::read_write::<DrmGetCap>(b'd', 0x0C);
+const DRM_IOCTL_GEM_CLOSE: Opcode = opcode::write::<DrmGemClose>(b'd', 0x09);
+const DRM_IOCTL_PRIME_FD_TO_HANDLE: Opcode = opcode::read_write::<DrmPrimeHandle>(b'd', 0x2E);
+const DRM_IOCTL_MODE_GETENCODER: Opcode = opcode::read_write::<DrmModeGetEncoder>(b'd', 0xA6);
+const DRM_IOCTL_MODE_GETPLANE: Opcode = opcode::read_write::<DrmModeGetPlane>(b'd', 0xB6);
+const DRM_IOCTL_MODE_GETPROPBLOB: Opcode = opcode::read_write::<DrmModeGetBlob>(b'd', 0xAC);
+const DRM_IOCTL_MODE_RMFB: Opcode = opcode::read_write::<u32>(b'd', 0xAF);
+const DRM_IOCTL_MODE_CREATE_DUMB: Opcode = opcode::read_write::<DrmModeCreateDumb>(b'd', 0xB2);
+const DRM_IOCTL_MODE_MAP_DUMB: Opcode = opcode::read_write::<DrmModeMapDumb>(b'd', 0xB3);
+const DRM_IOCTL_MODE_DESTROY_DUMB: Opcode = opcode::read_write::<DrmModeDestroyDumb>(b'd', 0xB4);
+const DRM_IOCTL_MODE_ADDFB2: Opcode = opcode::read_write::<DrmModeFbCmd2>(b'd', 0xB8);
+const DRM_IOCTL_MODE_ATOMIC: Opcode = opcode::read_write::<DrmModeAtomic>(b'd', 0xBC);
+const DRM_IOCTL_SYNCOBJ_DESTROY: Opcode = opcode::read_write::<DrmSyncobjDestroy>(b'd', 0xC0);
+const DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE: Opcode = opcode::read_write::<DrmSyncobjHandle>(b'd', 0xC2);
+const DRM_IOCTL_SYNCOBJ_QUERY: Opcode = opcode::read_write::<DrmSyncobjTimelineArray>(b'd', 0xCB);
+const DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL: Opcode =
+    opcode::read_write::<DrmSyncobjTimelineArray>(b'd', 0xCD);
+const DRM_IOCTL_SYNCOBJ_EVENTFD: Opcode = opcode::read_write::<DrmSyncobjEventfd>(b'd', 0xCF);
+const DRM_IOCTL_CRTC_GET_SEQUENCE: Opcode = opcode::read_write::<DrmCrtcGetSequence>(b'd', 0x3B);
+const DRM_IOCTL_CRTC_QUEUE_SEQUENCE: Opcode =
+    opcode::read_write::<DrmCrtcQueueSequence>(b'd', 0x3C);
+
+/// # Safety
+///
+/// `OPCODE` must be a DRM ioctl taking a `T` as argument
+pub(super) unsafe fn drm_ioctl<const OPCODE: Opcode, T>(
+    fd: impl AsFd,
+    value: &mut T,
+) -> io::Result<()> {
+    unsafe { ioctl(fd, Updater::<OPCODE, T>::new(value)) }.map_err(io::Error::from)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Encoder {
+    pub crtc_id: u32,
+    pub possible_crtcs: u32,
+}
+
+pub fn get_encoder(device: &DrmDevice, encoder_id: u32) -> Result<Encoder> {
+    let mut encoder = DrmModeGetEncoder {
+        encoder_id,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_GETENCODER, _>(device, &mut encoder)? };
+
+    Ok(Encoder {
+        crtc_id: encoder.crtc_id,
+        possible_crtcs: encoder.possible_crtcs,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub id: u32,
+    pub possible_crtcs: u32,
+    /// Formats the plane can scan out, with an implicit modifier
+    pub formats: Vec<u32>,
+}
+
+impl Plane {
+    pub fn supports_crtc(&self, crtc_index: usize) -> bool {
+        self.possible_crtcs & (1 << crtc_index) != 0
+    }
+}
+
+pub fn get_plane(device: &DrmDevice, plane_id: u32) -> Result<Plane> {
+    let mut plane = DrmModeGetPlane {
+        plane_id,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_GETPLANE, _>(device, &mut plane)? };
+
+    // The first call only tells how many formats there are
+    let mut formats = vec![0u32; plane.count_format_types as usize];
+    plane.format_type_ptr = formats.as_mut_ptr() as u64;
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_GETPLANE, _>(device, &mut plane)? };
+
+    formats.truncate(plane.count_format_types as usize);
+
+    Ok(Plane {
+        id: plane.plane_id,
+        possible_crtcs: plane.possible_crtcs,
+        formats,
+    })
+}
+
+pub fn get_property_blob(device: &DrmDevice, blob_id: u32) -> Result<Vec<u8>> {
+    let mut blob = DrmModeGetBlob {
+        blob_id,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_GETPROPBLOB, _>(device, &mut blob)? };
+
+    let mut data = vec![0u8; blob.length as usize];
+    blob.data = data.as_mut_ptr() as u64;
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_GETPROPBLOB, _>(device, &mut blob)? };
+
+    data.truncate(blob.length as usize);
+
+    Ok(data)
+}
+
+/// Format and modifier pairs a plane can scan out
+///
+/// Planes without an `IN_FORMATS` property only take linear buffers.
+pub fn plane_formats(
+    device: &DrmDevice,
+    plane: &Plane,
+    properties: &Properties,
+) -> Vec<(u32, u64)> {
+    let blob = properties
+        .value(c"IN_FORMATS")
+        .filter(|blob_id| *blob_id != 0)
+        .and_then(|blob_id| get_property_blob(device, blob_id as u32).ok());
+
+    match blob {
+        Some(blob) => parse_in_formats(&blob),
+        None => plane
+            .formats
+            .iter()
+            .map(|format| (*format, DRM_FORMAT_MOD_LINEAR))
+            .collect(),
+    }
+}
+
+/// Parses a `struct drm_format_modifier_blob`
+fn parse_in_formats(blob: &[u8]) -> Vec<(u32, u64)> {
+    let u32_at = |at: usize| {
+        blob.get(at..at + 4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+    };
+    let u64_at = |at: usize| {
+        blob.get(at..at + 8)
+            .map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+    };
+
+    let (Some(count_formats), Some(formats_offset), Some(count_modifiers), Some(modifiers_offset)) =
+        (u32_at(8), u32_at(12), u32_at(16), u32_at(20))
+    else {
+        return Vec::new();
+    };
+
+    let formats: Vec<u32> = (0..count_formats as usize)
+        .map_while(|index| u32_at(formats_offset as usize + index * 4))
+        .collect();
+
+    let mut pairs = Vec::new();
+
+    for index in 0..count_modifiers as usize {
+        // struct drm_format_modifier, a bitmask of 64 formats starting at
+        // `offset` that support the modifier
+        let at = modifiers_offset as usize + index * 24;
+        let (Some(mask), Some(offset), Some(modifier)) =
+            (u64_at(at), u32_at(at + 8), u64_at(at + 16))
+        else {
+            break;
+        };
+
+        for bit in 0..64 {
+            if mask & (1 << bit) != 0
+                && let Some(format) = formats.get(offset as usize + bit)
+            {
+                pairs.push((*format, modifier));
+            }
+        }
+    }
+
+    pairs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaneType {
+    Overlay,
+    Primary,
+    Cursor,
+}
+
+impl PlaneType {
+    fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::Overlay),
+            1 => Some(Self::Primary),
+            2 => Some(Self::Cursor),
+            _ => None,
+        }
+    }
+}
+
+/// Property ids and current values of a DRM object, looked up by name
+#[derive(Debug, Default)]
+pub struct Properties {
+    props: HashMap<CString, (u32, u64)>,
+}
+
+impl Properties {
+    pub fn value(&self, name: &CStr) -> Option<u64> {
+        self.props.get(name).map(|(_, value)| *value)
+    }
+
+    pub fn id(&self, name: &CStr) -> Option<u32> {
+        self.props.get(name).map(|(id, _)| *id)
+    }
+
+    pub fn plane_type(&self) -> Option<PlaneType> {
+        self.value(c"type").and_then(PlaneType::from_raw)
+    }
+}
+
+/// Ids of the plane properties needed to show a framebuffer
+#[derive(Debug, Clone, Copy)]
+pub struct PlaneProps {
+    pub fb_id: u32,
+    pub crtc_id: u32,
+    pub src_x: u32,
+    pub src_y: u32,
+    pub src_w: u32,
+    pub src_h: u32,
+    pub crtc_x: u32,
+    pub crtc_y: u32,
+    pub crtc_w: u32,
+    pub crtc_h: u32,
+}
+
+impl PlaneProps {
+    /// `None` if the plane lacks one of the atomic properties
+    pub fn new(properties: &Properties) -> Option<Self> {
+        Some(Self {
+            fb_id: properties.id(c"FB_ID")?,
+            crtc_id: properties.id(c"CRTC_ID")?,
+            src_x: properties.id(c"SRC_X")?,
+            src_y: properties.id(c"SRC_Y")?,
+            src_w: properties.id(c"SRC_W")?,
+            src_h: properties.id(c"SRC_H")?,
+            crtc_x: properties.id(c"CRTC_X")?,
+            crtc_y: properties.id(c"CRTC_Y")?,
+            crtc_w: properties.id(c"CRTC_W")?,
+            crtc_h: properties.id(c"CRTC_H")?,
+        })
+    }
+}
+
+pub fn properties(device: &DrmDevice, object_id: u32, object_type: u32) -> Result<Properties> {
+    let (props, values) = unsafe { device.get_properties(object_id, object_type)? };
+
+    let mut properties = Properties::default();
+
+    for (index, prop) in props.into_iter().enumerate() {
+        let (name, _) = unsafe { device.get_property(prop)? };
+        properties.props.insert(name, (prop, values[index]));
+    }
+
+    Ok(properties)
+}
+
+fn get_cap(device: impl AsFd, capability: u64) -> Result<u64> {
+    let mut cap = DrmGetCap {
+        capability,
+        value: 0,
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_GET_CAP, _>(device, &mut cap)? };
+
+    Ok(cap.value)
+}
+
+/// Size cursor plane buffers have to be allocated with
+pub fn cursor_size(device: impl AsFd) -> (u32, u32) {
+    let size = |capability| {
+        get_cap(&device, capability)
+            .ok()
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(DEFAULT_CURSOR_SIZE)
+    };
+
+    (size(DRM_CAP_CURSOR_WIDTH), size(DRM_CAP_CURSOR_HEIGHT))
+}
+
+/// Whether atomic commits can flip without waiting for the vblank
+pub fn supports_async_flips(device: impl AsFd) -> bool {
+    get_cap(device, DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP).is_ok_and(|value| value != 0)
+}
+
+/// Whether syncobjs can hold timelines rather than a single fence
+pub fn supports_syncobj_timelines(device: impl AsFd) -> bool {
+    get_cap(device, DRM_CAP_SYNCOBJ_TIMELINE).is_ok_and(|value| value != 0)
+}
+
+/// Imports a syncobj exported by another process
+pub fn syncobj_fd_to_handle(device: impl AsFd, fd: impl AsFd) -> Result<u32> {
+    let mut syncobj = DrmSyncobjHandle {
+        fd: fd.as_fd().as_raw_fd(),
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE, _>(device, &mut syncobj)? };
+
+    Ok(syncobj.handle)
+}
+
+pub fn syncobj_destroy(device: impl AsFd, handle: u32) -> Result<()> {
+    let mut destroy = DrmSyncobjDestroy { handle, pad: 0 };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_SYNCOBJ_DESTROY, _>(device, &mut destroy)? };
+
+    Ok(())
+}
+
+/// Signals a timeline point from the CPU
+pub fn syncobj_timeline_signal(device: impl AsFd, handle: u32, point: u64) -> Result<()> {
+    let mut signal = DrmSyncobjTimelineArray {
+        handles: &handle as *const u32 as u64,
+        points: &point as *const u64 as u64,
+        count_handles: 1,
+        flags: 0,
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL, _>(device, &mut signal)? };
+
+    Ok(())
+}
+
+/// Latest signalled point of a timeline
+pub fn syncobj_query(device: impl AsFd, handle: u32) -> Result<u64> {
+    let mut point = 0u64;
+    let mut query = DrmSyncobjTimelineArray {
+        handles: &handle as *const u32 as u64,
+        points: &mut point as *mut u64 as u64,
+        count_handles: 1,
+        flags: 0,
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_SYNCOBJ_QUERY, _>(device, &mut query)? };
+
+    Ok(point)
+}
+
+/// Makes the eventfd readable once the timeline point is signalled
+pub fn syncobj_eventfd(
+    device: impl AsFd,
+    handle: u32,
+    point: u64,
+    eventfd: impl AsFd,
+) -> Result<()> {
+    let mut wait = DrmSyncobjEventfd {
+        handle,
+        flags: 0,
+        point,
+        fd: eventfd.as_fd().as_raw_fd(),
+        pad: 0,
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_SYNCOBJ_EVENTFD, _>(device, &mut wait)? };
+
+    Ok(())
+}
+
+/// A CPU mappable buffer allocated by the display driver
+#[derive(Debug, Clone, Copy)]
+pub struct DumbBuffer {
+    pub handle: u32,
+    pub pitch: u32,
+    pub size: u64,
+}
+
+pub fn create_dumb_buffer(device: impl AsFd, width: u32, height: u32) -> Result<DumbBuffer> {
+    let mut create = DrmModeCreateDumb {
+        width,
+        height,
+        bpp: 32,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_CREATE_DUMB, _>(device, &mut create)? };
+
+    Ok(DumbBuffer {
+        handle: create.handle,
+        pitch: create.pitch,
+        size: create.size,
+    })
+}
+
+/// Offset to `mmap` the dumb buffer at, on the device fd
+pub fn map_dumb_buffer(device: impl AsFd, handle: u32) -> Result<u64> {
+    let mut map = DrmModeMapDumb {
+        handle,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_MAP_DUMB, _>(device, &mut map)? };
+
+    Ok(map.offset)
+}
+
+pub fn destroy_dumb_buffer(device: impl AsFd, handle: u32) -> Result<()> {
+    let mut destroy = DrmModeDestroyDumb { handle };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_DESTROY_DUMB, _>(device, &mut destroy)? };
+
+    Ok(())
+}
+
+/// Turns a dmabuf into a GEM handle of the device
+pub fn prime_fd_to_handle(device: impl AsFd, fd: impl AsFd) -> Result<u32> {
+    let mut prime = DrmPrimeHandle {
+        fd: fd.as_fd().as_raw_fd(),
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_PRIME_FD_TO_HANDLE, _>(device, &mut prime)? };
+
+    Ok(prime.handle)
+}
+
+pub fn gem_close(device: impl AsFd, handle: u32) -> Result<()> {
+    let close = DrmGemClose { handle, pad: 0 };
+
+    unsafe { ioctl(device, Setter::<DRM_IOCTL_GEM_CLOSE, _>::new(close)) }
+        .map_err(io::Error::from)?;
+
+    Ok(())
+}
+
+/// Handle, pitch and offset of a framebuffer plane
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferPlane {
+    pub handle: u32,
+    pub pitch: u32,
+    pub offset: u32,
+}
+
+/// Registers a framebuffer, without a modifier the driver picks the layout
+/// it uses for buffers it allocated itself
+pub fn add_framebuffer(
+    device: impl AsFd,
+    width: u32,
+    height: u32,
+    format: u32,
+    modifier: Option<u64>,
+    planes: &[FramebufferPlane],
+) -> Result<u32> {
+    let mut cmd = DrmModeFbCmd2 {
+        width,
+        height,
+        pixel_format: format,
+        flags: if modifier.is_some() {
+            DRM_MODE_FB_MODIFIERS
+        } else {
+            0
+        },
+        ..Default::default()
+    };
+
+    for (index, plane) in planes.iter().enumerate().take(4) {
+        cmd.handles[index] = plane.handle;
+        cmd.pitches[index] = plane.pitch;
+        cmd.offsets[index] = plane.offset;
+        cmd.modifier[index] = modifier.unwrap_or_default();
+    }
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_ADDFB2, _>(device, &mut cmd)? };
+
+    Ok(cmd.fb_id)
+}
+
+pub fn remove_framebuffer(device: impl AsFd, fb_id: u32) -> Result<()> {
+    let mut fb_id = fb_id;
+
+    unsafe { drm_ioctl::<DRM_IOCTL_MODE_RMFB, _>(device, &mut fb_id)? };
+
+    Ok(())
+}
+
+/// Property changes applied together by an atomic commit
+#[derive(Debug, Default)]
+pub struct AtomicRequest {
+    objects: BTreeMap<u32, Vec<(u32, u64)>>,
+}
+
+impl AtomicRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn set(&mut self, object_id: u32, property_id: u32, value: u64) -> &mut Self {
+        self.objects
+            .entry(object_id)
+            .or_default()
+            .push((property_id, value));
+        self
+    }
+
+    /// Checks whether the hardware would accept the request, without
+    /// applying it
+    pub fn test(&self, device: impl AsFd) -> Result<()> {
+        self.submit(device, DRM_MODE_ATOMIC_TEST_ONLY)
+    }
+
+    /// Applies the request at the next vblank, without blocking
+    pub fn commit(&self, device: impl AsFd) -> Result<()> {
+        self.submit(device, DRM_MODE_ATOMIC_NONBLOCK)
+    }
+
+    /// Like [`Self::commit`], also returning a fence that signals once the
+    /// CRTC shows the result, if it has an `OUT_FENCE_PTR` property
+    ///
+    /// The fence gets timestamped with the vblank the flip happened at.
+    pub fn commit_fenced(
+        &mut self,
+        device: impl AsFd,
+        crtc_id: u32,
+        out_fence_ptr: Option<u32>,
+    ) -> Result<Option<OwnedFd>> {
+        let Some(out_fence_ptr) = out_fence_ptr else {
+            self.commit(device)?;
+            return Ok(None);
+        };
+
+        let mut fence: i32 = -1;
+        self.set(crtc_id, out_fence_ptr, &raw mut fence as u64);
+
+        let committed = self.submit(device, DRM_MODE_ATOMIC_NONBLOCK);
+
+        // The pointer is only valid for this commit
+        if let Some(props) = self.objects.get_mut(&crtc_id) {
+            props.retain(|(id, _)| *id != out_fence_ptr);
+        }
+
+        committed?;
+
+        ensure!(fence >= 0, "No out fence was returned");
+
+        // SAFETY: the kernel just created the fd for us
+        Ok(Some(unsafe { OwnedFd::from_raw_fd(fence) }))
+    }
+
+    /// Applies a request that turns CRTCs on or off, waiting for it to
+    /// complete
+    pub fn commit_modeset(&self, device: impl AsFd) -> Result<()> {
+        self.submit(device, DRM_MODE_ATOMIC_ALLOW_MODESET)
+    }
+
+    /// Like [`Self::test`] for an asynchronous flip
+    pub fn test_async(&self, device: impl AsFd) -> Result<()> {
+        self.submit(device, DRM_MODE_ATOMIC_TEST_ONLY | DRM_MODE_PAGE_FLIP_ASYNC)
+    }
+
+    /// Applies the request right away, tearing
+    ///
+    /// Drivers only accept framebuffer changes of the primary plane this way.
+    pub fn commit_async(&self, device: impl AsFd) -> Result<()> {
+        self.submit(device, DRM_MODE_ATOMIC_NONBLOCK | DRM_MODE_PAGE_FLIP_ASYNC)
+    }
+
+    fn submit(&self, device: impl AsFd, flags: u32) -> Result<()> {
+        let objects: Vec<u32> = self.objects.keys().copied().collect();
+        let counts: Vec<u32> = self
+            .objects
+            .values()
+            .map(|props| props.len() as u32)
+            .collect();
+        let props: Vec<u32> = self.objects.values().flatten().map(|(id, _)| *id).collect();
+        let values: Vec<u64> = self
+            .objects
+            .values()
+            .flatten()
+            .map(|(_, value)| *value)
+            .collect();
+
+        let mut atomic = DrmModeAtomic {
+            flags,
+            count_objs: objects.len() as u32,
+            objs_ptr: objects.as_ptr() as u64,
+            count_props_ptr: counts.as_ptr() as u64,
+            props_ptr: props.as_ptr() as u64,
+            prop_values_ptr: values.as_ptr() as u64,
+            ..Default::default()
+        };
+
+        unsafe { drm_ioctl::<DRM_IOCTL_MODE_ATOMIC, _>(device, &mut atomic)? };
+
+        Ok(())
+    }
+}
+
+/// Opens a second file description for an already open DRM device
+///
+/// Vblank events are delivered to the file description that queued them, and
+/// the Vulkan WSI reads (and drops) every event on the fd it was given, so
+/// frame scheduling has to use its own.
+pub fn reopen(device: &DrmDevice) -> io::Result<OwnedFd> {
+    let path = format!("/proc/self/fd/{}", device.as_fd().as_raw_fd());
+
+    Ok(rustix::fs::open(
+        path,
+        OFlags::RDWR | OFlags::CLOEXEC | OFlags::NONBLOCK,
+        Mode::empty(),
+    )?)
+}
+
+/// The most recent vblank of a CRTC
+#[derive(Debug, Clone, Copy)]
+pub struct Vblank {
+    pub sequence: u64,
+    /// `CLOCK_MONOTONIC` timestamp of the first pixel going out
+    pub time_ns: i64,
+}
+
+pub fn crtc_sequence(device: impl AsFd, crtc_id: u32) -> Result<Vblank> {
+    let mut sequence = DrmCrtcGetSequence {
+        crtc_id,
+        ..Default::default()
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_CRTC_GET_SEQUENCE, _>(device, &mut sequence)? };
+
+    Ok(Vblank {
+        sequence: sequence.sequence,
+        time_ns: sequence.sequence_ns,
+    })
+}
+
+/// Asks for a [`DrmEvent::Sequence`] carrying `user_data` on the next vblank
+/// of the CRTC
+pub fn queue_next_vblank(device: impl AsFd, crtc_id: u32, user_data: u64) -> Result<()> {
+    let mut sequence = DrmCrtcQueueSequence {
+        crtc_id,
+        flags: DRM_CRTC_SEQUENCE_RELATIVE | DRM_CRTC_SEQUENCE_NEXT_ON_MISS,
+        sequence: 1,
+        user_data,
+    };
+
+    unsafe { drm_ioctl::<DRM_IOCTL_CRTC_QUEUE_SEQUENCE, _>(device, &mut sequence)? };
+
+    Ok(())
+}
+
+/// When the single fence of a `sync_file` signalled, `None` if it didn't
+/// yet
+pub fn sync_file_timestamp(fd: impl AsFd) -> Result<Option<i64>> {
+    let mut fence = SyncFenceInfo::default();
+    let mut info = SyncFileInfo {
+        num_fences: 1,
+        sync_fence_info: &raw mut fence as u64,
+        ..Default::default()
+    };
+
+    unsafe { ioctl(fd, Updater::<SYNC_IOC_FILE_INFO, _>::new(&mut info)) }
+        .map_err(io::Error::from)?;
+
+    match info.status {
+        SYNC_FILE_SIGNALED => Ok(Some(fence.timestamp_ns as i64)),
+        0 => Ok(None),
+        error => Err(io::Error::from_raw_os_error(-error).into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DrmEvent {
+    Sequence { user_data: u64, vblank: Vblank },
+}
+
+/// Reads every pending event from the DRM fd
+///
+/// Events the kernel sends that we never asked for are skipped.
+pub fn read_events(fd: impl AsFd) -> io::Result<Vec<DrmEvent>> {
+    // Same as libdrm, the kernel never splits an event across reads
+    let mut buf = [0u8; 1024];
+    let len = rustix::io::read(fd, &mut buf)?;
+
+    let mut events = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= len {
+        let header = |at: usize| u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap());
+        let ty = header(offset);
+        let length = header(offset + 4) as usize;
+
+        if length < 8 || offset + length > len {
+            break;
+        }
+
+        if ty == DRM_EVENT_CRTC_SEQUENCE && length >= 32 {
+            let field = |at: usize| {
+                u64::from_ne_bytes(buf[offset + at..offset + at + 8].try_into().unwrap())
+            };
+
+            events.push(DrmEvent::Sequence {
+                user_data: field(8),
+                vblank: Vblank {
+                    time_ns: field(16) as i64,
+                    sequence: field(24),
+                },
+            });
+        }
+
+        offset += length;
+    }
+
+    Ok(events)
+}
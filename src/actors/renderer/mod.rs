@@ -1,31 +1,405 @@
-use stagecraft::{Actor, Context, HasMailbox};
-use tracing::debug;
+use std::{collections::HashMap, os::fd::OwnedFd, time::Duration};
 
-use crate::actors::session::SessionRef;
+use stagecraft::{Actor, Context, Handle, HasMailbox};
+use tokio::io::{Interest, unix::AsyncFd};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
 
-use self::wgpu_context::WgpuContext;
+use waynest::ObjectId;
 
+use crate::{
+    OutputConfig,
+    actors::{
+        compositor::{Compositor, CompositorMessage},
+        session::SessionRef,
+    },
+    protocol::wayland::surface::SurfaceId,
+};
+
+use self::{
+    damage::Region,
+    drm::{DrmEvent, Vblank},
+    scene::Scene,
+    scheduler::Deadline,
+    wgpu_context::WgpuContext,
+};
+
+pub use self::{
+    dmabuf::{Dmabuf, DmabufContent, DmabufFeedback, DmabufFormat, DmabufPlane},
+    output::{OutputId, OutputInfo, Presented},
+    scene::{
+        Layer, Preferred, ShmContent, SubsurfaceStack, SurfaceContent, SurfaceUpdate, Viewport,
+    },
+    scheduler::monotonic_ns,
+    syncobj::{SyncPoint, SyncobjDevice, Timeline},
+    texture::{SHM_FORMATS, shm_bytes_per_pixel},
+};
+
+mod cursor;
+mod cursor_plane;
+pub mod damage;
+mod dmabuf;
+mod drm;
+mod output;
+mod overlay;
+mod pipeline;
+mod scanout;
+mod scene;
+mod scheduler;
+mod syncobj;
+mod texture;
 mod wgpu_context;
 
+/// How often FIFO barriers no output paces get released, about 60Hz
+const BARRIER_INTERVAL: Duration = Duration::from_nanos(16_666_667);
+
 #[stagecraft::message(Renderer)]
 pub enum RendererMessage {
     #[call]
     Suspend,
     #[call]
     Resume,
-    Render,
+    /// Formats clients can allocate dmabufs in, `None` if import isn't
+    /// supported
+    #[call(Option<DmabufFeedback>)]
+    DmabufFeedback,
+    /// Imports explicit sync timelines, `None` if the driver can't
+    #[call(Option<SyncobjDevice>)]
+    SyncobjDevice,
+    /// Outputs to advertise as `wl_output` globals
+    #[call(Vec<OutputInfo>)]
+    Outputs,
+    Render {
+        output: OutputId,
+        generation: u64,
+    },
+    Vblank {
+        output: OutputId,
+        vblank: Vblank,
+    },
+    /// Fired by the barrier timer
+    ReleaseBarriers,
+    CommitSurface {
+        update: SurfaceUpdate,
+    },
+    PlaceSurface {
+        surface: SurfaceId,
+        x: i32,
+        y: i32,
+        layer: Layer,
+    },
+    UnmapSurface {
+        surface: SurfaceId,
+    },
+    DestroySurface {
+        surface: SurfaceId,
+    },
+    RemoveClient {
+        client_id: u32,
+    },
+    MoveCursor {
+        x: f64,
+        y: f64,
+    },
+    /// Shows nothing but lock surfaces while locked, answered with
+    /// `LockShown` once every output shows that
+    SetLocked {
+        locked: bool,
+    },
+    /// Turns an output off or back on, returns whether it did
+    #[call(bool)]
+    SetPower {
+        output: OutputId,
+        on: bool,
+    },
+    /// The topmost surface at the given layout position other than
+    /// `exclude`, with the position in its local coordinates
+    #[call(Option<(SurfaceId, f64, f64)>)]
+    SurfaceAt {
+        x: f64,
+        y: f64,
+        exclude: Option<SurfaceId>,
+    },
+    /// The placed surface under the given layout position, with subsurfaces
+    /// resolved to their parent
+    #[call(Option<SurfaceId>)]
+    PlacedAt {
+        x: f64,
+        y: f64,
+    },
+    /// Whether any of the given surfaces is shown
+    #[call(bool)]
+    AnyVisible {
+        surfaces: Vec<SurfaceId>,
+    },
 }
 
 pub struct Renderer {
     session_ref: SessionRef,
+    compositor_handle: Handle<Compositor>,
     wgpu_context: Option<WgpuContext<'static>>,
+    scene: Scene,
+    output_configs: HashMap<String, OutputConfig>,
+    /// Kept across suspends, clients keep using the same GPU
+    dmabuf_feedback: Option<DmabufFeedback>,
+    /// Kept across suspends as well, client timelines live on the device
+    syncobj_device: Option<SyncobjDevice>,
+    /// Outputs of the last context, kept across suspends so clients
+    /// connecting meanwhile still see them
+    outputs: Vec<OutputInfo>,
+    /// Stops forwarding vblank events of the current context
+    events_token: Option<CancellationToken>,
+    /// Whether a release of unpaced FIFO barriers is pending
+    barrier_timer: bool,
+    /// Whether the compositor waits for the outputs to show the lock screen
+    locking: bool,
 }
 
 impl Renderer {
-    pub fn new(session_ref: SessionRef) -> Self {
+    pub fn new(
+        session_ref: SessionRef,
+        compositor_handle: Handle<Compositor>,
+        output_configs: HashMap<String, OutputConfig>,
+    ) -> Self {
         Self {
             session_ref,
+            compositor_handle,
             wgpu_context: None,
+            scene: Scene::new(),
+            output_configs,
+            dmabuf_feedback: None,
+            syncobj_device: None,
+            outputs: Vec::new(),
+            events_token: None,
+            barrier_timer: false,
+            locking: false,
+        }
+    }
+
+    /// Queues a repaint of the given layout region
+    ///
+    /// Nothing gets rendered as long as no output has pending damage.
+    async fn damage(&mut self, region: Region, ctx: &mut Context<Self>) {
+        if let Some(ref mut context) = self.wgpu_context {
+            context.add_damage(&region);
+            self.schedule_frames(ctx);
+        }
+
+        // Buffers replaced while nothing is being drawn can go back right away
+        if self
+            .wgpu_context
+            .as_mut()
+            .is_none_or(|context| context.is_idle())
+        {
+            self.release_buffers().await;
+        }
+    }
+
+    async fn release_buffers(&mut self) {
+        let context = self.wgpu_context.as_ref();
+
+        // Scanned out buffers stay in use until the next frame replaces them
+        let buffers: Vec<_> = self
+            .scene
+            .take_released(|dmabuf| context.is_some_and(|context| context.is_scanned_out(dmabuf)))
+            .into_iter()
+            .map(|(surface, content)| {
+                for point in &content.release {
+                    point.signal();
+                }
+
+                (surface, content.buffer)
+            })
+            .collect();
+
+        if !buffers.is_empty() {
+            let _ = self
+                .compositor_handle
+                .cast(CompositorMessage::ReleaseBuffers { buffers })
+                .await;
+        }
+    }
+
+    /// Tells clients what became of the updates they asked for presentation
+    /// feedback on, `None` meaning it was never shown
+    async fn presentation_feedback(&self, feedback: Vec<(SurfaceId, ObjectId, Option<Presented>)>) {
+        if !feedback.is_empty() {
+            let _ = self
+                .compositor_handle
+                .cast(CompositorMessage::PresentationFeedback { feedback })
+                .await;
+        }
+    }
+
+    async fn send_discarded(&mut self) {
+        let discarded = self
+            .scene
+            .take_discarded()
+            .into_iter()
+            .map(|(surface, feedback)| (surface, feedback, None))
+            .collect();
+
+        self.presentation_feedback(discarded).await;
+    }
+
+    /// Tells clients about surfaces that moved to an output of another scale
+    /// or transform
+    async fn update_preferred(&mut self) {
+        let Some(ref context) = self.wgpu_context else {
+            return;
+        };
+
+        let surfaces = self.scene.update_preferred(&context.outputs());
+
+        if !surfaces.is_empty() {
+            let _ = self
+                .compositor_handle
+                .cast(CompositorMessage::Preferred { surfaces })
+                .await;
+        }
+    }
+
+    /// Arms a render timer for every output that has something new to show
+    /// and isn't busy with a previous frame
+    fn schedule_frames(&mut self, ctx: &mut Context<Self>) {
+        let Some(ref mut context) = self.wgpu_context else {
+            return;
+        };
+
+        for (output, deadline) in context.schedule_frames(&self.scene) {
+            let Deadline {
+                time_ns,
+                generation,
+            } = deadline;
+            let delay = Duration::from_nanos((time_ns - monotonic_ns()).max(0) as u64);
+            let handle = ctx.handle();
+
+            ctx.track(async move {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                let _ = handle
+                    .cast(RendererMessage::Render { output, generation })
+                    .await;
+            });
+        }
+    }
+
+    /// Releases the FIFO barriers of surfaces no powered output shows
+    ///
+    /// Keeps going at a steady rate while there are any, so clients neither
+    /// block until the outputs come back nor spin on their frames.
+    async fn release_barriers(&mut self, ctx: &mut Context<Self>) {
+        let active = self
+            .wgpu_context
+            .as_ref()
+            .map(WgpuContext::active_bounds)
+            .unwrap_or_default();
+
+        let surfaces = self.scene.take_unpaced_barriers(&active);
+
+        if surfaces.is_empty() {
+            return;
+        }
+
+        let _ = self
+            .compositor_handle
+            .cast(CompositorMessage::FrameDone {
+                callbacks: surfaces
+                    .into_iter()
+                    .map(|surface| (surface, Vec::new()))
+                    .collect(),
+                time: (monotonic_ns() / 1_000_000) as u32,
+            })
+            .await;
+
+        self.arm_barrier_timer(ctx);
+    }
+
+    fn arm_barrier_timer(&mut self, ctx: &mut Context<Self>) {
+        if self.barrier_timer {
+            return;
+        }
+
+        self.barrier_timer = true;
+        let handle = ctx.handle();
+
+        ctx.track(async move {
+            tokio::time::sleep(BARRIER_INTERVAL).await;
+            let _ = handle.cast(RendererMessage::ReleaseBarriers).await;
+        });
+    }
+
+    /// Tells the compositor once every output that's on flipped a frame
+    /// drawn since locking, with nothing to show the session is locked too
+    async fn check_lock_shown(&mut self) {
+        if !self.locking
+            || !self
+                .wgpu_context
+                .as_ref()
+                .is_none_or(WgpuContext::shows_lock_frames)
+        {
+            return;
+        }
+
+        self.locking = false;
+
+        let _ = self
+            .compositor_handle
+            .cast(CompositorMessage::LockShown)
+            .await;
+    }
+
+    fn stop_events(&mut self) {
+        if let Some(token) = self.events_token.take() {
+            token.cancel();
+        }
+    }
+}
+
+/// Forwards the vblank events of the DRM device to the renderer until cancelled
+async fn forward_drm_events(fd: OwnedFd, renderer: Handle<Renderer>, token: CancellationToken) {
+    let fd = match AsyncFd::with_interest(fd, Interest::READABLE) {
+        Ok(fd) => fd,
+        Err(e) => {
+            error!("Failed to watch DRM events: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let mut guard = tokio::select! {
+            _ = token.cancelled() => break,
+            guard = fd.readable() => match guard {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("Failed to wait for DRM events: {e}");
+                    break;
+                }
+            },
+        };
+
+        let events = match guard.try_io(|fd| drm::read_events(fd.get_ref())) {
+            Ok(Ok(events)) => events,
+            Ok(Err(e)) => {
+                error!("Failed to read DRM events: {e}");
+                break;
+            }
+            Err(_would_block) => continue,
+        };
+
+        for event in events {
+            match event {
+                DrmEvent::Sequence { user_data, vblank } => {
+                    let _ = renderer
+                        .cast(RendererMessage::Vblank {
+                            output: user_data as OutputId,
+                            vblank,
+                        })
+                        .await;
+                }
+            }
         }
     }
 }
@@ -45,37 +419,224 @@ impl Actor for Renderer {
         match msg {
             RendererMessage::Suspend { respond_to } => {
                 debug!("Suspending renderer");
+                self.stop_events();
                 self.wgpu_context = None;
                 let _ = respond_to.send(());
+
+                // Nothing presents anymore until resumed
+                self.release_barriers(ctx).await;
+                self.check_lock_shown().await;
             }
             RendererMessage::Resume { respond_to } => {
                 debug!("Resuming renderer");
 
                 if self.wgpu_context.is_none() {
                     debug!("Creating wgpu context");
-                    match WgpuContext::new(&self.session_ref).await {
-                        Ok(wgpu_ctx) => self.wgpu_context = Some(wgpu_ctx),
-                        Err(e) => tracing::error!("Failed to create wgpu context: {e}"),
+                    match WgpuContext::new(&self.session_ref, &self.scene, &self.output_configs)
+                        .await
+                    {
+                        Ok(wgpu_ctx) => {
+                            match wgpu_ctx.vblank_events() {
+                                Ok(fd) => {
+                                    let token = ctx.child_token();
+                                    ctx.track(forward_drm_events(fd, ctx.handle(), token.clone()));
+                                    self.events_token = Some(token);
+                                }
+                                Err(e) => error!("Failed to set up vblank events: {e}"),
+                            }
+
+                            self.outputs = wgpu_ctx.outputs();
+
+                            let _ = self
+                                .compositor_handle
+                                .cast(CompositorMessage::OutputsChanged {
+                                    outputs: self.outputs.clone(),
+                                })
+                                .await;
+
+                            self.dmabuf_feedback = wgpu_ctx.dmabuf_feedback();
+
+                            if self.syncobj_device.is_none() {
+                                self.syncobj_device = wgpu_ctx.syncobj_device();
+                            }
+
+                            self.wgpu_context = Some(wgpu_ctx);
+                        }
+                        Err(e) => error!("Failed to create wgpu context: {e}"),
                     }
                 }
 
                 let _ = respond_to.send(());
 
-                let _ = ctx.handle().cast(RendererMessage::Render).await;
+                self.update_preferred().await;
+
+                // Freshly configured outputs start fully damaged
+                self.damage(Region::new(), ctx).await;
             }
-            RendererMessage::Render => {
-                if let Some(ref context) = self.wgpu_context {
-                    if let Err(e) = context.present() {
-                        tracing::error!("Present failed: {e}");
-                    }
-                    let _ = ctx.handle().cast(RendererMessage::Render).await;
+            RendererMessage::DmabufFeedback { respond_to } => {
+                let _ = respond_to.send(self.dmabuf_feedback.clone());
+            }
+            RendererMessage::SyncobjDevice { respond_to } => {
+                let _ = respond_to.send(self.syncobj_device.clone());
+            }
+            RendererMessage::Outputs { respond_to } => {
+                let _ = respond_to.send(self.outputs.clone());
+            }
+            RendererMessage::Render { output, generation } => {
+                if let Some(ref mut context) = self.wgpu_context
+                    && let Err(e) = context.render_output(output, generation, &mut self.scene)
+                {
+                    error!("Present failed: {e}");
+                }
+            }
+            RendererMessage::Vblank { output, vblank } => {
+                let Some(ref mut context) = self.wgpu_context else {
+                    return;
+                };
+
+                let (callbacks, presented) = context.vblank(output, vblank, &mut self.scene);
+
+                if !callbacks.is_empty() {
+                    let _ = self
+                        .compositor_handle
+                        .cast(CompositorMessage::FrameDone {
+                            callbacks,
+                            time: (vblank.time_ns / 1_000_000) as u32,
+                        })
+                        .await;
                 }
+
+                self.presentation_feedback(
+                    presented
+                        .into_iter()
+                        .map(|(surface, feedback, presented)| (surface, feedback, Some(presented)))
+                        .collect(),
+                )
+                .await;
+
+                // Nothing samples the replaced buffers anymore
+                self.release_buffers().await;
+
+                self.check_lock_shown().await;
+
+                // Whatever changed while the frame was in flight
+                self.schedule_frames(ctx);
+            }
+            RendererMessage::ReleaseBarriers => {
+                self.barrier_timer = false;
+                self.release_barriers(ctx).await;
+            }
+            RendererMessage::CommitSurface { update } => {
+                let fifo_barrier = update.fifo_barrier;
+                let damage = self.scene.commit(update);
+                self.send_discarded().await;
+                self.update_preferred().await;
+
+                // Commits without damage may still wait on frame callbacks
+                self.damage(damage, ctx).await;
+
+                // Outputs that are off won't clear the barrier
+                if fifo_barrier {
+                    self.arm_barrier_timer(ctx);
+                }
+            }
+            RendererMessage::PlaceSurface {
+                surface,
+                x,
+                y,
+                layer,
+            } => {
+                let damage = self.scene.place(surface, x, y, layer);
+                self.update_preferred().await;
+                self.damage(damage, ctx).await;
+            }
+            RendererMessage::UnmapSurface { surface } => {
+                let damage = self.scene.unmap(surface);
+                self.damage(damage, ctx).await;
+            }
+            RendererMessage::DestroySurface { surface } => {
+                let damage = self.scene.remove(surface);
+                self.send_discarded().await;
+
+                if let Some(ref mut context) = self.wgpu_context {
+                    context.forget_surface(surface);
+                }
+
+                self.damage(damage, ctx).await;
+            }
+            RendererMessage::RemoveClient { client_id } => {
+                let damage = self.scene.remove_client(client_id);
+
+                if let Some(ref mut context) = self.wgpu_context {
+                    context.forget_client(client_id);
+                }
+
+                self.damage(damage, ctx).await;
+            }
+            RendererMessage::SetLocked { locked } => {
+                self.scene.set_locked(locked);
+                self.locking = locked;
+
+                if let Some(ref mut context) = self.wgpu_context {
+                    context.await_lock_frames(locked);
+                    context.damage_all();
+                    self.schedule_frames(ctx);
+                }
+
+                self.check_lock_shown().await;
+            }
+            RendererMessage::SetPower {
+                output,
+                on,
+                respond_to,
+            } => {
+                let powered = match self.wgpu_context {
+                    Some(ref mut context) => context.set_power(output, on),
+                    None => false,
+                };
+
+                let _ = respond_to.send(powered);
+
+                // Whatever changed while the output was off
+                if on {
+                    self.schedule_frames(ctx);
+                } else {
+                    self.release_barriers(ctx).await;
+                    self.check_lock_shown().await;
+                }
+            }
+            RendererMessage::MoveCursor { x, y } => {
+                let damage = self.scene.move_cursor(x, y);
+
+                // Outputs with a cursor plane don't need repainting
+                if let Some(ref mut context) = self.wgpu_context {
+                    context.move_cursor(&damage, &self.scene);
+                    self.schedule_frames(ctx);
+                }
+            }
+            RendererMessage::SurfaceAt {
+                x,
+                y,
+                exclude,
+                respond_to,
+            } => {
+                let _ = respond_to.send(self.scene.surface_at(x, y, exclude));
+            }
+            RendererMessage::PlacedAt { x, y, respond_to } => {
+                let _ = respond_to.send(self.scene.placed_at(x, y));
+            }
+            RendererMessage::AnyVisible {
+                surfaces,
+                respond_to,
+            } => {
+                let _ = respond_to.send(self.scene.any_visible(&surfaces));
             }
         }
     }
 
     async fn on_stop(&mut self, _ctx: &mut Context<Self>) {
         debug!("Renderer stopping, dropping wgpu context");
+        self.stop_events();
         self.wgpu_context = None;
     }
 }
@@ -0,0 +1,1103 @@
+use std::{collections::HashMap, os::fd::OwnedFd, sync::Arc};
+
+use anyhow::{Context, Result};
+use ash::vk::Handle;
+use diretto::{Connector, Device as DrmDevice, sys::DRM_MODE_OBJECT_CRTC};
+use tracing::{debug, trace, warn};
+use wgpu::hal::api::Vulkan;
+
+use waynest::ObjectId;
+
+use crate::{AdaptiveSync, OutputConfig, Transform, protocol::wayland::surface::SurfaceId};
+
+use super::{
+    cursor_plane::CursorPlane,
+    damage::{DamageRing, Rect, Region},
+    dmabuf::{self, Dmabuf, DmabufFormat},
+    drm::{self, AtomicRequest, PlaneProps, Vblank},
+    overlay::{OverlayContent, OverlayPlane},
+    pipeline::{Quad, QuadPipeline},
+    scanout::{Framebuffer, Scanout},
+    scene::Scene,
+    scheduler::FrameScheduler,
+    texture::ClientTexture,
+};
+
+/// How many previous frames worth of damage are remembered, swapchains
+/// deeper than this just get fully repainted
+const DAMAGE_HISTORY: usize = 4;
+
+const BACKGROUND: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+/// Covers whatever no lock surface does while the session is locked
+const LOCKED_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// How much earlier than the vblank event an out fence may be timestamped
+/// while still belonging to it, with adaptive sync there's no refresh rate to
+/// count vblanks with
+const LATE_FENCE_SLACK_NS: i64 = 1_000_000;
+
+/// Samples the whole texture
+const FULL_UV: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+pub type OutputId = u32;
+
+/// Everything needed to describe an output to the rest of the compositor
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    pub id: OutputId,
+    pub name: String,
+    /// Position and size in the global layout, in logical pixels
+    pub geometry: Rect,
+    /// Size of the mode in physical pixels
+    pub mode: (u32, u32),
+    /// Refresh rate in mHz
+    pub refresh: u32,
+    /// Physical pixels per logical pixel
+    pub scale: f64,
+    pub transform: Transform,
+}
+
+/// When and how a frame made it on screen, for `wp_presentation`
+#[derive(Debug, Clone, Copy)]
+pub struct Presented {
+    pub output: OutputId,
+    /// `CLOCK_MONOTONIC` time of the vblank the frame was shown at
+    pub time_ns: i64,
+    /// `None` with adaptive sync, the display has no fixed refresh rate
+    pub refresh_ns: Option<u32>,
+    /// Vblank counter of the CRTC
+    pub sequence: u64,
+    /// Flipped at the vblank rather than right away
+    pub vsync: bool,
+    /// Shown straight from the client buffer, without being composited
+    pub zero_copy: bool,
+    /// The flip was observed, rather than assumed to happen at the vblank
+    /// following the submission
+    pub hw_completion: bool,
+}
+
+#[derive(Debug)]
+pub struct OutputDrm {
+    pub connector: Connector,
+    pub crtc_id: u32,
+    pub plane_id: u32,
+    /// `None` if the primary plane lacks atomic properties
+    pub plane_props: Option<PlaneProps>,
+    /// Free cursor plane the CRTC can use
+    pub cursor_plane: Option<(u32, PlaneProps)>,
+    /// Free overlay plane the CRTC can use, with the formats it supports
+    pub overlay_plane: Option<(u32, PlaneProps, Vec<DmabufFormat>)>,
+    /// `VRR_ENABLED` property of the CRTC, if the display is adaptive sync
+    /// capable
+    pub vrr_enabled: Option<u32>,
+    /// `OUT_FENCE_PTR` property of the CRTC, telling when our commits land
+    pub out_fence_ptr: Option<u32>,
+    /// Whether the driver can flip the primary plane without waiting for the
+    /// vblank
+    pub async_flips: bool,
+    pub mode: diretto::Mode,
+}
+
+/// Adaptive sync state of the CRTC
+#[derive(Debug)]
+struct Vrr {
+    policy: AdaptiveSync,
+    prop: u32,
+    wanted: bool,
+    /// `None` until we set it, whoever used the CRTC before might have left
+    /// it enabled
+    committed: Option<bool>,
+}
+
+impl Vrr {
+    fn is_dirty(&self) -> bool {
+        self.committed != Some(self.wanted)
+    }
+
+    fn add_to(&self, crtc_id: u32, request: &mut AtomicRequest) {
+        if self.is_dirty() {
+            request.set(crtc_id, self.prop, self.wanted as u64);
+        }
+    }
+}
+
+/// Tracks the age of swapchain images
+///
+/// wgpu doesn't expose `VK_EXT_buffer_age`-like information, so we identify
+/// images by their raw Vulkan handle and remember when each of them was last
+/// rendered to.
+#[derive(Debug, Default)]
+struct BufferAges {
+    frame: u64,
+    images: HashMap<u64, u64>,
+}
+
+impl BufferAges {
+    fn acquire(&mut self, texture: &wgpu::Texture) -> (Option<u64>, usize) {
+        let image =
+            unsafe { texture.as_hal::<Vulkan>() }.map(|hal| unsafe { hal.raw_handle() }.as_raw());
+
+        let age = image
+            .and_then(|image| self.images.get(&image))
+            .map_or(0, |last| (self.frame + 1 - last) as usize);
+
+        (image, age)
+    }
+
+    fn presented(&mut self, image: Option<u64>) {
+        self.frame += 1;
+
+        if let Some(image) = image {
+            self.images.insert(image, self.frame);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.images.clear();
+    }
+}
+
+/// How far an output got showing the session lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum LockFrame {
+    /// Nothing to wait for
+    #[default]
+    Shown,
+    /// The next frame drawn shows the lock
+    Pending,
+    /// Drawn, waiting for its flip
+    InFlight,
+}
+
+pub struct Output<'s> {
+    id: OutputId,
+    name: String,
+    drm: OutputDrm,
+    surface: wgpu::Surface<'s>,
+    config: wgpu::SurfaceConfiguration,
+    /// Position in the global layout
+    position: (i32, i32),
+    /// Physical pixels per logical pixel, the layout being in logical ones
+    scale: f64,
+    /// Applied to the layout to fit the rotated or mirrored display
+    transform: Transform,
+    /// Damage accumulated since the last frame, in output local coordinates
+    damage: Region,
+    history: DamageRing,
+    ages: BufferAges,
+    instances: Option<wgpu::Buffer>,
+    scheduler: FrameScheduler,
+    /// Mode blob the CRTC had when it was turned off, `None` while it's on
+    off_mode: Option<u64>,
+    scanout: Option<Scanout>,
+    /// `None` when the cursor gets composited
+    cursor_plane: Option<CursorPlane>,
+    overlay: Option<OverlayPlane>,
+    /// `None` if the display can't do adaptive sync
+    vrr: Option<Vrr>,
+    allow_tearing: bool,
+    /// The frame in flight was flipped right away instead of at a vblank
+    tearing: bool,
+    /// The last frame only updated the cursor and overlay planes
+    plane_frame: bool,
+    /// Presentation feedback of the frame in flight, and whether each
+    /// surface is on a plane
+    frame_feedback: Vec<(SurfaceId, Vec<ObjectId>, bool)>,
+    /// Signals once the frame in flight is on screen, `None` if it gets
+    /// flipped by the Vulkan WSI or right away
+    out_fence: Option<OwnedFd>,
+    lock_frame: LockFrame,
+}
+
+impl<'s> Output<'s> {
+    pub fn new(
+        name: String,
+        drm: OutputDrm,
+        surface: wgpu::Surface<'s>,
+        config: wgpu::SurfaceConfiguration,
+        position: (i32, i32),
+        settings: &OutputConfig,
+    ) -> Self {
+        let scheduler = FrameScheduler::new(
+            name.clone(),
+            drm.mode.wsi_refresh_rate(),
+            settings.max_render_time(),
+        );
+        let adaptive_sync = settings.adaptive_sync;
+        let scanout = Scanout::new(&drm);
+
+        if drm.vrr_enabled.is_none() && adaptive_sync != AdaptiveSync::Off {
+            warn!("{name} doesn't support adaptive sync");
+        }
+
+        let vrr = drm.vrr_enabled.map(|prop| Vrr {
+            policy: adaptive_sync,
+            prop,
+            wanted: adaptive_sync == AdaptiveSync::On,
+            committed: None,
+        });
+
+        let mut output = Self {
+            id: drm.connector.connector_id.into(),
+            name,
+            drm,
+            surface,
+            config,
+            position,
+            scale: settings.scale(),
+            transform: settings.transform,
+            damage: Region::new(),
+            history: DamageRing::new(DAMAGE_HISTORY),
+            ages: BufferAges::default(),
+            instances: None,
+            scheduler,
+            off_mode: None,
+            scanout,
+            cursor_plane: None,
+            overlay: None,
+            vrr,
+            allow_tearing: settings.allow_tearing,
+            tearing: false,
+            plane_frame: false,
+            frame_feedback: Vec::new(),
+            out_fence: None,
+            lock_frame: LockFrame::Shown,
+        };
+
+        output.damage_all();
+
+        output
+    }
+
+    pub fn id(&self) -> OutputId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn drm(&self) -> &OutputDrm {
+        &self.drm
+    }
+
+    pub fn scheduler(&mut self) -> &mut FrameScheduler {
+        &mut self.scheduler
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    pub fn configure(&mut self, device: &wgpu::Device) {
+        self.surface.configure(device, &self.config);
+        self.history.reset();
+        self.ages.reset();
+        self.damage_all();
+    }
+
+    /// Position and size in the global layout
+    pub fn geometry(&self) -> Rect {
+        let (width, height) = self.untransformed_size();
+
+        Rect::new(
+            self.position.0,
+            self.position.1,
+            (width as f64 / self.scale).round() as i32,
+            (height as f64 / self.scale).round() as i32,
+        )
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    pub fn info(&self) -> OutputInfo {
+        OutputInfo {
+            id: self.id,
+            name: self.name.clone(),
+            geometry: self.geometry(),
+            mode: self.mode_size(),
+            refresh: self.drm.mode.wsi_refresh_rate(),
+            scale: self.scale,
+            transform: self.transform,
+        }
+    }
+
+    /// Size of the mode in physical pixels
+    pub fn mode_size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    /// Bounds in physical pixels, what everything gets drawn in
+    fn local_bounds(&self) -> Rect {
+        Rect::new(0, 0, self.config.width as i32, self.config.height as i32)
+    }
+
+    /// Size in physical pixels before the transform, the way the layout
+    /// sees it
+    fn untransformed_size(&self) -> (i32, i32) {
+        self.transform
+            .size((self.config.width as i32, self.config.height as i32))
+    }
+
+    /// Maps a rectangle of the layout to physical output pixels
+    fn to_local(&self, rect: Rect) -> Rect {
+        rect.translate(-self.position.0, -self.position.1)
+            .scale(self.scale)
+            .transform(self.transform, self.untransformed_size())
+    }
+
+    /// Adds damage expressed in layout coordinates
+    pub fn add_damage(&mut self, region: &Region) {
+        let local = region
+            .clip(self.geometry())
+            .translate(-self.position.0, -self.position.1)
+            .scale_out(self.scale)
+            .transform(self.transform, self.untransformed_size())
+            .clip(self.local_bounds());
+
+        // Nothing shows through an opaque surface on the overlay plane, its
+        // own updates only need a plane commit
+        let hidden = self
+            .overlay
+            .as_ref()
+            .and_then(OverlayPlane::current)
+            .filter(|content| dmabuf::is_opaque(content.dmabuf.format))
+            .map(|content| content.rect);
+
+        match hidden {
+            Some(hidden) => self.damage.extend(
+                &local
+                    .rects()
+                    .iter()
+                    .filter(|rect| !hidden.contains(rect))
+                    .copied()
+                    .collect(),
+            ),
+            None => self.damage.extend(&local),
+        }
+    }
+
+    pub fn damage_all(&mut self) {
+        self.damage.add(self.local_bounds());
+    }
+
+    pub fn has_damage(&self) -> bool {
+        !self.damage.is_empty()
+    }
+
+    pub fn set_cursor_plane(&mut self, plane: CursorPlane) {
+        self.cursor_plane = Some(plane);
+        self.damage_all();
+    }
+
+    pub fn set_overlay_plane(&mut self, plane: OverlayPlane) {
+        self.overlay = Some(plane);
+    }
+
+    /// Whether buffers of the given format and modifier can skip composition
+    pub fn can_scan_out(&self, format: DmabufFormat) -> bool {
+        self.overlay
+            .as_ref()
+            .is_some_and(|plane| plane.has_format(format))
+    }
+
+    /// Whether the cursor has to be drawn as part of the output contents
+    pub fn composites_cursor(&self) -> bool {
+        self.cursor_plane.is_none()
+    }
+
+    /// Follows a cursor move, `damage` covering its old and new position
+    pub fn move_cursor(&mut self, damage: &Region, cursor: Rect) {
+        let bounds = self.local_bounds();
+        let local = self.to_local(cursor);
+
+        match self.cursor_plane {
+            Some(ref mut plane) => {
+                plane.set_position(local.intersects(&bounds).then_some((local.x, local.y)));
+            }
+            None => self.add_damage(damage),
+        }
+    }
+
+    /// Whether a frame is needed to show new contents or plane state
+    pub fn needs_frame(&self) -> bool {
+        self.has_damage()
+            || self
+                .cursor_plane
+                .as_ref()
+                .is_some_and(CursorPlane::is_dirty)
+            || self.overlay.as_ref().is_some_and(OverlayPlane::is_dirty)
+            || self.vrr.as_ref().is_some_and(Vrr::is_dirty)
+    }
+
+    /// Turns adaptive sync on or off according to the output policy
+    pub fn update_adaptive_sync(&mut self, scene: &Scene) {
+        let bounds = self.geometry();
+
+        let Some(ref mut vrr) = self.vrr else {
+            return;
+        };
+
+        vrr.wanted = match vrr.policy {
+            AdaptiveSync::Off => false,
+            AdaptiveSync::On => true,
+            AdaptiveSync::FullscreenOnly => scene
+                .topmost(bounds)
+                .is_some_and(|(_, rect)| rect == bounds),
+        };
+    }
+
+    /// Picks what the overlay plane should show next
+    pub fn update_planes(&mut self, scene: &Scene) {
+        let candidate = self.overlay_candidate(scene);
+
+        if let Some(ref mut overlay) = self.overlay {
+            overlay.set_pending(candidate);
+        }
+    }
+
+    /// The topmost surface if it's a dmabuf the overlay plane can show
+    ///
+    /// Surfaces covering the whole output are left to direct scanout.
+    fn overlay_candidate(&self, scene: &Scene) -> Option<OverlayContent> {
+        let overlay = self.overlay.as_ref()?;
+        let bounds = self.geometry();
+
+        let (id, rect) = scene.topmost(bounds)?;
+
+        if !bounds.contains(&rect) || rect == bounds {
+            return None;
+        }
+
+        // A composited cursor would end up below it
+        if self.composites_cursor() && scene.cursor_rect().intersects(&rect) {
+            return None;
+        }
+
+        let content = scene.direct_content(id, self.transform)?;
+
+        overlay
+            .supports(id, &content.dmabuf)
+            .then(|| OverlayContent {
+                surface: id,
+                rect: self.to_local(rect),
+                dmabuf: content.dmabuf.clone(),
+            })
+    }
+
+    /// Updates the cursor and overlay planes and adaptive sync without
+    /// touching the primary plane
+    ///
+    /// The primary plane gets flipped by the Vulkan WSI, which can't be
+    /// combined with our own commits. Plane updates get frames of their own,
+    /// taking turns with composited frames while both change. Returns
+    /// `false` if this frame has to be composited instead.
+    pub fn commit_planes(&mut self, device: &Arc<DrmDevice>) -> bool {
+        let cursor_dirty = self
+            .cursor_plane
+            .as_ref()
+            .is_some_and(CursorPlane::is_dirty);
+        let mut overlay_dirty = self.overlay.as_ref().is_some_and(OverlayPlane::is_dirty);
+        let vrr_dirty = self.vrr.as_ref().is_some_and(Vrr::is_dirty);
+
+        if !(cursor_dirty || overlay_dirty || vrr_dirty) || (self.has_damage() && self.plane_frame)
+        {
+            self.plane_frame = false;
+            return false;
+        }
+
+        let (mut request, framebuffer) = match self.test_planes(device) {
+            Ok(prepared) => prepared,
+            Err(e) if overlay_dirty => {
+                // Most likely the overlay content, composite that instead
+                trace!("Overlay plane of {} can't be used: {e}", self.name);
+
+                let Some(ref mut overlay) = self.overlay else {
+                    return false;
+                };
+
+                if let Some(rect) = overlay.reject() {
+                    self.damage.add(rect);
+                }
+                overlay_dirty = overlay.is_dirty();
+
+                if !(cursor_dirty || overlay_dirty || vrr_dirty) {
+                    return false;
+                }
+
+                match self.test_planes(device) {
+                    Ok(prepared) => prepared,
+                    Err(e) => return self.planes_failed(e),
+                }
+            }
+            Err(e) => return self.planes_failed(e),
+        };
+
+        match request.commit_fenced(&**device, self.drm.crtc_id, self.drm.out_fence_ptr) {
+            Ok(fence) => self.out_fence = fence,
+            Err(e) => return self.planes_failed(e),
+        }
+
+        if cursor_dirty && let Some(ref mut plane) = self.cursor_plane {
+            plane.committed();
+        }
+
+        if overlay_dirty
+            && let Some(ref mut overlay) = self.overlay
+            && let Some(uncovered) = overlay.committed(framebuffer)
+        {
+            self.damage.add(uncovered);
+        }
+
+        if vrr_dirty {
+            self.vrr_committed();
+        }
+
+        self.plane_frame = true;
+
+        true
+    }
+
+    /// Builds a commit of everything dirty and checks that it would work
+    fn test_planes(&self, device: &Arc<DrmDevice>) -> Result<(AtomicRequest, Option<Framebuffer>)> {
+        let mut request = AtomicRequest::new();
+
+        if let Some(ref plane) = self.cursor_plane
+            && plane.is_dirty()
+        {
+            plane.add_to(&mut request);
+        }
+
+        let framebuffer = match self.overlay {
+            Some(ref plane) if plane.is_dirty() => plane.prepare(&mut request)?,
+            _ => None,
+        };
+
+        if let Some(ref vrr) = self.vrr {
+            vrr.add_to(self.drm.crtc_id, &mut request);
+        }
+
+        request.test(&**device)?;
+
+        Ok((request, framebuffer))
+    }
+
+    /// Records that a commit with the wanted adaptive sync state went
+    /// through, frames get paced accordingly from now on
+    fn vrr_committed(&mut self) {
+        if let Some(ref mut vrr) = self.vrr {
+            vrr.committed = Some(vrr.wanted);
+            self.scheduler.set_adaptive_sync(vrr.wanted);
+
+            debug!(
+                "Adaptive sync {} on {}",
+                if vrr.wanted { "enabled" } else { "disabled" },
+                self.name
+            );
+        }
+    }
+
+    /// Gives up on the cursor and overlay planes and adaptive sync,
+    /// compositing everything
+    fn planes_failed(&mut self, e: anyhow::Error) -> bool {
+        warn!(
+            "Plane commit on {} failed, compositing everything: {e}",
+            self.name
+        );
+
+        // Dropping them removes their framebuffers, taking them off screen
+        self.cursor_plane = None;
+        self.overlay = None;
+        self.vrr = None;
+        self.scheduler.set_adaptive_sync(false);
+        self.damage_all();
+
+        false
+    }
+
+    /// Shows a client buffer covering the whole output on the primary plane
+    ///
+    /// Returns `false` if the output has to be composited instead.
+    ///
+    /// With `tearing` the buffer is flipped right away if the output allows
+    /// it.
+    pub fn scanout(
+        &mut self,
+        device: &Arc<DrmDevice>,
+        dmabuf: &Arc<Dmabuf>,
+        tearing: bool,
+    ) -> Result<bool> {
+        let Some(ref mut scanout) = self.scanout else {
+            return Ok(false);
+        };
+
+        // We own the commit, the cursor can move along and the overlay has
+        // nothing left to show
+        let mut request = AtomicRequest::new();
+        if let Some(ref plane) = self.cursor_plane
+            && plane.is_dirty()
+        {
+            plane.add_to(&mut request);
+        }
+
+        if let Some(ref mut overlay) = self.overlay
+            && overlay.current().is_some()
+        {
+            overlay.set_pending(None);
+            overlay.prepare(&mut request)?;
+        }
+
+        let vrr_dirty = self.vrr.as_ref().is_some_and(Vrr::is_dirty);
+        if let Some(ref vrr) = self.vrr {
+            vrr.add_to(self.drm.crtc_id, &mut request);
+        }
+
+        if !scanout.present(device, dmabuf, request, tearing && self.allow_tearing)? {
+            return Ok(false);
+        }
+
+        let tearing = scanout.is_tearing();
+        let fence = scanout.take_fence();
+        self.set_tearing(tearing);
+        self.out_fence = fence;
+
+        if let Some(ref mut plane) = self.cursor_plane {
+            plane.committed();
+        }
+
+        if let Some(ref mut overlay) = self.overlay {
+            overlay.committed(None);
+        }
+
+        if vrr_dirty {
+            self.vrr_committed();
+        }
+
+        // Nothing composited is on screen anymore, the swapchain contents
+        // are stale once composition takes over again
+        self.damage = Region::new();
+        self.history.reset();
+        self.ages.reset();
+
+        Ok(true)
+    }
+
+    /// Records whether the frame in flight tears, the next one likely does
+    /// too and goes out as soon as possible
+    pub fn set_tearing(&mut self, tearing: bool) {
+        self.tearing = tearing;
+        self.scheduler.set_tearing(tearing);
+    }
+
+    /// Goes back to compositing, starting with a full repaint
+    pub fn stop_scanout(&mut self) {
+        if let Some(ref mut scanout) = self.scanout
+            && scanout.is_active()
+        {
+            scanout.stop();
+            self.damage_all();
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.off_mode.is_none()
+    }
+
+    /// Turns the CRTC off or back on with the mode it had, DPMS style
+    ///
+    /// Frames aren't scheduled while off, waking up repaints everything.
+    pub fn set_power(&mut self, device: &Arc<DrmDevice>, on: bool) -> Result<()> {
+        if on == self.is_on() {
+            return Ok(());
+        }
+
+        let crtc_id = self.drm.crtc_id;
+        let props = drm::properties(device, crtc_id, DRM_MODE_OBJECT_CRTC)?;
+        let active = props.id(c"ACTIVE").context("CRTC has no ACTIVE property")?;
+        let mode_id = props
+            .id(c"MODE_ID")
+            .context("CRTC has no MODE_ID property")?;
+
+        let mut request = AtomicRequest::new();
+
+        let off_mode = match self.off_mode {
+            Some(mode) => {
+                request.set(crtc_id, mode_id, mode).set(crtc_id, active, 1);
+                None
+            }
+            None => {
+                // Without the current mode there'd be nothing to turn back
+                // on with
+                let mode = props
+                    .value(c"MODE_ID")
+                    .filter(|mode| *mode != 0)
+                    .context("CRTC has no mode to restore")?;
+
+                request.set(crtc_id, active, 0);
+                Some(mode)
+            }
+        };
+
+        request.commit_modeset(&**device)?;
+        self.off_mode = off_mode;
+
+        if on {
+            debug!("Turned {} on", self.name);
+            self.scheduler.resume();
+            self.damage_all();
+        } else {
+            debug!("Turned {} off", self.name);
+            self.scheduler.pause();
+        }
+
+        Ok(())
+    }
+
+    /// Whether the dmabuf is on a plane, directly showing on screen
+    pub fn shows(&self, dmabuf: &Arc<Dmabuf>) -> bool {
+        self.scanout
+            .as_ref()
+            .is_some_and(|scanout| scanout.shows(dmabuf))
+            || self
+                .overlay
+                .as_ref()
+                .is_some_and(|overlay| overlay.shows(dmabuf))
+    }
+
+    /// Presentation feedback to send once the submitted frame is on screen
+    pub fn add_frame_feedback(
+        &mut self,
+        surface: SurfaceId,
+        feedback: Vec<ObjectId>,
+        zero_copy: bool,
+    ) {
+        self.frame_feedback.push((surface, feedback, zero_copy));
+    }
+
+    /// Where the frame in flight landed and whether the flip was observed,
+    /// `None` if it isn't on screen yet
+    ///
+    /// Frames we commit ourselves come with a fence the kernel signals at the
+    /// vblank they flip at, which may be later than the one they were
+    /// submitted for. The Vulkan WSI consumes the flip events of composited
+    /// frames, those are assumed to land at the vblank following their
+    /// submission.
+    pub fn landed(&mut self, vblank: Vblank) -> Option<(Vblank, bool)> {
+        let Some(ref fence) = self.out_fence else {
+            return Some((vblank, false));
+        };
+
+        match drm::sync_file_timestamp(fence) {
+            Ok(None) => None,
+            Ok(Some(time_ns)) => {
+                self.out_fence = None;
+
+                // Fences usually signal along with the vblank event, a late
+                // one has the timestamp of an earlier vblank
+                let behind = (vblank.time_ns - time_ns).max(0);
+                let missed = match self.scheduler.refresh_ns() {
+                    Some(refresh) => (behind + refresh as i64 / 2) / refresh as i64,
+                    None => (behind > LATE_FENCE_SLACK_NS) as i64,
+                };
+
+                Some((
+                    Vblank {
+                        sequence: vblank.sequence.saturating_sub(missed as u64),
+                        time_ns,
+                    },
+                    true,
+                ))
+            }
+            Err(e) => {
+                warn!("Failed to query the out fence of {}: {e}", self.name);
+                self.out_fence = None;
+                Some((vblank, false))
+            }
+        }
+    }
+
+    /// Gives up on a frame that couldn't be submitted
+    pub fn abort(&mut self) {
+        self.out_fence = None;
+        self.scheduler.abort();
+
+        if self.lock_frame == LockFrame::InFlight {
+            self.lock_frame = LockFrame::Pending;
+        }
+    }
+
+    /// Waits for the next frame drawn to be flipped, or stops waiting
+    pub fn await_lock_frame(&mut self, wait: bool) {
+        self.lock_frame = if wait {
+            LockFrame::Pending
+        } else {
+            LockFrame::Shown
+        };
+    }
+
+    /// A frame that changed what's on screen was submitted
+    pub fn frame_drawn(&mut self) {
+        if self.lock_frame == LockFrame::Pending {
+            self.lock_frame = LockFrame::InFlight;
+        }
+    }
+
+    /// Whether the frame asked for by [`Self::await_lock_frame`] is on
+    /// screen, outputs that are off don't show anything to begin with
+    pub fn shows_lock_frame(&self) -> bool {
+        self.lock_frame == LockFrame::Shown || !self.is_on()
+    }
+
+    /// Takes the feedback of the frame shown at `vblank`, `hw_completion`
+    /// if the flip was observed
+    pub fn take_frame_feedback(
+        &mut self,
+        vblank: Vblank,
+        hw_completion: bool,
+    ) -> Vec<(SurfaceId, ObjectId, Presented)> {
+        let refresh_ns = self.scheduler.refresh_ns();
+
+        self.frame_feedback
+            .drain(..)
+            .flat_map(|(surface, feedback, zero_copy)| {
+                let presented = Presented {
+                    output: self.id,
+                    time_ns: vblank.time_ns,
+                    refresh_ns,
+                    sequence: vblank.sequence,
+                    vsync: !self.tearing,
+                    zero_copy,
+                    hw_completion,
+                };
+
+                feedback
+                    .into_iter()
+                    .map(move |feedback| (surface, feedback, presented))
+            })
+            .collect()
+    }
+
+    /// The last submitted frame made it on screen
+    pub fn presented(&mut self) {
+        self.out_fence = None;
+
+        if self.lock_frame == LockFrame::InFlight {
+            self.lock_frame = LockFrame::Shown;
+        }
+
+        if let Some(ref mut scanout) = self.scanout {
+            scanout.presented();
+        }
+
+        if let Some(ref mut overlay) = self.overlay {
+            overlay.presented();
+        }
+    }
+
+    /// Repaints the damaged parts of the output
+    ///
+    /// Does nothing if no damage was accumulated since the last frame, returns
+    /// whether a new frame was presented.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &QuadPipeline,
+        textures: &HashMap<SurfaceId, ClientTexture>,
+        cursor: &ClientTexture,
+        scene: &Scene,
+    ) -> Result<bool> {
+        if !self.has_damage() {
+            return Ok(false);
+        }
+
+        let frame = self
+            .surface
+            .get_current_texture()
+            .context("Failed to acquire next swapchain texture")?;
+
+        let (image, age) = self.ages.acquire(&frame.texture);
+        let bounds = self.local_bounds();
+
+        let damage = std::mem::take(&mut self.damage);
+        let repaint = match self.history.damage_for_age(age) {
+            Some(mut region) => {
+                region.extend(&damage);
+                region.clip(bounds)
+            }
+            None => Region::from_rect(bounds),
+        };
+        self.history.push(damage);
+
+        trace!(
+            "Repainting {} rects on output {} (buffer age {age})",
+            repaint.rects().len(),
+            self.name
+        );
+
+        let background = if scene.is_locked() {
+            LOCKED_BACKGROUND
+        } else {
+            BACKGROUND
+        };
+
+        let mut quads = vec![(
+            bounds,
+            self.quad(bounds, FULL_UV, Transform::Normal, background, 0),
+            pipeline.solid(),
+        )];
+
+        let on_overlay = self.overlay.as_ref().and_then(OverlayPlane::current);
+
+        for (id, rect) in scene.visible() {
+            let local = self.to_local(rect);
+
+            if !local.intersects(&bounds)
+                || on_overlay.is_some_and(|content| content.surface == id && content.rect == local)
+            {
+                continue;
+            }
+
+            if let Some(texture) = textures.get(&id) {
+                quads.push((
+                    local,
+                    self.quad(
+                        local,
+                        scene.uv(id),
+                        scene.buffer_transform(id),
+                        [1.0; 4],
+                        texture.flags(),
+                    ),
+                    texture.bind_group(),
+                ));
+            }
+        }
+
+        let cursor_rect = self.to_local(scene.cursor_rect());
+        if self.composites_cursor() && cursor_rect.intersects(&bounds) {
+            quads.push((
+                cursor_rect,
+                self.quad(
+                    cursor_rect,
+                    FULL_UV,
+                    Transform::Normal,
+                    [1.0; 4],
+                    cursor.flags(),
+                ),
+                cursor.bind_group(),
+            ));
+        }
+
+        let instances = self.instance_buffer(device, quads.len());
+        let data: Vec<Quad> = quads.iter().map(|(_, quad, _)| *quad).collect();
+        queue.write_buffer(&instances, 0, bytemuck::cast_slice(&data));
+
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                ..Default::default()
+            });
+
+            pass.set_pipeline(
+                pipeline
+                    .pipeline(self.config.format)
+                    .context("No pipeline for output format")?,
+            );
+            pass.set_vertex_buffer(0, instances.slice(..));
+
+            for rect in repaint.rects() {
+                pass.set_scissor_rect(
+                    rect.x as u32,
+                    rect.y as u32,
+                    rect.width as u32,
+                    rect.height as u32,
+                );
+
+                for (index, (quad_rect, _, bind_group)) in quads.iter().enumerate() {
+                    if !quad_rect.intersects(rect) {
+                        continue;
+                    }
+
+                    let index = index as u32;
+                    pass.set_bind_group(0, *bind_group, &[]);
+                    pass.draw(0..4, index..index + 1);
+                }
+            }
+        }
+
+        queue.submit([encoder.finish()]);
+        frame.present();
+
+        self.ages.presented(image);
+
+        Ok(true)
+    }
+
+    fn quad(
+        &self,
+        rect: Rect,
+        uv: [f32; 4],
+        buffer_transform: Transform,
+        color: [f32; 4],
+        flags: u32,
+    ) -> Quad {
+        let width = self.config.width as f32;
+        let height = self.config.height as f32;
+
+        Quad {
+            rect: [
+                rect.x as f32 / width * 2.0 - 1.0,
+                1.0 - rect.y as f32 / height * 2.0,
+                rect.width as f32 / width * 2.0,
+                -(rect.height as f32) / height * 2.0,
+            ],
+            uv,
+            color,
+            flags,
+            surface_transform: self.transform.invert() as u32,
+            buffer_transform: buffer_transform as u32,
+            _padding: 0,
+        }
+    }
+
+    fn instance_buffer(&mut self, device: &wgpu::Device, count: usize) -> wgpu::Buffer {
+        let size = (count.next_power_of_two() * size_of::<Quad>()) as wgpu::BufferAddress;
+
+        if self
+            .instances
+            .as_ref()
+            .is_some_and(|buffer| buffer.size() < size)
+        {
+            self.instances = None;
+        }
+
+        self.instances
+            .get_or_insert_with(|| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("quad instances"),
+                    size,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .clone()
+    }
+}
@@ -0,0 +1,214 @@
+//! Overlay planes
+//!
+//! The topmost surface of an output can be shown on an overlay plane when
+//! it's backed by a dmabuf the plane can scan out, like video in a player.
+//! Its buffer updates then only take an atomic commit, the rest of the output
+//! doesn't have to be composited again.
+
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use diretto::Device as DrmDevice;
+
+use crate::protocol::wayland::surface::SurfaceId;
+
+use super::{
+    damage::Rect,
+    dmabuf::{Dmabuf, DmabufFormat},
+    drm::{AtomicRequest, PlaneProps},
+    scanout::Framebuffer,
+};
+
+/// What an overlay plane shows
+#[derive(Clone)]
+pub struct OverlayContent {
+    pub surface: SurfaceId,
+    /// Where on the output, in output local coordinates
+    pub rect: Rect,
+    pub dmabuf: Arc<Dmabuf>,
+}
+
+impl OverlayContent {
+    fn same(&self, other: &OverlayContent) -> bool {
+        self.surface == other.surface
+            && self.rect == other.rect
+            && Arc::ptr_eq(&self.dmabuf, &other.dmabuf)
+    }
+}
+
+struct Shown {
+    content: OverlayContent,
+    framebuffer: Framebuffer,
+}
+
+pub struct OverlayPlane {
+    device: Arc<DrmDevice>,
+    plane_id: u32,
+    crtc_id: u32,
+    props: PlaneProps,
+    formats: HashSet<DmabufFormat>,
+    /// What the plane should show after the next commit
+    pending: Option<OverlayContent>,
+    current: Option<Shown>,
+    /// Replaced by the last commit, stays on screen until the next vblank
+    retired: Option<Framebuffer>,
+    /// Last buffer layout the display engine refused, not tried again
+    rejected: Option<(SurfaceId, DmabufFormat)>,
+}
+
+impl OverlayPlane {
+    pub fn new(
+        device: &Arc<DrmDevice>,
+        plane_id: u32,
+        crtc_id: u32,
+        props: PlaneProps,
+        formats: &[DmabufFormat],
+    ) -> Self {
+        Self {
+            device: device.clone(),
+            plane_id,
+            crtc_id,
+            props,
+            formats: formats.iter().copied().collect(),
+            pending: None,
+            current: None,
+            retired: None,
+            rejected: None,
+        }
+    }
+
+    /// Whether the plane might be able to show the surface buffer
+    pub fn supports(&self, surface: SurfaceId, dmabuf: &Dmabuf) -> bool {
+        let format = DmabufFormat {
+            format: dmabuf.format,
+            modifier: dmabuf.modifier,
+        };
+
+        self.formats.contains(&format) && self.rejected != Some((surface, format))
+    }
+
+    /// Whether the plane takes buffers of the given format and modifier
+    pub fn has_format(&self, format: DmabufFormat) -> bool {
+        self.formats.contains(&format)
+    }
+
+    pub fn set_pending(&mut self, content: Option<OverlayContent>) {
+        self.pending = content;
+    }
+
+    /// Whether the plane doesn't show the pending content yet
+    pub fn is_dirty(&self) -> bool {
+        match (&self.pending, &self.current) {
+            (None, None) => false,
+            (Some(pending), Some(current)) => !pending.same(&current.content),
+            _ => true,
+        }
+    }
+
+    /// Surface currently on the plane
+    pub fn current(&self) -> Option<&OverlayContent> {
+        self.current.as_ref().map(|shown| &shown.content)
+    }
+
+    /// Whether the dmabuf is on screen or about to be
+    pub fn shows(&self, dmabuf: &Arc<Dmabuf>) -> bool {
+        self.current
+            .as_ref()
+            .map(|shown| &shown.framebuffer)
+            .into_iter()
+            .chain(&self.retired)
+            .any(|framebuffer| Arc::ptr_eq(framebuffer.dmabuf(), dmabuf))
+    }
+
+    /// Adds the pending state to a commit
+    ///
+    /// Returns the framebuffer of a newly shown buffer, to be handed to
+    /// [`Self::committed`] once the commit went through.
+    pub fn prepare(&self, request: &mut AtomicRequest) -> Result<Option<Framebuffer>> {
+        let Some(ref content) = self.pending else {
+            request.set(self.plane_id, self.props.fb_id, 0).set(
+                self.plane_id,
+                self.props.crtc_id,
+                0,
+            );
+            return Ok(None);
+        };
+
+        let (framebuffer, fb_id) = match self.current {
+            Some(ref shown) if Arc::ptr_eq(&shown.content.dmabuf, &content.dmabuf) => {
+                (None, shown.framebuffer.id())
+            }
+            _ => {
+                let framebuffer = Framebuffer::new(self.device.clone(), content.dmabuf.clone())?;
+                let fb_id = framebuffer.id();
+                (Some(framebuffer), fb_id)
+            }
+        };
+
+        let (width, height) = (content.dmabuf.width as u64, content.dmabuf.height as u64);
+        let rect = content.rect;
+
+        request
+            .set(self.plane_id, self.props.fb_id, fb_id as u64)
+            .set(self.plane_id, self.props.crtc_id, self.crtc_id as u64)
+            .set(self.plane_id, self.props.src_x, 0)
+            .set(self.plane_id, self.props.src_y, 0)
+            .set(self.plane_id, self.props.src_w, width << 16)
+            .set(self.plane_id, self.props.src_h, height << 16)
+            .set(self.plane_id, self.props.crtc_x, rect.x as i64 as u64)
+            .set(self.plane_id, self.props.crtc_y, rect.y as i64 as u64)
+            .set(self.plane_id, self.props.crtc_w, rect.width as u64)
+            .set(self.plane_id, self.props.crtc_h, rect.height as u64);
+
+        Ok(framebuffer)
+    }
+
+    /// Records that a commit with the pending state went through
+    ///
+    /// Returns where the previous content was if it moved or went away,
+    /// which then has to be composited again.
+    pub fn committed(&mut self, framebuffer: Option<Framebuffer>) -> Option<Rect> {
+        let previous = self.current().map(|content| content.rect);
+
+        match (self.pending.clone(), framebuffer) {
+            (Some(content), Some(framebuffer)) => {
+                let replaced = self.current.replace(Shown {
+                    content,
+                    framebuffer,
+                });
+                self.retired = replaced.map(|shown| shown.framebuffer);
+            }
+            (Some(content), None) => {
+                if let Some(ref mut shown) = self.current {
+                    shown.content = content;
+                }
+            }
+            (None, _) => self.retired = self.current.take().map(|shown| shown.framebuffer),
+        }
+
+        previous.filter(|rect| self.current().is_none_or(|content| content.rect != *rect))
+    }
+
+    /// The display engine can't show the pending content, it gets
+    /// composited instead
+    ///
+    /// Returns where the content is, which then has to be composited.
+    pub fn reject(&mut self) -> Option<Rect> {
+        let content = self.pending.take()?;
+
+        self.rejected = Some((
+            content.surface,
+            DmabufFormat {
+                format: content.dmabuf.format,
+                modifier: content.dmabuf.modifier,
+            },
+        ));
+
+        Some(content.rect)
+    }
+
+    /// The last commit made it on screen
+    pub fn presented(&mut self) {
+        self.retired = None;
+    }
+}
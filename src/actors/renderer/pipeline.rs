@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+
+/// Forces the alpha channel of the sampled texel to 1, used by formats with
+/// an unused alpha channel
+pub const FLAG_OPAQUE: u32 = 1 << 0;
+
+/// Swaps the red and blue channels of the sampled texel, for formats that
+/// only exist in the other channel order on the GPU side
+pub const FLAG_SWAP_RB: u32 = 1 << 1;
+
+/// A single textured rectangle, drawn as an instanced triangle strip
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct Quad {
+    /// Destination origin and size in normalized device coordinates
+    pub rect: [f32; 4],
+    /// Source origin and size in texture coordinates
+    pub uv: [f32; 4],
+    /// Premultiplied color the texel gets multiplied with
+    pub color: [f32; 4],
+    pub flags: u32,
+    /// `wl_output.transform` from the destination to the source rectangle,
+    /// undoing the output transform
+    pub surface_transform: u32,
+    /// `wl_output.transform` from the source rectangle to the texture, the
+    /// buffer transform of the surface
+    pub buffer_transform: u32,
+    pub _padding: u32,
+}
+
+impl Quad {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        0 => Float32x4,
+        1 => Float32x4,
+        2 => Float32x4,
+        3 => Uint32,
+        4 => Uint32,
+        5 => Uint32,
+    ];
+
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Quad>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+pub struct QuadPipeline {
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// 1x1 white texture used to draw solid colored quads
+    solid: wgpu::BindGroup,
+}
+
+impl QuadPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        formats: impl IntoIterator<Item = wgpu::TextureFormat>,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/quad.wgsl"));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("quad"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("quad"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipelines = formats
+            .into_iter()
+            .map(|format| {
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("quad"),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: Some("vs_main"),
+                        compilation_options: Default::default(),
+                        buffers: &[Quad::layout()],
+                    },
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleStrip,
+                        ..Default::default()
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: Some("fs_main"),
+                        compilation_options: Default::default(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview_mask: None,
+                    cache: None,
+                });
+
+                (format, pipeline)
+            })
+            .collect();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("quad"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let white = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("solid"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            white.as_image_copy(),
+            &[0xff; 4],
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: None,
+            },
+            white.size(),
+        );
+
+        let solid = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &white.create_view(&wgpu::TextureViewDescriptor::default()),
+        );
+
+        Self {
+            pipelines,
+            bind_group_layout,
+            sampler,
+            solid,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn bind_texture(&self, device: &wgpu::Device, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        Self::create_bind_group(device, &self.bind_group_layout, &self.sampler, view)
+    }
+
+    pub fn pipeline(&self, format: wgpu::TextureFormat) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&format)
+    }
+
+    pub fn solid(&self) -> &wgpu::BindGroup {
+        &self.solid
+    }
+}
@@ -0,0 +1,282 @@
+//! Direct scanout of fullscreen client buffers
+//!
+//! When a single dmabuf surface covers a whole output, its buffer can go on
+//! the primary plane as is, skipping composition. A test-only commit checks
+//! whether the display engine can show it, otherwise the output just gets
+//! composited like usual.
+
+use std::{os::fd::OwnedFd, sync::Arc};
+
+use anyhow::Result;
+use diretto::Device as DrmDevice;
+use tracing::{trace, warn};
+
+use super::{
+    dmabuf::Dmabuf,
+    drm::{self, AtomicRequest, FramebufferPlane, PlaneProps},
+    output::OutputDrm,
+};
+
+/// A client dmabuf registered as a DRM framebuffer
+pub struct Framebuffer {
+    device: Arc<DrmDevice>,
+    id: u32,
+    dmabuf: Arc<Dmabuf>,
+}
+
+impl Framebuffer {
+    pub fn new(device: Arc<DrmDevice>, dmabuf: Arc<Dmabuf>) -> Result<Self> {
+        let mut planes = Vec::with_capacity(dmabuf.planes.len());
+
+        for plane in &dmabuf.planes {
+            match drm::prime_fd_to_handle(&*device, &plane.fd) {
+                Ok(handle) => planes.push(FramebufferPlane {
+                    handle,
+                    pitch: plane.stride,
+                    offset: plane.offset,
+                }),
+                Err(e) => {
+                    close_handles(&device, &planes);
+                    return Err(e);
+                }
+            }
+        }
+
+        let id = drm::add_framebuffer(
+            &*device,
+            dmabuf.width,
+            dmabuf.height,
+            dmabuf.format,
+            Some(dmabuf.modifier),
+            &planes,
+        );
+
+        // The framebuffer holds its own reference to the buffer
+        close_handles(&device, &planes);
+
+        Ok(Self {
+            device,
+            id: id?,
+            dmabuf,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn dmabuf(&self) -> &Arc<Dmabuf> {
+        &self.dmabuf
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        if let Err(e) = drm::remove_framebuffer(&*self.device, self.id) {
+            warn!("Failed to remove framebuffer {}: {e}", self.id);
+        }
+    }
+}
+
+/// Planes of the same buffer share a handle, which must only be closed once
+fn close_handles(device: &DrmDevice, planes: &[FramebufferPlane]) {
+    let mut handles: Vec<u32> = planes.iter().map(|plane| plane.handle).collect();
+    handles.sort_unstable();
+    handles.dedup();
+
+    for handle in handles {
+        if let Err(e) = drm::gem_close(device, handle) {
+            warn!("Failed to close GEM handle {handle}: {e}");
+        }
+    }
+}
+
+/// Client buffers shown on the primary plane of an output
+pub struct Scanout {
+    plane_id: u32,
+    crtc_id: u32,
+    props: PlaneProps,
+    /// On the plane, or about to be at the next vblank
+    current: Option<Framebuffer>,
+    /// Replaced by the last commit, stays on screen until the next vblank
+    retired: Option<Framebuffer>,
+    /// Whether the driver can flip without waiting for the vblank
+    async_flips: bool,
+    /// The last buffer was flipped right away, tearing
+    tearing: bool,
+    /// `OUT_FENCE_PTR` property of the CRTC
+    out_fence_ptr: Option<u32>,
+    /// Signals once the last commit is on screen
+    fence: Option<OwnedFd>,
+}
+
+impl Scanout {
+    /// `None` if the primary plane can't be driven through atomic commits
+    pub fn new(drm: &OutputDrm) -> Option<Self> {
+        Some(Self {
+            plane_id: drm.plane_id,
+            crtc_id: drm.crtc_id,
+            props: drm.plane_props?,
+            current: None,
+            retired: None,
+            async_flips: drm.async_flips,
+            tearing: false,
+            out_fence_ptr: drm.out_fence_ptr,
+            fence: None,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn is_tearing(&self) -> bool {
+        self.tearing
+    }
+
+    /// Fence of the last commit, `None` if it was flipped right away or the
+    /// CRTC can't provide one
+    pub fn take_fence(&mut self) -> Option<OwnedFd> {
+        self.fence.take()
+    }
+
+    /// Whether the dmabuf is on screen or about to be
+    pub fn shows(&self, dmabuf: &Arc<Dmabuf>) -> bool {
+        [&self.current, &self.retired]
+            .into_iter()
+            .flatten()
+            .any(|framebuffer| Arc::ptr_eq(framebuffer.dmabuf(), dmabuf))
+    }
+
+    /// Puts the dmabuf on the primary plane, covering the whole CRTC, along
+    /// with whatever else `request` changes
+    ///
+    /// With `tearing` the buffer gets flipped right away if possible.
+    /// Returns `false` if the display engine can't show it.
+    pub fn present(
+        &mut self,
+        device: &Arc<DrmDevice>,
+        dmabuf: &Arc<Dmabuf>,
+        request: AtomicRequest,
+        tearing: bool,
+    ) -> Result<bool> {
+        let framebuffer = if self
+            .current
+            .as_ref()
+            .is_some_and(|framebuffer| Arc::ptr_eq(framebuffer.dmabuf(), dmabuf))
+        {
+            None
+        } else {
+            Some(Framebuffer::new(device.clone(), dmabuf.clone())?)
+        };
+
+        self.tearing = false;
+        self.fence = None;
+
+        // Asynchronous flips can only swap the buffer, nothing else may change
+        if tearing
+            && request.is_empty()
+            && let Some(framebuffer) = framebuffer
+        {
+            if !self.flip_async(device, &framebuffer) {
+                return self.flip(device, dmabuf, Some(framebuffer), request);
+            }
+
+            self.retired = self.current.replace(framebuffer);
+            self.tearing = true;
+
+            return Ok(true);
+        }
+
+        self.flip(device, dmabuf, framebuffer, request)
+    }
+
+    /// Swaps in a buffer of the same size as the current one right away
+    fn flip_async(&self, device: &Arc<DrmDevice>, framebuffer: &Framebuffer) -> bool {
+        let same_size = self.current.as_ref().is_some_and(|current| {
+            let (current, new) = (current.dmabuf(), framebuffer.dmabuf());
+            (current.width, current.height) == (new.width, new.height)
+        });
+
+        if !(self.async_flips && same_size) {
+            return false;
+        }
+
+        let mut request = AtomicRequest::new();
+        request.set(self.plane_id, self.props.fb_id, framebuffer.id() as u64);
+
+        match request
+            .test_async(&**device)
+            .and_then(|()| request.commit_async(&**device))
+        {
+            Ok(()) => true,
+            Err(e) => {
+                trace!(
+                    "Async flip on plane {} failed, waiting for vblank: {e}",
+                    self.plane_id
+                );
+                false
+            }
+        }
+    }
+
+    /// Shows the buffer from the next vblank on
+    fn flip(
+        &mut self,
+        device: &Arc<DrmDevice>,
+        dmabuf: &Arc<Dmabuf>,
+        framebuffer: Option<Framebuffer>,
+        mut request: AtomicRequest,
+    ) -> Result<bool> {
+        if let Some(ref framebuffer) = framebuffer {
+            let (width, height) = (dmabuf.width as u64, dmabuf.height as u64);
+
+            request
+                .set(self.plane_id, self.props.fb_id, framebuffer.id() as u64)
+                .set(self.plane_id, self.props.crtc_id, self.crtc_id as u64)
+                .set(self.plane_id, self.props.src_x, 0)
+                .set(self.plane_id, self.props.src_y, 0)
+                // Source coordinates are 16.16 fixed point
+                .set(self.plane_id, self.props.src_w, width << 16)
+                .set(self.plane_id, self.props.src_h, height << 16)
+                .set(self.plane_id, self.props.crtc_x, 0)
+                .set(self.plane_id, self.props.crtc_y, 0)
+                .set(self.plane_id, self.props.crtc_w, width)
+                .set(self.plane_id, self.props.crtc_h, height);
+        }
+
+        // Same buffer as before and nothing else to change
+        if request.is_empty() {
+            return Ok(true);
+        }
+
+        if let Err(e) = request.test(&**device) {
+            trace!(
+                "Plane {} can't scan out dmabuf of format {:#x} with modifier {:#x}: {e}",
+                self.plane_id, dmabuf.format, dmabuf.modifier
+            );
+            return Ok(false);
+        }
+
+        self.fence = request.commit_fenced(&**device, self.crtc_id, self.out_fence_ptr)?;
+
+        if let Some(framebuffer) = framebuffer {
+            self.retired = self.current.replace(framebuffer);
+        }
+
+        Ok(true)
+    }
+
+    /// Hands the plane back to composition, the last buffer stays on screen
+    /// until the next composited frame replaces it
+    pub fn stop(&mut self) {
+        if let Some(framebuffer) = self.current.take() {
+            self.retired = Some(framebuffer);
+        }
+    }
+
+    /// The last commit made it on screen
+    pub fn presented(&mut self) {
+        self.retired = None;
+    }
+}
@@ -0,0 +1,1085 @@
+use std::{collections::HashMap, sync::Arc};
+
+use waynest::ObjectId;
+
+use crate::{
+    Transform,
+    protocol::wayland::{shm::Format, surface::SurfaceId},
+};
+
+use super::{
+    cursor::CursorImage,
+    damage::{Area, Rect, Region},
+    dmabuf::{Dmabuf, DmabufContent},
+    output::{OutputId, OutputInfo},
+};
+
+/// Pixels copied out of a client shm buffer
+#[derive(Debug)]
+pub struct ShmContent {
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub format: Format,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum SurfaceContent {
+    Shm(ShmContent),
+    Dmabuf(DmabufContent),
+}
+
+impl SurfaceContent {
+    pub fn size(&self) -> (i32, i32) {
+        match self {
+            Self::Shm(shm) => (shm.width as i32, shm.height as i32),
+            Self::Dmabuf(content) => (content.dmabuf.width as i32, content.dmabuf.height as i32),
+        }
+    }
+
+    /// The `wl_buffer` that has to stay untouched while this is on screen
+    fn held_buffer(&mut self) -> Option<&mut DmabufContent> {
+        match self {
+            Self::Shm(_) => None,
+            Self::Dmabuf(content) => Some(content),
+        }
+    }
+
+    fn into_held_buffer(self) -> Option<DmabufContent> {
+        match self {
+            Self::Shm(_) => None,
+            Self::Dmabuf(content) => Some(content),
+        }
+    }
+}
+
+/// State applied by a `wl_surface.commit`
+#[derive(Debug)]
+pub struct SurfaceUpdate {
+    pub surface: SurfaceId,
+    /// New buffer contents, `None` if no buffer was attached since the last commit
+    pub content: Option<SurfaceContent>,
+    /// Set when a null buffer was attached
+    pub unmapped: bool,
+    /// Damage in surface local coordinates
+    pub damage: Region,
+    /// `wl_surface.frame` callbacks to fire once the update has been shown
+    pub frame_callbacks: Vec<ObjectId>,
+    /// `wp_presentation_feedback` objects waiting on this update
+    pub presentation_feedback: Vec<ObjectId>,
+    /// The client prefers showing updates right away over avoiding tearing
+    pub tearing: bool,
+    /// The client holds back further updates until the next refresh cycle
+    pub fifo_barrier: bool,
+    /// New stacking of the subsurfaces, `None` if unchanged
+    pub subsurfaces: Option<SubsurfaceStack>,
+    /// New cropping and scaling, `None` if unchanged
+    pub viewport: Option<Viewport>,
+    /// New `wl_surface.set_buffer_scale`, `None` if unchanged
+    pub buffer_scale: Option<i32>,
+    /// New `wl_surface.set_buffer_transform`, `None` if unchanged
+    pub buffer_transform: Option<Transform>,
+    /// New `wl_surface.set_input_region`, `Some(None)` for the whole surface
+    pub input_region: Option<Option<Area>>,
+}
+
+/// What a surface should be rendered for, taken from the output it's mostly
+/// on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preferred {
+    pub output: OutputId,
+    pub scale: f64,
+    pub transform: Transform,
+}
+
+/// Cropping and scaling of the buffer of a surface, set through
+/// `wp_viewport`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Viewport {
+    /// Part of the buffer to show as `x, y, width, height` in buffer
+    /// coordinates, all of it if `None`
+    pub source: Option<[f64; 4]>,
+    /// Size the source gets scaled to, the size of the source if `None`
+    pub destination: Option<(i32, i32)>,
+}
+
+impl Viewport {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Surface size of a buffer of the given size, after dividing by the
+    /// buffer scale
+    pub fn surface_size(&self, buffer: (i32, i32)) -> (i32, i32) {
+        match (self.destination, self.source) {
+            (Some(size), _) => size,
+            (None, Some([_, _, width, height])) => (width as i32, height as i32),
+            (None, None) => buffer,
+        }
+    }
+
+    /// Source rectangle as origin and size in texture coordinates, `buffer`
+    /// being the buffer size divided by the buffer scale
+    pub fn uv(&self, buffer: (i32, i32)) -> [f32; 4] {
+        match self.source {
+            Some([x, y, width, height]) if buffer.0 > 0 && buffer.1 > 0 => {
+                let (buffer_width, buffer_height) = (buffer.0 as f64, buffer.1 as f64);
+
+                [
+                    (x / buffer_width) as f32,
+                    (y / buffer_height) as f32,
+                    (width / buffer_width) as f32,
+                    (height / buffer_height) as f32,
+                ]
+            }
+            _ => [0.0, 0.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// Bands of the stack, surfaces on a higher layer are always above those on
+/// a lower one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Layer {
+    Background,
+    Bottom,
+    /// Where toplevels live
+    #[default]
+    Normal,
+    Top,
+    Overlay,
+    /// Session lock surfaces, the only ones shown while locked
+    Lock,
+}
+
+/// Subsurfaces around their parent, with their offset from it
+#[derive(Debug, Clone, Default)]
+pub struct SubsurfaceStack {
+    /// Bottom to top
+    pub below: Vec<(SurfaceId, i32, i32)>,
+    /// Bottom to top
+    pub above: Vec<(SurfaceId, i32, i32)>,
+}
+
+#[derive(Debug, Default)]
+struct SceneSurface {
+    content: Option<SurfaceContent>,
+    /// Bumped every time new content is committed, so textures can tell when
+    /// they're stale
+    serial: u64,
+    size: (i32, i32),
+    position: Option<(i32, i32)>,
+    layer: Layer,
+    frame_callbacks: Vec<ObjectId>,
+    /// Feedback for the latest update, until a frame shows it
+    presentation_feedback: Vec<ObjectId>,
+    tearing: bool,
+    /// Cleared along with the frame callbacks
+    fifo_barrier: bool,
+    /// Set for subsurfaces, which follow their parent around
+    parent: Option<SurfaceId>,
+    offset: (i32, i32),
+    /// Subsurfaces stacked below and above, bottom to top
+    below: Vec<SurfaceId>,
+    above: Vec<SurfaceId>,
+    viewport: Viewport,
+    /// Buffer pixels per surface pixel, 0 until first committed
+    buffer_scale: i32,
+    /// How the client rotated or mirrored its buffer
+    buffer_transform: Transform,
+    /// Where pointer input lands in surface coordinates, `None` for everywhere
+    input_region: Option<Area>,
+    /// Last told to the client
+    preferred: Option<Preferred>,
+}
+
+impl SceneSurface {
+    fn accepts_input(&self, x: i32, y: i32) -> bool {
+        self.input_region
+            .as_ref()
+            .is_none_or(|area| area.contains_point(x, y))
+    }
+
+    fn rect(&self) -> Option<Rect> {
+        self.position
+            .map(|(x, y)| Rect::new(x, y, self.size.0, self.size.1))
+    }
+
+    /// Size of the buffer in surface coordinates
+    fn buffer_size(&self) -> Option<(i32, i32)> {
+        let scale = self.buffer_scale.max(1);
+
+        self.content.as_ref().map(|content| {
+            let (width, height) = self.buffer_transform.invert().size(content.size());
+            (width / scale, height / scale)
+        })
+    }
+}
+
+/// Everything the renderer needs to know about what's on screen, in layout
+/// coordinates
+///
+/// Every mutation returns the region of the layout that has to be repainted.
+#[derive(Debug)]
+pub struct Scene {
+    surfaces: HashMap<SurfaceId, SceneSurface>,
+    /// Placed surfaces from bottom to top
+    stack: Vec<SurfaceId>,
+    /// Hides everything but the lock layer
+    locked: bool,
+    cursor_image: CursorImage,
+    cursor_position: (f64, f64),
+    /// Client buffers no longer shown, to be released once in flight frames
+    /// stop sampling them
+    released: Vec<(SurfaceId, DmabufContent)>,
+    /// Presentation feedback of updates that were replaced before being shown
+    discarded: Vec<(SurfaceId, ObjectId)>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            surfaces: HashMap::new(),
+            stack: Vec::new(),
+            locked: false,
+            cursor_image: CursorImage::default_arrow(),
+            cursor_position: (0.0, 0.0),
+            released: Vec::new(),
+            discarded: Vec::new(),
+        }
+    }
+
+    pub fn commit(&mut self, update: SurfaceUpdate) -> Region {
+        let surface = self.surfaces.entry(update.surface).or_default();
+        let mut damage = Region::new();
+
+        let old_rect = surface.rect();
+        surface.frame_callbacks.extend(update.frame_callbacks);
+        surface.tearing = update.tearing;
+        surface.fifo_barrier |= update.fifo_barrier;
+
+        // The previous update never made it on screen
+        let replaced = std::mem::replace(
+            &mut surface.presentation_feedback,
+            update.presentation_feedback,
+        );
+        self.discarded.extend(
+            replaced
+                .into_iter()
+                .map(|feedback| (update.surface, feedback)),
+        );
+
+        let previous = if update.unmapped {
+            surface.content.take()
+        } else if let Some(content) = update.content {
+            surface.serial += 1;
+            surface.content.replace(content)
+        } else {
+            None
+        };
+
+        let viewport_changed = update
+            .viewport
+            .is_some_and(|viewport| viewport != surface.viewport);
+
+        if let Some(viewport) = update.viewport {
+            surface.viewport = viewport;
+        }
+
+        if let Some(scale) = update.buffer_scale {
+            surface.buffer_scale = scale;
+        }
+
+        if let Some(transform) = update.buffer_transform {
+            surface.buffer_transform = transform;
+        }
+
+        if let Some(input_region) = update.input_region {
+            surface.input_region = input_region;
+        }
+
+        surface.size = surface
+            .buffer_size()
+            .map_or((0, 0), |buffer| surface.viewport.surface_size(buffer));
+
+        // Committing the same buffer again keeps it in use
+        if let Some(previous) = previous.and_then(SurfaceContent::into_held_buffer) {
+            match surface
+                .content
+                .as_mut()
+                .and_then(SurfaceContent::held_buffer)
+            {
+                Some(current) if Arc::ptr_eq(&current.dmabuf, &previous.dmabuf) => {
+                    // Earlier release points get signalled along with this one
+                    current.release.extend(previous.release);
+                }
+                _ => self.released.push((update.surface, previous)),
+            }
+        }
+
+        match (old_rect, surface.rect()) {
+            (Some(old), Some(new)) if old != new => {
+                damage.add(old);
+                damage.add(new);
+            }
+            (Some(old), None) => damage.add(old),
+            // Buffer damage can't be mapped through the viewport precisely
+            (_, Some(rect)) if viewport_changed || !surface.viewport.is_identity() => {
+                damage.add(rect);
+            }
+            (_, Some(rect)) => {
+                damage.extend(
+                    &update
+                        .damage
+                        .clip(Rect::new(0, 0, rect.width, rect.height))
+                        .translate(rect.x, rect.y),
+                );
+            }
+            (None, None) => {}
+        }
+
+        if let Some(stack) = update.subsurfaces {
+            self.restack(update.surface, stack, &mut damage);
+        }
+
+        // Subsurfaces show up once their parent has content
+        self.reposition_children(update.surface, &mut damage);
+
+        damage
+    }
+
+    /// Applies the subsurface stacking a parent committed
+    fn restack(&mut self, id: SurfaceId, stack: SubsurfaceStack, damage: &mut Region) {
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+
+        surface.below = stack.below.iter().map(|(child, ..)| *child).collect();
+        surface.above = stack.above.iter().map(|(child, ..)| *child).collect();
+
+        for &(child, x, y) in stack.below.iter().chain(&stack.above) {
+            let child = self.surfaces.entry(child).or_default();
+
+            // The child may have moved relative to its siblings
+            if let Some(rect) = child.rect() {
+                damage.add(rect);
+            }
+
+            child.parent = Some(id);
+            child.offset = (x, y);
+        }
+    }
+
+    /// Moves the subsurfaces of `id` along with it, hiding them while it
+    /// isn't shown
+    fn reposition_children(&mut self, id: SurfaceId, damage: &mut Region) {
+        let Some(surface) = self.surfaces.get(&id) else {
+            return;
+        };
+
+        let origin = surface
+            .rect()
+            .filter(|rect| !rect.is_empty())
+            .map(|rect| (rect.x, rect.y));
+        let children: Vec<_> = surface
+            .below
+            .iter()
+            .chain(&surface.above)
+            .copied()
+            .collect();
+
+        for child_id in children {
+            let Some(child) = self.surfaces.get_mut(&child_id) else {
+                continue;
+            };
+
+            let position = origin.map(|(x, y)| (x + child.offset.0, y + child.offset.1));
+
+            if child.position != position {
+                if let Some(old) = child.rect() {
+                    damage.add(old);
+                }
+
+                child.position = position;
+
+                if let Some(new) = child.rect() {
+                    damage.add(new);
+                }
+            }
+
+            self.reposition_children(child_id, damage);
+        }
+    }
+
+    /// Moves a surface, putting it on top of its layer if it wasn't placed
+    /// there yet
+    pub fn place(&mut self, id: SurfaceId, x: i32, y: i32, layer: Layer) -> Region {
+        let surface = self.surfaces.entry(id).or_default();
+        let mut damage = Region::new();
+
+        if let Some(old) = surface.rect() {
+            damage.add(old);
+        }
+
+        surface.position = Some((x, y));
+        let moved_layer = surface.layer != layer;
+        surface.layer = layer;
+
+        if let Some(new) = surface.rect() {
+            damage.add(new);
+        }
+
+        if moved_layer || !self.stack.contains(&id) {
+            self.stack.retain(|surface| *surface != id);
+
+            let index = self
+                .stack
+                .iter()
+                .rposition(|other| {
+                    self.surfaces
+                        .get(other)
+                        .is_some_and(|other| other.layer <= layer)
+                })
+                .map_or(0, |index| index + 1);
+            self.stack.insert(index, id);
+        }
+
+        self.reposition_children(id, &mut damage);
+
+        damage
+    }
+
+    pub fn unmap(&mut self, id: SurfaceId) -> Region {
+        self.stack.retain(|surface| *surface != id);
+
+        let mut damage = Region::new();
+
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return damage;
+        };
+
+        if let Some(rect) = surface.rect() {
+            damage.add(rect);
+        }
+
+        surface.position = None;
+
+        // Subsurfaces are unmapped along with their `wl_subsurface`
+        if let Some(parent) = surface.parent.take()
+            && let Some(parent) = self.surfaces.get_mut(&parent)
+        {
+            parent.below.retain(|child| *child != id);
+            parent.above.retain(|child| *child != id);
+        }
+
+        self.reposition_children(id, &mut damage);
+
+        damage
+    }
+
+    pub fn remove(&mut self, id: SurfaceId) -> Region {
+        let damage = self.unmap(id);
+
+        let Some(surface) = self.surfaces.remove(&id) else {
+            return damage;
+        };
+
+        for child in surface.below.iter().chain(&surface.above) {
+            if let Some(child) = self.surfaces.get_mut(child) {
+                child.parent = None;
+            }
+        }
+
+        if let Some(content) = surface.content.and_then(SurfaceContent::into_held_buffer) {
+            self.released.push((id, content));
+        }
+
+        self.discarded.extend(
+            surface
+                .presentation_feedback
+                .into_iter()
+                .map(|feedback| (id, feedback)),
+        );
+
+        damage
+    }
+
+    /// Buffers that can be given back to their clients
+    ///
+    /// Buffers for which `in_use` returns true are kept for a later call.
+    pub fn take_released(
+        &mut self,
+        in_use: impl Fn(&Arc<Dmabuf>) -> bool,
+    ) -> Vec<(SurfaceId, DmabufContent)> {
+        let (kept, released): (Vec<_>, Vec<_>) = std::mem::take(&mut self.released)
+            .into_iter()
+            .partition(|(_, content)| in_use(&content.dmabuf));
+
+        self.released = kept;
+
+        released
+    }
+
+    /// Presentation feedback of updates that will never be shown
+    pub fn take_discarded(&mut self) -> Vec<(SurfaceId, ObjectId)> {
+        std::mem::take(&mut self.discarded)
+    }
+
+    /// Takes the presentation feedback of the latest update of a surface,
+    /// once it's part of a frame
+    pub fn take_presentation_feedback(&mut self, id: SurfaceId) -> Vec<ObjectId> {
+        self.surfaces
+            .get_mut(&id)
+            .map(|surface| std::mem::take(&mut surface.presentation_feedback))
+            .unwrap_or_default()
+    }
+
+    pub fn remove_client(&mut self, client_id: u32) -> Region {
+        let ids: Vec<_> = self
+            .surfaces
+            .keys()
+            .filter(|id| id.client_id == client_id)
+            .copied()
+            .collect();
+
+        let mut damage = Region::new();
+
+        for id in ids {
+            damage.extend(&self.remove(id));
+        }
+
+        // Nobody left to tell
+        self.discarded.retain(|(id, _)| id.client_id != client_id);
+
+        damage
+    }
+
+    pub fn move_cursor(&mut self, x: f64, y: f64) -> Region {
+        let mut damage = Region::from_rect(self.cursor_rect());
+        self.cursor_position = (x, y);
+        damage.add(self.cursor_rect());
+        damage
+    }
+
+    pub fn cursor_image(&self) -> &CursorImage {
+        &self.cursor_image
+    }
+
+    pub fn cursor_rect(&self) -> Rect {
+        Rect::new(
+            self.cursor_position.0.floor() as i32 - self.cursor_image.hotspot_x,
+            self.cursor_position.1.floor() as i32 - self.cursor_image.hotspot_y,
+            self.cursor_image.width as i32,
+            self.cursor_image.height as i32,
+        )
+    }
+
+    pub fn content(&self, id: SurfaceId) -> Option<(&SurfaceContent, u64)> {
+        self.surfaces.get(&id).and_then(|surface| {
+            surface
+                .content
+                .as_ref()
+                .map(|content| (content, surface.serial))
+        })
+    }
+
+    /// Dmabuf contents shown without `wp_viewport` cropping or scaling,
+    /// which planes of an output with the given transform can show directly
+    ///
+    /// The buffer has to be transformed like the output already.
+    pub fn direct_content(&self, id: SurfaceId, transform: Transform) -> Option<&DmabufContent> {
+        let surface = self.surfaces.get(&id)?;
+
+        match surface.content {
+            Some(SurfaceContent::Dmabuf(ref content))
+                if surface.viewport.is_identity() && surface.buffer_transform == transform =>
+            {
+                Some(content)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn buffer_transform(&self, id: SurfaceId) -> Transform {
+        self.surfaces
+            .get(&id)
+            .map_or(Transform::Normal, |surface| surface.buffer_transform)
+    }
+
+    /// Part of the buffer of a surface to sample, in texture coordinates
+    pub fn uv(&self, id: SurfaceId) -> [f32; 4] {
+        self.surfaces
+            .get(&id)
+            .and_then(|surface| {
+                surface
+                    .buffer_size()
+                    .map(|buffer| surface.viewport.uv(buffer))
+            })
+            .unwrap_or([0.0, 0.0, 1.0, 1.0])
+    }
+
+    /// Picks the output each surface overlaps the most, returning the
+    /// surfaces for which it or its scale and transform changed
+    ///
+    /// Surfaces that aren't on screen get those of the first output, which
+    /// new windows get mapped on.
+    pub fn update_preferred(&mut self, outputs: &[OutputInfo]) -> Vec<(SurfaceId, Preferred)> {
+        let Some(first) = outputs.first() else {
+            return Vec::new();
+        };
+
+        let mut changed = Vec::new();
+
+        for (&id, surface) in &mut self.surfaces {
+            let output = surface
+                .rect()
+                .and_then(|rect| {
+                    outputs
+                        .iter()
+                        .filter_map(|output| {
+                            let overlap = rect.intersection(&output.geometry)?;
+                            Some((overlap.width as i64 * overlap.height as i64, output))
+                        })
+                        .max_by_key(|(area, _)| *area)
+                })
+                .map_or(first, |(_, output)| output);
+
+            let preferred = Preferred {
+                output: output.id,
+                scale: output.scale,
+                transform: output.transform,
+            };
+
+            if surface.preferred != Some(preferred) {
+                surface.preferred = Some(preferred);
+                changed.push((id, preferred));
+            }
+        }
+
+        changed
+    }
+
+    /// Whether the surface asked for its updates to be shown right away
+    pub fn wants_tearing(&self, id: SurfaceId) -> bool {
+        self.surfaces
+            .get(&id)
+            .is_some_and(|surface| surface.tearing)
+    }
+
+    /// Surfaces that get frame callbacks or have their FIFO barrier cleared
+    /// when `bounds` gets repainted
+    ///
+    /// Surfaces that aren't on screen anywhere are paced by whichever output
+    /// asks first.
+    fn paced_by(surface: &SceneSurface, bounds: Rect) -> bool {
+        (!surface.frame_callbacks.is_empty() || surface.fifo_barrier)
+            && surface
+                .rect()
+                .filter(|rect| !rect.is_empty())
+                .is_none_or(|rect| rect.intersects(&bounds))
+    }
+
+    pub fn has_frame_callbacks(&self, bounds: Rect) -> bool {
+        self.surfaces
+            .values()
+            .any(|surface| Self::paced_by(surface, bounds))
+    }
+
+    /// Takes the frame callbacks of the surfaces paced by `bounds`, clearing
+    /// their FIFO barriers
+    ///
+    /// Surfaces only waiting on their barrier come with no callbacks.
+    pub fn take_frame_callbacks(&mut self, bounds: Rect) -> Vec<(SurfaceId, Vec<ObjectId>)> {
+        self.surfaces
+            .iter_mut()
+            .filter(|(_, surface)| Self::paced_by(surface, bounds))
+            .map(|(id, surface)| {
+                surface.fifo_barrier = false;
+                (*id, std::mem::take(&mut surface.frame_callbacks))
+            })
+            .collect()
+    }
+
+    /// Clears the FIFO barriers none of the `active` output bounds paces
+    ///
+    /// Nothing presents these surfaces while their outputs are off or gone,
+    /// their clients would otherwise block until the outputs come back.
+    pub fn take_unpaced_barriers(&mut self, active: &[Rect]) -> Vec<SurfaceId> {
+        self.surfaces
+            .iter_mut()
+            .filter(|(_, surface)| {
+                surface.fifo_barrier
+                    && !active.iter().any(|bounds| Self::paced_by(surface, *bounds))
+            })
+            .map(|(id, surface)| {
+                surface.fifo_barrier = false;
+                *id
+            })
+            .collect()
+    }
+
+    /// The surface on top of everything else within `bounds`
+    pub fn topmost(&self, bounds: Rect) -> Option<(SurfaceId, Rect)> {
+        self.visible()
+            .filter(|(_, rect)| rect.intersects(&bounds))
+            .last()
+    }
+
+    /// The topmost surface at the given layout position other than
+    /// `exclude`, with the position in surface local coordinates
+    pub fn surface_at(
+        &self,
+        x: f64,
+        y: f64,
+        exclude: Option<SurfaceId>,
+    ) -> Option<(SurfaceId, f64, f64)> {
+        let (px, py) = (x.floor() as i32, y.floor() as i32);
+
+        self.visible()
+            .filter(|(id, rect)| {
+                Some(*id) != exclude
+                    && rect.contains_point(px, py)
+                    && self
+                        .surfaces
+                        .get(id)
+                        .is_some_and(|surface| surface.accepts_input(px - rect.x, py - rect.y))
+            })
+            .last()
+            .map(|(id, rect)| (id, x - rect.x as f64, y - rect.y as f64))
+    }
+
+    /// The placed surface whose tree holds the topmost surface at the given
+    /// layout position
+    pub fn placed_at(&self, x: f64, y: f64) -> Option<SurfaceId> {
+        let (mut id, ..) = self.surface_at(x, y, None)?;
+
+        while let Some(parent) = self.surfaces.get(&id).and_then(|surface| surface.parent) {
+            id = parent;
+        }
+
+        Some(id)
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether any of the given surfaces is shown
+    pub fn any_visible(&self, surfaces: &[SurfaceId]) -> bool {
+        self.visible().any(|(id, _)| surfaces.contains(&id))
+    }
+
+    /// Placed surfaces with their layout rectangle, from bottom to top
+    ///
+    /// Only lock surfaces are visible while the session is locked.
+    pub fn visible(&self) -> impl Iterator<Item = (SurfaceId, Rect)> + '_ {
+        let mut order = Vec::new();
+
+        for id in &self.stack {
+            let hidden = self.locked
+                && self
+                    .surfaces
+                    .get(id)
+                    .is_none_or(|surface| surface.layer != Layer::Lock);
+
+            if !hidden {
+                self.push_subtree(*id, &mut order);
+            }
+        }
+
+        order.into_iter().filter_map(|id| {
+            self.surfaces
+                .get(&id)
+                .and_then(SceneSurface::rect)
+                .filter(|rect| !rect.is_empty())
+                .map(|rect| (id, rect))
+        })
+    }
+
+    /// Adds a surface and its subsurfaces to `order`, from bottom to top
+    fn push_subtree(&self, id: SurfaceId, order: &mut Vec<SurfaceId>) {
+        let Some(surface) = self.surfaces.get(&id) else {
+            return;
+        };
+
+        for child in &surface.below {
+            self.push_subtree(*child, order);
+        }
+
+        order.push(id);
+
+        for child in &surface.above {
+            self.push_subtree(*child, order);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(object: u32) -> SurfaceId {
+        SurfaceId::new(1, unsafe { ObjectId::from_raw(object) })
+    }
+
+    fn update(surface: SurfaceId) -> SurfaceUpdate {
+        SurfaceUpdate {
+            surface,
+            content: None,
+            unmapped: false,
+            damage: Region::new(),
+            frame_callbacks: Vec::new(),
+            presentation_feedback: Vec::new(),
+            tearing: false,
+            fifo_barrier: false,
+            subsurfaces: None,
+            viewport: None,
+            buffer_scale: None,
+            buffer_transform: None,
+            input_region: None,
+        }
+    }
+
+    /// Commits an shm buffer of the given size
+    fn attach(scene: &mut Scene, surface: SurfaceId, width: u32, height: u32) -> Region {
+        scene.commit(SurfaceUpdate {
+            content: Some(SurfaceContent::Shm(ShmContent {
+                width,
+                height,
+                stride: width * 4,
+                format: Format::Argb8888,
+                pixels: Vec::new(),
+            })),
+            ..update(surface)
+        })
+    }
+
+    fn stack(scene: &mut Scene, parent: SurfaceId, stack: SubsurfaceStack) -> Region {
+        scene.commit(SurfaceUpdate {
+            subsurfaces: Some(stack),
+            ..update(parent)
+        })
+    }
+
+    fn visible(scene: &Scene) -> Vec<SurfaceId> {
+        scene.visible().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn subsurfaces_follow_place_above_and_place_below() {
+        let mut scene = Scene::new();
+        let (parent, first, second) = (id(1), id(2), id(3));
+
+        attach(&mut scene, parent, 100, 100);
+        attach(&mut scene, first, 20, 20);
+        attach(&mut scene, second, 20, 20);
+        scene.place(parent, 0, 0, Layer::Normal);
+
+        stack(
+            &mut scene,
+            parent,
+            SubsurfaceStack {
+                below: vec![(first, 0, 0)],
+                above: vec![(second, 10, 10)],
+            },
+        );
+
+        assert_eq!(visible(&scene), [first, parent, second]);
+
+        // `first` placed above `second`
+        stack(
+            &mut scene,
+            parent,
+            SubsurfaceStack {
+                below: Vec::new(),
+                above: vec![(second, 10, 10), (first, 0, 0)],
+            },
+        );
+
+        assert_eq!(visible(&scene), [parent, second, first]);
+
+        // `second` placed below the parent
+        stack(
+            &mut scene,
+            parent,
+            SubsurfaceStack {
+                below: vec![(second, 10, 10)],
+                above: vec![(first, 0, 0)],
+            },
+        );
+
+        assert_eq!(visible(&scene), [second, parent, first]);
+    }
+
+    #[test]
+    fn placed_surfaces_stack_by_layer() {
+        let mut scene = Scene::new();
+        let (window, panel, wallpaper) = (id(1), id(2), id(3));
+
+        for surface in [window, panel, wallpaper] {
+            attach(&mut scene, surface, 10, 10);
+        }
+
+        scene.place(window, 0, 0, Layer::Normal);
+        scene.place(panel, 0, 0, Layer::Top);
+        scene.place(wallpaper, 0, 0, Layer::Background);
+
+        assert_eq!(visible(&scene), [wallpaper, window, panel]);
+    }
+
+    #[test]
+    fn removing_a_parent_takes_its_subtree_along() {
+        let mut scene = Scene::new();
+        let (parent, child, grandchild) = (id(1), id(2), id(3));
+
+        attach(&mut scene, parent, 100, 100);
+        attach(&mut scene, child, 20, 20);
+        attach(&mut scene, grandchild, 10, 10);
+        scene.place(parent, 0, 0, Layer::Normal);
+
+        stack(
+            &mut scene,
+            child,
+            SubsurfaceStack {
+                below: Vec::new(),
+                above: vec![(grandchild, 0, 30)],
+            },
+        );
+        stack(
+            &mut scene,
+            parent,
+            SubsurfaceStack {
+                below: Vec::new(),
+                above: vec![(child, 150, 0)],
+            },
+        );
+
+        assert_eq!(visible(&scene), [parent, child, grandchild]);
+
+        let damage = scene.remove(parent);
+
+        assert_eq!(visible(&scene), []);
+        assert_eq!(
+            damage.rects(),
+            &[
+                Rect::new(0, 0, 100, 100),
+                Rect::new(150, 0, 20, 20),
+                Rect::new(150, 30, 10, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn moving_damages_the_old_and_new_position() {
+        let mut scene = Scene::new();
+        let (parent, child) = (id(1), id(2));
+
+        attach(&mut scene, parent, 100, 100);
+        attach(&mut scene, child, 20, 20);
+        scene.place(parent, 0, 0, Layer::Normal);
+        stack(
+            &mut scene,
+            parent,
+            SubsurfaceStack {
+                below: Vec::new(),
+                above: vec![(child, 150, 0)],
+            },
+        );
+
+        let damage = scene.place(parent, 0, 200, Layer::Normal);
+
+        assert_eq!(
+            damage.rects(),
+            &[
+                Rect::new(0, 0, 100, 100),
+                Rect::new(0, 200, 100, 100),
+                Rect::new(150, 0, 20, 20),
+                Rect::new(150, 200, 20, 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn unmapping_damages_what_was_shown() {
+        let mut scene = Scene::new();
+        let (window, other) = (id(1), id(2));
+
+        attach(&mut scene, window, 100, 100);
+        scene.place(window, 10, 10, Layer::Normal);
+
+        assert_eq!(scene.unmap(window).rects(), &[Rect::new(10, 10, 100, 100)]);
+        assert!(scene.unmap(window).is_empty());
+        assert_eq!(visible(&scene), []);
+
+        // Attaching a null buffer unmaps too
+        attach(&mut scene, other, 50, 50);
+        scene.place(other, 0, 0, Layer::Normal);
+
+        let damage = scene.commit(SurfaceUpdate {
+            unmapped: true,
+            ..update(other)
+        });
+
+        assert_eq!(damage.rects(), &[Rect::new(0, 0, 50, 50)]);
+        assert_eq!(visible(&scene), []);
+    }
+
+    #[test]
+    fn input_region_decides_what_gets_hit() {
+        let mut scene = Scene::new();
+        let (window, panel) = (id(1), id(2));
+
+        attach(&mut scene, window, 100, 100);
+        attach(&mut scene, panel, 100, 100);
+        scene.place(window, 0, 0, Layer::Normal);
+        scene.place(panel, 0, 0, Layer::Top);
+
+        assert_eq!(
+            scene.surface_at(50.0, 50.0, None),
+            Some((panel, 50.0, 50.0))
+        );
+
+        // Only the top strip of the panel takes input
+        let mut area = Area::new();
+        area.add(Rect::new(0, 0, 100, 20));
+        scene.commit(SurfaceUpdate {
+            input_region: Some(Some(area)),
+            ..update(panel)
+        });
+
+        assert_eq!(
+            scene.surface_at(50.0, 10.5, None),
+            Some((panel, 50.0, 10.5))
+        );
+        assert_eq!(
+            scene.surface_at(50.0, 50.0, None),
+            Some((window, 50.0, 50.0))
+        );
+
+        // An empty region lets everything through
+        scene.commit(SurfaceUpdate {
+            input_region: Some(Some(Area::new())),
+            ..update(panel)
+        });
+
+        assert_eq!(scene.placed_at(50.0, 10.5), Some(window));
+
+        scene.commit(SurfaceUpdate {
+            input_region: Some(None),
+            ..update(panel)
+        });
+
+        assert_eq!(scene.placed_at(50.0, 50.0), Some(panel));
+    }
+}
@@ -0,0 +1,432 @@
+//! Per output frame pacing
+//!
+//! Outputs are only repainted when something changed, at most once per
+//! vblank. With a max render time configured the repaint is delayed until just
+//! before the next vblank, so the frame picks up the latest client commits.
+//! With adaptive sync the display waits for frames instead, so they go out as
+//! soon as clients commit something new. The same goes for frames flipped
+//! without waiting for the vblank, tearing.
+
+use std::time::Duration;
+
+use rustix::time::{ClockId, clock_gettime};
+use tracing::{debug, trace};
+
+use super::drm::Vblank;
+
+/// How often frame timing stats get logged
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Used when the mode doesn't report a sensible refresh rate
+const FALLBACK_REFRESH_NS: i64 = 16_666_667;
+
+/// Current `CLOCK_MONOTONIC` time, the clock DRM timestamps vblanks with
+pub fn monotonic_ns() -> i64 {
+    let now = clock_gettime(ClockId::Monotonic);
+    now.tv_sec * 1_000_000_000 + now.tv_nsec
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Nothing new to show
+    Idle,
+    /// A repaint is queued for the deadline
+    Scheduled,
+    /// A frame was submitted, waiting for the vblank it lands on
+    InFlight,
+}
+
+/// When to repaint an output
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    pub time_ns: i64,
+    /// Identifies the request, timers for superseded requests are ignored
+    pub generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct FrameStats {
+    since_ns: i64,
+    frames: u32,
+    missed: u32,
+    render_total_ns: i64,
+    render_max_ns: i64,
+}
+
+impl FrameStats {
+    fn report(&mut self, output: &str, now_ns: i64) {
+        let elapsed = now_ns - self.since_ns;
+
+        if elapsed < STATS_INTERVAL.as_nanos() as i64 {
+            return;
+        }
+
+        if self.frames > 0 {
+            let average = self.render_total_ns / self.frames as i64;
+
+            debug!(
+                "Output {output}: {} frames in {:.1}s, render time avg {:.2}ms max {:.2}ms, {} missed deadlines",
+                self.frames,
+                elapsed as f64 / 1e9,
+                average as f64 / 1e6,
+                self.render_max_ns as f64 / 1e6,
+                self.missed,
+            );
+        }
+
+        *self = Self {
+            since_ns: now_ns,
+            ..Default::default()
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameScheduler {
+    output: String,
+    refresh_ns: i64,
+    max_render_time_ns: Option<i64>,
+    adaptive_sync: bool,
+    tearing: bool,
+    /// The output is powered off, nothing gets scheduled
+    paused: bool,
+    state: State,
+    generation: u64,
+    /// Vblank the scheduled frame is meant to be shown at
+    target_ns: i64,
+    stats: FrameStats,
+}
+
+impl FrameScheduler {
+    /// `refresh` is the mode refresh rate in mHz
+    pub fn new(output: String, refresh: u32, max_render_time: Option<Duration>) -> Self {
+        let refresh_ns = if refresh > 0 {
+            1_000_000_000_000 / refresh as i64
+        } else {
+            FALLBACK_REFRESH_NS
+        };
+
+        Self {
+            output,
+            refresh_ns,
+            max_render_time_ns: max_render_time.map(|time| time.as_nanos() as i64),
+            adaptive_sync: false,
+            tearing: false,
+            paused: false,
+            state: State::Idle,
+            generation: 0,
+            target_ns: 0,
+            stats: FrameStats {
+                since_ns: monotonic_ns(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Whether the CRTC refreshes when frames arrive rather than at a fixed
+    /// rate
+    pub fn set_adaptive_sync(&mut self, enabled: bool) {
+        self.adaptive_sync = enabled;
+    }
+
+    /// Whether frames get flipped right away rather than at the vblank
+    pub fn set_tearing(&mut self, tearing: bool) {
+        self.tearing = tearing;
+    }
+
+    /// Time between vblanks, `None` with adaptive sync
+    pub fn refresh_ns(&self) -> Option<u32> {
+        (!self.adaptive_sync).then_some(self.refresh_ns as u32)
+    }
+
+    /// Stops scheduling frames while the output is off, dropping the one
+    /// scheduled if any
+    ///
+    /// A frame in flight still completes with the vblank event the kernel
+    /// sends when turning the CRTC off. FIFO barriers of the surfaces on a
+    /// paused output are released by the renderer instead.
+    pub fn pause(&mut self) {
+        self.paused = true;
+
+        if self.state == State::Scheduled {
+            self.state = State::Idle;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Whether a submitted frame waits for its vblank
+    pub fn is_in_flight(&self) -> bool {
+        self.state == State::InFlight
+    }
+
+    /// Requests a repaint, `last` being the most recent vblank of the output
+    ///
+    /// Returns `None` if a frame is already scheduled or in flight, pending
+    /// changes get picked up once it's done, or if the output is off.
+    pub fn schedule(&mut self, last: Vblank, now_ns: i64) -> Option<Deadline> {
+        if self.paused || self.state != State::Idle {
+            return None;
+        }
+
+        let max_render_time = self.max_render_time_ns.unwrap_or(0);
+
+        // First vblank we can still make it to in time, a deadline right
+        // now still counts
+        let mut target = last.time_ns + self.refresh_ns;
+        if target - max_render_time < now_ns {
+            let behind = now_ns - (target - max_render_time);
+            target += (behind + self.refresh_ns - 1) / self.refresh_ns * self.refresh_ns;
+        }
+
+        // Without a max render time there's no point in waiting, and with
+        // adaptive sync or tearing flips there's no vblank to wait for
+        let (target, time_ns) = match self.max_render_time_ns {
+            _ if self.adaptive_sync || self.tearing => (now_ns + self.refresh_ns, now_ns),
+            Some(max_render_time) => (target, target - max_render_time),
+            None => (target, now_ns),
+        };
+
+        self.state = State::Scheduled;
+        self.generation = self.generation.wrapping_add(1);
+        self.target_ns = target;
+
+        trace!(
+            "Scheduled repaint of {} in {:.2}ms",
+            self.output,
+            (time_ns - now_ns) as f64 / 1e6
+        );
+
+        Some(Deadline {
+            time_ns,
+            generation: self.generation,
+        })
+    }
+
+    /// Whether a render timer is still current
+    pub fn should_render(&self, generation: u64) -> bool {
+        self.state == State::Scheduled && self.generation == generation
+    }
+
+    /// Records a frame submitted between `start_ns` and `end_ns`
+    ///
+    /// `rendered` is false when there was nothing to repaint and the frame
+    /// only exists to pace frame callbacks.
+    pub fn submitted(&mut self, rendered: bool, start_ns: i64, end_ns: i64) {
+        self.state = State::InFlight;
+
+        if !rendered {
+            return;
+        }
+
+        let render_time = end_ns - start_ns;
+
+        self.stats.frames += 1;
+        self.stats.render_total_ns += render_time;
+        self.stats.render_max_ns = self.stats.render_max_ns.max(render_time);
+
+        if end_ns > self.target_ns {
+            self.stats.missed += 1;
+            trace!(
+                "Output {} missed its deadline by {:.2}ms",
+                self.output,
+                (end_ns - self.target_ns) as f64 / 1e6
+            );
+        }
+    }
+
+    /// Gives up on a frame that couldn't be submitted
+    pub fn abort(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Handles the vblank following a submitted frame
+    ///
+    /// Returns whether a frame was in flight, meaning clients waiting on frame
+    /// callbacks can draw again.
+    pub fn vblank(&mut self, vblank: Vblank, now_ns: i64) -> bool {
+        trace!(
+            "Vblank {} on {}, {:.2}ms ago",
+            vblank.sequence,
+            self.output,
+            (now_ns - vblank.time_ns) as f64 / 1e6
+        );
+
+        self.stats.report(&self.output, now_ns);
+
+        let presented = self.state == State::InFlight;
+        if presented {
+            self.state = State::Idle;
+        }
+
+        presented
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 50Hz, a 20ms refresh period
+    const REFRESH: u32 = 50_000;
+    const MS: i64 = 1_000_000;
+
+    fn vblank(time_ns: i64) -> Vblank {
+        Vblank {
+            sequence: 0,
+            time_ns,
+        }
+    }
+
+    #[test]
+    fn renders_right_away_without_max_render_time() {
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, None);
+
+        let deadline = scheduler.schedule(vblank(0), 5 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 5 * MS);
+        assert!(scheduler.should_render(deadline.generation));
+    }
+
+    #[test]
+    fn waits_until_max_render_time_before_the_vblank() {
+        let max_render_time = Duration::from_millis(4);
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+
+        let deadline = scheduler.schedule(vblank(0), 5 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 16 * MS);
+    }
+
+    #[test]
+    fn skips_vblanks_it_can_no_longer_make() {
+        let max_render_time = Duration::from_millis(4);
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+
+        let deadline = scheduler.schedule(vblank(0), 18 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 36 * MS);
+
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+
+        let deadline = scheduler.schedule(vblank(0), 57 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 76 * MS);
+    }
+
+    #[test]
+    fn makes_a_vblank_whose_deadline_is_now() {
+        let max_render_time = Duration::from_millis(4);
+
+        // Exactly one period behind the first deadline
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+        let deadline = scheduler.schedule(vblank(0), 36 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 36 * MS);
+
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+        let deadline = scheduler.schedule(vblank(0), 16 * MS).unwrap();
+
+        assert_eq!(deadline.time_ns, 16 * MS);
+
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+        let deadline = scheduler.schedule(vblank(0), 36 * MS + 1).unwrap();
+
+        assert_eq!(deadline.time_ns, 56 * MS);
+    }
+
+    #[test]
+    fn adaptive_sync_and_tearing_render_right_away() {
+        let max_render_time = Duration::from_millis(4);
+
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+        scheduler.set_adaptive_sync(true);
+
+        assert_eq!(
+            scheduler.schedule(vblank(0), 5 * MS).unwrap().time_ns,
+            5 * MS
+        );
+        assert_eq!(scheduler.refresh_ns(), None);
+
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, Some(max_render_time));
+        scheduler.set_tearing(true);
+
+        assert_eq!(
+            scheduler.schedule(vblank(0), 5 * MS).unwrap().time_ns,
+            5 * MS
+        );
+        assert_eq!(scheduler.refresh_ns(), Some(20 * MS as u32));
+    }
+
+    #[test]
+    fn one_frame_at_a_time() {
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, None);
+
+        let first = scheduler.schedule(vblank(0), 5 * MS).unwrap();
+        assert!(scheduler.schedule(vblank(0), 6 * MS).is_none());
+
+        scheduler.submitted(true, 6 * MS, 8 * MS);
+        assert!(scheduler.schedule(vblank(0), 9 * MS).is_none());
+
+        assert!(scheduler.vblank(vblank(20 * MS), 21 * MS));
+        assert!(scheduler.is_idle());
+
+        let second = scheduler.schedule(vblank(20 * MS), 22 * MS).unwrap();
+        assert_ne!(first.generation, second.generation);
+        assert!(!scheduler.should_render(first.generation));
+        assert!(scheduler.should_render(second.generation));
+    }
+
+    #[test]
+    fn vblank_without_a_frame_in_flight() {
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, None);
+
+        assert!(!scheduler.vblank(vblank(20 * MS), 21 * MS));
+
+        scheduler.schedule(vblank(20 * MS), 22 * MS).unwrap();
+        assert!(!scheduler.vblank(vblank(40 * MS), 41 * MS));
+        assert!(!scheduler.is_idle());
+    }
+
+    #[test]
+    fn abort_allows_rescheduling() {
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, None);
+
+        scheduler.schedule(vblank(0), 5 * MS).unwrap();
+        scheduler.abort();
+
+        assert!(scheduler.schedule(vblank(0), 6 * MS).is_some());
+    }
+
+    #[test]
+    fn nothing_gets_scheduled_while_paused() {
+        let mut scheduler = FrameScheduler::new("test".into(), REFRESH, None);
+
+        let deadline = scheduler.schedule(vblank(0), 5 * MS).unwrap();
+        scheduler.pause();
+
+        assert!(!scheduler.should_render(deadline.generation));
+        assert!(scheduler.schedule(vblank(0), 6 * MS).is_none());
+
+        scheduler.resume();
+
+        assert!(scheduler.schedule(vblank(0), 7 * MS).is_some());
+    }
+
+    #[test]
+    fn falls_back_without_a_refresh_rate() {
+        let scheduler = FrameScheduler::new("test".into(), 0, None);
+
+        assert_eq!(scheduler.refresh_ns(), Some(FALLBACK_REFRESH_NS as u32));
+    }
+}
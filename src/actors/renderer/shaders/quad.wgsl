@@ -0,0 +1,73 @@
+const FLAG_OPAQUE: u32 = 1u;
+const FLAG_SWAP_RB: u32 = 2u;
+
+// wl_output.transform bits
+const TRANSFORM_TURNS: u32 = 3u;
+const TRANSFORM_FLIPPED: u32 = 4u;
+
+struct Instance {
+    // Destination rectangle in normalized device coordinates (origin, size)
+    @location(0) rect: vec4<f32>,
+    // Source rectangle in texture coordinates (origin, size)
+    @location(1) uv: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) flags: u32,
+    // Transform from the destination to the source rectangle
+    @location(4) surface_transform: u32,
+    // Transform from the source rectangle to the texture
+    @location(5) buffer_transform: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) flags: u32,
+}
+
+@group(0) @binding(0) var t_texture: texture_2d<f32>;
+@group(0) @binding(1) var s_texture: sampler;
+
+// Moves a point of the unit square along with the square getting flipped
+// around the vertical axis, then turned counter-clockwise
+fn transform(t: u32, point: vec2<f32>) -> vec2<f32> {
+    var p = point;
+
+    if (t & TRANSFORM_FLIPPED) != 0u {
+        p.x = 1.0 - p.x;
+    }
+
+    for (var i = 0u; i < (t & TRANSFORM_TURNS); i++) {
+        p = vec2<f32>(p.y, 1.0 - p.x);
+    }
+
+    return p;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: Instance) -> VertexOutput {
+    let corner = vec2<f32>(f32(index & 1u), f32((index >> 1u) & 1u));
+    let source = transform(instance.surface_transform, corner);
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(instance.rect.xy + corner * instance.rect.zw, 0.0, 1.0);
+    out.uv = transform(instance.buffer_transform, instance.uv.xy + source * instance.uv.zw);
+    out.color = instance.color;
+    out.flags = instance.flags;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(t_texture, s_texture, in.uv);
+
+    if (in.flags & FLAG_SWAP_RB) != 0u {
+        color = color.bgra;
+    }
+
+    if (in.flags & FLAG_OPAQUE) != 0u {
+        color.a = 1.0;
+    }
+
+    return color * in.color;
+}
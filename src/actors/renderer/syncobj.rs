@@ -0,0 +1,127 @@
+//! Explicit synchronization through DRM syncobj timelines
+//!
+//! Clients pass a timeline point to wait on before their buffer can be read,
+//! and one to signal once the compositor is done with it, instead of relying
+//! on fences implicitly attached to the dmabuf.
+
+use std::{fmt, sync::Arc};
+
+use anyhow::Result;
+use diretto::Device as DrmDevice;
+use rustix::{
+    event::{EventfdFlags, eventfd},
+    fd::AsFd,
+};
+use tokio::io::{Interest, unix::AsyncFd};
+use tracing::warn;
+
+use super::drm;
+
+/// Imports client timelines on the device the renderer runs on
+#[derive(Clone)]
+pub struct SyncobjDevice {
+    device: Arc<DrmDevice>,
+}
+
+impl fmt::Debug for SyncobjDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncobjDevice").finish_non_exhaustive()
+    }
+}
+
+impl SyncobjDevice {
+    /// `None` if the driver lacks timeline support
+    pub(super) fn new(device: &Arc<DrmDevice>) -> Option<Self> {
+        drm::supports_syncobj_timelines(&**device).then(|| Self {
+            device: device.clone(),
+        })
+    }
+
+    pub fn import_timeline(&self, fd: impl AsFd) -> Result<Timeline> {
+        let handle = drm::syncobj_fd_to_handle(&*self.device, fd)?;
+
+        Ok(Timeline {
+            device: self.device.clone(),
+            handle,
+        })
+    }
+}
+
+/// A client syncobj timeline
+pub struct Timeline {
+    device: Arc<DrmDevice>,
+    handle: u32,
+}
+
+impl fmt::Debug for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeline")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl Drop for Timeline {
+    fn drop(&mut self) {
+        if let Err(e) = drm::syncobj_destroy(&*self.device, self.handle) {
+            warn!("Failed to destroy syncobj {}: {e}", self.handle);
+        }
+    }
+}
+
+/// A point on a client timeline
+#[derive(Debug, Clone)]
+pub struct SyncPoint {
+    pub timeline: Arc<Timeline>,
+    pub point: u64,
+}
+
+impl SyncPoint {
+    /// Waits until the point is signalled, without blocking the executor
+    pub async fn wait(&self) -> Result<()> {
+        let fd = eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?;
+
+        drm::syncobj_eventfd(
+            &*self.timeline.device,
+            self.timeline.handle,
+            self.point,
+            &fd,
+        )?;
+
+        let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+        let _ = fd.readable().await?;
+
+        Ok(())
+    }
+
+    /// Whether the point was already signalled
+    ///
+    /// Failing queries count as signalled, waiting on them would fail just
+    /// the same.
+    pub fn is_signalled(&self) -> bool {
+        match drm::syncobj_query(&*self.timeline.device, self.timeline.handle) {
+            Ok(point) => point >= self.point,
+            Err(e) => {
+                warn!("Failed to query syncobj {}: {e}", self.timeline.handle);
+                true
+            }
+        }
+    }
+
+    pub fn signal(&self) {
+        if let Err(e) =
+            drm::syncobj_timeline_signal(&*self.timeline.device, self.timeline.handle, self.point)
+        {
+            warn!(
+                "Failed to signal point {} of syncobj {}: {e}",
+                self.point, self.timeline.handle
+            );
+        }
+    }
+
+    /// Whether both points are on the same timeline, `self` not before
+    /// `other`
+    pub fn not_before(&self, other: &SyncPoint) -> bool {
+        Arc::ptr_eq(&self.timeline, &other.timeline) && self.point >= other.point
+    }
+}
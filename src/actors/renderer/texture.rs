@@ -0,0 +1,290 @@
+use std::{borrow::Cow, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use wgpu::TextureFormat;
+
+use crate::protocol::wayland::shm::Format;
+
+use super::{
+    dmabuf::{Dmabuf, DmabufImporter},
+    pipeline::{FLAG_OPAQUE, FLAG_SWAP_RB, QuadPipeline},
+    scene::{ShmContent, SurfaceContent},
+};
+
+/// Where the pixels of a texture come from
+enum Source {
+    /// Uploaded from shm contents on every commit
+    Shm { format: Format, upload: ShmUpload },
+    /// Imported once, the client renders straight into it
+    Dmabuf { dmabuf: Arc<Dmabuf>, flags: u32 },
+}
+
+/// GPU copy of the contents of a client surface
+pub struct ClientTexture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    source: Source,
+    /// Serial of the scene content this texture was last uploaded from
+    serial: u64,
+}
+
+/// Every shm format the renderer can sample from
+pub const SHM_FORMATS: [Format; 14] = [
+    Format::Argb8888,
+    Format::Xrgb8888,
+    Format::Abgr8888,
+    Format::Xbgr8888,
+    Format::Rgb565,
+    Format::Argb2101010,
+    Format::Xrgb2101010,
+    Format::Abgr2101010,
+    Format::Xbgr2101010,
+    Format::Argb16161616f,
+    Format::Xrgb16161616f,
+    Format::Abgr16161616f,
+    Format::Xbgr16161616f,
+    Format::Bgr888,
+];
+
+/// Size of a pixel of a supported shm format, `None` for unsupported ones
+pub fn shm_bytes_per_pixel(format: Format) -> Option<u32> {
+    match format {
+        Format::Rgb565 => Some(2),
+        Format::Bgr888 => Some(3),
+        Format::Argb8888
+        | Format::Xrgb8888
+        | Format::Abgr8888
+        | Format::Xbgr8888
+        | Format::Argb2101010
+        | Format::Xrgb2101010
+        | Format::Abgr2101010
+        | Format::Xbgr2101010 => Some(4),
+        Format::Argb16161616f
+        | Format::Xrgb16161616f
+        | Format::Abgr16161616f
+        | Format::Xbgr16161616f => Some(8),
+        _ => None,
+    }
+}
+
+/// CPU side fixups for formats wgpu has no texture format for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    None,
+    /// Expanded to XRGB8888
+    Rgb565,
+    /// Padded to XBGR8888
+    Bgr888,
+}
+
+/// How the pixels of a shm format end up in a texture
+///
+/// DRM formats are named after the channel order of a little endian word,
+/// so `Argb8888` is laid out as B, G, R, A in memory. Formats only available
+/// with red and blue swapped on the GPU get swizzled back in the shader.
+#[derive(Debug, Clone, Copy)]
+struct ShmUpload {
+    texture_format: TextureFormat,
+    flags: u32,
+    conversion: Conversion,
+}
+
+impl ShmUpload {
+    fn new(format: Format) -> Result<Self> {
+        let (texture_format, flags, conversion) = match format {
+            Format::Argb8888 => (TextureFormat::Bgra8Unorm, 0, Conversion::None),
+            Format::Xrgb8888 => (TextureFormat::Bgra8Unorm, FLAG_OPAQUE, Conversion::None),
+            Format::Abgr8888 => (TextureFormat::Rgba8Unorm, 0, Conversion::None),
+            Format::Xbgr8888 => (TextureFormat::Rgba8Unorm, FLAG_OPAQUE, Conversion::None),
+            Format::Rgb565 => (TextureFormat::Bgra8Unorm, FLAG_OPAQUE, Conversion::Rgb565),
+            Format::Bgr888 => (TextureFormat::Rgba8Unorm, FLAG_OPAQUE, Conversion::Bgr888),
+            Format::Abgr2101010 => (TextureFormat::Rgb10a2Unorm, 0, Conversion::None),
+            Format::Xbgr2101010 => (TextureFormat::Rgb10a2Unorm, FLAG_OPAQUE, Conversion::None),
+            Format::Argb2101010 => (TextureFormat::Rgb10a2Unorm, FLAG_SWAP_RB, Conversion::None),
+            Format::Xrgb2101010 => (
+                TextureFormat::Rgb10a2Unorm,
+                FLAG_SWAP_RB | FLAG_OPAQUE,
+                Conversion::None,
+            ),
+            Format::Abgr16161616f => (TextureFormat::Rgba16Float, 0, Conversion::None),
+            Format::Xbgr16161616f => (TextureFormat::Rgba16Float, FLAG_OPAQUE, Conversion::None),
+            Format::Argb16161616f => (TextureFormat::Rgba16Float, FLAG_SWAP_RB, Conversion::None),
+            Format::Xrgb16161616f => (
+                TextureFormat::Rgba16Float,
+                FLAG_SWAP_RB | FLAG_OPAQUE,
+                Conversion::None,
+            ),
+            _ => bail!("Unsupported shm format {format:?}"),
+        };
+
+        Ok(Self {
+            texture_format,
+            flags,
+            conversion,
+        })
+    }
+
+    /// Pixels ready to be uploaded, along with their stride
+    fn pixels<'a>(&self, shm: &'a ShmContent) -> (Cow<'a, [u8]>, u32) {
+        let expand = |bytes_per_pixel: usize, convert: fn(&[u8]) -> [u8; 4]| {
+            let width = shm.width as usize;
+
+            let pixels = shm
+                .pixels
+                .chunks(shm.stride as usize)
+                .take(shm.height as usize)
+                .flat_map(|row| {
+                    row[..width * bytes_per_pixel]
+                        .chunks_exact(bytes_per_pixel)
+                        .flat_map(convert)
+                })
+                .collect();
+
+            (Cow::Owned(pixels), shm.width * 4)
+        };
+
+        match self.conversion {
+            Conversion::None => (Cow::Borrowed(&shm.pixels), shm.stride),
+            Conversion::Rgb565 => expand(2, |pixel| {
+                let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let r = (value >> 11) as u8 & 0x1f;
+                let g = (value >> 5) as u8 & 0x3f;
+                let b = value as u8 & 0x1f;
+
+                // Replicate the high bits so full intensity stays full
+                [b << 3 | b >> 2, g << 2 | g >> 4, r << 3 | r >> 2, 0xff]
+            }),
+            Conversion::Bgr888 => expand(3, |pixel| [pixel[0], pixel[1], pixel[2], 0xff]),
+        }
+    }
+}
+
+impl ClientTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &QuadPipeline,
+        importer: Option<&DmabufImporter>,
+        content: &SurfaceContent,
+        serial: u64,
+    ) -> Result<Self> {
+        let (texture, source) = match content {
+            SurfaceContent::Shm(shm) => {
+                let upload = ShmUpload::new(shm.format)?;
+
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("client shm"),
+                    size: wgpu::Extent3d {
+                        width: shm.width,
+                        height: shm.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: upload.texture_format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+
+                let source = Source::Shm {
+                    format: shm.format,
+                    upload,
+                };
+
+                (texture, source)
+            }
+            SurfaceContent::Dmabuf(content) => {
+                let importer = importer.context("Dmabuf import isn't supported")?;
+                let (texture, flags) = importer.import(device, &content.dmabuf)?;
+
+                let source = Source::Dmabuf {
+                    dmabuf: content.dmabuf.clone(),
+                    flags,
+                };
+
+                (texture, source)
+            }
+        };
+
+        let bind_group = pipeline.bind_texture(
+            device,
+            &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+        );
+
+        let client_texture = Self {
+            texture,
+            bind_group,
+            source,
+            serial,
+        };
+
+        if let SurfaceContent::Shm(shm) = content {
+            client_texture.write_shm(queue, shm);
+        }
+
+        Ok(client_texture)
+    }
+
+    /// Uploads new contents, reusing the existing texture when possible
+    ///
+    /// Returns `false` if the texture has to be recreated because the size or
+    /// format changed, or a different dmabuf got attached.
+    pub fn update(&mut self, queue: &wgpu::Queue, content: &SurfaceContent, serial: u64) -> bool {
+        match (content, &self.source) {
+            (SurfaceContent::Shm(shm), Source::Shm { format, .. }) => {
+                let size = self.texture.size();
+
+                if shm.format != *format || shm.width != size.width || shm.height != size.height {
+                    return false;
+                }
+
+                self.write_shm(queue, shm);
+            }
+            (SurfaceContent::Dmabuf(content), Source::Dmabuf { dmabuf, .. }) => {
+                if !Arc::ptr_eq(&content.dmabuf, dmabuf) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+
+        self.serial = serial;
+
+        true
+    }
+
+    fn write_shm(&self, queue: &wgpu::Queue, shm: &ShmContent) {
+        let Source::Shm { upload, .. } = &self.source else {
+            return;
+        };
+
+        let (pixels, stride) = upload.pixels(shm);
+
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            &pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(stride),
+                rows_per_image: None,
+            },
+            self.texture.size(),
+        );
+    }
+
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn flags(&self) -> u32 {
+        match &self.source {
+            Source::Shm { upload, .. } => upload.flags,
+            Source::Dmabuf { flags, .. } => *flags,
+        }
+    }
+}
@@ -1,42 +1,93 @@
-use std::ffi::CString;
+use std::{collections::HashMap, ffi::CString, sync::Arc};
 
 use anyhow::{Context, Result};
 use diretto::{
-    ClientCapability, Connector, Device as DrmDevice, ModeType, sys::DRM_MODE_OBJECT_PLANE,
+    ClientCapability, Connector, Device as DrmDevice, ModeType,
+    sys::{DRM_MODE_OBJECT_CONNECTOR, DRM_MODE_OBJECT_CRTC, DRM_MODE_OBJECT_PLANE},
+};
+use rustix::fd::{AsFd, AsRawFd, OwnedFd};
+use tracing::{debug, trace, warn};
+use waynest::ObjectId;
+use wgpu::{Backends, ExperimentalFeatures, PresentMode, SurfaceTargetUnsafe, hal::api::Vulkan};
+
+use crate::{
+    OutputConfig, Transform,
+    actors::session::{SessionExt, SessionRef},
+    protocol::wayland::surface::SurfaceId,
 };
-use rustix::fd::{AsFd, AsRawFd};
-use tracing::{debug, trace};
-use wgpu::{Backends, ExperimentalFeatures, PresentMode, SurfaceTargetUnsafe};
 
-use crate::actors::session::{SessionExt, SessionRef};
+use super::{
+    cursor_plane::CursorPlane,
+    damage::{Rect, Region},
+    dmabuf::{self, Dmabuf, DmabufFeedback, DmabufFormat, DmabufImporter},
+    drm::{self, PlaneProps, PlaneType, Vblank},
+    output::{Output, OutputDrm, OutputId, OutputInfo, Presented},
+    overlay::OverlayPlane,
+    pipeline::QuadPipeline,
+    scene::{Scene, ShmContent, SurfaceContent},
+    scheduler::{Deadline, monotonic_ns},
+    syncobj::SyncobjDevice,
+    texture::ClientTexture,
+};
+
+const CONNECTOR_NAMES: [&str; 21] = [
+    "Unknown",
+    "VGA",
+    "DVI-I",
+    "DVI-D",
+    "DVI-A",
+    "Composite",
+    "SVIDEO",
+    "LVDS",
+    "Component",
+    "DIN",
+    "DP",
+    "HDMI-A",
+    "HDMI-B",
+    "TV",
+    "eDP",
+    "Virtual",
+    "DSI",
+    "DPI",
+    "Writeback",
+    "SPI",
+    "USB",
+];
+
+fn connector_name(connector: &Connector) -> String {
+    let ty = u32::from(connector.connector_type) as usize;
+    let name = CONNECTOR_NAMES.get(ty).copied().unwrap_or("Unknown");
+
+    format!("{name}-{}", u32::from(connector.connector_type_id))
+}
 
-#[derive(Debug)]
 struct DrmState {
     device: DrmDevice,
-    connector: Connector,
-    mode: diretto::Mode,
-    plane_id: u32,
+    outputs: Vec<(String, OutputDrm)>,
 }
 
 pub struct WgpuContext<'s> {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface<'s>,
-    _drm_state: DrmState,
+    outputs: Vec<Output<'s>>,
+    pipeline: QuadPipeline,
+    textures: HashMap<SurfaceId, ClientTexture>,
+    cursor: ClientTexture,
+    dmabuf: Option<DmabufImporter>,
+    /// Separate file description used to queue and receive vblank events
+    vblank_fd: OwnedFd,
+    /// Shared with the framebuffers of scanned out client buffers
+    drm_device: Arc<DrmDevice>,
 }
 
 impl<'s> WgpuContext<'s> {
-    pub async fn new(session_ref: &SessionRef) -> Result<Self> {
+    pub async fn new(
+        session_ref: &SessionRef,
+        scene: &Scene,
+        configs: &HashMap<String, OutputConfig>,
+    ) -> Result<Self> {
         let drm_state = Self::create_drm_resources(session_ref).await?;
-
-        let surface_target = SurfaceTargetUnsafe::Drm {
-            fd: drm_state.device.as_fd().as_raw_fd(),
-            plane: drm_state.plane_id,
-            connector_id: drm_state.connector.connector_id.into(),
-            width: drm_state.mode.display_width() as u32,
-            height: drm_state.mode.display_height() as u32,
-            refresh_rate: drm_state.mode.wsi_refresh_rate(),
-        };
+        let vblank_fd = drm::reopen(&drm_state.device).context("Failed to reopen DRM device")?;
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: Backends::VULKAN,
@@ -45,7 +96,22 @@ impl<'s> WgpuContext<'s> {
             ..Default::default()
         });
 
-        let surface = unsafe { instance.create_surface_unsafe(surface_target)? };
+        let mut surfaces = Vec::with_capacity(drm_state.outputs.len());
+
+        for (name, output_drm) in drm_state.outputs {
+            let surface_target = SurfaceTargetUnsafe::Drm {
+                fd: drm_state.device.as_fd().as_raw_fd(),
+                plane: output_drm.plane_id,
+                connector_id: output_drm.connector.connector_id.into(),
+                width: output_drm.mode.display_width() as u32,
+                height: output_drm.mode.display_height() as u32,
+                refresh_rate: output_drm.mode.wsi_refresh_rate(),
+            };
+
+            let surface = unsafe { instance.create_surface_unsafe(surface_target)? };
+
+            surfaces.push((name, output_drm, surface));
+        }
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -55,39 +121,169 @@ impl<'s> WgpuContext<'s> {
             .await
             .context("Failed to find an appropriate adapter")?;
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: adapter.limits(),
-                memory_hints: wgpu::MemoryHints::MemoryUsage,
-                trace: wgpu::Trace::Off,
-                experimental_features: ExperimentalFeatures::disabled(),
-            })
-            .await
-            .context("Failed to create device")?;
+        let (device, queue) = Self::create_device(&adapter)?;
 
-        let mut config = surface
-            .get_default_config(
-                &adapter,
-                drm_state.mode.display_width().into(),
-                drm_state.mode.display_height().into(),
-            )
-            .context("Surface not supported by adapter")?;
+        let drm_device = Arc::new(drm_state.device);
+
+        let main_device = rustix::fs::fstat(&*drm_device)?.st_rdev;
+        let dmabuf = match DmabufImporter::new(&device, main_device) {
+            Ok(Some(importer)) => Some(importer),
+            Ok(None) => {
+                warn!("Vulkan device can't import dmabufs");
+                None
+            }
+            Err(e) => {
+                warn!("Failed to set up dmabuf import: {e}");
+                None
+            }
+        };
 
-        config.present_mode = PresentMode::AutoVsync;
-        surface.configure(&device, &config);
+        let mut outputs = Vec::with_capacity(surfaces.len());
+        let mut x = 0;
+
+        for (name, output_drm, surface) in surfaces {
+            let width = output_drm.mode.display_width() as u32;
+            let height = output_drm.mode.display_height() as u32;
+
+            let mut config = surface
+                .get_default_config(&adapter, width, height)
+                .context("Surface not supported by adapter")?;
+
+            // Client buffers are already sRGB encoded, blend them as they are
+            config.format = config.format.remove_srgb_suffix();
+            // Always supported and never tears, unlike FIFO_RELAXED
+            config.present_mode = PresentMode::Fifo;
+
+            let settings = configs.get(&name).cloned().unwrap_or_default();
+
+            let cursor_plane = output_drm.cursor_plane;
+            let overlay_plane = output_drm.overlay_plane.clone();
+
+            let mut output = Output::new(name, output_drm, surface, config, (x, 0), &settings);
+            output.configure(&device);
+
+            // The plane shows the cursor image as is, rotated outputs
+            // composite it instead
+            if let Some((plane_id, props)) = cursor_plane
+                && output.transform() == Transform::Normal
+            {
+                match CursorPlane::new(
+                    &drm_device,
+                    plane_id,
+                    output.drm().crtc_id,
+                    props,
+                    scene.cursor_image(),
+                ) {
+                    Ok(Some(plane)) => {
+                        output.set_cursor_plane(plane);
+                        output.move_cursor(&Region::new(), scene.cursor_rect());
+                    }
+                    Ok(None) => debug!("Cursor image too large for the cursor plane"),
+                    Err(e) => warn!("Failed to set up cursor plane: {e}"),
+                }
+            }
+
+            if let Some((plane_id, props, formats)) = overlay_plane {
+                output.set_overlay_plane(OverlayPlane::new(
+                    &drm_device,
+                    plane_id,
+                    output.drm().crtc_id,
+                    props,
+                    &formats,
+                ));
+            }
+
+            debug!(
+                "Configured output {} on CRTC {} at {:?}",
+                output.name(),
+                output.drm().crtc_id,
+                output.geometry()
+            );
+
+            // Outputs sit side by side in logical pixels
+            x += output.geometry().width;
+            outputs.push(output);
+        }
+
+        let pipeline = QuadPipeline::new(&device, &queue, outputs.iter().map(Output::format));
+
+        let cursor_image = scene.cursor_image();
+        let cursor = ClientTexture::new(
+            &device,
+            &queue,
+            &pipeline,
+            None,
+            &SurfaceContent::Shm(ShmContent {
+                width: cursor_image.width,
+                height: cursor_image.height,
+                stride: cursor_image.stride(),
+                format: crate::protocol::wayland::shm::Format::Argb8888,
+                pixels: cursor_image.pixels.clone(),
+            }),
+            0,
+        )?;
 
         debug!("Created WGPU resources");
 
         Ok(Self {
-            surface,
             device,
             queue,
-            _drm_state: drm_state,
+            outputs,
+            pipeline,
+            textures: HashMap::new(),
+            cursor,
+            dmabuf,
+            vblank_fd,
+            drm_device,
         })
     }
 
+    /// Opens the device, enabling the extensions needed for dmabuf import
+    /// when the driver supports them
+    fn create_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let desc = wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+            memory_hints: wgpu::MemoryHints::MemoryUsage,
+            trace: wgpu::Trace::Off,
+            experimental_features: ExperimentalFeatures::disabled(),
+        };
+
+        let open_device = {
+            let hal_adapter =
+                unsafe { adapter.as_hal::<Vulkan>() }.context("Adapter isn't a Vulkan one")?;
+
+            let dmabuf_extensions: Vec<_> = dmabuf::DEVICE_EXTENSIONS
+                .into_iter()
+                .filter(|name| hal_adapter.supports_extension(name))
+                .collect();
+            let dmabuf_supported = dmabuf_extensions.len() == dmabuf::DEVICE_EXTENSIONS.len();
+
+            unsafe {
+                hal_adapter.open_with_callback(
+                    desc.required_features,
+                    &desc.memory_hints,
+                    Some(Box::new(move |args| {
+                        if !dmabuf_supported {
+                            return;
+                        }
+
+                        for name in dmabuf_extensions {
+                            if !args.extensions.contains(&name) {
+                                args.extensions.push(name);
+                            }
+                        }
+                    })),
+                )
+            }
+            .context("Failed to open device")?
+        };
+
+        unsafe { adapter.create_device_from_hal(open_device, &desc) }
+            .context("Failed to create device")
+    }
+
     async fn create_drm_resources(session_ref: &SessionRef) -> Result<DrmState> {
         let fd = session_ref
             .open_device(CString::new("/dev/dri/card1").unwrap())
@@ -100,113 +296,571 @@ impl<'s> WgpuContext<'s> {
 
         let resources = device.get_resources()?;
 
-        // Find connected connector
-        let connector = {
-            let mut found_connector = None;
-            for connector_id in &resources.connectors {
-                let connector = device.get_connector(*connector_id, false)?;
-                if connector.connection.is_connected() {
-                    found_connector = Some(connector);
-                    break;
+        let crtcs: Vec<u32> = resources.crtcs.iter().map(|id| u32::from(*id)).collect();
+
+        let planes = device
+            .get_plane_resources()?
+            .into_iter()
+            .map(|id| {
+                let plane = drm::get_plane(&device, id)?;
+                let props = drm::properties(&device, id, DRM_MODE_OBJECT_PLANE)?;
+                let ty = props.plane_type();
+
+                // Only overlay planes get assigned client buffers of any format
+                let formats = match ty {
+                    Some(PlaneType::Overlay) => drm::plane_formats(&device, &plane, &props)
+                        .into_iter()
+                        .map(|(format, modifier)| DmabufFormat { format, modifier })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+
+                Ok((plane, ty, PlaneProps::new(&props), formats))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let async_flips = drm::supports_async_flips(&device);
+
+        let mut used_crtcs = Vec::new();
+        let mut used_planes = Vec::new();
+        let mut outputs = Vec::new();
+
+        for connector_id in &resources.connectors {
+            let connector = device.get_connector(*connector_id, false)?;
+            if !connector.connection.is_connected() {
+                continue;
+            }
+
+            let name = connector_name(&connector);
+
+            let Some(mode) = Self::best_mode(&connector) else {
+                warn!("No suitable mode found for {name}");
+                continue;
+            };
+
+            // Find a free CRTC one of the connector encoders can drive,
+            // preferring the one that is already active
+            let mut crtc = None;
+            for encoder_id in &connector.encoders {
+                let encoder = drm::get_encoder(&device, u32::from(*encoder_id))?;
+
+                for (index, crtc_id) in crtcs.iter().copied().enumerate() {
+                    if encoder.possible_crtcs & (1 << index) == 0 || used_crtcs.contains(&crtc_id) {
+                        continue;
+                    }
+
+                    if crtc.is_none() || encoder.crtc_id == crtc_id {
+                        crtc = Some((crtc_id, index));
+                    }
                 }
             }
-            found_connector.ok_or_else(|| anyhow::anyhow!("No connected display found"))?
-        };
 
-        // Find best mode
-        let mode = {
-            let mut best_mode = None;
-            let mut max_area = 0;
+            let Some((crtc_id, crtc_index)) = crtc else {
+                warn!("No free CRTC for {name}");
+                continue;
+            };
+
+            // Find primary plane
+            let Some((plane_id, plane_props)) = planes
+                .iter()
+                .find(|(plane, ty, _, _)| {
+                    *ty == Some(PlaneType::Primary)
+                        && plane.supports_crtc(crtc_index)
+                        && !used_planes.contains(&plane.id)
+                })
+                .map(|(plane, _, props, _)| (plane.id, *props))
+            else {
+                warn!("No primary plane found for {name}");
+                continue;
+            };
+
+            trace!("Found primary plane {plane_id} for CRTC {crtc_id}");
+
+            // Adaptive sync needs both the display and the CRTC to support it
+            let vrr_capable =
+                drm::properties(&device, u32::from(*connector_id), DRM_MODE_OBJECT_CONNECTOR)?
+                    .value(c"vrr_capable")
+                    == Some(1);
+            let crtc_props = drm::properties(&device, crtc_id, DRM_MODE_OBJECT_CRTC)?;
+            let vrr_enabled = crtc_props.id(c"VRR_ENABLED").filter(|_| vrr_capable);
+            let out_fence_ptr = crtc_props.id(c"OUT_FENCE_PTR");
+
+            if vrr_enabled.is_some() {
+                trace!("{name} supports adaptive sync");
+            }
 
-            for current_mode in connector.modes.iter().copied() {
-                if current_mode.ty().contains(ModeType::DEFAULT) {
-                    best_mode = Some(current_mode);
-                    break;
+            let cursor_plane = planes
+                .iter()
+                .find(|(plane, ty, _, _)| {
+                    *ty == Some(PlaneType::Cursor)
+                        && plane.supports_crtc(crtc_index)
+                        && !used_planes.contains(&plane.id)
+                })
+                .and_then(|(plane, _, props, _)| Some((plane.id, (*props)?)));
+
+            match cursor_plane {
+                Some((cursor_id, _)) => {
+                    trace!("Found cursor plane {cursor_id} for CRTC {crtc_id}");
+                    used_planes.push(cursor_id);
                 }
+                None => debug!("No cursor plane for {name}, compositing the cursor"),
+            }
 
-                let area =
-                    current_mode.display_width() as u32 * current_mode.display_height() as u32;
-                if area > max_area {
-                    best_mode = Some(current_mode);
-                    max_area = area;
+            let overlay_plane = planes
+                .iter()
+                .find(|(plane, ty, props, formats)| {
+                    *ty == Some(PlaneType::Overlay)
+                        && props.is_some()
+                        && !formats.is_empty()
+                        && plane.supports_crtc(crtc_index)
+                        && !used_planes.contains(&plane.id)
+                })
+                .and_then(|(plane, _, props, formats)| {
+                    Some((plane.id, (*props)?, formats.clone()))
+                });
+
+            match overlay_plane {
+                Some((overlay_id, _, ref formats)) => {
+                    trace!(
+                        "Found overlay plane {overlay_id} for CRTC {crtc_id} with {} formats",
+                        formats.len()
+                    );
+                    used_planes.push(overlay_id);
                 }
+                None => debug!("No overlay plane for {name}"),
+            }
+
+            debug!(
+                "Selected mode {}x{}@{} for {name}",
+                mode.display_width(),
+                mode.display_height(),
+                mode.vertical_refresh_rate()
+            );
+
+            used_crtcs.push(crtc_id);
+            used_planes.push(plane_id);
+
+            outputs.push((
+                name,
+                OutputDrm {
+                    connector,
+                    crtc_id,
+                    plane_id,
+                    plane_props,
+                    cursor_plane,
+                    overlay_plane,
+                    vrr_enabled,
+                    out_fence_ptr,
+                    async_flips,
+                    mode,
+                },
+            ));
+        }
+
+        if outputs.is_empty() {
+            anyhow::bail!("No connected display found");
+        }
+
+        Ok(DrmState { device, outputs })
+    }
+
+    fn best_mode(connector: &Connector) -> Option<diretto::Mode> {
+        let mut best_mode = None;
+        let mut max_area = 0;
+
+        for current_mode in connector.modes.iter().copied() {
+            if current_mode.ty().contains(ModeType::DEFAULT) {
+                return Some(current_mode);
+            }
+
+            let area = current_mode.display_width() as u32 * current_mode.display_height() as u32;
+            if area > max_area {
+                best_mode = Some(current_mode);
+                max_area = area;
             }
-            best_mode.ok_or_else(|| anyhow::anyhow!("No suitable mode found"))?
+        }
+
+        best_mode
+    }
+
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.outputs.iter().map(Output::info).collect()
+    }
+
+    pub fn damage_all(&mut self) {
+        for output in &mut self.outputs {
+            output.damage_all();
+        }
+    }
+
+    pub fn add_damage(&mut self, region: &Region) {
+        if region.is_empty() {
+            return;
+        }
+
+        for output in &mut self.outputs {
+            output.add_damage(region);
+        }
+    }
+
+    /// Follows a cursor move, `damage` covering its old and new position
+    pub fn move_cursor(&mut self, damage: &Region, scene: &Scene) {
+        for output in &mut self.outputs {
+            output.move_cursor(damage, scene.cursor_rect());
+        }
+    }
+
+    /// Turns an output off or back on, returns whether it made it
+    ///
+    /// An output that failed keeps its previous state.
+    pub fn set_power(&mut self, id: OutputId, on: bool) -> bool {
+        let Some(output) = self.outputs.iter_mut().find(|output| output.id() == id) else {
+            return false;
         };
 
-        debug!(
-            "Selected mode {}x{}@{}",
-            mode.display_width(),
-            mode.display_height(),
-            mode.vertical_refresh_rate()
-        );
-
-        // Find primary plane
-        let plane_id = {
-            let plane_resources = device.get_plane_resources()?;
-            let mut primary_plane = None;
-
-            for id in plane_resources {
-                let (props, values) = unsafe { device.get_properties(id, DRM_MODE_OBJECT_PLANE)? };
-
-                for (index, prop) in props.into_iter().enumerate() {
-                    let (name, _) = unsafe { device.get_property(prop)? };
-                    let current_value = values[index];
-
-                    if name.as_c_str() == c"type" && current_value == 1 {
-                        trace!("Found primary plane: {}", id);
-                        primary_plane = Some(id);
-                        break;
-                    }
+        if let Err(e) = output.set_power(&self.drm_device, on) {
+            warn!(
+                "Failed to turn {} {}: {e}",
+                output.name(),
+                if on { "on" } else { "off" }
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Waits for every output to flip a frame drawn from now on, or stops
+    /// waiting
+    pub fn await_lock_frames(&mut self, wait: bool) {
+        for output in &mut self.outputs {
+            output.await_lock_frame(wait);
+        }
+    }
+
+    /// Whether every output that's on shows a frame drawn since
+    /// [`Self::await_lock_frames`]
+    pub fn shows_lock_frames(&self) -> bool {
+        self.outputs.iter().all(Output::shows_lock_frame)
+    }
+
+    /// Layout bounds of the outputs that keep presenting frames
+    pub fn active_bounds(&self) -> Vec<Rect> {
+        self.outputs
+            .iter()
+            .filter(|output| output.is_on())
+            .map(Output::geometry)
+            .collect()
+    }
+
+    /// A new handle to the file vblank events are delivered on
+    pub fn vblank_events(&self) -> Result<OwnedFd> {
+        Ok(self.vblank_fd.try_clone()?)
+    }
+
+    /// Schedules a repaint of every idle output with something new to show
+    pub fn schedule_frames(&mut self, scene: &Scene) -> Vec<(OutputId, Deadline)> {
+        let mut deadlines = Vec::new();
+
+        for output in &mut self.outputs {
+            if output.scheduler().is_paused() || !output.scheduler().is_idle() {
+                continue;
+            }
+
+            output.update_planes(scene);
+            output.update_adaptive_sync(scene);
+
+            if !(output.needs_frame() || scene.has_frame_callbacks(output.geometry())) {
+                continue;
+            }
+
+            let now = monotonic_ns();
+
+            // An inactive CRTC has no meaningful vblank, just render right away
+            let last =
+                drm::crtc_sequence(&self.vblank_fd, output.drm().crtc_id).unwrap_or(Vblank {
+                    sequence: 0,
+                    time_ns: now,
+                });
+
+            if let Some(deadline) = output.scheduler().schedule(last, now) {
+                deadlines.push((output.id(), deadline));
+            }
+        }
+
+        deadlines
+    }
+
+    /// Repaints an output whose render deadline was reached
+    ///
+    /// Timers that were superseded in the meantime are ignored.
+    pub fn render_output(
+        &mut self,
+        id: OutputId,
+        generation: u64,
+        scene: &mut Scene,
+    ) -> Result<()> {
+        let Some(index) = self.outputs.iter().position(|output| output.id() == id) else {
+            return Ok(());
+        };
+
+        if !self.outputs[index].scheduler().should_render(generation) {
+            return Ok(());
+        }
+
+        let start = monotonic_ns();
+
+        self.outputs[index].update_planes(scene);
+        self.outputs[index].update_adaptive_sync(scene);
+
+        let scanned_out = self.try_scanout(index, scene);
+
+        // Only client buffers flipped on their own can tear
+        if !scanned_out {
+            self.outputs[index].set_tearing(false);
+        }
+
+        let (composited, rendered) =
+            if scanned_out || self.outputs[index].commit_planes(&self.drm_device) {
+                (false, Ok(true))
+            } else {
+                self.sync_textures(scene);
+
+                let rendered = self.outputs[index].render(
+                    &self.device,
+                    &self.queue,
+                    &self.pipeline,
+                    &self.textures,
+                    &self.cursor,
+                    scene,
+                );
+
+                (true, rendered)
+            };
+
+        let output = &mut self.outputs[index];
+
+        let queued = drm::queue_next_vblank(&self.vblank_fd, output.drm().crtc_id, id as u64);
+
+        match (rendered, queued) {
+            (Ok(rendered), Ok(())) => {
+                output
+                    .scheduler()
+                    .submitted(rendered, start, monotonic_ns());
+
+                if rendered {
+                    output.frame_drawn();
                 }
 
-                if primary_plane.is_some() {
-                    break;
+                Self::collect_feedback(output, composited, scene);
+                Ok(())
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                output.abort();
+                Err(e)
+            }
+        }
+    }
+
+    /// Hands the presentation feedback of the surfaces a submitted frame
+    /// shows to the output
+    ///
+    /// Frames that only updated planes show nothing but the surfaces on them.
+    fn collect_feedback(output: &mut Output, composited: bool, scene: &mut Scene) {
+        let bounds = output.geometry();
+        let surfaces: Vec<_> = scene
+            .visible()
+            .filter(|(_, rect)| rect.intersects(&bounds))
+            .map(|(id, _)| id)
+            .collect();
+
+        for id in surfaces {
+            let zero_copy = match scene.content(id) {
+                Some((SurfaceContent::Dmabuf(content), _)) => output.shows(&content.dmabuf),
+                _ => false,
+            };
+
+            if !(composited || zero_copy) {
+                continue;
+            }
+
+            let feedback = scene.take_presentation_feedback(id);
+            if !feedback.is_empty() {
+                output.add_frame_feedback(id, feedback, zero_copy);
+            }
+        }
+    }
+
+    /// Puts a fullscreen client buffer straight on the primary plane of an
+    /// output
+    ///
+    /// Returns `false` if the output has to be composited instead.
+    fn try_scanout(&mut self, index: usize, scene: &Scene) -> bool {
+        let output = &mut self.outputs[index];
+
+        let Some((id, dmabuf)) = Self::scanout_candidate(output, scene) else {
+            output.stop_scanout();
+            return false;
+        };
+
+        match output.scanout(&self.drm_device, &dmabuf, scene.wants_tearing(id)) {
+            Ok(true) => true,
+            Ok(false) => {
+                output.stop_scanout();
+                false
+            }
+            Err(e) => {
+                warn!("Direct scanout on {} failed: {e}", output.name());
+                output.stop_scanout();
+                false
+            }
+        }
+    }
+
+    /// The topmost surface and its dmabuf if it exactly covers the output
+    fn scanout_candidate(output: &Output, scene: &Scene) -> Option<(SurfaceId, Arc<Dmabuf>)> {
+        let bounds = output.geometry();
+
+        // A composited cursor would disappear
+        if output.composites_cursor() && scene.cursor_rect().intersects(&bounds) {
+            return None;
+        }
+
+        let (id, rect) = scene.topmost(bounds)?;
+
+        if rect != bounds {
+            return None;
+        }
+
+        // The plane shows the buffer unscaled
+        scene
+            .direct_content(id, output.transform())
+            .filter(|content| (content.dmabuf.width, content.dmabuf.height) == output.mode_size())
+            .map(|content| (id, content.dmabuf.clone()))
+    }
+
+    /// Whether a dmabuf is on the screen of some output
+    pub fn is_scanned_out(&self, dmabuf: &Arc<Dmabuf>) -> bool {
+        self.outputs.iter().any(|output| output.shows(dmabuf))
+    }
+
+    /// Handles a vblank event of an output
+    ///
+    /// Returns the frame callbacks that can be fired and the presentation
+    /// feedback to send if the output finished showing a frame.
+    pub fn vblank(
+        &mut self,
+        id: OutputId,
+        vblank: Vblank,
+        scene: &mut Scene,
+    ) -> (
+        Vec<(SurfaceId, Vec<ObjectId>)>,
+        Vec<(SurfaceId, ObjectId, Presented)>,
+    ) {
+        let Some(output) = self.outputs.iter_mut().find(|output| output.id() == id) else {
+            return (Vec::new(), Vec::new());
+        };
+
+        let (vblank, hw_completion) = if output.scheduler().is_in_flight() {
+            match output.landed(vblank) {
+                Some(landed) => landed,
+                // Missed this vblank, wait for the next one
+                None => {
+                    match drm::queue_next_vblank(&self.vblank_fd, output.drm().crtc_id, id as u64) {
+                        Ok(()) => return (Vec::new(), Vec::new()),
+                        Err(e) => {
+                            warn!("Failed to wait for the flip on {}: {e}", output.name());
+                            (vblank, false)
+                        }
+                    }
                 }
             }
-            primary_plane.ok_or_else(|| anyhow::anyhow!("No primary plane found"))?
+        } else {
+            (vblank, false)
         };
 
-        Ok(DrmState {
-            device,
-            connector,
-            mode,
-            plane_id,
-        })
+        if output.scheduler().vblank(vblank, monotonic_ns()) {
+            output.presented();
+
+            (
+                scene.take_frame_callbacks(output.geometry()),
+                output.take_frame_feedback(vblank, hw_completion),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        }
+    }
+
+    pub fn dmabuf_feedback(&self) -> Option<DmabufFeedback> {
+        let mut feedback = self.dmabuf.as_ref()?.feedback();
+
+        for output in &self.outputs {
+            let scanout: Vec<_> = feedback
+                .formats
+                .iter()
+                .copied()
+                .filter(|format| output.can_scan_out(*format))
+                .collect();
+
+            if !scanout.is_empty() {
+                feedback.scanout.insert(output.id(), scanout);
+            }
+        }
+
+        Some(feedback)
     }
 
-    pub fn present(&self) -> Result<()> {
-        let frame = self
-            .surface
-            .get_current_texture()
-            .context("Failed to acquire next swapchain texture")?;
+    /// Only useful along with dmabuf import, shm buffers can't be synced
+    pub fn syncobj_device(&self) -> Option<SyncobjDevice> {
+        self.dmabuf
+            .as_ref()
+            .and_then(|_| SyncobjDevice::new(&self.drm_device))
+    }
 
-        let texture_view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    /// Whether no output has a frame scheduled or in flight
+    pub fn is_idle(&mut self) -> bool {
+        self.outputs
+            .iter_mut()
+            .all(|output| output.scheduler().is_idle())
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    pub fn forget_surface(&mut self, id: SurfaceId) {
+        self.textures.remove(&id);
+    }
 
-        let renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &texture_view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
-                    store: wgpu::StoreOp::Store,
-                },
-                depth_slice: None,
-            })],
-            ..Default::default()
-        });
+    pub fn forget_client(&mut self, client_id: u32) {
+        self.textures.retain(|id, _| id.client_id != client_id);
+    }
 
-        drop(renderpass);
-        self.queue.submit([encoder.finish()]);
-        frame.present();
+    /// Makes sure every visible surface has an up to date texture
+    fn sync_textures(&mut self, scene: &Scene) {
+        for (id, _) in scene.visible() {
+            let Some((content, serial)) = scene.content(id) else {
+                continue;
+            };
 
-        Ok(())
+            if let Some(texture) = self.textures.get_mut(&id) {
+                if texture.serial() == serial || texture.update(&self.queue, content, serial) {
+                    continue;
+                }
+            }
+
+            match ClientTexture::new(
+                &self.device,
+                &self.queue,
+                &self.pipeline,
+                self.dmabuf.as_ref(),
+                content,
+                serial,
+            ) {
+                Ok(texture) => {
+                    self.textures.insert(id, texture);
+                }
+                Err(e) => {
+                    warn!("Failed to upload surface contents: {e}");
+                    self.textures.remove(&id);
+                }
+            }
+        }
     }
 }
@@ -14,6 +14,24 @@ pub enum VerdiError {
     Input(#[from] colpetto::Error),
     #[error("Seat error: {0}")]
     Seat(#[from] saddle::Error),
+    #[error("Client error on object {object_id} (code {code}): {message}")]
+    Client {
+        object_id: ObjectId,
+        code: u32,
+        message: String,
+    },
+}
+
+impl VerdiError {
+    /// A protocol violation by the client, which gets reported through
+    /// `wl_display.error` before disconnecting it
+    pub fn client(object_id: ObjectId, code: u32, message: impl Into<String>) -> Self {
+        Self::Client {
+            object_id,
+            code,
+            message: message.into(),
+        }
+    }
 }
 
 impl From<io::Error> for VerdiError {
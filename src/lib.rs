@@ -1,6 +1,6 @@
 #![allow(clippy::default_constructed_unit_structs)]
 
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
@@ -22,4 +22,262 @@ pub use crate::{
 pub struct Config {
     /// Custom wayland socket path
     pub socket: Option<PathBuf>,
+    /// Per output settings, keyed by connector name (e.g. `eDP-1`)
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputConfig>,
+    /// Executables allowed to use privileged protocols, like clipboard
+    /// managers watching the selection or idle daemons turning the outputs
+    /// off
+    ///
+    /// Paths have to match the resolved executable, e.g. `/usr/bin/wl-paste`.
+    #[serde(default)]
+    pub privileged_clients: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Milliseconds reserved for rendering before each vblank
+    ///
+    /// Repaints start as late as this allows, lowering latency. Unset repaints
+    /// as soon as there's something new to show.
+    pub max_render_time: Option<u64>,
+    /// Variable refresh rate, only used if the display supports it
+    pub adaptive_sync: AdaptiveSync,
+    /// Whether fullscreen clients asking for it may have their buffers
+    /// flipped right away, tearing
+    pub allow_tearing: bool,
+    /// How many physical pixels make up a logical one, fractional values
+    /// like 1.25 included
+    ///
+    /// Clients get it as their preferred scale, rounded to 120ths as
+    /// `wp_fractional_scale_v1` expresses it.
+    pub scale: f64,
+    /// Rotation and mirroring of the output, e.g. `90` for a portrait
+    /// monitor
+    pub transform: Transform,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            max_render_time: None,
+            adaptive_sync: AdaptiveSync::Off,
+            allow_tearing: true,
+            scale: 1.0,
+            transform: Transform::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdaptiveSync {
+    #[default]
+    Off,
+    On,
+    /// Only while a single surface covers the whole output, like a game
+    FullscreenOnly,
+}
+
+/// Rotation and mirroring, with the values and meaning of
+/// `wl_output.transform`
+///
+/// Rotations are counter-clockwise, flipped variants are mirrored around
+/// the vertical axis before being rotated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    #[default]
+    Normal = 0,
+    #[serde(rename = "90")]
+    Rotate90 = 1,
+    #[serde(rename = "180")]
+    Rotate180 = 2,
+    #[serde(rename = "270")]
+    Rotate270 = 3,
+    Flipped = 4,
+    #[serde(rename = "flipped-90")]
+    Flipped90 = 5,
+    #[serde(rename = "flipped-180")]
+    Flipped180 = 6,
+    #[serde(rename = "flipped-270")]
+    Flipped270 = 7,
+}
+
+impl Transform {
+    /// The transform with the given `wl_output.transform` value
+    pub fn from_raw(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Normal,
+            1 => Self::Rotate90,
+            2 => Self::Rotate180,
+            3 => Self::Rotate270,
+            4 => Self::Flipped,
+            5 => Self::Flipped90,
+            6 => Self::Flipped180,
+            7 => Self::Flipped270,
+            _ => return None,
+        })
+    }
+
+    /// Number of counter-clockwise quarter turns
+    pub fn quarter_turns(self) -> u32 {
+        self as u32 & 3
+    }
+
+    pub fn is_flipped(self) -> bool {
+        self as u32 & 4 != 0
+    }
+
+    /// Whether width and height trade places
+    pub fn swaps_axes(self) -> bool {
+        self.quarter_turns() % 2 == 1
+    }
+
+    /// The transform undoing this one
+    pub fn invert(self) -> Self {
+        if self.is_flipped() {
+            // Mirroring reverses the direction of the rotation
+            self
+        } else {
+            Self::from_raw((4 - self.quarter_turns()) & 3).unwrap_or_default()
+        }
+    }
+
+    /// Size of something of the given size once transformed
+    pub fn size(self, (width, height): (i32, i32)) -> (i32, i32) {
+        if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Where a point ends up once something of the given size containing
+    /// it gets transformed
+    pub fn point(
+        self,
+        (mut x, mut y): (f64, f64),
+        (mut width, mut height): (f64, f64),
+    ) -> (f64, f64) {
+        if self.is_flipped() {
+            x = width - x;
+        }
+
+        for _ in 0..self.quarter_turns() {
+            // Counter-clockwise, the right edge becomes the top one
+            (x, y) = (y, width - x);
+            (width, height) = (height, width);
+        }
+
+        (x, y)
+    }
+}
+
+impl OutputConfig {
+    pub fn max_render_time(&self) -> Option<Duration> {
+        self.max_render_time.map(Duration::from_millis)
+    }
+
+    /// The configured scale in 120ths, falling back to 1 if it isn't
+    /// positive
+    pub fn scale(&self) -> f64 {
+        let scale = (self.scale * 120.0).round() / 120.0;
+
+        if scale.is_finite() && scale > 0.0 {
+            scale
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Transform; 8] = [
+        Transform::Normal,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    #[test]
+    fn invert_reverses_rotations() {
+        assert_eq!(Transform::Normal.invert(), Transform::Normal);
+        assert_eq!(Transform::Rotate90.invert(), Transform::Rotate270);
+        assert_eq!(Transform::Rotate180.invert(), Transform::Rotate180);
+        assert_eq!(Transform::Rotate270.invert(), Transform::Rotate90);
+    }
+
+    #[test]
+    fn invert_keeps_flipped_transforms() {
+        for transform in ALL.into_iter().filter(|t| t.is_flipped()) {
+            assert_eq!(transform.invert(), transform);
+        }
+    }
+
+    #[test]
+    fn invert_twice_is_identity() {
+        for transform in ALL {
+            assert_eq!(transform.invert().invert(), transform);
+        }
+    }
+
+    #[test]
+    fn size_swaps_axes_on_quarter_turns() {
+        for transform in ALL {
+            let expected = if transform.quarter_turns() % 2 == 1 {
+                (1080, 1920)
+            } else {
+                (1920, 1080)
+            };
+
+            assert_eq!(transform.size((1920, 1080)), expected, "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn size_of_inverse_restores_size() {
+        for transform in ALL {
+            let size = transform.size((640, 480));
+
+            assert_eq!(transform.invert().size(size), (640, 480), "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn point_rotates_counter_clockwise() {
+        let size = (640.0, 480.0);
+
+        assert_eq!(Transform::Normal.point((10.0, 20.0), size), (10.0, 20.0));
+        assert_eq!(Transform::Rotate90.point((10.0, 20.0), size), (20.0, 630.0));
+        assert_eq!(
+            Transform::Rotate180.point((10.0, 20.0), size),
+            (630.0, 460.0)
+        );
+        assert_eq!(Transform::Flipped.point((10.0, 20.0), size), (630.0, 20.0));
+    }
+
+    #[test]
+    fn point_of_inverse_restores_point() {
+        for transform in ALL {
+            let point = transform.point((10.0, 20.0), (640.0, 480.0));
+            let (width, height) = transform.size((640, 480));
+
+            assert_eq!(
+                transform
+                    .invert()
+                    .point(point, (width as f64, height as f64)),
+                (10.0, 20.0),
+                "{transform:?}"
+            );
+        }
+    }
 }
@@ -91,7 +91,14 @@ fn main() -> AnyResult<()> {
 
         let token = CancellationToken::new();
 
-        stagecraft::spawn::<verdi::Compositor>(token.clone(), CompositorInit { socket_path });
+        stagecraft::spawn::<verdi::Compositor>(
+            token.clone(),
+            CompositorInit {
+                socket_path,
+                outputs: config.outputs,
+                privileged_clients: config.privileged_clients,
+            },
+        );
 
         token.cancelled().await;
     });
@@ -1,4 +1,20 @@
 #![allow(unused)]
 
+pub mod commit_timing;
+pub mod data_control;
+pub mod fifo;
+pub mod fractional_scale;
+pub mod idle_inhibit;
+pub mod idle_notify;
+pub mod layer_shell;
+pub mod linux_dmabuf;
+pub mod linux_drm_syncobj;
+pub mod output_power;
+pub mod presentation_time;
+pub mod primary_selection;
+pub mod session_lock;
+pub mod tearing_control;
+pub mod viewporter;
 pub mod wayland;
+pub mod wlr_data_control;
 pub mod xdg;
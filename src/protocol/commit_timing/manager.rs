@@ -0,0 +1,45 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{commit_timing::timer::CommitTimer, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::staging::commit_timing_v1::wp_commit_timing_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct CommitTimingManager;
+
+impl WpCommitTimingManagerV1 for CommitTimingManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_timer(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_commit_timer() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::CommitTimerExists as u32,
+                "Surface already has a commit timer".to_string(),
+            ));
+        }
+
+        client.insert(id, CommitTimer::new(&surface));
+
+        Ok(())
+    }
+}
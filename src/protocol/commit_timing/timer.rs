@@ -0,0 +1,78 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::surface::Surface};
+
+pub use waynest_protocols::server::staging::commit_timing_v1::wp_commit_timer_v1::*;
+
+/// Lets a surface hold a commit until the time it should be shown
+///
+/// Times are on the `wp_presentation` clock.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct CommitTimer {
+    surface: Weak<Surface>,
+}
+
+impl CommitTimer {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+}
+
+impl WpCommitTimerV1 for CommitTimer {
+    type Connection = Client;
+
+    async fn set_timestamp(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        tv_sec_hi: u32,
+        tv_sec_lo: u32,
+        tv_nsec: u32,
+    ) -> Result<()> {
+        let Some(surface) = self.surface.upgrade() else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::SurfaceDestroyed as u32,
+                "Surface was destroyed".to_string(),
+            ));
+        };
+
+        if tv_nsec >= 1_000_000_000 {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidTimestamp as u32,
+                format!("Invalid nanoseconds {tv_nsec}"),
+            ));
+        }
+
+        let secs = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+        let time_ns = i64::try_from(secs)
+            .unwrap_or(i64::MAX)
+            .saturating_mul(1_000_000_000)
+            .saturating_add(tv_nsec as i64);
+
+        if !surface.set_target_time(time_ns).await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::TimestampExists as u32,
+                "Commit already has a timestamp".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_commit_timer().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+}
@@ -0,0 +1,364 @@
+//! `ext_data_control_v1`, and the wlroots protocol it grew out of
+//!
+//! Both are implemented by [`data_control_protocol`], they only differ in
+//! naming and in the wlroots one getting the primary selection with version
+//! 2.
+
+/// Implements a data control protocol, the `manager`, `device`, `source` and
+/// `offer` modules each holding one of its interfaces
+///
+/// Devices of versions before `primary_selection_version` don't hear about
+/// the primary selection, all do if it isn't given.
+macro_rules! data_control_protocol {
+    (
+        protocol: $($protocol:ident)::+,
+        manager: $manager_mod:ident::$manager_trait:ident as $manager:ident,
+        device: $device_mod:ident::$device_trait:ident as $device:ident,
+        source: $source_mod:ident::$source_trait:ident as $source:ident,
+        offer: $offer_mod:ident::$offer_trait:ident as $offer:ident,
+        $(primary_selection_version: $primary_version:literal,)?
+    ) => {
+        pub mod manager {
+            use waynest::ObjectId;
+            use waynest_server::{Client as _, RequestDispatcher};
+
+            use crate::{Client, Result, VerdiError, actors::compositor::SelectionKind};
+
+            use super::{device::$device, source::$source};
+
+            pub use $($protocol)::+::$manager_mod::*;
+
+            /// Lets privileged clients like clipboard managers watch and set
+            /// the selections without focus
+            #[derive(Debug, RequestDispatcher)]
+            #[waynest(error = VerdiError, connection = Client)]
+            pub struct $manager {
+                version: u32,
+            }
+
+            impl $manager {
+                pub fn new(version: u32) -> Self {
+                    Self { version }
+                }
+            }
+
+            impl $manager_trait for $manager {
+                type Connection = Client;
+
+                async fn create_data_source(
+                    &self,
+                    client: &mut Self::Connection,
+                    _sender_id: ObjectId,
+                    id: ObjectId,
+                ) -> Result<()> {
+                    client.insert(id, $source::default());
+
+                    Ok(())
+                }
+
+                async fn get_data_device(
+                    &self,
+                    client: &mut Self::Connection,
+                    _sender_id: ObjectId,
+                    id: ObjectId,
+                    _seat: ObjectId,
+                ) -> Result<()> {
+                    let device = $device::new(self.version);
+
+                    for kind in [SelectionKind::Clipboard, SelectionKind::Primary] {
+                        let selection = client.control_selection(kind).cloned();
+                        device
+                            .send_selection(client, id, kind, selection.as_ref())
+                            .await?;
+                    }
+
+                    client.insert(id, device);
+                    client.add_data_control_device(id);
+
+                    Ok(())
+                }
+
+                async fn destroy(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                ) -> Result<()> {
+                    client.destroy_object(sender_id).await
+                }
+            }
+        }
+
+        pub mod device {
+            use waynest::ObjectId;
+            use waynest_server::{Client as _, RequestDispatcher};
+
+            use crate::{
+                Client, Result, VerdiError,
+                actors::compositor::{CompositorMessage, SelectionKind},
+                protocol::wayland::data_device::OfferedSelection,
+            };
+
+            use super::{offer::$offer, source::$source};
+
+            pub use $($protocol)::+::$device_mod::*;
+
+            #[derive(Debug, RequestDispatcher)]
+            #[waynest(error = VerdiError, connection = Client)]
+            pub struct $device {
+                version: u32,
+            }
+
+            impl $device {
+                pub fn new(version: u32) -> Self {
+                    Self { version }
+                }
+
+                /// Introduces an offer for a selection, `None` telling the
+                /// client it was cleared
+                pub async fn send_selection(
+                    &self,
+                    client: &mut Client,
+                    sender_id: ObjectId,
+                    kind: SelectionKind,
+                    selection: Option<&OfferedSelection>,
+                ) -> Result<()> {
+                    $(
+                        if kind == SelectionKind::Primary && self.version < $primary_version {
+                            return Ok(());
+                        }
+                    )?
+
+                    let offer = match selection {
+                        Some(selection) => {
+                            let id = client.next_object_id();
+                            let offer = $offer::new(selection);
+
+                            self.data_offer(client, sender_id, id).await?;
+                            offer.advertise(client, id).await?;
+                            client.insert(id, offer);
+
+                            Some(id)
+                        }
+                        None => None,
+                    };
+
+                    match kind {
+                        SelectionKind::Clipboard => {
+                            self.selection(client, sender_id, offer).await
+                        }
+                        SelectionKind::Primary => {
+                            self.primary_selection(client, sender_id, offer).await
+                        }
+                    }
+                }
+
+                async fn set(
+                    &self,
+                    client: &mut Client,
+                    sender_id: ObjectId,
+                    kind: SelectionKind,
+                    source: Option<ObjectId>,
+                ) -> Result<()> {
+                    let source = match source {
+                        Some(id) => {
+                            let source = client
+                                .get::<$source>(id)
+                                .ok_or(VerdiError::MissingObject(id))?;
+
+                            if !source.claim() {
+                                return Err(VerdiError::client(
+                                    sender_id,
+                                    Error::UsedSource as u32,
+                                    "Source was already used".to_string(),
+                                ));
+                            }
+
+                            Some((id, source.mime_types().await))
+                        }
+                        None => None,
+                    };
+
+                    let _ = client
+                        .compositor()
+                        .cast(CompositorMessage::ControlSelection {
+                            client_id: client.id(),
+                            kind,
+                            source,
+                        })
+                        .await;
+
+                    Ok(())
+                }
+            }
+
+            impl $device_trait for $device {
+                type Connection = Client;
+
+                async fn set_selection(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                    source: Option<ObjectId>,
+                ) -> Result<()> {
+                    self.set(client, sender_id, SelectionKind::Clipboard, source)
+                        .await
+                }
+
+                async fn destroy(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                ) -> Result<()> {
+                    client.remove_data_control_device(sender_id);
+                    client.destroy_object(sender_id).await
+                }
+
+                async fn set_primary_selection(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                    source: Option<ObjectId>,
+                ) -> Result<()> {
+                    self.set(client, sender_id, SelectionKind::Primary, source)
+                        .await
+                }
+            }
+        }
+
+        pub mod source {
+            use std::sync::atomic::{AtomicBool, Ordering};
+
+            use waynest::ObjectId;
+            use waynest_server::RequestDispatcher;
+
+            use crate::{Client, Result, VerdiError, protocol::wayland::selection::SourceData};
+
+            pub use $($protocol)::+::$source_mod::*;
+
+            /// Data a data control client sets as a selection, written into
+            /// a pipe for each transfer
+            #[derive(Debug, RequestDispatcher, Default)]
+            #[waynest(error = VerdiError, connection = Client)]
+            pub struct $source {
+                data: SourceData,
+                used: AtomicBool,
+            }
+
+            impl $source {
+                pub async fn mime_types(&self) -> Vec<String> {
+                    self.data.mime_types().await
+                }
+
+                /// Returns `false` if the source was already set as a
+                /// selection
+                pub fn claim(&self) -> bool {
+                    !self.used.swap(true, Ordering::Relaxed)
+                }
+            }
+
+            impl $source_trait for $source {
+                type Connection = Client;
+
+                async fn offer(
+                    &self,
+                    _client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                    mime_type: String,
+                ) -> Result<()> {
+                    if self.used.load(Ordering::Relaxed) {
+                        return Err(VerdiError::client(
+                            sender_id,
+                            Error::InvalidOffer as u32,
+                            "Source was already used".to_string(),
+                        ));
+                    }
+
+                    self.data.offer(mime_type).await;
+
+                    Ok(())
+                }
+
+                async fn destroy(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                ) -> Result<()> {
+                    SourceData::destroy(client, sender_id).await
+                }
+            }
+        }
+
+        pub mod offer {
+            use std::os::fd::OwnedFd;
+
+            use waynest::ObjectId;
+            use waynest_server::RequestDispatcher;
+
+            use crate::{
+                Client, Result, VerdiError,
+                protocol::wayland::{data_device::OfferedSelection, selection::OfferData},
+            };
+
+            pub use $($protocol)::+::$offer_mod::*;
+
+            /// A selection as seen by a data control client
+            #[derive(Debug, RequestDispatcher)]
+            #[waynest(error = VerdiError, connection = Client)]
+            pub struct $offer {
+                data: OfferData,
+            }
+
+            impl $offer {
+                pub fn new(selection: &OfferedSelection) -> Self {
+                    Self {
+                        data: OfferData::selection(selection),
+                    }
+                }
+
+                /// Lists the mime types of the offer, right after it was
+                /// introduced
+                pub async fn advertise(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+                    for mime_type in self.data.mime_types() {
+                        self.offer(client, sender_id, mime_type.clone()).await?;
+                    }
+
+                    Ok(())
+                }
+            }
+
+            impl $offer_trait for $offer {
+                type Connection = Client;
+
+                async fn receive(
+                    &self,
+                    client: &mut Self::Connection,
+                    _sender_id: ObjectId,
+                    mime_type: String,
+                    fd: OwnedFd,
+                ) -> Result<()> {
+                    self.data.receive(client, mime_type, fd).await;
+
+                    Ok(())
+                }
+
+                async fn destroy(
+                    &self,
+                    client: &mut Self::Connection,
+                    sender_id: ObjectId,
+                ) -> Result<()> {
+                    client.destroy_object(sender_id).await
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use data_control_protocol;
+
+data_control_protocol! {
+    protocol: waynest_protocols::server::staging::ext_data_control_v1,
+    manager: ext_data_control_manager_v1::ExtDataControlManagerV1 as DataControlManager,
+    device: ext_data_control_device_v1::ExtDataControlDeviceV1 as DataControlDevice,
+    source: ext_data_control_source_v1::ExtDataControlSourceV1 as DataControlSource,
+    offer: ext_data_control_offer_v1::ExtDataControlOfferV1 as DataControlOffer,
+}
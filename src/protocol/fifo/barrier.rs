@@ -0,0 +1,62 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::surface::Surface};
+
+pub use waynest_protocols::server::staging::fifo_v1::wp_fifo_v1::*;
+
+/// Lets a surface queue its commits so each one is shown for at least a
+/// refresh cycle
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Fifo {
+    surface: Weak<Surface>,
+}
+
+impl Fifo {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+
+    fn surface(&self, sender_id: ObjectId) -> Result<Arc<Surface>> {
+        self.surface.upgrade().ok_or_else(|| {
+            VerdiError::client(
+                sender_id,
+                Error::SurfaceDestroyed as u32,
+                "Surface was destroyed".to_string(),
+            )
+        })
+    }
+}
+
+impl WpFifoV1 for Fifo {
+    type Connection = Client;
+
+    async fn set_barrier(&self, _client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        self.surface(sender_id)?.set_fifo_barrier().await;
+
+        Ok(())
+    }
+
+    async fn wait_barrier(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+    ) -> Result<()> {
+        self.surface(sender_id)?.wait_fifo_barrier().await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_fifo().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+}
@@ -0,0 +1,45 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{fifo::barrier::Fifo, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::staging::fifo_v1::wp_fifo_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct FifoManager;
+
+impl WpFifoManagerV1 for FifoManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_fifo(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_fifo() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::AlreadyExists as u32,
+                "Surface already has a fifo object".to_string(),
+            ));
+        }
+
+        client.insert(id, Fifo::new(&surface));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+pub mod barrier;
+pub mod manager;
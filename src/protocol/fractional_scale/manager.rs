@@ -0,0 +1,52 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{fractional_scale::scale::FractionalScale, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::staging::fractional_scale_v1::wp_fractional_scale_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct FractionalScaleManager;
+
+impl WpFractionalScaleManagerV1 for FractionalScaleManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_fractional_scale(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_fractional_scale(id).await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::FractionalScaleExists as u32,
+                "Surface already has a fractional scale object".to_string(),
+            ));
+        }
+
+        let fractional_scale = FractionalScale::new(&surface);
+
+        // Later changes are sent as the surface moves between outputs
+        if let Some(scale) = surface.preferred_scale().await {
+            fractional_scale.send_scale(client, id, scale).await?;
+        }
+
+        client.insert(id, fractional_scale);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::surface::Surface};
+
+pub use waynest_protocols::server::staging::fractional_scale_v1::wp_fractional_scale_v1::*;
+
+/// Tells the client the scale of the output its surface is on, so it can
+/// render at that resolution and scale down through `wp_viewport`
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct FractionalScale {
+    surface: Weak<Surface>,
+}
+
+impl FractionalScale {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+
+    /// Sends the scale as the numerator of a fraction over 120
+    pub async fn send_scale(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        scale: f64,
+    ) -> Result<()> {
+        self.preferred_scale(client, sender_id, (scale * 120.0).round() as u32)
+            .await
+    }
+}
+
+impl WpFractionalScaleV1 for FractionalScale {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_fractional_scale().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+}
@@ -0,0 +1,26 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{Client, Result, VerdiError, actors::compositor::CompositorMessage};
+
+pub use waynest_protocols::server::unstable::idle_inhibit_unstable_v1::zwp_idle_inhibitor_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct IdleInhibitor;
+
+impl ZwpIdleInhibitorV1 for IdleInhibitor {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::IdleInhibitorDestroyed {
+                client_id: client.id(),
+                inhibitor: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+}
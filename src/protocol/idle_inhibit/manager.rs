@@ -0,0 +1,50 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::CompositorMessage,
+    protocol::{
+        idle_inhibit::inhibitor::IdleInhibitor,
+        wayland::surface::{Surface, SurfaceId},
+    },
+};
+
+pub use waynest_protocols::server::unstable::idle_inhibit_unstable_v1::zwp_idle_inhibit_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct IdleInhibitManager;
+
+impl ZwpIdleInhibitManagerV1 for IdleInhibitManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn create_inhibitor(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        client.insert(id, IdleInhibitor);
+
+        // Only holds idle back while the surface is visible
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::NewIdleInhibitor {
+                surface: SurfaceId::new(client.id(), surface),
+                inhibitor: id,
+            })
+            .await;
+
+        Ok(())
+    }
+}
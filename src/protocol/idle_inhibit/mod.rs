@@ -0,0 +1,2 @@
+pub mod inhibitor;
+pub mod manager;
@@ -0,0 +1,28 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{Client, Result, VerdiError, actors::compositor::CompositorMessage};
+
+pub use waynest_protocols::server::staging::ext_idle_notify_v1::ext_idle_notification_v1::*;
+
+/// Gets `idled` once there was no activity for its timeout, and `resumed`
+/// with the next input
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct IdleNotification;
+
+impl ExtIdleNotificationV1 for IdleNotification {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::IdleNotificationDestroyed {
+                client_id: client.id(),
+                notification: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+}
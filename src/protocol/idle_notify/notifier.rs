@@ -0,0 +1,68 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError, actors::compositor::CompositorMessage,
+    protocol::idle_notify::notification::IdleNotification,
+};
+
+pub use waynest_protocols::server::staging::ext_idle_notify_v1::ext_idle_notifier_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct IdleNotifier;
+
+impl IdleNotifier {
+    async fn create_notification(
+        &self,
+        client: &mut Client,
+        id: ObjectId,
+        timeout: u32,
+        inhibitable: bool,
+    ) -> Result<()> {
+        client.insert(id, IdleNotification);
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::NewIdleNotification {
+                client_id: client.id(),
+                notification: id,
+                timeout,
+                inhibitable,
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+impl ExtIdleNotifierV1 for IdleNotifier {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_idle_notification(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        timeout: u32,
+        _seat: ObjectId,
+    ) -> Result<()> {
+        self.create_notification(client, id, timeout, true).await
+    }
+
+    async fn get_input_idle_notification(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        timeout: u32,
+        _seat: ObjectId,
+    ) -> Result<()> {
+        // Only input counts, idle inhibitors don't hold it back
+        self.create_notification(client, id, timeout, false).await
+    }
+}
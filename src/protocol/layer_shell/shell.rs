@@ -0,0 +1,105 @@
+use tracing::debug;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{
+        layer_shell::surface::{LayerSurface, scene_layer},
+        wayland::{
+            output::Output,
+            surface::{Role, Surface},
+        },
+    },
+};
+
+pub use waynest_protocols::server::wlr::wlr_layer_shell_unstable_v1::zwlr_layer_shell_v1::*;
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct LayerShell {
+    version: u32,
+}
+
+impl LayerShell {
+    pub fn new(version: u32) -> Self {
+        Self { version }
+    }
+}
+
+impl ZwlrLayerShellV1 for LayerShell {
+    type Connection = Client;
+
+    async fn get_layer_surface(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+        output: Option<ObjectId>,
+        layer: Layer,
+        namespace: String,
+    ) -> Result<()> {
+        let wl_surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        match wl_surface.role() {
+            Some(Role::LayerSurface) => {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::AlreadyConstructed as u32,
+                    "Surface already has a layer surface".to_string(),
+                ));
+            }
+            Some(_) => {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::Role as u32,
+                    "Surface already has another role".to_string(),
+                ));
+            }
+            None => {}
+        }
+
+        if wl_surface.has_buffer().await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::AlreadyConstructed as u32,
+                "Surface already has a buffer".to_string(),
+            ));
+        }
+
+        // Without an output the compositor picks the one the pointer is on
+        let output = output
+            .map(|output| {
+                client
+                    .get::<Output>(output)
+                    .map(|object| object.id())
+                    .ok_or(VerdiError::MissingObject(output))
+            })
+            .transpose()?;
+
+        debug!("New layer surface for {namespace} on {layer:?}");
+
+        wl_surface.set_role(Role::LayerSurface, sender_id, Error::Role as u32)?;
+        wl_surface.set_layer_surface(id).await;
+
+        client.insert(
+            id,
+            LayerSurface::new(
+                self.version,
+                surface,
+                wl_surface,
+                output,
+                scene_layer(layer),
+            ),
+        );
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+}
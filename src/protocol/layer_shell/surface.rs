@@ -0,0 +1,583 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::{
+        compositor::CompositorMessage,
+        renderer::{Layer, OutputId, damage::Rect},
+    },
+    protocol::{
+        layer_shell::shell,
+        wayland::surface::{Surface, SurfaceId},
+    },
+};
+
+pub use waynest_protocols::server::wlr::wlr_layer_shell_unstable_v1::zwlr_layer_surface_v1::*;
+
+/// First version with on demand keyboard interactivity
+const ON_DEMAND_VERSION: u32 = 4;
+
+/// Distance kept from the anchored edges, in logical pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Margin {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+/// The double buffered state of a layer surface
+#[derive(Debug, Clone, Copy)]
+pub struct LayerState {
+    pub layer: Layer,
+    /// Requested size, 0 to stretch between opposite anchors
+    pub size: (u32, u32),
+    pub anchor: Anchor,
+    /// Space to reserve along the exclusive edge, -1 to ignore the space
+    /// other surfaces reserved
+    pub exclusive_zone: i32,
+    /// Edge the exclusive zone applies to, empty to derive it from the
+    /// anchors
+    pub exclusive_edge: Anchor,
+    pub margin: Margin,
+    pub keyboard_interactivity: KeyboardInteractivity,
+}
+
+impl LayerState {
+    fn new(layer: Layer) -> Self {
+        Self {
+            layer,
+            size: (0, 0),
+            anchor: Anchor::empty(),
+            exclusive_zone: 0,
+            exclusive_edge: Anchor::empty(),
+            margin: Margin::default(),
+            keyboard_interactivity: KeyboardInteractivity::None,
+        }
+    }
+
+    /// Where the surface goes within `bounds`, its size being what it gets
+    /// configured with
+    ///
+    /// Surfaces anchored to both or neither of two opposite edges are
+    /// centered between them.
+    pub fn geometry(&self, bounds: Rect) -> Rect {
+        let (width, x) = place_along(
+            self.size.0,
+            (bounds.x, bounds.width),
+            (self.margin.left, self.margin.right),
+            (
+                self.anchor.contains(Anchor::Left),
+                self.anchor.contains(Anchor::Right),
+            ),
+        );
+
+        let (height, y) = place_along(
+            self.size.1,
+            (bounds.y, bounds.height),
+            (self.margin.top, self.margin.bottom),
+            (
+                self.anchor.contains(Anchor::Top),
+                self.anchor.contains(Anchor::Bottom),
+            ),
+        );
+
+        Rect::new(x, y, width, height)
+    }
+
+    /// The edge the exclusive zone is reserved along, with how much to
+    /// reserve including the margin, `None` if the surface reserves nothing
+    pub fn exclusive(&self) -> Option<(Anchor, i32)> {
+        if self.exclusive_zone <= 0 {
+            return None;
+        }
+
+        let mut edges = [
+            (Anchor::Top, Anchor::Left | Anchor::Right, self.margin.top),
+            (
+                Anchor::Bottom,
+                Anchor::Left | Anchor::Right,
+                self.margin.bottom,
+            ),
+            (Anchor::Left, Anchor::Top | Anchor::Bottom, self.margin.left),
+            (
+                Anchor::Right,
+                Anchor::Top | Anchor::Bottom,
+                self.margin.right,
+            ),
+        ]
+        .into_iter();
+
+        let (edge, _, margin) = if !self.exclusive_edge.is_empty() {
+            edges.find(|(edge, ..)| *edge == self.exclusive_edge)?
+        } else {
+            // Anchored to one edge, or to one edge and both of its neighbours
+            edges.find(|(edge, sides, _)| self.anchor == *edge || self.anchor == *edge | *sides)?
+        };
+
+        Some((edge, self.exclusive_zone + margin))
+    }
+
+    /// Takes the exclusive zone off `usable`, the area left for surfaces that
+    /// respect the exclusive zones of others
+    pub fn reserve(&self, usable: &mut Rect) {
+        let Some((edge, amount)) = self.exclusive() else {
+            return;
+        };
+
+        if edge == Anchor::Top || edge == Anchor::Bottom {
+            let amount = amount.min(usable.height);
+            usable.height -= amount;
+
+            if edge == Anchor::Top {
+                usable.y += amount;
+            }
+        } else {
+            let amount = amount.min(usable.width);
+            usable.width -= amount;
+
+            if edge == Anchor::Left {
+                usable.x += amount;
+            }
+        }
+    }
+
+    /// Whether the surface may get keyboard focus at all
+    pub fn wants_keyboard(&self) -> bool {
+        !matches!(self.keyboard_interactivity, KeyboardInteractivity::None)
+    }
+
+    /// Whether the surface takes keyboard focus from everything else while
+    /// mapped, only honored on the layers above toplevels
+    pub fn grabs_keyboard(&self) -> bool {
+        matches!(
+            self.keyboard_interactivity,
+            KeyboardInteractivity::Exclusive
+        ) && self.layer >= Layer::Top
+    }
+}
+
+/// Size and position along one axis, given the requested size, the bounds as
+/// `(start, length)`, the margins and which of the two edges are anchored
+fn place_along(
+    size: u32,
+    (start, length): (i32, i32),
+    (margin_start, margin_end): (i32, i32),
+    anchored: (bool, bool),
+) -> (i32, i32) {
+    let size = match size {
+        0 => (length - margin_start - margin_end).max(0),
+        size => size as i32,
+    };
+
+    let position = match anchored {
+        (true, false) => start + margin_start,
+        (false, true) => start + length - margin_end - size,
+        (true, true) => start + margin_start + (length - margin_start - margin_end - size) / 2,
+        (false, false) => start + (length - size) / 2,
+    };
+
+    (size, position)
+}
+
+pub fn scene_layer(layer: shell::Layer) -> Layer {
+    match layer {
+        shell::Layer::Background => Layer::Background,
+        shell::Layer::Bottom => Layer::Bottom,
+        shell::Layer::Top => Layer::Top,
+        shell::Layer::Overlay => Layer::Overlay,
+    }
+}
+
+#[derive(Debug)]
+struct Configure {
+    pending: LayerState,
+    /// Set when `pending` changed since the last commit
+    changed: bool,
+    /// Serials of configure events not acked yet, oldest first
+    serials: Vec<u32>,
+    /// Set once a configure was acked, cleared again when unmapped
+    acked: bool,
+}
+
+/// A surface on one of the layers around toplevels, for panels, wallpapers,
+/// notifications and the like
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct LayerSurface {
+    version: u32,
+    surface_id: ObjectId,
+    wl_surface: Arc<Surface>,
+    /// Output the client asked for, `None` to leave it to the compositor
+    output: Option<OutputId>,
+    state: RwLock<Configure>,
+}
+
+impl LayerSurface {
+    pub fn new(
+        version: u32,
+        surface_id: ObjectId,
+        wl_surface: Arc<Surface>,
+        output: Option<OutputId>,
+        layer: Layer,
+    ) -> Self {
+        Self {
+            version,
+            surface_id,
+            wl_surface,
+            output,
+            state: RwLock::new(Configure {
+                pending: LayerState::new(layer),
+                // The initial commit always gets a configure
+                changed: true,
+                serials: Vec::new(),
+                acked: false,
+            }),
+        }
+    }
+
+    pub fn output(&self) -> Option<OutputId> {
+        self.output
+    }
+
+    /// Checks the state as of a `wl_surface.commit`, returning it if it
+    /// changed
+    ///
+    /// `has_buffer` is whether the surface has a buffer from that commit on,
+    /// committing a null buffer unmaps the surface and starts over with the
+    /// initial commit.
+    pub async fn commit(
+        &self,
+        sender_id: ObjectId,
+        has_buffer: bool,
+    ) -> Result<Option<LayerState>> {
+        let mut state = self.state.write().await;
+        let pending = state.pending;
+
+        if (pending.size.0 == 0 && !pending.anchor.contains(Anchor::Left | Anchor::Right))
+            || (pending.size.1 == 0 && !pending.anchor.contains(Anchor::Top | Anchor::Bottom))
+        {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidSize as u32,
+                "A size of 0 needs anchors on both sides".to_string(),
+            ));
+        }
+
+        if !pending.anchor.contains(pending.exclusive_edge) {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidExclusiveEdge as u32,
+                "The exclusive edge isn't anchored".to_string(),
+            ));
+        }
+
+        if !has_buffer {
+            if state.acked {
+                state.acked = false;
+                state.changed = true;
+
+                return Ok(None);
+            }
+        } else if !state.acked {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidSurfaceState as u32,
+                "Buffer attached before the first configure was acked".to_string(),
+            ));
+        }
+
+        Ok(std::mem::take(&mut state.changed).then_some(pending))
+    }
+
+    /// Tells the client the size to use, to be acked before it takes effect
+    pub async fn send_configure(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        let serial = client.next_event_serial();
+        self.state.write().await.serials.push(serial);
+
+        self.configure(client, sender_id, serial, width, height)
+            .await
+    }
+
+    async fn update(&self, f: impl FnOnce(&mut LayerState)) {
+        let mut state = self.state.write().await;
+
+        f(&mut state.pending);
+        state.changed = true;
+    }
+}
+
+impl ZwlrLayerSurfaceV1 for LayerSurface {
+    type Connection = Client;
+
+    async fn set_size(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        self.update(|state| state.size = (width, height)).await;
+
+        Ok(())
+    }
+
+    async fn set_anchor(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        anchor: Anchor,
+    ) -> Result<()> {
+        self.update(|state| state.anchor = anchor).await;
+
+        Ok(())
+    }
+
+    async fn set_exclusive_zone(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        zone: i32,
+    ) -> Result<()> {
+        self.update(|state| state.exclusive_zone = zone.max(-1))
+            .await;
+
+        Ok(())
+    }
+
+    async fn set_margin(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        top: i32,
+        right: i32,
+        bottom: i32,
+        left: i32,
+    ) -> Result<()> {
+        self.update(|state| {
+            state.margin = Margin {
+                top,
+                right,
+                bottom,
+                left,
+            }
+        })
+        .await;
+
+        Ok(())
+    }
+
+    async fn set_keyboard_interactivity(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        keyboard_interactivity: KeyboardInteractivity,
+    ) -> Result<()> {
+        if self.version < ON_DEMAND_VERSION
+            && matches!(keyboard_interactivity, KeyboardInteractivity::OnDemand)
+        {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidKeyboardInteractivity as u32,
+                "On demand keyboard interactivity needs version 4".to_string(),
+            ));
+        }
+
+        self.update(|state| state.keyboard_interactivity = keyboard_interactivity)
+            .await;
+
+        Ok(())
+    }
+
+    async fn get_popup(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        _popup: ObjectId,
+    ) -> Result<()> {
+        // There's no `xdg_popup` yet, nothing valid can be passed here
+        Err(VerdiError::client(
+            sender_id,
+            Error::InvalidSurfaceState as u32,
+            "Popups aren't supported".to_string(),
+        ))
+    }
+
+    async fn ack_configure(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        serial: u32,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        let Some(index) = state.serials.iter().position(|sent| *sent == serial) else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidSurfaceState as u32,
+                format!("Unknown configure serial {serial}"),
+            ));
+        };
+
+        // Acking a configure skips the ones sent before it
+        state.serials.drain(..=index);
+        state.acked = true;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // Unmaps the surface, which can't get a role again
+        self.wl_surface.release_layer_surface().await;
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::RemoveLayerSurface {
+                surface: SurfaceId::new(client.id(), self.surface_id),
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn set_layer(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        layer: shell::Layer,
+    ) -> Result<()> {
+        self.update(|state| state.layer = scene_layer(layer)).await;
+
+        Ok(())
+    }
+
+    async fn set_exclusive_edge(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        edge: Anchor,
+    ) -> Result<()> {
+        if edge.bits().count_ones() > 1 {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidExclusiveEdge as u32,
+                "The exclusive edge has to be a single edge".to_string(),
+            ));
+        }
+
+        self.update(|state| state.exclusive_edge = edge).await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: Rect = Rect::new(0, 0, 1920, 1080);
+
+    fn state(anchor: Anchor, size: (u32, u32), exclusive_zone: i32) -> LayerState {
+        LayerState {
+            size,
+            anchor,
+            exclusive_zone,
+            ..LayerState::new(Layer::Top)
+        }
+    }
+
+    #[test]
+    fn top_panel_reserves_its_height() {
+        let panel = state(Anchor::Top | Anchor::Left | Anchor::Right, (0, 30), 30);
+
+        assert_eq!(panel.geometry(OUTPUT), Rect::new(0, 0, 1920, 30));
+        assert_eq!(panel.exclusive(), Some((Anchor::Top, 30)));
+
+        let mut usable = OUTPUT;
+        panel.reserve(&mut usable);
+
+        assert_eq!(usable, Rect::new(0, 30, 1920, 1050));
+    }
+
+    #[test]
+    fn exclusive_zone_includes_the_margin() {
+        let mut dock = state(Anchor::Bottom, (600, 40), 40);
+        dock.margin.bottom = 8;
+
+        assert_eq!(dock.geometry(OUTPUT), Rect::new(660, 1032, 600, 40));
+        assert_eq!(dock.exclusive(), Some((Anchor::Bottom, 48)));
+
+        let mut usable = OUTPUT;
+        dock.reserve(&mut usable);
+
+        assert_eq!(usable, Rect::new(0, 0, 1920, 1032));
+    }
+
+    #[test]
+    fn corner_needs_an_exclusive_edge() {
+        let mut corner = state(Anchor::Top | Anchor::Left, (200, 200), 200);
+
+        assert_eq!(corner.exclusive(), None);
+
+        corner.exclusive_edge = Anchor::Left;
+        corner.margin.left = 5;
+
+        assert_eq!(corner.exclusive(), Some((Anchor::Left, 205)));
+
+        let mut usable = OUTPUT;
+        corner.reserve(&mut usable);
+
+        assert_eq!(usable, Rect::new(205, 0, 1715, 1080));
+    }
+
+    #[test]
+    fn non_positive_zones_reserve_nothing() {
+        for zone in [0, -1] {
+            let surface = state(Anchor::Top | Anchor::Left | Anchor::Right, (0, 30), zone);
+
+            assert_eq!(surface.exclusive(), None);
+
+            let mut usable = OUTPUT;
+            surface.reserve(&mut usable);
+
+            assert_eq!(usable, OUTPUT);
+        }
+    }
+
+    #[test]
+    fn reserve_stops_at_the_usable_area() {
+        let surface = state(Anchor::Right | Anchor::Top | Anchor::Bottom, (0, 0), 5000);
+
+        let mut usable = OUTPUT;
+        surface.reserve(&mut usable);
+
+        assert_eq!(usable, Rect::new(0, 0, 0, 1080));
+    }
+
+    #[test]
+    fn stretched_surface_fills_what_is_left() {
+        let panel = state(Anchor::Top | Anchor::Left | Anchor::Right, (0, 30), 30);
+        let mut usable = OUTPUT;
+        panel.reserve(&mut usable);
+
+        let mut window = state(Anchor::all(), (0, 0), 0);
+        window.margin = Margin {
+            top: 10,
+            right: 10,
+            bottom: 10,
+            left: 10,
+        };
+
+        assert_eq!(window.geometry(usable), Rect::new(10, 40, 1900, 1030));
+    }
+}
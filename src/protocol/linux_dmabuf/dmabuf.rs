@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::DmabufFeedback,
+    protocol::{
+        linux_dmabuf::{feedback::Feedback, params::Params},
+        wayland::surface::Surface,
+    },
+};
+
+pub use waynest_protocols::server::stable::linux_dmabuf_v1::zwp_linux_dmabuf_v1::*;
+
+/// First version announcing formats through feedback objects
+const FEEDBACK_VERSION: u32 = 4;
+
+/// First version announcing modifiers
+const MODIFIER_VERSION: u32 = 3;
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct LinuxDmabuf {
+    feedback: Arc<DmabufFeedback>,
+}
+
+impl LinuxDmabuf {
+    pub fn new(feedback: DmabufFeedback) -> Self {
+        Self {
+            feedback: Arc::new(feedback),
+        }
+    }
+
+    /// Announces the supported formats to clients binding a version without
+    /// feedback support
+    pub async fn advertise_formats(
+        &self,
+        client: &mut <Self as ZwpLinuxDmabufV1>::Connection,
+        sender_id: ObjectId,
+        version: u32,
+    ) -> Result<()> {
+        if version >= FEEDBACK_VERSION {
+            return Ok(());
+        }
+
+        if version >= MODIFIER_VERSION {
+            for format in &self.feedback.formats {
+                self.modifier(
+                    client,
+                    sender_id,
+                    format.format,
+                    (format.modifier >> 32) as u32,
+                    format.modifier as u32,
+                )
+                .await?;
+            }
+
+            return Ok(());
+        }
+
+        let mut formats: Vec<_> = self
+            .feedback
+            .formats
+            .iter()
+            .map(|format| format.format)
+            .collect();
+        formats.dedup();
+
+        for format in formats {
+            self.format(client, sender_id, format).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ZwpLinuxDmabufV1 for LinuxDmabuf {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn create_params(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        params_id: ObjectId,
+    ) -> Result<()> {
+        client.insert(params_id, Params::new(self.feedback.clone()));
+
+        Ok(())
+    }
+
+    async fn get_default_feedback(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+    ) -> Result<()> {
+        let feedback = Feedback::new(self.feedback.clone());
+
+        feedback.send_feedback(client, id, None).await?;
+
+        client.insert(id, feedback);
+
+        Ok(())
+    }
+
+    async fn get_surface_feedback(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        surface_id: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface_id)
+            .ok_or(VerdiError::MissingObject(surface_id))?;
+
+        let feedback = Feedback::for_surface(self.feedback.clone(), &surface);
+
+        feedback
+            .send_feedback(client, id, surface.output().await)
+            .await?;
+
+        client.insert(id, feedback);
+        surface.add_dmabuf_feedback(id).await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::Write,
+    os::fd::{AsFd, OwnedFd},
+    sync::{Arc, Weak},
+};
+
+use rustix::fs::{MemfdFlags, SealFlags, fcntl_add_seals, memfd_create};
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::{DmabufFeedback, DmabufFormat, OutputId},
+    protocol::wayland::surface::Surface,
+};
+
+pub use waynest_protocols::server::stable::linux_dmabuf_v1::zwp_linux_dmabuf_feedback_v1::*;
+
+/// Size of a format table entry, a `u32` format, padding and a `u64` modifier
+const FORMAT_TABLE_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Feedback {
+    feedback: Arc<DmabufFeedback>,
+    /// Set for surface feedback, which follows the surface across outputs
+    surface: Option<Weak<Surface>>,
+}
+
+/// Writes the format and modifier pairs to a sealed memfd clients can map
+fn format_table(formats: &[DmabufFormat]) -> std::io::Result<OwnedFd> {
+    let fd = memfd_create(
+        "verdi-dmabuf-formats",
+        MemfdFlags::CLOEXEC | MemfdFlags::ALLOW_SEALING,
+    )?;
+
+    let mut table = Vec::with_capacity(formats.len() * FORMAT_TABLE_ENTRY_SIZE);
+    for format in formats {
+        table.extend_from_slice(&format.format.to_ne_bytes());
+        table.extend_from_slice(&[0; 4]);
+        table.extend_from_slice(&format.modifier.to_ne_bytes());
+    }
+
+    let mut file = File::from(fd);
+    file.write_all(&table)?;
+
+    // Clients map the table read only, make sure it stays as sent
+    fcntl_add_seals(
+        &file,
+        SealFlags::SHRINK | SealFlags::GROW | SealFlags::WRITE | SealFlags::SEAL,
+    )?;
+
+    Ok(file.into())
+}
+
+impl Feedback {
+    pub fn new(feedback: Arc<DmabufFeedback>) -> Self {
+        Self {
+            feedback,
+            surface: None,
+        }
+    }
+
+    pub fn for_surface(feedback: Arc<DmabufFeedback>, surface: &Arc<Surface>) -> Self {
+        Self {
+            feedback,
+            surface: Some(Arc::downgrade(surface)),
+        }
+    }
+
+    /// Sends the whole feedback
+    ///
+    /// Surfaces on an output with an overlay plane first get a scanout
+    /// tranche with the pairs it takes, then everything goes in a tranche
+    /// for compositing on the main device.
+    pub async fn send_feedback(
+        &self,
+        client: &mut <Self as ZwpLinuxDmabufFeedbackV1>::Connection,
+        sender_id: ObjectId,
+        output: Option<OutputId>,
+    ) -> Result<()> {
+        let feedback = &self.feedback;
+        let table = format_table(&feedback.formats)?;
+        let size = (feedback.formats.len() * FORMAT_TABLE_ENTRY_SIZE) as u32;
+        let device = feedback.main_device.to_ne_bytes().to_vec();
+
+        self.format_table(client, sender_id, table.as_fd(), size)
+            .await?;
+        self.main_device(client, sender_id, device.clone()).await?;
+
+        if let Some(scanout) = output.and_then(|output| feedback.scanout.get(&output)) {
+            // Indices into the format table, which holds every scanout pair
+            let indices = scanout
+                .iter()
+                .filter_map(|format| feedback.formats.iter().position(|other| other == format))
+                .flat_map(|index| (index as u16).to_ne_bytes())
+                .collect();
+
+            self.tranche_target_device(client, sender_id, device.clone())
+                .await?;
+            self.tranche_formats(client, sender_id, indices).await?;
+            self.tranche_flags(client, sender_id, TrancheFlags::Scanout)
+                .await?;
+            self.tranche_done(client, sender_id).await?;
+        }
+
+        let indices = (0..feedback.formats.len() as u16)
+            .flat_map(u16::to_ne_bytes)
+            .collect();
+
+        self.tranche_target_device(client, sender_id, device)
+            .await?;
+        self.tranche_formats(client, sender_id, indices).await?;
+        self.tranche_flags(client, sender_id, TrancheFlags::empty())
+            .await?;
+        self.tranche_done(client, sender_id).await?;
+
+        self.done(client, sender_id).await
+    }
+}
+
+impl ZwpLinuxDmabufFeedbackV1 for Feedback {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.as_ref().and_then(Weak::upgrade) {
+            surface.remove_dmabuf_feedback(sender_id).await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+}
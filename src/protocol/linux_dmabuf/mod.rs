@@ -0,0 +1,3 @@
+pub mod dmabuf;
+pub mod feedback;
+pub mod params;
@@ -0,0 +1,257 @@
+use std::{os::fd::OwnedFd, sync::Arc};
+
+use rustix::fs::{SeekFrom, seek};
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::{Dmabuf, DmabufFeedback, DmabufFormat, DmabufPlane},
+    protocol::wayland::buffer::Buffer,
+};
+
+pub use waynest_protocols::server::stable::linux_dmabuf_v1::zwp_linux_buffer_params_v1::*;
+
+/// Most planes a dmabuf can have
+const MAX_PLANES: usize = 4;
+
+#[derive(Debug, Default)]
+struct State {
+    /// Set once a buffer was created, params are single use
+    used: bool,
+    planes: [Option<DmabufPlane>; MAX_PLANES],
+    /// Shared by all planes
+    modifier: Option<u64>,
+}
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Params {
+    /// What the renderer can import
+    feedback: Arc<DmabufFeedback>,
+    state: RwLock<State>,
+}
+
+impl Params {
+    pub fn new(feedback: Arc<DmabufFeedback>) -> Self {
+        Self {
+            feedback,
+            state: RwLock::default(),
+        }
+    }
+
+    /// Validates the collected planes and turns them into a dmabuf
+    ///
+    /// Protocol violations are errors, `Ok(None)` means the buffer is valid
+    /// but can't be imported.
+    async fn take_dmabuf(
+        &self,
+        sender_id: ObjectId,
+        width: i32,
+        height: i32,
+        format: u32,
+        flags: Flags,
+    ) -> Result<Option<Dmabuf>> {
+        let mut state = self.state.write().await;
+
+        if state.used {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::AlreadyUsed as u32,
+                "Params were already used to create a buffer",
+            ));
+        }
+
+        state.used = true;
+
+        let planes: Vec<_> = state.planes.iter_mut().map(Option::take).collect();
+
+        // Planes have to be set without gaps, starting from the first
+        let count = planes.iter().take_while(|plane| plane.is_some()).count();
+        if count == 0 || planes[count..].iter().any(Option::is_some) {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::Incomplete as u32,
+                "Missing dmabuf planes",
+            ));
+        }
+
+        let (width, height) = match (u32::try_from(width), u32::try_from(height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+            _ => {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::InvalidDimensions as u32,
+                    format!("Invalid buffer size {width}x{height}"),
+                ));
+            }
+        };
+
+        let planes: Vec<DmabufPlane> = planes.into_iter().flatten().collect();
+
+        for (index, plane) in planes.iter().enumerate() {
+            // Only the first plane is guaranteed to span the full height,
+            // subsampled planes are checked for at least one row
+            let rows = if index == 0 { height as u64 } else { 1 };
+            let end = plane.offset as u64 + plane.stride as u64 * rows;
+
+            // Not every driver supports seeking dmabufs, their size is then
+            // left for the import to check
+            if let Ok(size) = seek(&plane.fd, SeekFrom::End(0))
+                && end > size
+            {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::OutOfBounds as u32,
+                    format!("Plane {index} ends past the dmabuf at byte {end}"),
+                ));
+            }
+        }
+
+        let modifier = state.modifier.unwrap_or_default();
+
+        let Some((max_width, max_height)) =
+            self.feedback.max_extent(DmabufFormat { format, modifier })
+        else {
+            return Ok(None);
+        };
+
+        // Only single plane pairs get advertised, more planes than the pair
+        // has are a client bug
+        if planes.len() != 1 {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::Incomplete as u32,
+                format!(
+                    "Format {format:#x} with modifier {modifier:#x} takes a single plane, got {}",
+                    planes.len()
+                ),
+            ));
+        }
+
+        // Flags ask for y-inverted or interlaced contents, neither of which
+        // can be displayed
+        let supported = flags.is_empty() && width <= max_width && height <= max_height;
+
+        Ok(supported.then(|| Dmabuf {
+            width,
+            height,
+            format,
+            modifier,
+            planes,
+        }))
+    }
+}
+
+impl ZwpLinuxBufferParamsV1 for Params {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn add(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        fd: OwnedFd,
+        plane_idx: u32,
+        offset: u32,
+        stride: u32,
+        modifier_hi: u32,
+        modifier_lo: u32,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        if state.used {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::AlreadyUsed as u32,
+                "Params were already used to create a buffer",
+            ));
+        }
+
+        let Some(plane) = state.planes.get_mut(plane_idx as usize) else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::PlaneIdx as u32,
+                format!("Plane index {plane_idx} is out of bounds"),
+            ));
+        };
+
+        if plane.is_some() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::PlaneSet as u32,
+                format!("Plane {plane_idx} was already set"),
+            ));
+        }
+
+        *plane = Some(DmabufPlane { fd, offset, stride });
+
+        let modifier = (modifier_hi as u64) << 32 | modifier_lo as u64;
+
+        match state.modifier {
+            Some(previous) if previous != modifier => Err(VerdiError::client(
+                sender_id,
+                Error::InvalidFormat as u32,
+                "All planes must use the same modifier",
+            )),
+            _ => {
+                state.modifier = Some(modifier);
+                Ok(())
+            }
+        }
+    }
+
+    async fn create(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        width: i32,
+        height: i32,
+        format: u32,
+        flags: Flags,
+    ) -> Result<()> {
+        let Some(dmabuf) = self
+            .take_dmabuf(sender_id, width, height, format, flags)
+            .await?
+        else {
+            return self.failed(client, sender_id).await;
+        };
+
+        let id = client.next_object_id();
+        client.insert(id, Buffer::dmabuf(id, Arc::new(dmabuf)));
+
+        self.created(client, sender_id, id).await
+    }
+
+    async fn create_immed(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        buffer_id: ObjectId,
+        width: i32,
+        height: i32,
+        format: u32,
+        flags: Flags,
+    ) -> Result<()> {
+        let Some(dmabuf) = self
+            .take_dmabuf(sender_id, width, height, format, flags)
+            .await?
+        else {
+            // The client already uses the buffer, there's no way to report
+            // the failure other than disconnecting
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidWlBuffer as u32,
+                format!("Unsupported dmabuf of format {format:#x}"),
+            ));
+        };
+
+        client.insert(buffer_id, Buffer::dmabuf(buffer_id, Arc::new(dmabuf)));
+
+        Ok(())
+    }
+}
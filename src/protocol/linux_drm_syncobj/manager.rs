@@ -0,0 +1,79 @@
+use std::os::fd::OwnedFd;
+
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::SyncobjDevice,
+    protocol::{
+        linux_drm_syncobj::{surface::SyncobjSurface, timeline::SyncobjTimeline},
+        wayland::surface::Surface,
+    },
+};
+
+pub use waynest_protocols::server::staging::linux_drm_syncobj_v1::wp_linux_drm_syncobj_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct SyncobjManager {
+    device: SyncobjDevice,
+}
+
+impl SyncobjManager {
+    pub fn new(device: SyncobjDevice) -> Self {
+        Self { device }
+    }
+}
+
+impl WpLinuxDrmSyncobjManagerV1 for SyncobjManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_surface(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_syncobj_surface(id).await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::SurfaceExists as u32,
+                "Surface already has a syncobj surface".to_string(),
+            ));
+        }
+
+        client.insert(id, SyncobjSurface::new(&surface));
+
+        Ok(())
+    }
+
+    async fn import_timeline(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        fd: OwnedFd,
+    ) -> Result<()> {
+        let timeline = self.device.import_timeline(&fd).map_err(|e| {
+            VerdiError::client(
+                sender_id,
+                Error::InvalidTimeline as u32,
+                format!("Failed to import timeline: {e}"),
+            )
+        })?;
+
+        client.insert(id, SyncobjTimeline::new(timeline));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod manager;
+pub mod surface;
+pub mod timeline;
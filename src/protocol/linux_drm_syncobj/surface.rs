@@ -0,0 +1,98 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::SyncPoint,
+    protocol::{linux_drm_syncobj::timeline::SyncobjTimeline, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::staging::linux_drm_syncobj_v1::wp_linux_drm_syncobj_surface_v1::*;
+
+/// Explicit synchronization for the buffers of a surface
+///
+/// Points are only validated once the surface commits.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct SyncobjSurface {
+    surface: Weak<Surface>,
+}
+
+impl SyncobjSurface {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+
+    fn sync_point(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        timeline: ObjectId,
+        point_hi: u32,
+        point_lo: u32,
+    ) -> Result<(Arc<Surface>, SyncPoint)> {
+        let surface = self.surface.upgrade().ok_or_else(|| {
+            VerdiError::client(
+                sender_id,
+                Error::NoSurface as u32,
+                "Surface was destroyed".to_string(),
+            )
+        })?;
+
+        let timeline = client
+            .get::<SyncobjTimeline>(timeline)
+            .ok_or(VerdiError::MissingObject(timeline))?;
+
+        let point = SyncPoint {
+            timeline: timeline.timeline().clone(),
+            point: ((point_hi as u64) << 32) | point_lo as u64,
+        };
+
+        Ok((surface, point))
+    }
+}
+
+impl WpLinuxDrmSyncobjSurfaceV1 for SyncobjSurface {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // Back to implicit sync with the next commit
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_syncobj_surface().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn set_acquire_point(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        timeline: ObjectId,
+        point_hi: u32,
+        point_lo: u32,
+    ) -> Result<()> {
+        let (surface, point) = self.sync_point(client, sender_id, timeline, point_hi, point_lo)?;
+        surface.set_acquire_point(point).await;
+
+        Ok(())
+    }
+
+    async fn set_release_point(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        timeline: ObjectId,
+        point_hi: u32,
+        point_lo: u32,
+    ) -> Result<()> {
+        let (surface, point) = self.sync_point(client, sender_id, timeline, point_hi, point_lo)?;
+        surface.set_release_point(point).await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, actors::renderer::Timeline};
+
+pub use waynest_protocols::server::staging::linux_drm_syncobj_v1::wp_linux_drm_syncobj_timeline_v1::*;
+
+/// A DRM syncobj timeline imported from a client
+///
+/// Points set on surfaces keep the timeline alive past its destruction.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct SyncobjTimeline {
+    timeline: Arc<Timeline>,
+}
+
+impl SyncobjTimeline {
+    pub fn new(timeline: Timeline) -> Self {
+        Self {
+            timeline: Arc::new(timeline),
+        }
+    }
+
+    pub fn timeline(&self) -> &Arc<Timeline> {
+        &self.timeline
+    }
+}
+
+impl WpLinuxDrmSyncobjTimelineV1 for SyncobjTimeline {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+}
@@ -0,0 +1,51 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::CompositorMessage,
+    protocol::{output_power::power::OutputPower, wayland::output::Output},
+};
+
+pub use waynest_protocols::server::wlr::wlr_output_power_management_unstable_v1::zwlr_output_power_manager_v1::*;
+
+/// Lets privileged clients like idle daemons turn outputs off and back on
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct OutputPowerManager;
+
+impl ZwlrOutputPowerManagerV1 for OutputPowerManager {
+    type Connection = Client;
+
+    async fn get_output_power(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        output: ObjectId,
+    ) -> Result<()> {
+        let output = client
+            .get::<Output>(output)
+            .map(|object| object.id())
+            .ok_or(VerdiError::MissingObject(output))?;
+
+        client.insert(id, OutputPower);
+
+        // The compositor sends the current mode, or `failed` if the output
+        // is gone already
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::NewOutputPower {
+                client_id: client.id(),
+                power: id,
+                output,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+}
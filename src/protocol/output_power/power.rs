@@ -0,0 +1,46 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{Client, Result, VerdiError, actors::compositor::CompositorMessage};
+
+pub use waynest_protocols::server::wlr::wlr_output_power_management_unstable_v1::zwlr_output_power_v1::*;
+
+/// Gets the power mode of its output whenever it changes, and `failed` once
+/// it can't change it anymore
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct OutputPower;
+
+impl ZwlrOutputPowerV1 for OutputPower {
+    type Connection = Client;
+
+    async fn set_mode(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        mode: Mode,
+    ) -> Result<()> {
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::SetOutputPower {
+                client_id: client.id(),
+                power: sender_id,
+                on: matches!(mode, Mode::On),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::OutputPowerDestroyed {
+                client_id: client.id(),
+                power: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+}
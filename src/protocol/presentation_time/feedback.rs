@@ -0,0 +1,65 @@
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, actors::renderer::Presented};
+
+pub use waynest_protocols::server::stable::presentation_time::wp_presentation_feedback::*;
+
+/// Waits for a content update to be shown, then destroys itself
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct PresentationFeedback;
+
+impl PresentationFeedback {
+    pub async fn send_presented(
+        &self,
+        client: &mut <Self as WpPresentationFeedback>::Connection,
+        sender_id: ObjectId,
+        presented: Presented,
+    ) -> Result<()> {
+        for output in client.output_objects(presented.output) {
+            self.sync_output(client, sender_id, output).await?;
+        }
+
+        let seconds = (presented.time_ns / 1_000_000_000) as u64;
+        let nanoseconds = (presented.time_ns % 1_000_000_000) as u32;
+
+        // Without an observed flip the timestamp is that of the vblank
+        // following the submission, which the frame may have missed
+        let mut flags = Kind::empty();
+        if presented.hw_completion {
+            flags |= Kind::HwClock | Kind::HwCompletion;
+        }
+        if presented.vsync {
+            flags |= Kind::Vsync;
+        }
+        if presented.zero_copy {
+            flags |= Kind::ZeroCopy;
+        }
+
+        self.presented(
+            client,
+            sender_id,
+            (seconds >> 32) as u32,
+            seconds as u32,
+            nanoseconds,
+            presented.refresh_ns.unwrap_or(0),
+            (presented.sequence >> 32) as u32,
+            presented.sequence as u32,
+            flags,
+        )
+        .await
+    }
+
+    pub async fn send_discarded(
+        &self,
+        client: &mut <Self as WpPresentationFeedback>::Connection,
+        sender_id: ObjectId,
+    ) -> Result<()> {
+        self.discarded(client, sender_id).await
+    }
+}
+
+impl WpPresentationFeedback for PresentationFeedback {
+    type Connection = Client;
+}
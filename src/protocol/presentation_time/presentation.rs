@@ -0,0 +1,53 @@
+use rustix::time::ClockId;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{presentation_time::feedback::PresentationFeedback, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::stable::presentation_time::wp_presentation::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Presentation;
+
+impl Presentation {
+    /// Tells the client which clock presentation timestamps are taken from,
+    /// the one DRM timestamps vblanks with
+    pub async fn advertise_clock(
+        &self,
+        client: &mut <Self as WpPresentation>::Connection,
+        sender_id: ObjectId,
+    ) -> Result<()> {
+        self.clock_id(client, sender_id, ClockId::Monotonic as u32)
+            .await
+    }
+}
+
+impl WpPresentation for Presentation {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn feedback(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        surface: ObjectId,
+        callback: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        client.insert(callback, PresentationFeedback::default());
+
+        surface.add_presentation_feedback(callback).await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use tracing::debug;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::{CompositorMessage, SelectionKind},
+    protocol::{
+        primary_selection::{offer::PrimarySelectionOffer, source::PrimarySelectionSource},
+        wayland::data_device::OfferedSelection,
+    },
+};
+
+pub use waynest_protocols::server::unstable::primary_selection_unstable_v1::zwp_primary_selection_device_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct PrimarySelectionDevice;
+
+impl PrimarySelectionDevice {
+    /// Introduces a `zwp_primary_selection_offer_v1` for the selection,
+    /// `None` telling the client there's no selection anymore
+    pub async fn send_selection(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        selection: Option<&OfferedSelection>,
+    ) -> Result<()> {
+        let offer = match selection {
+            Some(selection) => {
+                let id = client.next_object_id();
+                let offer = PrimarySelectionOffer::new(selection);
+
+                self.data_offer(client, sender_id, id).await?;
+                offer.advertise(client, id).await?;
+                client.insert(id, offer);
+
+                Some(id)
+            }
+            None => None,
+        };
+
+        self.selection(client, sender_id, offer).await
+    }
+}
+
+impl ZwpPrimarySelectionDeviceV1 for PrimarySelectionDevice {
+    type Connection = Client;
+
+    async fn set_selection(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        source: Option<ObjectId>,
+        serial: u32,
+    ) -> Result<()> {
+        // Only the focused client gets to set the selection
+        if !client.is_focus_serial(serial) {
+            debug!(
+                "Ignoring primary selection of client {} with serial {serial}",
+                client.id()
+            );
+            return Ok(());
+        }
+
+        let source = match source {
+            Some(id) => {
+                let source = client
+                    .get::<PrimarySelectionSource>(id)
+                    .ok_or(VerdiError::MissingObject(id))?;
+
+                Some((id, source.mime_types().await))
+            }
+            None => None,
+        };
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::SetSelection {
+                client_id: client.id(),
+                kind: SelectionKind::Primary,
+                source,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.remove_primary_selection_device(sender_id);
+        client.destroy_object(sender_id).await
+    }
+}
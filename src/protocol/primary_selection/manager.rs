@@ -0,0 +1,53 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::SelectionKind,
+    protocol::primary_selection::{device::PrimarySelectionDevice, source::PrimarySelectionSource},
+};
+
+pub use waynest_protocols::server::unstable::primary_selection_unstable_v1::zwp_primary_selection_device_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct PrimarySelectionDeviceManager;
+
+impl ZwpPrimarySelectionDeviceManagerV1 for PrimarySelectionDeviceManager {
+    type Connection = Client;
+
+    async fn create_source(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+    ) -> Result<()> {
+        client.insert(id, PrimarySelectionSource::default());
+
+        Ok(())
+    }
+
+    async fn get_device(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        _seat: ObjectId,
+    ) -> Result<()> {
+        let device = PrimarySelectionDevice::default();
+
+        // A focused client gets the current selection right away
+        if let Some(selection) = client.selection(SelectionKind::Primary).cloned() {
+            device.send_selection(client, id, Some(&selection)).await?;
+        }
+
+        client.insert(id, device);
+        client.add_primary_selection_device(id);
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+}
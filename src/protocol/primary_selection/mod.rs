@@ -0,0 +1,4 @@
+pub mod device;
+pub mod manager;
+pub mod offer;
+pub mod source;
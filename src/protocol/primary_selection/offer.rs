@@ -0,0 +1,55 @@
+use std::os::fd::OwnedFd;
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::wayland::{data_device::OfferedSelection, selection::OfferData},
+};
+
+pub use waynest_protocols::server::unstable::primary_selection_unstable_v1::zwp_primary_selection_offer_v1::*;
+
+/// The primary selection as seen by the client it's offered to
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct PrimarySelectionOffer {
+    data: OfferData,
+}
+
+impl PrimarySelectionOffer {
+    pub fn new(selection: &OfferedSelection) -> Self {
+        Self {
+            data: OfferData::selection(selection),
+        }
+    }
+
+    /// Lists the mime types of the offer, right after it was introduced
+    pub async fn advertise(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        for mime_type in self.data.mime_types() {
+            self.offer(client, sender_id, mime_type.clone()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ZwpPrimarySelectionOfferV1 for PrimarySelectionOffer {
+    type Connection = Client;
+
+    async fn receive(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        mime_type: String,
+        fd: OwnedFd,
+    ) -> Result<()> {
+        self.data.receive(client, mime_type, fd).await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+}
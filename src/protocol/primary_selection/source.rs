@@ -0,0 +1,39 @@
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::selection::SourceData};
+
+pub use waynest_protocols::server::unstable::primary_selection_unstable_v1::zwp_primary_selection_source_v1::*;
+
+/// Data a client offers as the primary selection, written into a pipe for
+/// each transfer
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct PrimarySelectionSource {
+    data: SourceData,
+}
+
+impl PrimarySelectionSource {
+    pub async fn mime_types(&self) -> Vec<String> {
+        self.data.mime_types().await
+    }
+}
+
+impl ZwpPrimarySelectionSourceV1 for PrimarySelectionSource {
+    type Connection = Client;
+
+    async fn offer(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        mime_type: String,
+    ) -> Result<()> {
+        self.data.offer(mime_type).await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        SourceData::destroy(client, sender_id).await
+    }
+}
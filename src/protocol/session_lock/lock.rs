@@ -0,0 +1,170 @@
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::{compositor::CompositorMessage, renderer::OutputId},
+    protocol::{
+        session_lock::surface::LockSurface,
+        wayland::{
+            output::Output,
+            surface::{Role, Surface, SurfaceId},
+        },
+    },
+};
+
+pub use waynest_protocols::server::staging::ext_session_lock_v1::ext_session_lock_v1::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Status {
+    /// Waiting on the compositor
+    #[default]
+    Pending,
+    /// `locked` was sent, only unlocking may end it
+    Locked,
+    /// `finished` was sent, the lock is of no use anymore
+    Finished,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    status: Status,
+    /// Outputs that already got a lock surface
+    outputs: Vec<OutputId>,
+}
+
+/// A request to lock the session, by a client that then covers every output
+/// with a lock surface
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct SessionLock {
+    state: RwLock<LockState>,
+}
+
+impl SessionLock {
+    /// Tells the client the session is locked, so it now owns the lock
+    pub async fn send_locked(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        self.state.write().await.status = Status::Locked;
+        self.locked(client, sender_id).await
+    }
+
+    /// Tells the client the compositor won't lock for it, or won't anymore
+    pub async fn send_finished(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        self.state.write().await.status = Status::Finished;
+        self.finished(client, sender_id).await
+    }
+}
+
+impl ExtSessionLockV1 for SessionLock {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if self.state.read().await.status == Status::Locked {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidDestroy as u32,
+                "The session is locked, it has to be unlocked instead".to_string(),
+            ));
+        }
+
+        // A lock given up on before it was answered keeps the session locked
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::LockDestroyed {
+                client_id: client.id(),
+                lock: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_lock_surface(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+        output: ObjectId,
+    ) -> Result<()> {
+        let wl_surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+        let output = client
+            .get::<Output>(output)
+            .map(|object| object.id())
+            .ok_or(VerdiError::MissingObject(output))?;
+
+        if wl_surface.role().is_some() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::Role as u32,
+                "Surface already has a role".to_string(),
+            ));
+        }
+
+        if wl_surface.has_buffer().await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::AlreadyConstructed as u32,
+                "Surface already has a buffer".to_string(),
+            ));
+        }
+
+        {
+            let mut state = self.state.write().await;
+
+            if state.outputs.contains(&output) {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::DuplicateOutput as u32,
+                    "Output already has a lock surface".to_string(),
+                ));
+            }
+
+            state.outputs.push(output);
+        }
+
+        wl_surface.set_role(Role::LockSurface, sender_id, Error::Role as u32)?;
+        wl_surface.set_lock_surface(id).await;
+
+        client.insert(id, LockSurface::new(surface, wl_surface));
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::NewLockSurface {
+                lock: sender_id,
+                surface: SurfaceId::new(client.id(), surface),
+                lock_surface: id,
+                output,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn unlock_and_destroy(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+    ) -> Result<()> {
+        if self.state.read().await.status != Status::Locked {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidUnlock as u32,
+                "The session wasn't locked by this lock".to_string(),
+            ));
+        }
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::Unlock {
+                client_id: client.id(),
+                lock: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+}
@@ -0,0 +1,41 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError, actors::compositor::CompositorMessage,
+    protocol::session_lock::lock::SessionLock,
+};
+
+pub use waynest_protocols::server::staging::ext_session_lock_v1::ext_session_lock_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct SessionLockManager;
+
+impl ExtSessionLockManagerV1 for SessionLockManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn lock(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+    ) -> Result<()> {
+        client.insert(id, SessionLock::default());
+
+        // Answered with either `locked` or `finished`
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::Lock {
+                client_id: client.id(),
+                lock: id,
+            })
+            .await;
+
+        Ok(())
+    }
+}
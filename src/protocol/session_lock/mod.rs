@@ -0,0 +1,3 @@
+pub mod lock;
+pub mod manager;
+pub mod surface;
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::CompositorMessage,
+    protocol::wayland::surface::{Surface, SurfaceId},
+};
+
+pub use waynest_protocols::server::staging::ext_session_lock_v1::ext_session_lock_surface_v1::*;
+
+#[derive(Debug, Default)]
+struct Configure {
+    /// Configures not acked yet with their size, oldest first
+    sent: Vec<(u32, (u32, u32))>,
+    /// Size of the last acked configure
+    acked: Option<(u32, u32)>,
+}
+
+/// Covers one output while the session is locked, sized to it
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct LockSurface {
+    surface_id: ObjectId,
+    wl_surface: Arc<Surface>,
+    state: RwLock<Configure>,
+}
+
+impl LockSurface {
+    pub fn new(surface_id: ObjectId, wl_surface: Arc<Surface>) -> Self {
+        Self {
+            surface_id,
+            wl_surface,
+            state: RwLock::default(),
+        }
+    }
+
+    /// Checks the surface size as of a `wl_surface.commit`, `None` meaning
+    /// a null buffer
+    ///
+    /// Lock surfaces have to fill their output exactly, with the size of
+    /// the last acked configure.
+    pub async fn commit(&self, sender_id: ObjectId, size: Option<(i32, i32)>) -> Result<()> {
+        let Some(acked) = self.state.read().await.acked else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::CommitBeforeFirstAck as u32,
+                "Committed before acking the first configure".to_string(),
+            ));
+        };
+
+        let Some((width, height)) = size else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::NullBuffer as u32,
+                "Lock surfaces can't be unmapped".to_string(),
+            ));
+        };
+
+        if (width as u32, height as u32) != acked {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::DimensionsMismatch as u32,
+                format!(
+                    "Surface is {width}x{height} instead of {}x{}",
+                    acked.0, acked.1
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn send_configure(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        let serial = client.next_event_serial();
+        self.state
+            .write()
+            .await
+            .sent
+            .push((serial, (width, height)));
+
+        self.configure(client, sender_id, serial, width, height)
+            .await
+    }
+}
+
+impl ExtSessionLockSurfaceV1 for LockSurface {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        self.wl_surface.release_lock_surface().await;
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::RemoveLockSurface {
+                surface: SurfaceId::new(client.id(), self.surface_id),
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn ack_configure(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        serial: u32,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        let Some(index) = state.sent.iter().position(|(sent, _)| *sent == serial) else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidSerial as u32,
+                format!("Unknown configure serial {serial}"),
+            ));
+        };
+
+        // Acking a configure skips the ones sent before it
+        let (_, size) = state.sent.drain(..=index).last().unwrap_or_default();
+        state.acked = Some(size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::surface::Surface};
+
+pub use waynest_protocols::server::staging::tearing_control_v1::wp_tearing_control_v1::*;
+
+/// Lets a surface ask for its updates to be shown right away, tearing if
+/// need be
+///
+/// Becomes inert once the surface is gone.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct TearingControl {
+    surface: Weak<Surface>,
+}
+
+impl TearingControl {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+}
+
+impl WpTearingControlV1 for TearingControl {
+    type Connection = Client;
+
+    async fn set_presentation_hint(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        hint: PresentationHint,
+    ) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.set_tearing(hint == PresentationHint::Async).await;
+        }
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // Back to vsync with the next commit
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_tearing_control().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+}
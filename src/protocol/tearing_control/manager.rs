@@ -0,0 +1,45 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{tearing_control::control::TearingControl, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::staging::tearing_control_v1::wp_tearing_control_manager_v1::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct TearingControlManager;
+
+impl WpTearingControlManagerV1 for TearingControlManager {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_tearing_control(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_tearing_control() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::TearingControlExists as u32,
+                "Surface already has a tearing control".to_string(),
+            ));
+        }
+
+        client.insert(id, TearingControl::new(&surface));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::{viewporter::viewport::Viewport, wayland::surface::Surface},
+};
+
+pub use waynest_protocols::server::stable::viewporter::wp_viewporter::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Viewporter;
+
+impl WpViewporter for Viewporter {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_viewport(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+    ) -> Result<()> {
+        let surface = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+
+        if !surface.claim_viewport(id).await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::ViewportExists as u32,
+                "Surface already has a viewport".to_string(),
+            ));
+        }
+
+        client.insert(id, Viewport::new(&surface));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::{Arc, Weak};
+
+use waynest::{Fixed, ObjectId};
+use waynest_server::RequestDispatcher;
+
+use crate::{Client, Result, VerdiError, protocol::wayland::surface::Surface};
+
+pub use waynest_protocols::server::stable::viewporter::wp_viewport::*;
+
+/// Crops and scales the buffer of a surface
+///
+/// Both the source rectangle and the destination size are double-buffered
+/// and checked against the buffer when the surface commits.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Viewport {
+    surface: Weak<Surface>,
+}
+
+impl Viewport {
+    pub fn new(surface: &Arc<Surface>) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+        }
+    }
+
+    fn surface(&self, sender_id: ObjectId) -> Result<Arc<Surface>> {
+        self.surface.upgrade().ok_or_else(|| {
+            VerdiError::client(
+                sender_id,
+                Error::NoSurface as u32,
+                "Surface was destroyed".to_string(),
+            )
+        })
+    }
+}
+
+fn bad_value(sender_id: ObjectId, message: &str) -> VerdiError {
+    VerdiError::client(sender_id, Error::BadValue as u32, message.to_string())
+}
+
+impl WpViewport for Viewport {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.release_viewport().await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn set_source(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        x: Fixed,
+        y: Fixed,
+        width: Fixed,
+        height: Fixed,
+    ) -> Result<()> {
+        let surface = self.surface(sender_id)?;
+        let [x, y, width, height] = [x, y, width, height].map(|value| value.as_raw());
+
+        // All -1 unsets the source rectangle
+        if [x, y, width, height] == [-256; 4] {
+            surface.set_viewport_source(None).await;
+            return Ok(());
+        }
+
+        if x < 0 || y < 0 || width <= 0 || height <= 0 {
+            return Err(bad_value(sender_id, "Invalid source rectangle"));
+        }
+
+        let source = [x, y, width, height].map(|value| value as f64 / 256.0);
+        surface.set_viewport_source(Some(source)).await;
+
+        Ok(())
+    }
+
+    async fn set_destination(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        let surface = self.surface(sender_id)?;
+
+        // Both -1 unsets the destination size
+        if (width, height) == (-1, -1) {
+            surface.set_viewport_destination(None).await;
+            return Ok(());
+        }
+
+        if width <= 0 || height <= 0 {
+            return Err(bad_value(sender_id, "Invalid destination size"));
+        }
+
+        surface
+            .set_viewport_destination(Some((width, height)))
+            .await;
+
+        Ok(())
+    }
+}
@@ -1,29 +1,42 @@
-use std::sync::{Arc, Weak};
+use std::sync::Arc;
 
 use waynest::ObjectId;
-use waynest_server::{Connection, RequestDispatcher};
+use waynest_server::RequestDispatcher;
 
 use crate::{
+    Client,
+    actors::renderer::{Dmabuf, DmabufContent, ShmContent, SurfaceContent},
     error::{Result, VerdiError},
     protocol::wayland::{shm::Format, shm_pool::ShmPool},
 };
 
 pub use waynest_protocols::server::core::wayland::wl_buffer::*;
 
+/// Where the pixels of a buffer live
+#[derive(Debug)]
+enum BufferKind {
+    Shm {
+        pool: Arc<ShmPool>,
+        offset: usize,
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: Format,
+    },
+    Dmabuf(Arc<Dmabuf>),
+}
+
 #[derive(Debug, RequestDispatcher)]
-#[waynest(error = VerdiError)]
+#[waynest(error = VerdiError, connection = Client)]
 pub struct Buffer {
-    pool: Weak<ShmPool>,
-    offset: usize,
-    width: u32,
-    height: u32,
-    stride: u32,
-    format: Format,
+    id: ObjectId,
+    kind: BufferKind,
 }
 
 impl Buffer {
     pub fn new(
-        pool: Weak<ShmPool>,
+        id: ObjectId,
+        pool: Arc<ShmPool>,
         offset: i32,
         width: i32,
         height: i32,
@@ -31,56 +44,84 @@ impl Buffer {
         format: Format,
     ) -> Self {
         Self {
-            pool,
-            offset: offset as usize,
-            width: width as u32,
-            height: height as u32,
-            stride: stride as u32,
-            format,
+            id,
+            kind: BufferKind::Shm {
+                pool,
+                offset: offset as usize,
+                width: width as u32,
+                height: height as u32,
+                stride: stride as u32,
+                format,
+            },
         }
     }
 
-    pub fn get_pixel_data(&self) -> Result<Option<&[u8]>> {
-        if let Some(pool) = self.pool.upgrade() {
-            // Calculate buffer size based on height and stride
-            let buffer_size = self.height as usize * self.stride as usize;
-
-            // Get access to the mapped memory (this will need to be implemented)
-            // For now, return None as placeholder
-            Ok(None)
-        } else {
-            Ok(None)
+    pub fn dmabuf(id: ObjectId, dmabuf: Arc<Dmabuf>) -> Self {
+        Self {
+            id,
+            kind: BufferKind::Dmabuf(dmabuf),
         }
     }
 
-    pub fn width(&self) -> u32 {
-        self.width
+    /// Snapshots the buffer for the renderer
+    ///
+    /// Shm contents get copied out of the pool, dmabufs are shared and have to
+    /// stay untouched until the renderer releases them.
+    pub async fn contents(&self) -> Result<SurfaceContent> {
+        match self.kind {
+            BufferKind::Shm {
+                ref pool,
+                offset,
+                width,
+                height,
+                stride,
+                format,
+            } => {
+                let buffer_size = height as usize * stride as usize;
+                let pixels = pool.read(offset, buffer_size).await?;
+
+                Ok(SurfaceContent::Shm(ShmContent {
+                    width,
+                    height,
+                    stride,
+                    format,
+                    pixels,
+                }))
+            }
+            BufferKind::Dmabuf(ref dmabuf) => Ok(SurfaceContent::Dmabuf(DmabufContent {
+                buffer: self.id,
+                dmabuf: dmabuf.clone(),
+                release: Vec::new(),
+            })),
+        }
     }
 
-    pub fn height(&self) -> u32 {
-        self.height
+    pub fn id(&self) -> ObjectId {
+        self.id
     }
 
-    pub fn stride(&self) -> u32 {
-        self.stride
+    /// Width and height in pixels
+    pub fn size(&self) -> (i32, i32) {
+        match self.kind {
+            BufferKind::Shm { width, height, .. } => (width as i32, height as i32),
+            BufferKind::Dmabuf(ref dmabuf) => (dmabuf.width as i32, dmabuf.height as i32),
+        }
     }
 
-    pub fn format(&self) -> Format {
-        self.format
+    /// Whether the contents are copied on commit, leaving the buffer free for
+    /// the client to reuse right away
+    pub fn is_shm(&self) -> bool {
+        matches!(self.kind, BufferKind::Shm { .. })
     }
 }
 
 impl WlBuffer for Buffer {
-    type Connection = Connection<VerdiError>;
+    type Connection = Client;
 
-    async fn destroy(
-        &self,
-        _client: &mut Self::Connection,
-        _sender_id: ObjectId,
-    ) -> Result<()> {
-        // Buffer is destroyed when this object is dropped
-        // In a full implementation, we might need to send release events
-        // or notify surfaces that are using this buffer
-        Ok(())
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // Shm contents were copied on commit and the renderer holds its own
+        // reference to dmabufs, so dropping it from the store is all that's
+        // needed
+        client.destroy_object(sender_id).await
     }
-}
\ No newline at end of file
+}
@@ -3,14 +3,26 @@ use waynest_server::{Client as _, RequestDispatcher};
 
 use crate::{
     Client, Result, VerdiError,
-    protocol::wayland::surface::{Surface, WlSurface},
+    protocol::wayland::{
+        region::Region,
+        surface::{Surface, WlSurface},
+    },
 };
 
 pub use waynest_protocols::server::core::wayland::wl_compositor::*;
 
-#[derive(Debug, RequestDispatcher, Default)]
+#[derive(Debug, RequestDispatcher)]
 #[waynest(error = VerdiError, connection = Client)]
-pub struct Compositor;
+pub struct Compositor {
+    /// Surfaces are created with the version the client bound
+    version: u32,
+}
+
+impl Compositor {
+    pub fn new(version: u32) -> Self {
+        Self { version }
+    }
+}
 
 impl WlCompositor for Compositor {
     type Connection = Client;
@@ -21,17 +33,19 @@ impl WlCompositor for Compositor {
         _sender_id: ObjectId,
         id: ObjectId,
     ) -> Result<()> {
-        connection.insert(id, Surface::default());
+        connection.insert(id, Surface::new(self.version));
 
         Ok(())
     }
 
     async fn create_region(
         &self,
-        _client: &mut Self::Connection,
+        connection: &mut Self::Connection,
         _sender_id: ObjectId,
-        _id: ObjectId,
+        id: ObjectId,
     ) -> Result<()> {
-        todo!()
+        connection.insert(id, Region::default());
+
+        Ok(())
     }
 }
@@ -0,0 +1,256 @@
+use tracing::debug;
+use waynest::{Fixed, ObjectId};
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::{CompositorMessage, DragSource, SelectionKind},
+    protocol::wayland::{
+        data_device_manager::{DND_ACTIONS_VERSION, DndAction},
+        data_offer::DataOffer,
+        data_source::{DataSource, WlDataSource},
+        surface::{Role, Surface, SurfaceId},
+    },
+};
+
+pub use waynest_protocols::server::core::wayland::wl_data_device::*;
+
+/// The selection as the compositor offers it to clients
+#[derive(Debug, Clone)]
+pub struct OfferedSelection {
+    /// Tells offers apart, those of a replaced selection can't be received
+    /// from anymore
+    pub id: u64,
+    pub mime_types: Vec<String>,
+}
+
+/// A drag as offered to the client whose surface it entered
+#[derive(Debug, Clone)]
+pub struct OfferedDrag {
+    /// Tells offers apart, each surface the drag enters gets a new one
+    pub id: u64,
+    pub mime_types: Vec<String>,
+    /// Actions the source allows
+    pub actions: DndAction,
+}
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct DataDevice {
+    version: u32,
+}
+
+impl DataDevice {
+    pub fn new(version: u32) -> Self {
+        Self { version }
+    }
+
+    /// Introduces a `wl_data_offer` for the selection, `None` telling the
+    /// client there's no selection anymore
+    pub async fn send_selection(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        selection: Option<&OfferedSelection>,
+    ) -> Result<()> {
+        let offer = match selection {
+            Some(selection) => {
+                let id = client.next_object_id();
+                let offer = DataOffer::selection(selection, self.version);
+
+                self.data_offer(client, sender_id, id).await?;
+                offer.advertise(client, id).await?;
+                client.insert(id, offer);
+
+                Some(id)
+            }
+            None => None,
+        };
+
+        self.selection(client, sender_id, offer).await
+    }
+
+    /// Tells the client a drag entered one of its surfaces, returning the
+    /// `wl_data_offer` introduced for it
+    pub async fn send_drag_enter(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        serial: u32,
+        surface: ObjectId,
+        (x, y): (f64, f64),
+        drag: Option<&OfferedDrag>,
+    ) -> Result<Option<ObjectId>> {
+        let offer = match drag {
+            Some(drag) => {
+                let id = client.next_object_id();
+                let offer = DataOffer::drag(drag, self.version);
+
+                self.data_offer(client, sender_id, id).await?;
+                offer.advertise(client, id).await?;
+                client.insert(id, offer);
+
+                // Clients from before actions only ever copy
+                if self.version < DND_ACTIONS_VERSION {
+                    let _ = client
+                        .compositor()
+                        .cast(CompositorMessage::DragActions {
+                            offer: drag.id,
+                            actions: DndAction::Copy,
+                            preferred: DndAction::Copy,
+                        })
+                        .await;
+                }
+
+                Some(id)
+            }
+            None => None,
+        };
+
+        self.enter(
+            client,
+            sender_id,
+            serial,
+            surface,
+            fixed(x),
+            fixed(y),
+            offer,
+        )
+        .await?;
+
+        Ok(offer)
+    }
+
+    pub async fn send_drag_motion(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        time: u32,
+        (x, y): (f64, f64),
+    ) -> Result<()> {
+        self.motion(client, sender_id, time, fixed(x), fixed(y))
+            .await
+    }
+
+    pub async fn send_drop(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        WlDataDevice::drop(self, client, sender_id).await
+    }
+}
+
+fn fixed(value: f64) -> Fixed {
+    Fixed::from_raw((value * 256.0).round() as i32)
+}
+
+impl WlDataDevice for DataDevice {
+    type Connection = Client;
+
+    async fn start_drag(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        source: Option<ObjectId>,
+        origin: ObjectId,
+        icon: Option<ObjectId>,
+        serial: u32,
+    ) -> Result<()> {
+        client
+            .get::<Surface>(origin)
+            .ok_or(VerdiError::MissingObject(origin))?;
+
+        if let Some(icon) = icon {
+            let surface = client
+                .get::<Surface>(icon)
+                .ok_or(VerdiError::MissingObject(icon))?;
+
+            if surface.role().is_some_and(|role| role != Role::DndIcon) {
+                return Err(VerdiError::client(
+                    sender_id,
+                    Error::Role as u32,
+                    "Icon surface already has a role".to_string(),
+                ));
+            }
+
+            surface.set_role(Role::DndIcon, sender_id, Error::Role as u32)?;
+        }
+
+        let source = match source {
+            Some(id) => {
+                let source = client
+                    .get::<DataSource>(id)
+                    .ok_or(VerdiError::MissingObject(id))?;
+
+                // Only the focused client gets to start drags
+                if !client.is_focus_serial(serial) {
+                    debug!(
+                        "Cancelling drag of client {} with serial {serial}",
+                        client.id()
+                    );
+                    return source.cancelled(client, id).await;
+                }
+
+                Some(DragSource {
+                    source: id,
+                    mime_types: source.mime_types().await,
+                    actions: source.actions().await,
+                })
+            }
+            None if !client.is_focus_serial(serial) => return Ok(()),
+            None => None,
+        };
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::StartDrag {
+                client_id: client.id(),
+                source,
+                icon: icon.map(|icon| SurfaceId::new(client.id(), icon)),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn set_selection(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        source: Option<ObjectId>,
+        serial: u32,
+    ) -> Result<()> {
+        // Only the focused client gets to set the selection
+        if !client.is_focus_serial(serial) {
+            debug!(
+                "Ignoring selection of client {} with serial {serial}",
+                client.id()
+            );
+            return Ok(());
+        }
+
+        let source = match source {
+            Some(id) => {
+                let source = client
+                    .get::<DataSource>(id)
+                    .ok_or(VerdiError::MissingObject(id))?;
+
+                Some((id, source.mime_types().await))
+            }
+            None => None,
+        };
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::SetSelection {
+                client_id: client.id(),
+                kind: SelectionKind::Clipboard,
+                source,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn release(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.remove_data_device(sender_id);
+        client.destroy_object(sender_id).await
+    }
+}
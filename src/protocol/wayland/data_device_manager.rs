@@ -0,0 +1,60 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::SelectionKind,
+    protocol::wayland::{data_device::DataDevice, data_source::DataSource},
+};
+
+pub use waynest_protocols::server::core::wayland::wl_data_device_manager::*;
+
+/// First version with drag-and-drop actions, drags only copy before
+pub const DND_ACTIONS_VERSION: u32 = 3;
+
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct DataDeviceManager {
+    version: u32,
+}
+
+impl DataDeviceManager {
+    pub fn new(version: u32) -> Self {
+        Self { version }
+    }
+}
+
+impl WlDataDeviceManager for DataDeviceManager {
+    type Connection = Client;
+
+    async fn create_data_source(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+    ) -> Result<()> {
+        client.insert(id, DataSource::new(self.version));
+
+        Ok(())
+    }
+
+    async fn get_data_device(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        id: ObjectId,
+        _seat: ObjectId,
+    ) -> Result<()> {
+        let device = DataDevice::new(self.version);
+
+        // A focused client gets the current selection right away
+        if let Some(selection) = client.selection(SelectionKind::Clipboard).cloned() {
+            device.send_selection(client, id, Some(&selection)).await?;
+        }
+
+        client.insert(id, device);
+        client.add_data_device(id);
+
+        Ok(())
+    }
+}
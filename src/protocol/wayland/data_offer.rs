@@ -0,0 +1,192 @@
+use std::os::fd::OwnedFd;
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::compositor::CompositorMessage,
+    protocol::wayland::{
+        data_device::{OfferedDrag, OfferedSelection},
+        data_device_manager::{DND_ACTIONS_VERSION, DndAction},
+        selection::OfferData,
+    },
+};
+
+pub use waynest_protocols::server::core::wayland::wl_data_offer::*;
+
+/// The selection or a drag as seen by the client it's offered to
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct DataOffer {
+    version: u32,
+    data: OfferData,
+    /// Actions of the drag source, `None` for the selection
+    source_actions: Option<DndAction>,
+}
+
+impl DataOffer {
+    pub fn selection(selection: &OfferedSelection, version: u32) -> Self {
+        Self {
+            version,
+            data: OfferData::selection(selection),
+            source_actions: None,
+        }
+    }
+
+    pub fn drag(drag: &OfferedDrag, version: u32) -> Self {
+        Self {
+            version,
+            data: OfferData::new(drag.id, drag.mime_types.clone()),
+            source_actions: Some(drag.actions),
+        }
+    }
+
+    /// Lists what the offer has, right after it was introduced
+    pub async fn advertise(&self, client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        for mime_type in self.data.mime_types() {
+            self.offer(client, sender_id, mime_type.clone()).await?;
+        }
+
+        if let Some(actions) = self.source_actions
+            && self.version >= DND_ACTIONS_VERSION
+        {
+            self.source_actions(client, sender_id, actions).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the client which action the drag would perform if dropped
+    pub async fn send_action(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        action: DndAction,
+    ) -> Result<()> {
+        if self.version < DND_ACTIONS_VERSION {
+            return Ok(());
+        }
+
+        self.action(client, sender_id, action).await
+    }
+}
+
+impl WlDataOffer for DataOffer {
+    type Connection = Client;
+
+    async fn accept(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        _serial: u32,
+        mime_type: Option<String>,
+    ) -> Result<()> {
+        // Only drags care about what the other side would take
+        if self.source_actions.is_none() {
+            return Ok(());
+        }
+
+        let mime_type = mime_type.filter(|mime_type| self.data.has(mime_type));
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::DragAccept {
+                offer: self.data.id(),
+                mime_type,
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn receive(
+        &self,
+        client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        mime_type: String,
+        fd: OwnedFd,
+    ) -> Result<()> {
+        self.data.receive(client, mime_type, fd).await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // A drop is given up on when the offer goes away before finishing
+        if self.source_actions.is_some() {
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::DragOfferDestroyed {
+                    offer: self.data.id(),
+                })
+                .await;
+        }
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn finish(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if self.source_actions.is_none() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidFinish as u32,
+                "The selection can't be finished".to_string(),
+            ));
+        }
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::DragFinished {
+                offer: self.data.id(),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn set_actions(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        dnd_actions: DndAction,
+        preferred_action: DndAction,
+    ) -> Result<()> {
+        if self.source_actions.is_none() {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidOffer as u32,
+                "The offer isn't from a drag".to_string(),
+            ));
+        }
+
+        if !DndAction::all().contains(dnd_actions) {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidActionMask as u32,
+                "Unknown drag-and-drop actions".to_string(),
+            ));
+        }
+
+        // The preferred action is a single one, or none
+        if !DndAction::all().contains(preferred_action) || preferred_action.bits().count_ones() > 1
+        {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidAction as u32,
+                "Invalid preferred action".to_string(),
+            ));
+        }
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::DragActions {
+                offer: self.data.id(),
+                actions: dnd_actions,
+                preferred: preferred_action,
+            })
+            .await;
+
+        Ok(())
+    }
+}
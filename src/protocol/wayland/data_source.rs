@@ -0,0 +1,88 @@
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::wayland::{
+        data_device_manager::{DND_ACTIONS_VERSION, DndAction},
+        selection::SourceData,
+    },
+};
+
+pub use waynest_protocols::server::core::wayland::wl_data_source::*;
+
+/// Data a client offers to others, which it writes into a pipe for each
+/// transfer
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct DataSource {
+    version: u32,
+    data: SourceData,
+    /// Drag-and-drop actions, `None` until set
+    actions: RwLock<Option<DndAction>>,
+}
+
+impl DataSource {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            ..Default::default()
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub async fn mime_types(&self) -> Vec<String> {
+        self.data.mime_types().await
+    }
+
+    /// Actions a drag of the source allows
+    pub async fn actions(&self) -> DndAction {
+        if self.version < DND_ACTIONS_VERSION {
+            return DndAction::Copy;
+        }
+
+        self.actions.read().await.unwrap_or(DndAction::empty())
+    }
+}
+
+impl WlDataSource for DataSource {
+    type Connection = Client;
+
+    async fn offer(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        mime_type: String,
+    ) -> Result<()> {
+        self.data.offer(mime_type).await;
+
+        Ok(())
+    }
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        SourceData::destroy(client, sender_id).await
+    }
+
+    async fn set_actions(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        dnd_actions: DndAction,
+    ) -> Result<()> {
+        if !DndAction::all().contains(dnd_actions) {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidActionMask as u32,
+                "Unknown drag-and-drop actions".to_string(),
+            ));
+        }
+
+        *self.actions.write().await = Some(dnd_actions);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,19 @@
+pub mod buffer;
 pub mod callback;
 pub mod compositor;
+pub mod data_device;
+pub mod data_device_manager;
+pub mod data_offer;
+pub mod data_source;
 pub mod display;
 pub mod output;
+pub mod region;
 pub mod registry;
 pub mod seat;
+pub mod selection;
 pub mod shm;
 pub mod shm_pool;
+mod sigbus;
+pub mod subcompositor;
+pub mod subsurface;
 pub mod surface;
@@ -1,18 +1,92 @@
 use waynest::ObjectId;
-use waynest_server::RequestDispatcher;
+use waynest_server::{Client as _, RequestDispatcher};
 
-use crate::{Client, Result, VerdiError};
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::{OutputId, OutputInfo},
+};
 
 pub use waynest_protocols::server::core::wayland::wl_output::*;
 
-#[derive(Debug, RequestDispatcher, Default)]
+/// First version with the `scale` and `done` events
+const DONE_VERSION: u32 = 2;
+
+/// First version with the `name` and `description` events
+const NAME_VERSION: u32 = 4;
+
+#[derive(Debug, RequestDispatcher)]
 #[waynest(error = VerdiError, connection = Client)]
-pub struct Output;
+pub struct Output {
+    output: OutputId,
+}
+
+impl Output {
+    pub fn new(output: OutputId) -> Self {
+        Self { output }
+    }
+
+    /// The output this object stands for
+    pub fn id(&self) -> OutputId {
+        self.output
+    }
+
+    /// Describes the output to a freshly bound object
+    pub async fn send_info(
+        &self,
+        client: &mut <Self as WlOutput>::Connection,
+        sender_id: ObjectId,
+        version: u32,
+        info: &OutputInfo,
+    ) -> Result<()> {
+        let transform = Transform::try_from(info.transform as u32).unwrap_or(Transform::Normal);
+
+        self.geometry(
+            client,
+            sender_id,
+            info.geometry.x,
+            info.geometry.y,
+            // The physical size isn't known
+            0,
+            0,
+            Subpixel::Unknown,
+            "Unknown".to_string(),
+            info.name.clone(),
+            transform,
+        )
+        .await?;
+
+        self.mode(
+            client,
+            sender_id,
+            Mode::Current,
+            info.mode.0 as i32,
+            info.mode.1 as i32,
+            info.refresh as i32,
+        )
+        .await?;
+
+        if version >= NAME_VERSION {
+            self.name(client, sender_id, info.name.clone()).await?;
+            self.description(client, sender_id, info.name.clone())
+                .await?;
+        }
+
+        if version >= DONE_VERSION {
+            // Fractional scales round up, clients downscale what they draw
+            self.scale(client, sender_id, info.scale.ceil() as i32)
+                .await?;
+            self.done(client, sender_id).await?;
+        }
+
+        Ok(())
+    }
+}
 
 impl WlOutput for Output {
     type Connection = Client;
 
-    async fn release(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        todo!()
+    async fn release(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.remove_output(sender_id);
+        client.destroy_object(sender_id).await
     }
 }
@@ -0,0 +1,77 @@
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::damage::{Area, Rect},
+};
+
+pub use waynest_protocols::server::core::wayland::wl_region::*;
+
+/// An area clients build up before setting it as a surface input or opaque
+/// region, which copies it
+#[derive(Debug, Default, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Region {
+    area: RwLock<Area>,
+}
+
+impl Region {
+    pub async fn area(&self) -> Area {
+        self.area.read().await.clone()
+    }
+}
+
+/// Copies the area of a region passed as a request argument, `None` being
+/// left to the request to interpret
+pub async fn copy_area(client: &mut Client, region: Option<ObjectId>) -> Result<Option<Area>> {
+    let Some(id) = region else {
+        return Ok(None);
+    };
+
+    let region = client
+        .get::<Region>(id)
+        .ok_or(VerdiError::MissingObject(id))?;
+
+    Ok(Some(region.area().await))
+}
+
+impl WlRegion for Region {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn add(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        self.area.write().await.add(Rect::new(x, y, width, height));
+
+        Ok(())
+    }
+
+    async fn subtract(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        self.area
+            .write()
+            .await
+            .subtract(Rect::new(x, y, width, height));
+
+        Ok(())
+    }
+}
@@ -3,13 +3,34 @@ use waynest_server::{Client as _, RequestDispatcher};
 
 use crate::{
     Client, Result, VerdiError,
+    actors::renderer::RendererExt,
     protocol::{
+        commit_timing::manager::{CommitTimingManager, WpCommitTimingManagerV1},
+        data_control::manager::{DataControlManager, ExtDataControlManagerV1},
+        fifo::manager::{FifoManager, WpFifoManagerV1},
+        fractional_scale::manager::{FractionalScaleManager, WpFractionalScaleManagerV1},
+        idle_inhibit::manager::{IdleInhibitManager, ZwpIdleInhibitManagerV1},
+        idle_notify::notifier::{ExtIdleNotifierV1, IdleNotifier},
+        layer_shell::shell::{LayerShell, ZwlrLayerShellV1},
+        linux_dmabuf::dmabuf::{LinuxDmabuf, ZwpLinuxDmabufV1},
+        linux_drm_syncobj::manager::{SyncobjManager, WpLinuxDrmSyncobjManagerV1},
+        output_power::manager::{OutputPowerManager, ZwlrOutputPowerManagerV1},
+        presentation_time::presentation::{Presentation, WpPresentation},
+        primary_selection::manager::{
+            PrimarySelectionDeviceManager, ZwpPrimarySelectionDeviceManagerV1,
+        },
+        session_lock::manager::{ExtSessionLockManagerV1, SessionLockManager},
+        tearing_control::manager::{TearingControlManager, WpTearingControlManagerV1},
+        viewporter::manager::{Viewporter, WpViewporter},
         wayland::{
             compositor::{Compositor, WlCompositor},
+            data_device_manager::{DataDeviceManager, WlDataDeviceManager},
             output::{Output, WlOutput},
             seat::{Seat, WlSeat},
             shm::{Shm, WlShm},
+            subcompositor::{Subcompositor, WlSubcompositor},
         },
+        wlr_data_control::manager::{WlrDataControlManager, ZwlrDataControlManagerV1},
         xdg::wm_base::{WmBase, XdgWmBase},
     },
 };
@@ -23,7 +44,27 @@ impl RegistryGlobals {
     pub const SHM: u32 = 1;
     pub const WM_BASE: u32 = 2;
     pub const SEAT: u32 = 3;
-    pub const OUTPUT: u32 = 4;
+    pub const LINUX_DMABUF: u32 = 5;
+    pub const PRESENTATION: u32 = 6;
+    pub const TEARING_CONTROL: u32 = 7;
+    pub const LINUX_DRM_SYNCOBJ: u32 = 8;
+    pub const FIFO: u32 = 9;
+    pub const COMMIT_TIMING: u32 = 10;
+    pub const SUBCOMPOSITOR: u32 = 11;
+    pub const VIEWPORTER: u32 = 12;
+    pub const FRACTIONAL_SCALE: u32 = 13;
+    pub const DATA_DEVICE_MANAGER: u32 = 14;
+    pub const PRIMARY_SELECTION: u32 = 15;
+    pub const DATA_CONTROL: u32 = 16;
+    pub const WLR_DATA_CONTROL: u32 = 17;
+    pub const LAYER_SHELL: u32 = 18;
+    pub const SESSION_LOCK: u32 = 19;
+    pub const IDLE_NOTIFIER: u32 = 20;
+    pub const IDLE_INHIBIT: u32 = 21;
+    pub const OUTPUT_POWER: u32 = 22;
+    /// Every output gets a global of its own, named after its id with this
+    /// bit set
+    pub const OUTPUT_BASE: u32 = 1 << 31;
 }
 
 #[derive(Debug, RequestDispatcher, Default)]
@@ -45,6 +86,15 @@ impl Registry {
         )
         .await?;
 
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::SUBCOMPOSITOR,
+            Subcompositor::INTERFACE.to_string(),
+            Subcompositor::VERSION,
+        )
+        .await?;
+
         self.global(
             client,
             sender_id,
@@ -72,15 +122,182 @@ impl Registry {
         )
         .await?;
 
+        for output in client.renderer().outputs().await.unwrap_or_default() {
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::OUTPUT_BASE | output.id,
+                Output::INTERFACE.to_string(),
+                Output::VERSION,
+            )
+            .await?;
+        }
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::PRESENTATION,
+            Presentation::INTERFACE.to_string(),
+            Presentation::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::TEARING_CONTROL,
+            TearingControlManager::INTERFACE.to_string(),
+            TearingControlManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::FIFO,
+            FifoManager::INTERFACE.to_string(),
+            FifoManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::COMMIT_TIMING,
+            CommitTimingManager::INTERFACE.to_string(),
+            CommitTimingManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::VIEWPORTER,
+            Viewporter::INTERFACE.to_string(),
+            Viewporter::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::FRACTIONAL_SCALE,
+            FractionalScaleManager::INTERFACE.to_string(),
+            FractionalScaleManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::DATA_DEVICE_MANAGER,
+            DataDeviceManager::INTERFACE.to_string(),
+            DataDeviceManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::PRIMARY_SELECTION,
+            PrimarySelectionDeviceManager::INTERFACE.to_string(),
+            PrimarySelectionDeviceManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::LAYER_SHELL,
+            LayerShell::INTERFACE.to_string(),
+            LayerShell::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::SESSION_LOCK,
+            SessionLockManager::INTERFACE.to_string(),
+            SessionLockManager::VERSION,
+        )
+        .await?;
+
+        self.global(
+            client,
+            sender_id,
+            RegistryGlobals::IDLE_NOTIFIER,
+            IdleNotifier::INTERFACE.to_string(),
+            IdleNotifier::VERSION,
+        )
+        .await?;
+
         self.global(
             client,
             sender_id,
-            RegistryGlobals::OUTPUT,
-            Output::INTERFACE.to_string(),
-            Output::VERSION,
+            RegistryGlobals::IDLE_INHIBIT,
+            IdleInhibitManager::INTERFACE.to_string(),
+            IdleInhibitManager::VERSION,
         )
         .await?;
 
+        // Data control exposes every selection, only privileged clients get it
+        if client.is_privileged() {
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::DATA_CONTROL,
+                DataControlManager::INTERFACE.to_string(),
+                DataControlManager::VERSION,
+            )
+            .await?;
+
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::WLR_DATA_CONTROL,
+                WlrDataControlManager::INTERFACE.to_string(),
+                WlrDataControlManager::VERSION,
+            )
+            .await?;
+        }
+
+        // Turning the outputs off is up to the idle daemon, not any client
+        if client.is_privileged() {
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::OUTPUT_POWER,
+                OutputPowerManager::INTERFACE.to_string(),
+                OutputPowerManager::VERSION,
+            )
+            .await?;
+        }
+
+        // Only offered when the renderer can import dmabufs
+        if let Ok(Some(_)) = client.renderer().dmabuf_feedback().await {
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::LINUX_DMABUF,
+                LinuxDmabuf::INTERFACE.to_string(),
+                LinuxDmabuf::VERSION,
+            )
+            .await?;
+        }
+
+        // Only offered when the driver supports syncobj timelines
+        if let Ok(Some(_)) = client.renderer().syncobj_device().await {
+            self.global(
+                client,
+                sender_id,
+                RegistryGlobals::LINUX_DRM_SYNCOBJ,
+                SyncobjManager::INTERFACE.to_string(),
+                SyncobjManager::VERSION,
+            )
+            .await?;
+        }
+
         Ok(())
     }
 }
@@ -97,7 +314,7 @@ impl WlRegistry for Registry {
     ) -> Result<()> {
         match name {
             RegistryGlobals::COMPOSITOR => {
-                client.insert(new_id.object_id, Compositor::default());
+                client.insert(new_id.object_id, Compositor::new(new_id.version));
             }
             RegistryGlobals::SHM => {
                 let shm = Shm::default();
@@ -112,8 +329,96 @@ impl WlRegistry for Registry {
             RegistryGlobals::SEAT => {
                 client.insert(new_id.object_id, Seat::default());
             }
-            RegistryGlobals::OUTPUT => {
-                client.insert(new_id.object_id, Output::default());
+            name if name & RegistryGlobals::OUTPUT_BASE != 0 => {
+                let id = name & !RegistryGlobals::OUTPUT_BASE;
+
+                let outputs = client.renderer().outputs().await.unwrap_or_default();
+                let Some(info) = outputs.iter().find(|output| output.id == id) else {
+                    return Err(VerdiError::UnknownGlobal(name));
+                };
+
+                let output = Output::new(id);
+
+                output
+                    .send_info(client, new_id.object_id, new_id.version, info)
+                    .await?;
+
+                client.insert(new_id.object_id, output);
+                client.add_output(new_id.object_id, id);
+            }
+            RegistryGlobals::LINUX_DMABUF => {
+                let Ok(Some(feedback)) = client.renderer().dmabuf_feedback().await else {
+                    return Err(VerdiError::UnknownGlobal(name));
+                };
+
+                let dmabuf = LinuxDmabuf::new(feedback);
+
+                dmabuf
+                    .advertise_formats(client, new_id.object_id, new_id.version)
+                    .await?;
+
+                client.insert(new_id.object_id, dmabuf);
+            }
+            RegistryGlobals::PRESENTATION => {
+                let presentation = Presentation::default();
+
+                presentation
+                    .advertise_clock(client, new_id.object_id)
+                    .await?;
+
+                client.insert(new_id.object_id, presentation);
+            }
+            RegistryGlobals::TEARING_CONTROL => {
+                client.insert(new_id.object_id, TearingControlManager::default());
+            }
+            RegistryGlobals::LINUX_DRM_SYNCOBJ => {
+                let Ok(Some(device)) = client.renderer().syncobj_device().await else {
+                    return Err(VerdiError::UnknownGlobal(name));
+                };
+
+                client.insert(new_id.object_id, SyncobjManager::new(device));
+            }
+            RegistryGlobals::FIFO => {
+                client.insert(new_id.object_id, FifoManager::default());
+            }
+            RegistryGlobals::COMMIT_TIMING => {
+                client.insert(new_id.object_id, CommitTimingManager::default());
+            }
+            RegistryGlobals::SUBCOMPOSITOR => {
+                client.insert(new_id.object_id, Subcompositor::default());
+            }
+            RegistryGlobals::VIEWPORTER => {
+                client.insert(new_id.object_id, Viewporter::default());
+            }
+            RegistryGlobals::FRACTIONAL_SCALE => {
+                client.insert(new_id.object_id, FractionalScaleManager::default());
+            }
+            RegistryGlobals::DATA_DEVICE_MANAGER => {
+                client.insert(new_id.object_id, DataDeviceManager::new(new_id.version));
+            }
+            RegistryGlobals::PRIMARY_SELECTION => {
+                client.insert(new_id.object_id, PrimarySelectionDeviceManager::default());
+            }
+            RegistryGlobals::LAYER_SHELL => {
+                client.insert(new_id.object_id, LayerShell::new(new_id.version));
+            }
+            RegistryGlobals::SESSION_LOCK => {
+                client.insert(new_id.object_id, SessionLockManager::default());
+            }
+            RegistryGlobals::IDLE_NOTIFIER => {
+                client.insert(new_id.object_id, IdleNotifier::default());
+            }
+            RegistryGlobals::IDLE_INHIBIT => {
+                client.insert(new_id.object_id, IdleInhibitManager::default());
+            }
+            RegistryGlobals::DATA_CONTROL if client.is_privileged() => {
+                client.insert(new_id.object_id, DataControlManager::new(new_id.version));
+            }
+            RegistryGlobals::WLR_DATA_CONTROL if client.is_privileged() => {
+                client.insert(new_id.object_id, WlrDataControlManager::new(new_id.version));
+            }
+            RegistryGlobals::OUTPUT_POWER if client.is_privileged() => {
+                client.insert(new_id.object_id, OutputPowerManager::default());
             }
             _ => return Err(VerdiError::UnknownGlobal(name)),
         }
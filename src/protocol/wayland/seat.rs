@@ -9,37 +9,47 @@ pub use waynest_protocols::server::core::wayland::wl_seat::*;
 #[waynest(error = VerdiError, connection = Client)]
 pub struct Seat;
 
+/// No input devices are advertised yet, so asking for any of them is an
+/// error rather than a crash
+fn missing_capability(sender_id: ObjectId, device: &str) -> Result<()> {
+    Err(VerdiError::client(
+        sender_id,
+        Error::MissingCapability as u32,
+        format!("The seat has no {device}"),
+    ))
+}
+
 impl WlSeat for Seat {
     type Connection = Client;
 
     async fn get_pointer(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
+        sender_id: ObjectId,
         _id: ObjectId,
     ) -> Result<()> {
-        todo!()
+        missing_capability(sender_id, "pointer")
     }
 
     async fn get_keyboard(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
+        sender_id: ObjectId,
         _id: ObjectId,
     ) -> Result<()> {
-        todo!()
+        missing_capability(sender_id, "keyboard")
     }
 
     async fn get_touch(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
+        sender_id: ObjectId,
         _id: ObjectId,
     ) -> Result<()> {
-        todo!()
+        missing_capability(sender_id, "touch screen")
     }
 
-    async fn release(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        todo!()
+    async fn release(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
     }
 }
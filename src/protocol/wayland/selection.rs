@@ -0,0 +1,145 @@
+//! Bookkeeping shared by the offers and sources of `wl_data_device`, the
+//! primary selection and data control
+//!
+//! Transfers go straight from the client owning the data to the one
+//! receiving it, through the pipe passed along with `receive`. The
+//! compositor only forwards the fd to the source object the offer came from.
+
+use std::os::fd::{BorrowedFd, OwnedFd};
+
+use tokio::sync::RwLock;
+use waynest::ObjectId;
+use waynest_server::Client as _;
+
+use crate::{
+    Client, Result,
+    actors::compositor::CompositorMessage,
+    protocol::{
+        data_control::source::{DataControlSource, ExtDataControlSourceV1},
+        primary_selection::source::{PrimarySelectionSource, ZwpPrimarySelectionSourceV1},
+        wayland::{
+            data_device::OfferedSelection,
+            data_source::{DataSource, WlDataSource},
+        },
+        wlr_data_control::source::{WlrDataControlSource, ZwlrDataControlSourceV1},
+    },
+};
+
+/// What an offer object gives access to
+#[derive(Debug, Clone)]
+pub struct OfferData {
+    /// Tells offers apart across the compositor
+    id: u64,
+    mime_types: Vec<String>,
+}
+
+impl OfferData {
+    pub fn new(id: u64, mime_types: Vec<String>) -> Self {
+        Self { id, mime_types }
+    }
+
+    pub fn selection(selection: &OfferedSelection) -> Self {
+        Self::new(selection.id, selection.mime_types.clone())
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn mime_types(&self) -> &[String] {
+        &self.mime_types
+    }
+
+    pub fn has(&self, mime_type: &str) -> bool {
+        self.mime_types.iter().any(|offered| offered == mime_type)
+    }
+
+    /// Has the owner of the data write it into `fd`
+    pub async fn receive(&self, client: &mut Client, mime_type: String, fd: OwnedFd) {
+        // Dropping the fd closes the pipe, the client reads nothing
+        if !self.has(&mime_type) {
+            return;
+        }
+
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::ReceiveData {
+                offer: self.id,
+                mime_type,
+                fd,
+            })
+            .await;
+    }
+}
+
+/// Mime types a source object offers, in the order they were added
+#[derive(Debug, Default)]
+pub struct SourceData {
+    mime_types: RwLock<Vec<String>>,
+}
+
+impl SourceData {
+    pub async fn offer(&self, mime_type: String) {
+        let mut mime_types = self.mime_types.write().await;
+
+        if !mime_types.contains(&mime_type) {
+            mime_types.push(mime_type);
+        }
+    }
+
+    pub async fn mime_types(&self) -> Vec<String> {
+        self.mime_types.read().await.clone()
+    }
+
+    /// Destroys a source object, taking the selection or the drag along if
+    /// they came from it
+    pub async fn destroy(client: &mut Client, sender_id: ObjectId) -> Result<()> {
+        let _ = client
+            .compositor()
+            .cast(CompositorMessage::DataSourceDestroyed {
+                client_id: client.id(),
+                source: sender_id,
+            })
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+}
+
+/// Runs `$body` with `$object` bound to the source `$id`, whichever protocol
+/// it's from
+macro_rules! with_source {
+    ($client:expr, $id:expr, |$object:ident| $body:expr) => {
+        if let Some($object) = $client.get::<DataSource>($id) {
+            $body
+        } else if let Some($object) = $client.get::<PrimarySelectionSource>($id) {
+            $body
+        } else if let Some($object) = $client.get::<DataControlSource>($id) {
+            $body
+        } else if let Some($object) = $client.get::<WlrDataControlSource>($id) {
+            $body
+        } else {
+            Ok(())
+        }
+    };
+}
+
+/// Asks a source to write its data as `mime_type` into `fd`
+pub async fn send_data(
+    client: &mut Client,
+    source: ObjectId,
+    mime_type: String,
+    fd: BorrowedFd<'_>,
+) -> Result<()> {
+    with_source!(client, source, |object| {
+        object.send(client, source, mime_type, fd).await
+    })
+}
+
+/// Tells a source it isn't offered anymore, the selection having been
+/// replaced or the drag having ended
+pub async fn cancel_source(client: &mut Client, source: ObjectId) -> Result<()> {
+    with_source!(client, source, |object| {
+        object.cancelled(client, source).await
+    })
+}
@@ -5,6 +5,7 @@ use waynest_server::{Client as _, RequestDispatcher};
 
 use crate::{
     Client, Result, VerdiError,
+    actors::renderer::SHM_FORMATS,
     protocol::wayland::shm_pool::{ShmPool, WlShmPool},
 };
 
@@ -20,8 +21,9 @@ impl Shm {
         client: &mut <Self as WlShm>::Connection,
         sender_id: ObjectId,
     ) -> Result<()> {
-        self.format(client, sender_id, Format::Argb8888).await?;
-        self.format(client, sender_id, Format::Xrgb8888).await?;
+        for format in SHM_FORMATS {
+            self.format(client, sender_id, format).await?;
+        }
 
         Ok(())
     }
@@ -38,12 +40,12 @@ impl WlShm for Shm {
         fd: OwnedFd,
         size: i32,
     ) -> Result<()> {
-        client.insert(id, ShmPool::new(fd, size)?);
+        client.insert(id, ShmPool::new(id, fd, size)?);
 
         Ok(())
     }
 
-    async fn release(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        todo!()
+    async fn release(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
     }
 }
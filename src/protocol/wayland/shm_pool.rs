@@ -1,18 +1,34 @@
 use std::{io, os::fd::OwnedFd, ptr::null_mut};
 
-use rustix::mm::{MapFlags, MremapFlags, ProtFlags, mmap, mremap};
+use rustix::{
+    fs::{SealFlags, fcntl_get_seals},
+    mm::{MapFlags, MremapFlags, ProtFlags, mmap, mremap, munmap},
+};
 use tokio::sync::RwLock;
+use tracing::warn;
 use waynest::ObjectId;
-use waynest_server::RequestDispatcher;
-
-use crate::{Client, Result, VerdiError, protocol::wayland::shm::Format};
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::shm_bytes_per_pixel,
+    protocol::wayland::{
+        buffer::Buffer,
+        display::Error as DisplayError,
+        shm::{Error as ShmError, Format},
+        sigbus,
+    },
+};
 
 pub use waynest_protocols::server::core::wayland::wl_shm_pool::*;
 
 #[derive(Debug, RequestDispatcher)]
 #[waynest(error = VerdiError, connection = Client)]
 pub struct ShmPool {
+    id: ObjectId,
     _fd: OwnedFd,
+    /// Set when the file can't shrink anymore, so reads can't fault
+    sealed: bool,
     map: RwLock<Map>,
 }
 
@@ -26,10 +42,27 @@ struct Map {
     mem: *mut u8,
 }
 
+impl Drop for Map {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { munmap(self.mem.cast(), self.size) } {
+            warn!("Failed to unmap shm pool: {e}");
+        }
+    }
+}
+
 impl ShmPool {
-    pub fn new(fd: OwnedFd, size: i32) -> Result<Self> {
-        // FIXME: error handling when converting size
-        let size = size as usize;
+    pub fn new(id: ObjectId, fd: OwnedFd, size: i32) -> Result<Self> {
+        let size = match usize::try_from(size) {
+            Ok(size) if size > 0 => size,
+            _ => {
+                return Err(VerdiError::client(
+                    id,
+                    ShmError::InvalidStride as u32,
+                    format!("Invalid pool size {size}"),
+                ));
+            }
+        };
+
         let mem = unsafe {
             mmap(
                 null_mut(),
@@ -39,15 +72,61 @@ impl ShmPool {
                 &fd,
                 0,
             )
-            .map_err(io::Error::from)?
         }
+        .map_err(|e| {
+            VerdiError::client(
+                id,
+                ShmError::InvalidFd as u32,
+                format!("Failed to map pool: {e}"),
+            )
+        })?
         .cast();
 
+        // Anything but a memfd just isn't sealed
+        let sealed = fcntl_get_seals(&fd).is_ok_and(|seals| seals.contains(SealFlags::SHRINK));
+
         Ok(Self {
+            id,
             _fd: fd,
+            sealed,
             map: RwLock::new(Map { size, mem }),
         })
     }
+
+    pub async fn size(&self) -> usize {
+        self.map.read().await.size
+    }
+
+    /// Copies `len` bytes starting at `offset` out of the pool
+    ///
+    /// Fails with a client error if the client truncated the file backing
+    /// the pool.
+    pub async fn read(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        let map = self.map.read().await;
+
+        if offset.checked_add(len).is_none_or(|end| end > map.size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Buffer is out of the pool bounds",
+            )
+            .into());
+        }
+
+        // SAFETY: the range was checked against the size of the mapping above
+        let copy = || unsafe { std::slice::from_raw_parts(map.mem.add(offset), len) }.to_vec();
+
+        if self.sealed {
+            return Ok(copy());
+        }
+
+        sigbus::guarded(map.mem, map.size, copy)?.ok_or_else(|| {
+            VerdiError::client(
+                self.id,
+                ShmError::InvalidFd as u32,
+                "Pool file was truncated",
+            )
+        })
+    }
 }
 
 impl WlShmPool for ShmPool {
@@ -55,35 +134,86 @@ impl WlShmPool for ShmPool {
 
     async fn create_buffer(
         &self,
-        _client: &mut Self::Connection,
-        _sender_id: ObjectId,
-        _id: ObjectId,
-        _offset: i32,
-        _width: i32,
-        _height: i32,
-        _stride: i32,
-        _format: Format,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        offset: i32,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: Format,
     ) -> Result<()> {
-        todo!()
+        let Some(bytes_per_pixel) = shm_bytes_per_pixel(format) else {
+            return Err(VerdiError::client(
+                sender_id,
+                ShmError::InvalidFormat as u32,
+                format!("Unsupported format {format:?}"),
+            ));
+        };
+
+        let invalid_stride = |message: &str| -> Result<()> {
+            Err(VerdiError::client(
+                sender_id,
+                ShmError::InvalidStride as u32,
+                message,
+            ))
+        };
+
+        if offset < 0 || width <= 0 || height <= 0 || stride <= 0 {
+            return invalid_stride("Invalid width, height, stride or offset");
+        }
+
+        if (stride as u64) < width as u64 * bytes_per_pixel as u64 {
+            return invalid_stride("Stride is smaller than a row of pixels");
+        }
+
+        let end = offset as u64 + stride as u64 * height as u64;
+        if end > self.size().await as u64 {
+            return invalid_stride("Buffer doesn't fit in the pool");
+        }
+
+        let pool = client
+            .get::<Self>(sender_id)
+            .ok_or(VerdiError::MissingObject(sender_id))?;
+
+        client.insert(
+            id,
+            Buffer::new(id, pool, offset, width, height, stride, format),
+        );
+
+        Ok(())
     }
 
-    async fn destroy(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        todo!()
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        // Buffers keep the pool alive, the mapping goes away with the last one
+        client.destroy_object(sender_id).await
     }
 
     async fn resize(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
+        sender_id: ObjectId,
         size: i32,
     ) -> Result<()> {
         let mut write_guard = self.map.write().await;
         let old_size = write_guard.size;
-        let new_size = size as usize;
 
-        // FIXME: handle error when resize tries to reduce size
+        let new_size = match usize::try_from(size) {
+            Ok(new_size) if new_size >= old_size => new_size,
+            _ => {
+                return Err(VerdiError::client(
+                    sender_id,
+                    ShmError::InvalidStride as u32,
+                    format!("Pools can't shrink ({old_size} to {size} bytes)"),
+                ));
+            }
+        };
+
+        if new_size == old_size {
+            return Ok(());
+        }
 
-        // FIXME: better error handling
+        // On failure the old mapping is left untouched
         let mem = unsafe {
             mremap(
                 write_guard.mem.cast(),
@@ -91,8 +221,14 @@ impl WlShmPool for ShmPool {
                 new_size,
                 MremapFlags::MAYMOVE,
             )
-            .unwrap()
-        };
+        }
+        .map_err(|e| {
+            VerdiError::client(
+                ObjectId::DISPLAY,
+                DisplayError::NoMemory as u32,
+                format!("Failed to grow pool to {new_size} bytes: {e}"),
+            )
+        })?;
 
         write_guard.size = new_size;
         write_guard.mem = mem.cast();
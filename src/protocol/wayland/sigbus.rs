@@ -0,0 +1,112 @@
+//! Recovery from SIGBUS while reading client shm pools
+//!
+//! Clients can truncate the file backing a pool at any time, after which
+//! touching the missing pages raises SIGBUS. While a pool is being read, a
+//! fault inside it gets the whole mapping replaced with zeroed anonymous
+//! memory so the read can complete, and the caller learns the client
+//! misbehaved instead of the compositor getting killed.
+
+use std::{
+    cell::Cell,
+    ffi::{c_int, c_void},
+    io, mem, ptr,
+    sync::{
+        OnceLock,
+        atomic::{Ordering, compiler_fence},
+    },
+};
+
+thread_local! {
+    /// Address and length of the mapping being read by this thread
+    static ACCESS: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    static FAULTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Handler that was installed before ours, faults outside of guarded reads
+/// are forwarded to it. `None` if installing ours failed.
+static PREVIOUS: OnceLock<Option<libc::sigaction>> = OnceLock::new();
+
+fn install() -> io::Result<()> {
+    let previous = PREVIOUS.get_or_init(|| unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigbus as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = mem::zeroed();
+
+        (libc::sigaction(libc::SIGBUS, &action, &mut previous) == 0).then_some(previous)
+    });
+
+    match previous {
+        Some(_) => Ok(()),
+        None => Err(io::Error::other("Failed to install the SIGBUS handler")),
+    }
+}
+
+extern "C" fn handle_sigbus(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let addr = unsafe { (*info).si_addr() } as usize;
+
+    if let Some((start, len)) = ACCESS.get()
+        && addr >= start
+        && addr < start + len
+    {
+        // The faulting read gets retried once this returns, now finding zeroes
+        let mapped = unsafe {
+            libc::mmap(
+                start as *mut c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_FIXED | libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if mapped != libc::MAP_FAILED {
+            FAULTED.set(true);
+            return;
+        }
+    }
+
+    let Some(Some(previous)) = PREVIOUS.get() else {
+        return;
+    };
+
+    match previous.sa_sigaction {
+        libc::SIG_DFL | libc::SIG_IGN => unsafe {
+            // Returning re-executes the faulting instruction, which now
+            // terminates the process like it would have without us
+            libc::sigaction(libc::SIGBUS, previous, ptr::null_mut());
+        },
+        handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                unsafe { mem::transmute(handler) };
+            handler(signal, info, context);
+        }
+        handler => {
+            let handler: extern "C" fn(c_int) = unsafe { mem::transmute(handler) };
+            handler(signal);
+        }
+    }
+}
+
+/// Runs `f`, which reads from the `len` bytes mapped at `addr`, recovering
+/// from the mapping getting truncated
+///
+/// Returns `Ok(None)` if `f` ran into truncated memory, its result is then
+/// based on zeroes and should be discarded.
+pub fn guarded<T>(addr: *const u8, len: usize, f: impl FnOnce() -> T) -> io::Result<Option<T>> {
+    install()?;
+
+    ACCESS.set(Some((addr as usize, len)));
+    FAULTED.set(false);
+    compiler_fence(Ordering::SeqCst);
+
+    let result = f();
+
+    compiler_fence(Ordering::SeqCst);
+    ACCESS.set(None);
+
+    Ok((!FAULTED.replace(false)).then_some(result))
+}
@@ -0,0 +1,70 @@
+use waynest::ObjectId;
+use waynest_server::{Client as _, RequestDispatcher};
+
+use crate::{
+    Client, Result, VerdiError,
+    protocol::wayland::{
+        subsurface::Subsurface,
+        surface::{Role, Surface},
+    },
+};
+
+pub use waynest_protocols::server::core::wayland::wl_subcompositor::*;
+
+#[derive(Debug, RequestDispatcher, Default)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Subcompositor;
+
+impl WlSubcompositor for Subcompositor {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        client.destroy_object(sender_id).await
+    }
+
+    async fn get_subsurface(
+        &self,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        id: ObjectId,
+        surface: ObjectId,
+        parent: ObjectId,
+    ) -> Result<()> {
+        let child = client
+            .get::<Surface>(surface)
+            .ok_or(VerdiError::MissingObject(surface))?;
+        let parent_surface = client
+            .get::<Surface>(parent)
+            .ok_or(VerdiError::MissingObject(parent))?;
+
+        if child.role().is_some_and(|role| role != Role::Subsurface)
+            || child.parent().await.is_some()
+        {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::BadSurface as u32,
+                "Surface already has a role".to_string(),
+            ));
+        }
+
+        // Subsurfaces can't be stacked around themselves
+        if parent_surface.has_ancestor(&child).await {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::BadParent as u32,
+                "Parent is the surface itself or one of its subsurfaces".to_string(),
+            ));
+        }
+
+        child.set_role(Role::Subsurface, sender_id, Error::BadSurface as u32)?;
+        child.set_parent(&parent_surface, parent).await;
+        parent_surface.add_subsurface(surface).await;
+
+        client.insert(
+            id,
+            Subsurface::new(&child, surface, &parent_surface, parent),
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::{Arc, Weak};
+
+use waynest::ObjectId;
+use waynest_server::RequestDispatcher;
+
+use crate::{
+    Client, Result, VerdiError,
+    actors::renderer::RendererExt,
+    protocol::wayland::surface::{Surface, SurfaceId},
+};
+
+pub use waynest_protocols::server::core::wayland::wl_subsurface::*;
+
+/// A surface shown relative to its parent
+///
+/// Position and stacking changes only apply when the parent commits. In sync
+/// mode, which subsurfaces start in, commits of the subsurface itself are
+/// cached until then as well.
+#[derive(Debug, RequestDispatcher)]
+#[waynest(error = VerdiError, connection = Client)]
+pub struct Subsurface {
+    surface: Weak<Surface>,
+    surface_id: ObjectId,
+    parent: Weak<Surface>,
+    parent_id: ObjectId,
+}
+
+impl Subsurface {
+    pub fn new(
+        surface: &Arc<Surface>,
+        surface_id: ObjectId,
+        parent: &Arc<Surface>,
+        parent_id: ObjectId,
+    ) -> Self {
+        Self {
+            surface: Arc::downgrade(surface),
+            surface_id,
+            parent: Arc::downgrade(parent),
+            parent_id,
+        }
+    }
+
+    async fn place(&self, sender_id: ObjectId, sibling: ObjectId, above: bool) -> Result<()> {
+        let Some(parent) = self.parent.upgrade() else {
+            return Ok(());
+        };
+
+        let sibling = (sibling != self.parent_id).then_some(sibling);
+
+        if !parent
+            .place_subsurface(self.surface_id, sibling, above)
+            .await
+        {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::BadSurface as u32,
+                "Surface is neither a sibling nor the parent".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn set_mode(&self, client: &mut Client, sync: bool) -> Result<()> {
+        match self.surface.upgrade() {
+            Some(surface) => surface.set_sync(client, self.surface_id, sync).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl WlSubsurface for Subsurface {
+    type Connection = Client;
+
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some(surface) = self.surface.upgrade() {
+            surface.clear_parent(client).await?;
+        }
+
+        if let Some(parent) = self.parent.upgrade() {
+            parent.remove_subsurface(self.surface_id).await;
+        }
+
+        // Unmapped right away rather than with the next parent commit
+        let _ = client
+            .renderer()
+            .unmap_surface(SurfaceId::new(client.id(), self.surface_id))
+            .await;
+
+        client.destroy_object(sender_id).await
+    }
+
+    async fn set_position(
+        &self,
+        _client: &mut Self::Connection,
+        _sender_id: ObjectId,
+        x: i32,
+        y: i32,
+    ) -> Result<()> {
+        if let Some(parent) = self.parent.upgrade() {
+            parent.set_subsurface_position(self.surface_id, x, y).await;
+        }
+
+        Ok(())
+    }
+
+    async fn place_above(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        sibling: ObjectId,
+    ) -> Result<()> {
+        self.place(sender_id, sibling, true).await
+    }
+
+    async fn place_below(
+        &self,
+        _client: &mut Self::Connection,
+        sender_id: ObjectId,
+        sibling: ObjectId,
+    ) -> Result<()> {
+        self.place(sender_id, sibling, false).await
+    }
+
+    async fn set_sync(&self, client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
+        self.set_mode(client, true).await
+    }
+
+    async fn set_desync(&self, client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
+        self.set_mode(client, false).await
+    }
+}
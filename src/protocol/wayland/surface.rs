@@ -1,144 +1,1554 @@
-use std::sync::OnceLock;
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, OnceLock, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
+use tokio::{sync::RwLock, task::AbortHandle};
+use tracing::warn;
 use waynest::ObjectId;
-use waynest_server::RequestDispatcher;
+use waynest_protocols::server::core::wayland::wl_output;
+use waynest_server::{Client as _, RequestDispatcher};
 
-use crate::{Client, Result, VerdiError};
+use crate::{
+    Client, Result, Transform, VerdiError,
+    actors::{
+        compositor::CompositorMessage,
+        renderer::{
+            OutputId, Preferred, RendererExt, SubsurfaceStack, SurfaceContent, SurfaceUpdate,
+            SyncPoint, Viewport,
+            damage::{Area, Rect, Region},
+            monotonic_ns,
+        },
+    },
+    protocol::{
+        fractional_scale::scale::{FractionalScale, WpFractionalScaleV1},
+        layer_shell::surface::{LayerState, LayerSurface},
+        linux_dmabuf::feedback::Feedback,
+        linux_drm_syncobj::surface::Error as SyncobjError,
+        session_lock::surface::LockSurface,
+        viewporter::viewport::Error as ViewportError,
+        wayland::{
+            buffer::{Buffer, WlBuffer},
+            callback::Callback,
+            region,
+        },
+    },
+};
 
 pub use waynest_protocols::server::core::wayland::wl_surface::*;
 
+/// First version with the `preferred_buffer_scale` and
+/// `preferred_buffer_transform` events
+const PREFERRED_VERSION: u32 = 6;
+
+/// First version with `wl_surface.offset`, `wl_surface.attach` offsets have
+/// to be 0 from then on
+const OFFSET_VERSION: u32 = 5;
+
+/// Identifies a surface across the whole compositor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SurfaceId {
+    pub client_id: u32,
+    pub object_id: ObjectId,
+}
+
+impl SurfaceId {
+    pub fn new(client_id: u32, object_id: ObjectId) -> Self {
+        Self {
+            client_id,
+            object_id,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Role {
     XdgToplevel,
     XdgPopup,
     Cursor,
     Subsurface,
+    DndIcon,
+    LayerSurface,
+    LockSurface,
 }
 
 #[derive(Debug, Default)]
-struct State {}
+struct State {
+    /// `Some(None)` means a null buffer was attached
+    buffer: Option<Option<Arc<Buffer>>>,
+    /// Damage in surface local coordinates
+    damage: Region,
+    /// Damage in buffer coordinates, added to `damage` once the buffer
+    /// scale is known
+    buffer_damage: Region,
+    frame_callbacks: Vec<ObjectId>,
+    presentation_feedback: Vec<ObjectId>,
+    /// `wp_tearing_control_v1` presentation hint, `None` if unchanged
+    tearing: Option<bool>,
+    /// Explicit sync point to wait on before reading the buffer
+    acquire: Option<SyncPoint>,
+    /// Explicit sync point to signal once the buffer is released
+    release: Option<SyncPoint>,
+    /// Holds back later commits until the next refresh cycle
+    fifo_barrier: bool,
+    /// Waits for the FIFO barrier of earlier commits to clear
+    fifo_wait: bool,
+    /// `CLOCK_MONOTONIC` time the commit shouldn't be shown before
+    target_time_ns: Option<i64>,
+    /// New subsurface stacking, `None` if unchanged
+    subsurfaces: Option<Subsurfaces>,
+    /// New `wp_viewport` cropping and scaling, `None` if unchanged
+    viewport: Option<Viewport>,
+    /// New buffer scale, `None` if unchanged
+    buffer_scale: Option<i32>,
+    /// New buffer transform, `None` if unchanged
+    buffer_transform: Option<Transform>,
+    /// New opaque region, `Some(None)` for none
+    opaque_region: Option<Option<Area>>,
+    /// New input region, `Some(None)` for the whole surface
+    input_region: Option<Option<Area>>,
+    /// Position of the new buffer relative to the current one
+    offset: (i32, i32),
+    /// New `zwlr_layer_surface_v1` state, `None` if unchanged
+    layer: Option<LayerState>,
+}
+
+impl State {
+    /// Folds a later commit into this one, returning the buffer it replaced
+    fn merge(&mut self, newer: State) -> Option<Arc<Buffer>> {
+        let mut replaced = None;
+
+        if let Some(buffer) = newer.buffer {
+            if let Some(Some(old)) = self.buffer.replace(buffer)
+                && !matches!(self.buffer, Some(Some(ref new)) if Arc::ptr_eq(new, &old))
+            {
+                replaced = Some(old);
+            }
+
+            // The replaced buffer is never going to be read
+            if let Some(release) = self.release.take() {
+                release.signal();
+            }
+
+            self.acquire = newer.acquire;
+            self.release = newer.release;
+        }
+
+        self.damage.extend(&newer.damage);
+        self.buffer_damage.extend(&newer.buffer_damage);
+        self.frame_callbacks.extend(newer.frame_callbacks);
+        self.presentation_feedback
+            .extend(newer.presentation_feedback);
+        self.tearing = newer.tearing.or(self.tearing);
+        self.fifo_barrier |= newer.fifo_barrier;
+        self.fifo_wait |= newer.fifo_wait;
+        self.target_time_ns = newer.target_time_ns.or(self.target_time_ns);
+        self.subsurfaces = newer.subsurfaces.or(self.subsurfaces.take());
+        self.viewport = newer.viewport.or(self.viewport);
+        self.buffer_scale = newer.buffer_scale.or(self.buffer_scale);
+        self.buffer_transform = newer.buffer_transform.or(self.buffer_transform);
+        self.opaque_region = newer.opaque_region.or(self.opaque_region.take());
+        self.input_region = newer.input_region.or(self.input_region.take());
+        self.offset = (
+            self.offset.0 + newer.offset.0,
+            self.offset.1 + newer.offset.1,
+        );
+        self.layer = newer.layer.or(self.layer);
+
+        replaced
+    }
+
+    /// Checks the explicit sync points against the attached buffer, as
+    /// required once a `wp_linux_drm_syncobj_surface_v1` exists
+    fn check_sync_points(&self, syncobj_surface: ObjectId) -> Result<()> {
+        let error = |code: SyncobjError, message: &str| {
+            Err(VerdiError::client(
+                syncobj_surface,
+                code as u32,
+                message.to_string(),
+            ))
+        };
+
+        let Some(Some(ref buffer)) = self.buffer else {
+            if self.acquire.is_some() || self.release.is_some() {
+                return error(SyncobjError::NoBuffer, "Sync points set without a buffer");
+            }
+
+            return Ok(());
+        };
+
+        if buffer.is_shm() {
+            return error(
+                SyncobjError::UnsupportedBuffer,
+                "Shm buffers can't be explicitly synced",
+            );
+        }
+
+        let Some(ref acquire) = self.acquire else {
+            return error(SyncobjError::NoAcquirePoint, "Missing acquire point");
+        };
+
+        let Some(ref release) = self.release else {
+            return error(SyncobjError::NoReleasePoint, "Missing release point");
+        };
+
+        if acquire.not_before(release) {
+            return error(
+                SyncobjError::ConflictingPoints,
+                "Acquire point isn't before the release point",
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A subsurface and its position relative to the parent
+#[derive(Debug, Clone, Copy)]
+struct Child {
+    id: ObjectId,
+    x: i32,
+    y: i32,
+}
+
+/// Subsurfaces stacked below and above their parent, bottom to top
+#[derive(Debug, Clone, Default)]
+struct Subsurfaces {
+    below: Vec<Child>,
+    above: Vec<Child>,
+}
+
+impl Subsurfaces {
+    fn ids(&self) -> Vec<ObjectId> {
+        self.below
+            .iter()
+            .chain(&self.above)
+            .map(|child| child.id)
+            .collect()
+    }
+
+    fn contains(&self, id: ObjectId) -> bool {
+        self.below
+            .iter()
+            .chain(&self.above)
+            .any(|child| child.id == id)
+    }
+
+    fn get_mut(&mut self, id: ObjectId) -> Option<&mut Child> {
+        self.below
+            .iter_mut()
+            .chain(&mut self.above)
+            .find(|child| child.id == id)
+    }
+
+    fn remove(&mut self, id: ObjectId) -> Option<Child> {
+        for list in [&mut self.below, &mut self.above] {
+            if let Some(index) = list.iter().position(|child| child.id == id) {
+                return Some(list.remove(index));
+            }
+        }
+
+        None
+    }
+
+    /// Moves a subsurface right above or below a sibling, `None` standing
+    /// for the parent
+    ///
+    /// Returns `false` if the sibling isn't one.
+    fn place(&mut self, id: ObjectId, sibling: Option<ObjectId>, above: bool) -> bool {
+        if sibling.is_some_and(|sibling| sibling == id || !self.contains(sibling)) {
+            return false;
+        }
+
+        let Some(child) = self.remove(id) else {
+            return false;
+        };
+
+        let Some(sibling) = sibling else {
+            if above {
+                self.above.insert(0, child);
+            } else {
+                self.below.push(child);
+            }
+
+            return true;
+        };
+
+        let list = if self.below.iter().any(|child| child.id == sibling) {
+            &mut self.below
+        } else {
+            &mut self.above
+        };
+
+        if let Some(index) = list.iter().position(|child| child.id == sibling) {
+            list.insert(index + above as usize, child);
+        }
+
+        true
+    }
+
+    fn to_stack(&self, client_id: u32) -> SubsurfaceStack {
+        let convert = |children: &[Child]| {
+            children
+                .iter()
+                .map(|child| (SurfaceId::new(client_id, child.id), child.x, child.y))
+                .collect()
+        };
+
+        SubsurfaceStack {
+            below: convert(&self.below),
+            above: convert(&self.above),
+        }
+    }
+}
+
+/// What a subsurface knows about its parent
+#[derive(Debug, Clone)]
+struct ParentLink {
+    surface: Weak<Surface>,
+    id: ObjectId,
+    /// Commits get cached until the parent commits
+    sync: bool,
+}
 
 #[derive(Debug, Default)]
 struct DoubleBuffer {
     current: State,
     pending: State,
+    /// State committed by a synchronized subsurface, waiting on the parent
+    cached: Option<State>,
+    /// Set while the surface is a subsurface
+    parent: Option<ParentLink>,
+    /// Subsurface stacking as of the next commit
+    subsurfaces: Subsurfaces,
+    subsurfaces_changed: bool,
+    /// The `wp_viewport` of this surface, if any
+    viewport_id: Option<ObjectId>,
+    /// Cropping and scaling as of the next commit
+    viewport: Viewport,
+    viewport_changed: bool,
+    /// The `wp_linux_drm_syncobj_surface_v1` of this surface, if any
+    syncobj_surface: Option<ObjectId>,
+    /// The `wp_fractional_scale_v1` of this surface, if any
+    fractional_scale: Option<ObjectId>,
+    /// The `zwlr_layer_surface_v1` of this surface, if any
+    layer_surface: Option<ObjectId>,
+    /// The `ext_session_lock_surface_v1` of this surface, if any
+    lock_surface: Option<ObjectId>,
+    /// `zwp_linux_dmabuf_feedback_v1` objects resent when the surface moves
+    /// to another output
+    dmabuf_feedback: Vec<ObjectId>,
+    /// Scale and transform of the output the surface is on, as last told to
+    /// the client
+    preferred: Option<Preferred>,
+    /// Commits waiting on a FIFO barrier or target time, oldest first
+    queued: VecDeque<State>,
+    /// Set by an applied commit until the next refresh cycle
+    fifo_barrier: bool,
+    /// Acquire point and target time waits of queued commits
+    tasks: Vec<AbortHandle>,
+}
+
+impl DoubleBuffer {
+    /// Stops waiting on behalf of commits that are never going to be applied
+    fn abort_tasks(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Drops the queued and cached commits, signalling their release points
+    /// and returning the buffers to give back to the client
+    ///
+    /// A dmabuf the current state still shows is left to the renderer, which
+    /// releases it once it stops sampling it.
+    fn take_unapplied(&mut self) -> Vec<Arc<Buffer>> {
+        let current = self.current.buffer.clone().flatten();
+        let mut buffers: Vec<Arc<Buffer>> = Vec::new();
+
+        for state in self.queued.drain(..).chain(self.cached.take()) {
+            if let Some(release) = state.release {
+                release.signal();
+            }
+
+            let Some(Some(buffer)) = state.buffer else {
+                continue;
+            };
+
+            let shown = current
+                .as_ref()
+                .is_some_and(|current| Arc::ptr_eq(current, &buffer));
+
+            if (shown && !buffer.is_shm())
+                || buffers.iter().any(|other| Arc::ptr_eq(other, &buffer))
+            {
+                continue;
+            }
+
+            buffers.push(buffer);
+        }
+
+        buffers
+    }
+
+    /// The buffer the next commit shows, `None` if there's none
+    fn next_buffer(&self) -> Option<&Arc<Buffer>> {
+        match self.pending.buffer {
+            Some(ref buffer) => buffer.as_ref(),
+            None => self.current.buffer.as_ref().and_then(Option::as_ref),
+        }
+    }
+
+    /// The buffer scale as of the next commit
+    fn next_buffer_scale(&self) -> i32 {
+        self.pending
+            .buffer_scale
+            .or(self.current.buffer_scale)
+            .unwrap_or(1)
+    }
+
+    /// Size of the buffer the next commit shows in surface coordinates
+    fn next_surface_size(&self) -> Option<(f64, f64)> {
+        let transform = self
+            .pending
+            .buffer_transform
+            .or(self.current.buffer_transform)
+            .unwrap_or_default();
+        let scale = self.next_buffer_scale() as f64;
+
+        self.next_buffer().map(|buffer| {
+            let (width, height) = transform.invert().size(buffer.size());
+            (width as f64 / scale, height as f64 / scale)
+        })
+    }
+
+    /// Size of the surface as of the next commit, `None` without a buffer
+    fn next_size(&self) -> Option<(i32, i32)> {
+        self.next_surface_size()
+            .map(|(width, height)| self.viewport.surface_size((width as i32, height as i32)))
+    }
+
+    /// Checks that the buffer the next commit shows is made of whole surface
+    /// pixels
+    fn check_buffer_size(&self, surface: ObjectId) -> Result<()> {
+        if self.pending.buffer.is_none() && self.pending.buffer_scale.is_none() {
+            return Ok(());
+        }
+
+        let scale = self.next_buffer_scale();
+
+        if let Some(buffer) = self.next_buffer() {
+            let (width, height) = buffer.size();
+
+            if width % scale != 0 || height % scale != 0 {
+                return Err(VerdiError::client(
+                    surface,
+                    Error::InvalidSize as u32,
+                    format!("Buffer size {width}x{height} isn't a multiple of scale {scale}"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the viewport against the buffer the next commit shows
+    fn check_viewport(&self) -> Result<()> {
+        let Some(viewport_id) = self.viewport_id else {
+            return Ok(());
+        };
+
+        let Some([x, y, width, height]) = self.viewport.source else {
+            return Ok(());
+        };
+
+        if self.viewport.destination.is_none() && (width.fract() != 0.0 || height.fract() != 0.0) {
+            return Err(VerdiError::client(
+                viewport_id,
+                ViewportError::BadSize as u32,
+                "Source size isn't an integer and no destination is set".to_string(),
+            ));
+        }
+
+        // The source is in surface coordinates
+        if let Some((buffer_width, buffer_height)) = self.next_surface_size() {
+            if x + width > buffer_width || y + height > buffer_height {
+                return Err(VerdiError::client(
+                    viewport_id,
+                    ViewportError::OutOfBuffer as u32,
+                    "Source rectangle extends outside of the buffer".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a commit to the cached state, returning the buffer it replaced
+    fn cache(&mut self, pending: State) -> Option<Arc<Buffer>> {
+        match self.cached {
+            Some(ref mut cached) => cached.merge(pending),
+            None => {
+                self.cached = Some(pending);
+                None
+            }
+        }
+    }
+
+    /// Makes a commit the current state, telling whether it may tear
+    fn make_current(&mut self, mut pending: State) -> (State, bool) {
+        if let Some(ref buffer) = pending.buffer {
+            self.current.buffer = Some(buffer.clone());
+        }
+
+        if let Some(tearing) = pending.tearing {
+            self.current.tearing = Some(tearing);
+        }
+
+        if let Some(scale) = pending.buffer_scale {
+            self.current.buffer_scale = Some(scale);
+        }
+
+        if let Some(transform) = pending.buffer_transform {
+            self.current.buffer_transform = Some(transform);
+        }
+
+        if let Some(ref opaque_region) = pending.opaque_region {
+            self.current.opaque_region = Some(opaque_region.clone());
+        }
+
+        if let Some(ref input_region) = pending.input_region {
+            self.current.input_region = Some(input_region.clone());
+        }
+
+        let buffer_damage = std::mem::take(&mut pending.buffer_damage);
+
+        if let Some(Some(ref buffer)) = self.current.buffer {
+            let scale = self.current.buffer_scale.unwrap_or(1) as f64;
+            let transform = self.current.buffer_transform.unwrap_or_default();
+
+            pending.damage.extend(
+                &buffer_damage
+                    .transform(transform.invert(), buffer.size())
+                    .scale_out(1.0 / scale),
+            );
+        }
+
+        self.fifo_barrier |= pending.fifo_barrier;
+
+        (pending, self.current.tearing == Some(true))
+    }
 }
 
 #[derive(Debug, RequestDispatcher, Default)]
 #[waynest(error = VerdiError, connection = Client)]
 pub struct Surface {
+    /// Version of the `wl_compositor` the surface was created from
+    version: u32,
     role: OnceLock<Role>,
-    state: DoubleBuffer,
+    state: RwLock<DoubleBuffer>,
+    mapped: AtomicBool,
+    has_tearing_control: AtomicBool,
+    has_fifo: AtomicBool,
+    has_commit_timer: AtomicBool,
 }
 
 impl Surface {
-    pub fn set_role(&self, role: Role) -> Result<()> {
-        let _ = self.role.set(role); // FIXME: check if role is already set and don't try to set if it matches
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            ..Default::default()
+        }
+    }
+
+    /// Gives the surface its role for good, posting the `code` error on
+    /// `object` if it already has another one
+    ///
+    /// Taking the same role again, say for a new subsurface object, is fine.
+    pub fn set_role(&self, role: Role, object: ObjectId, code: u32) -> Result<()> {
+        match self.role.get_or_init(|| role) {
+            current if *current == role => Ok(()),
+            current => Err(VerdiError::client(
+                object,
+                code,
+                format!("Surface already has the {current:?} role"),
+            )),
+        }
+    }
+
+    pub fn role(&self) -> Option<Role> {
+        self.role.get().copied()
+    }
+
+    /// Returns `false` if the surface already has a `wp_tearing_control_v1`
+    pub fn claim_tearing_control(&self) -> bool {
+        !self.has_tearing_control.swap(true, Ordering::Relaxed)
+    }
+
+    /// Goes back to vsync with the next commit
+    pub async fn release_tearing_control(&self) {
+        self.has_tearing_control.store(false, Ordering::Relaxed);
+        self.set_tearing(false).await;
+    }
+
+    /// Whether updates may be shown right away, tearing, from the next
+    /// commit on
+    pub async fn set_tearing(&self, tearing: bool) {
+        self.state.write().await.pending.tearing = Some(tearing);
+    }
+
+    /// Returns `false` if the surface already has a
+    /// `wp_linux_drm_syncobj_surface_v1`
+    pub async fn claim_syncobj_surface(&self, id: ObjectId) -> bool {
+        let mut state = self.state.write().await;
+
+        if state.syncobj_surface.is_some() {
+            return false;
+        }
+
+        state.syncobj_surface = Some(id);
+        true
+    }
+
+    /// Goes back to implicit sync with the next commit
+    pub async fn release_syncobj_surface(&self) {
+        let mut state = self.state.write().await;
+
+        state.syncobj_surface = None;
+        state.pending.acquire = None;
+        state.pending.release = None;
+    }
+
+    pub async fn set_acquire_point(&self, point: SyncPoint) {
+        self.state.write().await.pending.acquire = Some(point);
+    }
+
+    pub async fn set_release_point(&self, point: SyncPoint) {
+        self.state.write().await.pending.release = Some(point);
+    }
+
+    /// Returns `false` if the surface already has a `wp_fifo_v1`
+    pub fn claim_fifo(&self) -> bool {
+        !self.has_fifo.swap(true, Ordering::Relaxed)
+    }
+
+    pub async fn release_fifo(&self) {
+        self.has_fifo.store(false, Ordering::Relaxed);
+
+        let mut state = self.state.write().await;
+        state.pending.fifo_barrier = false;
+        state.pending.fifo_wait = false;
+    }
+
+    /// Makes the next commit hold back later ones until the next refresh
+    /// cycle
+    pub async fn set_fifo_barrier(&self) {
+        self.state.write().await.pending.fifo_barrier = true;
+    }
+
+    /// Makes the next commit wait for earlier barriers to clear
+    pub async fn wait_fifo_barrier(&self) {
+        self.state.write().await.pending.fifo_wait = true;
+    }
+
+    /// Called once a refresh cycle paced by the surface is over, applies
+    /// the commits that were waiting on it
+    pub async fn clear_fifo_barrier(&self, client: &mut Client, id: ObjectId) -> Result<()> {
+        self.state.write().await.fifo_barrier = false;
+        self.apply_queued(client, id).await
+    }
+
+    /// Returns `false` if the surface already has a `wp_commit_timer_v1`
+    pub fn claim_commit_timer(&self) -> bool {
+        !self.has_commit_timer.swap(true, Ordering::Relaxed)
+    }
+
+    pub async fn release_commit_timer(&self) {
+        self.has_commit_timer.store(false, Ordering::Relaxed);
+        self.state.write().await.pending.target_time_ns = None;
+    }
+
+    /// Holds the next commit until the given `CLOCK_MONOTONIC` time
+    ///
+    /// Returns `false` if the next commit already has one.
+    pub async fn set_target_time(&self, time_ns: i64) -> bool {
+        let mut state = self.state.write().await;
+
+        if state.pending.target_time_ns.is_some() {
+            return false;
+        }
+
+        state.pending.target_time_ns = Some(time_ns);
+        true
+    }
+
+    /// Makes the surface a subsurface of `parent`, in sync mode
+    pub async fn set_parent(&self, parent: &Arc<Surface>, parent_id: ObjectId) {
+        self.state.write().await.parent = Some(ParentLink {
+            surface: Arc::downgrade(parent),
+            id: parent_id,
+            sync: true,
+        });
+    }
+
+    /// The parent of the subsurface, as long as both exist
+    pub async fn parent(&self) -> Option<(Arc<Surface>, ObjectId)> {
+        let link = self.state.read().await.parent.clone()?;
+
+        link.surface.upgrade().map(|surface| (surface, link.id))
+    }
+
+    /// Stops being a subsurface, dropping whatever state was cached
+    pub async fn clear_parent(&self, client: &mut Client) -> Result<()> {
+        let cached = {
+            let mut state = self.state.write().await;
+            state.parent = None;
+            state.cached.take()
+        };
+
+        let Some(cached) = cached else {
+            return Ok(());
+        };
+
+        if let Some(release) = cached.release {
+            release.signal();
+        }
+
+        if let Some(Some(buffer)) = cached.buffer {
+            buffer.release(client, buffer.id()).await?;
+        }
 
         Ok(())
     }
+
+    /// Whether `ancestor` is this surface or one of its parents
+    pub async fn has_ancestor(self: &Arc<Self>, ancestor: &Arc<Surface>) -> bool {
+        let mut surface = self.clone();
+
+        loop {
+            if Arc::ptr_eq(&surface, ancestor) {
+                return true;
+            }
+
+            let Some((parent, _)) = surface.parent().await else {
+                return false;
+            };
+
+            surface = parent;
+        }
+    }
+
+    /// Whether commits wait for the parent, because the subsurface or one
+    /// of its parents is in sync mode
+    async fn is_synchronized(&self) -> bool {
+        let mut link = self.state.read().await.parent.clone();
+
+        while let Some(parent) = link {
+            if parent.sync {
+                return true;
+            }
+
+            let Some(surface) = parent.surface.upgrade() else {
+                return false;
+            };
+
+            link = surface.state.read().await.parent.clone();
+        }
+
+        false
+    }
+
+    /// Switches between sync and desync mode
+    ///
+    /// Switching to desync mode applies the cached state right away unless
+    /// a parent is still in sync mode.
+    pub async fn set_sync(&self, client: &mut Client, id: ObjectId, sync: bool) -> Result<()> {
+        if let Some(ref mut parent) = self.state.write().await.parent {
+            parent.sync = sync;
+        }
+
+        if sync || self.is_synchronized().await {
+            return Ok(());
+        }
+
+        self.apply_cached(client, id).await
+    }
+
+    /// Returns `false` if the surface already has a `wp_viewport`
+    pub async fn claim_viewport(&self, id: ObjectId) -> bool {
+        let mut state = self.state.write().await;
+
+        if state.viewport_id.is_some() {
+            return false;
+        }
+
+        state.viewport_id = Some(id);
+        true
+    }
+
+    /// Drops cropping and scaling with the next commit
+    pub async fn release_viewport(&self) {
+        let mut state = self.state.write().await;
+
+        state.viewport_id = None;
+        state.viewport = Viewport::default();
+        state.viewport_changed = true;
+    }
+
+    /// Crops the buffer from the next commit on, `None` to show all of it
+    pub async fn set_viewport_source(&self, source: Option<[f64; 4]>) {
+        let mut state = self.state.write().await;
+
+        state.viewport.source = source;
+        state.viewport_changed = true;
+    }
+
+    /// Scales the buffer from the next commit on, `None` to keep the size of
+    /// the source
+    pub async fn set_viewport_destination(&self, destination: Option<(i32, i32)>) {
+        let mut state = self.state.write().await;
+
+        state.viewport.destination = destination;
+        state.viewport_changed = true;
+    }
+
+    /// Returns `false` if the surface already has a `wp_fractional_scale_v1`
+    pub async fn claim_fractional_scale(&self, id: ObjectId) -> bool {
+        let mut state = self.state.write().await;
+
+        if state.fractional_scale.is_some() {
+            return false;
+        }
+
+        state.fractional_scale = Some(id);
+        true
+    }
+
+    pub async fn release_fractional_scale(&self) {
+        self.state.write().await.fractional_scale = None;
+    }
+
+    /// Whether a buffer is attached, committed or not
+    pub async fn has_buffer(&self) -> bool {
+        self.state.read().await.next_buffer().is_some()
+    }
+
+    pub async fn set_layer_surface(&self, id: ObjectId) {
+        self.state.write().await.layer_surface = Some(id);
+    }
+
+    /// Unmaps the surface along with its `zwlr_layer_surface_v1`
+    pub async fn release_layer_surface(&self) {
+        self.state.write().await.layer_surface = None;
+        self.mapped.store(false, Ordering::Relaxed);
+    }
+
+    /// Lets the `zwlr_layer_surface_v1` of this surface check its state,
+    /// which goes along with the commit if it changed
+    async fn commit_layer_surface(&self, client: &mut Client) -> Result<()> {
+        let (layer_surface, has_buffer) = {
+            let state = self.state.read().await;
+            (state.layer_surface, state.next_buffer().is_some())
+        };
+
+        let Some((id, layer_surface)) =
+            layer_surface.and_then(|id| client.get::<LayerSurface>(id).map(|object| (id, object)))
+        else {
+            return Ok(());
+        };
+
+        let layer = layer_surface.commit(id, has_buffer).await?;
+        self.state.write().await.pending.layer = layer;
+
+        Ok(())
+    }
+
+    pub async fn set_lock_surface(&self, id: ObjectId) {
+        self.state.write().await.lock_surface = Some(id);
+    }
+
+    pub async fn release_lock_surface(&self) {
+        self.state.write().await.lock_surface = None;
+        self.mapped.store(false, Ordering::Relaxed);
+    }
+
+    /// Lets the `ext_session_lock_surface_v1` of this surface check the size
+    /// it's committed with
+    async fn commit_lock_surface(&self, client: &mut Client) -> Result<()> {
+        let (lock_surface, size) = {
+            let state = self.state.read().await;
+            (state.lock_surface, state.next_size())
+        };
+
+        match lock_surface.and_then(|id| client.get::<LockSurface>(id).map(|object| (id, object))) {
+            Some((id, lock_surface)) => lock_surface.commit(id, size).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Scale of the output the surface is on, `None` until the renderer
+    /// picked one
+    pub async fn preferred_scale(&self) -> Option<f64> {
+        self.state
+            .read()
+            .await
+            .preferred
+            .map(|preferred| preferred.scale)
+    }
+
+    /// Tells the client about the scale and transform of the output the
+    /// surface moved to
+    ///
+    /// `wl_surface` only gets integer scales, rounded up so buffers get
+    /// downscaled rather than blurrily upscaled.
+    pub async fn set_preferred(
+        &self,
+        client: &mut Client,
+        id: ObjectId,
+        preferred: Preferred,
+    ) -> Result<()> {
+        let (previous, fractional_scale, dmabuf_feedback) = {
+            let mut state = self.state.write().await;
+            (
+                state.preferred.replace(preferred),
+                state.fractional_scale,
+                state.dmabuf_feedback.clone(),
+            )
+        };
+
+        let integer = preferred.scale.ceil() as i32;
+
+        if self.version >= PREFERRED_VERSION {
+            if previous.is_none_or(|previous| previous.scale.ceil() as i32 != integer) {
+                self.preferred_buffer_scale(client, id, integer).await?;
+            }
+
+            if previous.is_none_or(|previous| previous.transform != preferred.transform)
+                && let Ok(transform) = wl_output::Transform::try_from(preferred.transform as u32)
+            {
+                self.preferred_buffer_transform(client, id, transform)
+                    .await?;
+            }
+        }
+
+        if let Some(fractional_scale) = fractional_scale
+            && let Some(object) = client.get::<FractionalScale>(fractional_scale)
+            && previous.is_none_or(|previous| previous.scale != preferred.scale)
+        {
+            object
+                .send_scale(client, fractional_scale, preferred.scale)
+                .await?;
+        }
+
+        // The scanout tranche depends on the planes of the output
+        if previous.is_none_or(|previous| previous.output != preferred.output) {
+            for id in dmabuf_feedback {
+                if let Some(object) = client.get::<Feedback>(id) {
+                    object
+                        .send_feedback(client, id, Some(preferred.output))
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The output the surface is mostly on, `None` until the renderer placed
+    /// it
+    pub async fn output(&self) -> Option<OutputId> {
+        self.state
+            .read()
+            .await
+            .preferred
+            .map(|preferred| preferred.output)
+    }
+
+    pub async fn add_dmabuf_feedback(&self, id: ObjectId) {
+        self.state.write().await.dmabuf_feedback.push(id);
+    }
+
+    pub async fn remove_dmabuf_feedback(&self, id: ObjectId) {
+        self.state
+            .write()
+            .await
+            .dmabuf_feedback
+            .retain(|feedback| *feedback != id);
+    }
+
+    /// Stacks a new subsurface on top of the others
+    pub async fn add_subsurface(&self, child: ObjectId) {
+        let mut state = self.state.write().await;
+        state.subsurfaces.above.push(Child {
+            id: child,
+            x: 0,
+            y: 0,
+        });
+        state.subsurfaces_changed = true;
+    }
+
+    pub async fn remove_subsurface(&self, child: ObjectId) {
+        let mut state = self.state.write().await;
+
+        if state.subsurfaces.remove(child).is_some() {
+            state.subsurfaces_changed = true;
+        }
+    }
+
+    /// Moves a subsurface relative to this surface, from the next commit on
+    pub async fn set_subsurface_position(&self, child: ObjectId, x: i32, y: i32) {
+        let mut state = self.state.write().await;
+
+        if let Some(entry) = state.subsurfaces.get_mut(child) {
+            (entry.x, entry.y) = (x, y);
+            state.subsurfaces_changed = true;
+        }
+    }
+
+    /// Restacks a subsurface right above or below a sibling, `None`
+    /// standing for this surface, from the next commit on
+    ///
+    /// Returns `false` if the sibling isn't one.
+    pub async fn place_subsurface(
+        &self,
+        child: ObjectId,
+        sibling: Option<ObjectId>,
+        above: bool,
+    ) -> bool {
+        let mut state = self.state.write().await;
+
+        if !state.subsurfaces.place(child, sibling, above) {
+            return false;
+        }
+
+        state.subsurfaces_changed = true;
+        true
+    }
+
+    /// Applies the state cached while in sync mode
+    async fn apply_cached(&self, client: &mut Client, id: ObjectId) -> Result<()> {
+        let ready = {
+            let mut state = self.state.write().await;
+            state.cached.take().map(|cached| state.make_current(cached))
+        };
+
+        match ready {
+            Some((pending, tearing)) => self.apply(client, id, pending, tearing).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Applies queued commits in order, until one has to keep waiting
+    ///
+    /// Synchronized subsurfaces cache them instead, until the parent
+    /// applies its own state.
+    pub async fn apply_queued(&self, client: &mut Client, id: ObjectId) -> Result<()> {
+        loop {
+            let synchronized = self.is_synchronized().await;
+
+            let (ready, replaced) = {
+                let mut state = self.state.write().await;
+
+                let Some(next) = state.queued.front() else {
+                    return Ok(());
+                };
+
+                if (next.fifo_wait && state.fifo_barrier)
+                    || next
+                        .target_time_ns
+                        .is_some_and(|time| time > monotonic_ns())
+                    || next
+                        .acquire
+                        .as_ref()
+                        .is_some_and(|acquire| !acquire.is_signalled())
+                {
+                    return Ok(());
+                }
+
+                let Some(pending) = state.queued.pop_front() else {
+                    return Ok(());
+                };
+
+                if synchronized {
+                    (None, state.cache(pending))
+                } else if state.cached.is_some() {
+                    // Whatever was cached goes along with this commit
+                    let replaced = state.cache(pending);
+                    let cached = state.cached.take().unwrap_or_default();
+
+                    (Some(state.make_current(cached)), replaced)
+                } else {
+                    (Some(state.make_current(pending)), None)
+                }
+            };
+
+            if let Some(buffer) = replaced {
+                buffer.release(client, buffer.id()).await?;
+            }
+
+            if let Some((pending, tearing)) = ready {
+                self.apply(client, id, pending, tearing).await?;
+            }
+        }
+    }
+
+    /// Hands a commit over to the renderer
+    async fn apply(
+        &self,
+        client: &mut Client,
+        sender_id: ObjectId,
+        pending: State,
+        tearing: bool,
+    ) -> Result<()> {
+        let id = SurfaceId::new(client.id(), sender_id);
+        let (offset, layer) = (pending.offset, pending.layer);
+
+        let mut update = SurfaceUpdate {
+            surface: id,
+            content: None,
+            unmapped: false,
+            damage: pending.damage,
+            frame_callbacks: pending.frame_callbacks,
+            presentation_feedback: pending.presentation_feedback,
+            tearing,
+            fifo_barrier: pending.fifo_barrier,
+            subsurfaces: pending
+                .subsurfaces
+                .map(|subsurfaces| subsurfaces.to_stack(client.id())),
+            viewport: pending.viewport,
+            buffer_scale: pending.buffer_scale,
+            buffer_transform: pending.buffer_transform,
+            input_region: pending.input_region,
+        };
+
+        match pending.buffer {
+            Some(Some(buffer)) => {
+                let mut content = buffer.contents().await?;
+
+                if let (SurfaceContent::Dmabuf(dmabuf), Some(release)) =
+                    (&mut content, pending.release)
+                {
+                    dmabuf.release.push(release);
+                }
+
+                update.content = Some(content);
+
+                // The contents have been copied, the client can reuse the buffer
+                // right away. Dmabufs get released once the renderer is done
+                // sampling them.
+                if buffer.is_shm() {
+                    buffer.release(client, buffer.id()).await?;
+                }
+            }
+            Some(None) => update.unmapped = true,
+            None => {}
+        }
+
+        let (has_content, unmapped) = (update.content.is_some(), update.unmapped);
+
+        let _ = client.renderer().commit_surface(update).await;
+
+        if self.role() == Some(Role::XdgToplevel) {
+            if has_content && !self.mapped.swap(true, Ordering::Relaxed) {
+                let _ = client
+                    .compositor()
+                    .cast(CompositorMessage::MapToplevel { surface: id })
+                    .await;
+            } else if unmapped && self.mapped.swap(false, Ordering::Relaxed) {
+                let _ = client
+                    .compositor()
+                    .cast(CompositorMessage::UnmapSurface { surface: id })
+                    .await;
+            }
+        }
+
+        let (layer_surface, lock_surface) = {
+            let state = self.state.read().await;
+            (state.layer_surface, state.lock_surface)
+        };
+
+        if lock_surface.is_some() && has_content && !self.mapped.swap(true, Ordering::Relaxed) {
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::MapLockSurface { surface: id })
+                .await;
+        }
+
+        if self.role() == Some(Role::LayerSurface)
+            && let Some(layer_surface) = layer_surface
+        {
+            if unmapped {
+                // Starts over with the initial commit
+                self.mapped.store(false, Ordering::Relaxed);
+
+                let _ = client
+                    .compositor()
+                    .cast(CompositorMessage::RemoveLayerSurface { surface: id })
+                    .await;
+            } else {
+                let mapped = has_content && !self.mapped.swap(true, Ordering::Relaxed);
+
+                if layer.is_some() || mapped {
+                    let output = client
+                        .get::<LayerSurface>(layer_surface)
+                        .and_then(|object| object.output());
+
+                    let _ = client
+                        .compositor()
+                        .cast(CompositorMessage::CommitLayerSurface {
+                            surface: id,
+                            layer_surface,
+                            output,
+                            state: layer,
+                            mapped: self.mapped.load(Ordering::Relaxed),
+                        })
+                        .await;
+                }
+            }
+        }
+
+        // The hotspot of a drag icon stays under the pointer
+        if self.role() == Some(Role::DndIcon) && offset != (0, 0) {
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::MoveDragIcon {
+                    surface: id,
+                    dx: offset.0,
+                    dy: offset.1,
+                })
+                .await;
+        }
+
+        // Synchronized subsurfaces show their cached state along with ours
+        let children = self.state.read().await.subsurfaces.ids();
+
+        for child in children {
+            if let Some(surface) = client.get::<Surface>(child) {
+                Box::pin(surface.apply_cached(client, child)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ties a `wp_presentation_feedback` to the next commit
+    pub async fn add_presentation_feedback(&self, feedback: ObjectId) {
+        self.state
+            .write()
+            .await
+            .pending
+            .presentation_feedback
+            .push(feedback);
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        // Disconnecting clients drop their surfaces without destroying them
+        self.state.get_mut().abort_tasks();
+    }
 }
 
 impl WlSurface for Surface {
     type Connection = Client;
 
-    async fn destroy(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        todo!()
+    async fn destroy(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        if let Some((parent, _)) = self.parent().await {
+            parent.remove_subsurface(sender_id).await;
+        }
+
+        let (layer_surface, lock_surface, unapplied) = {
+            let mut state = self.state.write().await;
+            state.abort_tasks();
+            (
+                state.layer_surface,
+                state.lock_surface,
+                state.take_unapplied(),
+            )
+        };
+
+        // Lets keyboard focus move on from a destroyed window
+        if lock_surface.is_some() {
+            self.mapped.store(false, Ordering::Relaxed);
+
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::RemoveLockSurface {
+                    surface: SurfaceId::new(client.id(), sender_id),
+                })
+                .await;
+        } else if layer_surface.is_some() {
+            self.mapped.store(false, Ordering::Relaxed);
+
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::RemoveLayerSurface {
+                    surface: SurfaceId::new(client.id(), sender_id),
+                })
+                .await;
+        } else if self.mapped.swap(false, Ordering::Relaxed) {
+            let _ = client
+                .compositor()
+                .cast(CompositorMessage::UnmapSurface {
+                    surface: SurfaceId::new(client.id(), sender_id),
+                })
+                .await;
+        }
+
+        let _ = client
+            .renderer()
+            .destroy_surface(SurfaceId::new(client.id(), sender_id))
+            .await;
+
+        for buffer in unapplied {
+            buffer.release(client, buffer.id()).await?;
+        }
+
+        client.destroy_object(sender_id).await
     }
 
     async fn attach(
         &self,
-        _client: &mut Self::Connection,
-        _sender_id: ObjectId,
-        _buffer: Option<ObjectId>,
-        _x: i32,
-        _y: i32,
+        client: &mut Self::Connection,
+        sender_id: ObjectId,
+        buffer: Option<ObjectId>,
+        x: i32,
+        y: i32,
     ) -> Result<()> {
-        todo!()
+        if self.version >= OFFSET_VERSION && (x, y) != (0, 0) {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidOffset as u32,
+                "Non-zero attach offset".to_string(),
+            ));
+        }
+
+        let buffer = buffer
+            .map(|id| {
+                client
+                    .get::<Buffer>(id)
+                    .ok_or(VerdiError::MissingObject(id))
+            })
+            .transpose()?;
+
+        let mut state = self.state.write().await;
+        state.pending.buffer = Some(buffer);
+        state.pending.offset = (state.pending.offset.0 + x, state.pending.offset.1 + y);
+
+        Ok(())
     }
 
     async fn damage(
         &self,
         _client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _x: i32,
-        _y: i32,
-        _width: i32,
-        _height: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
     ) -> Result<()> {
+        self.state
+            .write()
+            .await
+            .pending
+            .damage
+            .add(Rect::new(x, y, width, height));
+
         Ok(())
     }
 
     async fn frame(
         &self,
-        _client: &mut Self::Connection,
+        client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _callback: ObjectId,
+        callback: ObjectId,
     ) -> Result<()> {
-        todo!()
+        client.insert(callback, Callback::default());
+
+        self.state
+            .write()
+            .await
+            .pending
+            .frame_callbacks
+            .push(callback);
+
+        Ok(())
     }
 
     async fn set_opaque_region(
         &self,
-        _client: &mut Self::Connection,
+        client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _region: Option<ObjectId>,
+        region: Option<ObjectId>,
     ) -> Result<()> {
-        todo!()
+        let area = region::copy_area(client, region).await?;
+        self.state.write().await.pending.opaque_region = Some(area);
+
+        Ok(())
     }
 
     async fn set_input_region(
         &self,
-        _client: &mut Self::Connection,
+        client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _region: Option<ObjectId>,
+        region: Option<ObjectId>,
     ) -> Result<()> {
-        todo!()
+        let area = region::copy_area(client, region).await?;
+        self.state.write().await.pending.input_region = Some(area);
+
+        Ok(())
     }
 
-    async fn commit(&self, _client: &mut Self::Connection, _sender_id: ObjectId) -> Result<()> {
-        // FIXME: commit state
+    async fn commit(&self, client: &mut Self::Connection, sender_id: ObjectId) -> Result<()> {
+        match self.role() {
+            Some(Role::LayerSurface) => self.commit_layer_surface(client).await?,
+            Some(Role::LockSurface) => self.commit_lock_surface(client).await?,
+            _ => {}
+        }
 
-        Ok(())
+        let (target_time_ns, acquire) = {
+            let mut state = self.state.write().await;
+
+            if let Some(syncobj_surface) = state.syncobj_surface {
+                state.pending.check_sync_points(syncobj_surface)?;
+            }
+
+            state.check_buffer_size(sender_id)?;
+            state.check_viewport()?;
+
+            if std::mem::take(&mut state.viewport_changed) {
+                state.pending.viewport = Some(state.viewport);
+            }
+
+            if std::mem::take(&mut state.subsurfaces_changed) {
+                let subsurfaces = state.subsurfaces.clone();
+                state.pending.subsurfaces = Some(subsurfaces);
+            }
+
+            let pending = std::mem::take(&mut state.pending);
+            let target_time_ns = pending.target_time_ns;
+            let acquire = pending.acquire.clone();
+            state.queued.push_back(pending);
+
+            (target_time_ns, acquire)
+        };
+
+        let mut tasks = Vec::new();
+
+        // The client may still be rendering into the buffer, the commit comes
+        // back to the queue once it's done
+        if let Some(acquire) = acquire
+            && !acquire.is_signalled()
+        {
+            let handle = client.handle();
+
+            let task = tokio::spawn(async move {
+                if let Err(e) = acquire.wait().await {
+                    warn!("Failed to wait for acquire point: {e}");
+                }
+
+                handle.acquire_signalled(sender_id).await;
+            });
+
+            tasks.push(task.abort_handle());
+        }
+
+        // Comes back to the queue once the target time is reached
+        if let Some(time_ns) = target_time_ns {
+            let delay = Duration::from_nanos((time_ns - monotonic_ns()).max(0) as u64);
+
+            if !delay.is_zero() {
+                let handle = client.handle();
+
+                let task = tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    handle.commit_timer_expired(sender_id).await;
+                });
+
+                tasks.push(task.abort_handle());
+            }
+        }
+
+        if !tasks.is_empty() {
+            let mut state = self.state.write().await;
+            state.tasks.retain(|task| !task.is_finished());
+            state.tasks.extend(tasks);
+        }
+
+        self.apply_queued(client, sender_id).await
     }
 
     async fn set_buffer_transform(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
-        _transform: waynest_protocols::server::core::wayland::wl_output::Transform,
+        sender_id: ObjectId,
+        transform: wl_output::Transform,
     ) -> Result<()> {
-        todo!()
+        let Some(transform) = Transform::from_raw(transform as u32) else {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidTransform as u32,
+                "Unknown buffer transform".to_string(),
+            ));
+        };
+
+        self.state.write().await.pending.buffer_transform = Some(transform);
+
+        Ok(())
     }
 
     async fn set_buffer_scale(
         &self,
         _client: &mut Self::Connection,
-        _sender_id: ObjectId,
-        _scale: i32,
+        sender_id: ObjectId,
+        scale: i32,
     ) -> Result<()> {
-        todo!()
+        if scale <= 0 {
+            return Err(VerdiError::client(
+                sender_id,
+                Error::InvalidScale as u32,
+                format!("Buffer scale {scale} isn't positive"),
+            ));
+        }
+
+        self.state.write().await.pending.buffer_scale = Some(scale);
+
+        Ok(())
     }
 
     async fn damage_buffer(
         &self,
         _client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _x: i32,
-        _y: i32,
-        _width: i32,
-        _height: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
     ) -> Result<()> {
-        todo!()
+        self.state
+            .write()
+            .await
+            .pending
+            .buffer_damage
+            .add(Rect::new(x, y, width, height));
+
+        Ok(())
     }
 
     async fn offset(
         &self,
         _client: &mut Self::Connection,
         _sender_id: ObjectId,
-        _x: i32,
-        _y: i32,
+        x: i32,
+        y: i32,
     ) -> Result<()> {
-        todo!()
+        let mut state = self.state.write().await;
+        state.pending.offset = (state.pending.offset.0 + x, state.pending.offset.1 + y);
+
+        Ok(())
     }
 }
@@ -0,0 +1,13 @@
+//! The wlroots predecessor of `ext_data_control_v1`, for clipboard managers
+//! that don't support the latter yet
+
+use crate::protocol::data_control::data_control_protocol;
+
+data_control_protocol! {
+    protocol: waynest_protocols::server::wlr::wlr_data_control_unstable_v1,
+    manager: zwlr_data_control_manager_v1::ZwlrDataControlManagerV1 as WlrDataControlManager,
+    device: zwlr_data_control_device_v1::ZwlrDataControlDeviceV1 as WlrDataControlDevice,
+    source: zwlr_data_control_source_v1::ZwlrDataControlSourceV1 as WlrDataControlSource,
+    offer: zwlr_data_control_offer_v1::ZwlrDataControlOfferV1 as WlrDataControlOffer,
+    primary_selection_version: 2,
+}
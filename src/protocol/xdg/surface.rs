@@ -38,9 +38,21 @@ impl XdgSurface for Surface {
         sender_id: ObjectId,
         id: ObjectId,
     ) -> Result<()> {
-        client.insert(id, Toplevel::new(client.get::<Self>(sender_id).unwrap()));
+        let toplevel = Toplevel::new(client.get::<Self>(sender_id).unwrap());
 
-        self.wl_surface.set_role(Role::XdgToplevel)?;
+        self.wl_surface.set_role(
+            Role::XdgToplevel,
+            sender_id,
+            Error::AlreadyConstructed as u32,
+        )?;
+
+        // Let the client pick its own size until we have a window manager
+        toplevel.configure(client, id, 0, 0, Vec::new()).await?;
+
+        let serial = client.next_event_serial();
+        self.configure(client, sender_id, serial).await?;
+
+        client.insert(id, toplevel);
 
         Ok(())
     }
@@ -74,6 +86,6 @@ impl XdgSurface for Surface {
         _sender_id: ObjectId,
         _serial: u32,
     ) -> Result<()> {
-        todo!()
+        Ok(())
     }
 }